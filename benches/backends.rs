@@ -0,0 +1,39 @@
+// construction and force times for each tree backend over growing n.
+// run with `cargo bench --bench backends`, adding `--features gpu` for the gpu to be used; its build time
+// includes opening the device
+use barneshutt3d::{Body, Cuboid, Simulation, SimulationConfig, TreeBackend};
+use std::time::Instant;
+
+const SIZES: [usize; 4] = [10_000, 50_000, 100_000, 200_000];
+
+fn main() {
+    let space = Cuboid::from(([0.; 3], [1024.; 3]));
+    println!("bodies,backend,build,forces");
+    for n in SIZES {
+        let bodies: Vec<Body> = (0..n)
+            .map(|_| {
+                let mut body = rand::random::<Body>();
+                body.location = body.location * 1024.;
+                body
+            })
+            .collect();
+        for (name, backend) in [
+            ("pointer", TreeBackend::Pointer),
+            ("linear", TreeBackend::Linear),
+            ("gpu", TreeBackend::Gpu),
+        ] {
+            let config = SimulationConfig {
+                backend,
+                ..SimulationConfig::default()
+            };
+            let instant = Instant::now();
+            let simulation = Simulation::with_config(bodies.clone(), space, config);
+            let build = instant.elapsed();
+            let instant = Instant::now();
+            let accelerations = simulation.compute_accelerations();
+            let forces = instant.elapsed();
+            assert_eq!(accelerations.len(), n);
+            println!("{},{},{:?},{:?}", n, name, build, forces);
+        }
+    }
+}
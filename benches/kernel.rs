@@ -0,0 +1,80 @@
+// the near-field kernel on its own, scalar against simd in both precisions, then a whole force pass on a
+// plummer sphere. run with `cargo bench --bench kernel --features simd`, and the force pass without the
+// feature for the scalar baseline
+use barneshutt3d::kernel::{self, Sources};
+use barneshutt3d::{ic, Body, Cuboid, Point, Scalar, Simulation, SimulationConfig};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+const SOURCES: usize = 64;
+const CALLS: usize = 200_000;
+const BODIES: usize = 20_000;
+
+type Kernel<S> = fn(&Point<S>, &Sources<S>, S) -> Point<S>;
+
+fn main() {
+    println!("precision,kernel,interactions_per_second");
+    calls::<f64>();
+    calls::<f32>();
+    println!("precision,bucket,forces");
+    let bodies = ic::plummer(BODIES, 1., 1., &mut StdRng::seed_from_u64(0));
+    for bucket_size in [1, 4, 16] {
+        forces::<f64>(&bodies, bucket_size);
+        forces::<f32>(&bodies, bucket_size);
+    }
+}
+
+// a leaf-sized batch of sources around a fixed target, summed over and over by each kernel
+fn calls<S: Scalar>() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut coordinates = || -> Vec<S> { (0..SOURCES).map(|_| S::from_f64(rng.gen())).collect() };
+    let (x, y, z, mass) = (coordinates(), coordinates(), coordinates(), coordinates());
+    let sources = Sources {
+        x: &x,
+        y: &y,
+        z: &z,
+        mass: &mass,
+    };
+    let target = Point {
+        x: S::from_f64(0.5),
+        y: S::from_f64(0.5),
+        z: S::from_f64(0.5),
+    };
+    let softening = S::from_f64(0.01);
+    let kernels: [(&str, Kernel<S>); 2] = [("scalar", kernel::scalar), ("simd", S::direct_sum)];
+    for (name, kernel) in kernels {
+        let instant = Instant::now();
+        let mut total = Point::default();
+        for _ in 0..CALLS {
+            total += kernel(std::hint::black_box(&target), &sources, softening);
+        }
+        let elapsed = instant.elapsed();
+        assert!(total.x.is_finite());
+        println!(
+            "{},{},{:e}",
+            S::PRECISION,
+            name,
+            (SOURCES * CALLS) as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
+
+fn forces<S: Scalar>(bodies: &[Body], bucket_size: usize) {
+    let bodies: Vec<Body<S>> = bodies.iter().map(Body::cast).collect();
+    let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+    let config = SimulationConfig {
+        softening: 0.01,
+        bucket_size,
+        ..SimulationConfig::default()
+    };
+    let simulation = Simulation::with_config(bodies, space, config);
+    let mut best = Duration::MAX;
+    for _ in 0..3 {
+        let instant = Instant::now();
+        let accelerations = simulation.compute_accelerations();
+        best = best.min(instant.elapsed());
+        assert_eq!(accelerations.len(), BODIES);
+    }
+    println!("{},{},{:?}", S::PRECISION, bucket_size, best);
+}
@@ -0,0 +1,39 @@
+// force time and error against the direct sum for monopole and quadrupole nodes over a range of theta,
+// on a plummer sphere. run with `cargo bench --bench multipole`
+use barneshutt3d::{ic, Cuboid, MultipoleOrder, Simulation, SimulationConfig};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::time::Instant;
+
+const BODIES: usize = 20_000;
+const THETAS: [f64; 5] = [0.3, 0.5, 0.7, 0.9, 1.1];
+
+fn main() {
+    let bodies = ic::plummer(BODIES, 1., 1., &mut StdRng::seed_from_u64(0));
+    let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+    println!("theta,order,forces,rms_error,max_error");
+    for theta in THETAS {
+        for (name, multipole) in [
+            ("monopole", MultipoleOrder::Monopole),
+            ("quadrupole", MultipoleOrder::Quadrupole),
+        ] {
+            let config = SimulationConfig {
+                theta,
+                softening: 0.01,
+                bucket_size: 4,
+                multipole,
+                ..SimulationConfig::default()
+            };
+            let simulation = Simulation::with_config(bodies.clone(), space, config);
+            let instant = Instant::now();
+            let accelerations = simulation.compute_accelerations();
+            let forces = instant.elapsed();
+            assert_eq!(accelerations.len(), BODIES);
+            let error = simulation.force_error(theta);
+            println!(
+                "{},{},{:?},{:e},{:e}",
+                theta, name, forces, error.rms, error.max
+            );
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// build, force and direct potential energy times over a growing rayon pool; the one-thread row stands in for
+// the serial path. the clustered forces are those of the same bodies with a tenth of them packed into a cube of
+// a hundredth the box's side, whose walks run far deeper than the rest.
+// run with `cargo bench --features parallel --bench scaling`
+use barneshutt3d::diagnostics::potential_energy_direct;
+use barneshutt3d::{Body, Cuboid, Point, Range, Simulation, SimulationConfig};
+use std::time::{Duration, Instant};
+
+const BODIES: usize = 200_000;
+const THETA: f64 = 0.5;
+// the direct potential is o(n^2), so it only takes the first of the bodies
+const POTENTIAL_BODIES: usize = 20_000;
+
+fn main() {
+    let space = Cuboid {
+        x: Range {
+            start: 0.0,
+            end: 1024.0,
+        },
+        y: Range {
+            start: 0.0,
+            end: 1024.0,
+        },
+        z: Range {
+            start: 0.0,
+            end: 1024.0,
+        },
+    };
+    let bodies: Vec<Body> = (0..BODIES)
+        .map(|_| {
+            let mut body = rand::random::<Body>();
+            body.location = body.location * 1024.;
+            body
+        })
+        .collect();
+    let mut clustered = bodies.clone();
+    for body in &mut clustered[BODIES - BODIES / 10..] {
+        body.location = body.location / 100. + Point::from([256.; 3]);
+    }
+
+    let config = SimulationConfig {
+        theta: THETA,
+        ..SimulationConfig::default()
+    };
+
+    let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads = vec![1];
+    while threads.last().unwrap() * 2 <= available {
+        threads.push(threads.last().unwrap() * 2);
+    }
+    if *threads.last().unwrap() != available {
+        threads.push(available);
+    }
+
+    println!(
+        "threads,build,forces,clustered forces,potential,build speedup,forces speedup,clustered forces speedup,\
+         potential speedup"
+    );
+    let mut baseline: Option<(Duration, Duration, Duration, Duration)> = None;
+    for n in threads {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build()
+            .unwrap();
+        let (build, forces, clustered_forces, potential) = pool.install(|| {
+            let instant = Instant::now();
+            let simulation = Simulation::with_config(bodies.clone(), space, config);
+            let build = instant.elapsed();
+            let instant = Instant::now();
+            let accelerations = simulation.compute_accelerations();
+            let forces = instant.elapsed();
+            assert_eq!(accelerations.len(), BODIES);
+            let simulation = Simulation::with_config(clustered.clone(), space, config);
+            let instant = Instant::now();
+            let accelerations = simulation.compute_accelerations();
+            let clustered_forces = instant.elapsed();
+            assert_eq!(accelerations.len(), BODIES);
+            let instant = Instant::now();
+            let energy = potential_energy_direct(&bodies[..POTENTIAL_BODIES], 0.);
+            let potential = instant.elapsed();
+            assert!(energy < 0.);
+            (build, forces, clustered_forces, potential)
+        });
+        let (build_1, forces_1, clustered_forces_1, potential_1) =
+            *baseline.get_or_insert((build, forces, clustered_forces, potential));
+        println!(
+            "{},{:?},{:?},{:?},{:?},{:.2},{:.2},{:.2},{:.2}",
+            n,
+            build,
+            forces,
+            clustered_forces,
+            potential,
+            build_1.as_secs_f64() / build.as_secs_f64(),
+            forces_1.as_secs_f64() / forces.as_secs_f64(),
+            clustered_forces_1.as_secs_f64() / clustered_forces.as_secs_f64(),
+            potential_1.as_secs_f64() / potential.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,196 @@
+// criterion suite for the tree: construction, moments, forces at several theta and in two body orders, single
+// and dual walks on a clustered system, and a full step, on seeded plummer spheres. run with `cargo bench
+// --bench tree`, or e.g. `cargo bench --bench tree -- forces` for one group; criterion keeps the last run under
+// target/criterion and reports changes against it.
+// the force and step groups at 100k bodies take minutes, so filter them out, e.g. with `-- '/1000$'`, for a
+// quick pass
+use barneshutt3d::{
+    ic, Body, BodyTree, Cuboid, LinearOctree, Point, Simulation, SimulationConfig, Traversal,
+    WalkStack,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::hint::black_box;
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const THETAS: [f64; 3] = [0.3, 0.5, 0.8];
+const BUCKET_SIZE: usize = 4;
+
+fn bodies(n: usize) -> (Vec<Body>, Cuboid) {
+    let bodies = ic::plummer(n, 1., 1., &mut StdRng::seed_from_u64(n as u64));
+    let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+    (bodies, space)
+}
+
+fn config(theta: f64) -> SimulationConfig {
+    SimulationConfig {
+        theta,
+        softening: 0.01,
+        bucket_size: BUCKET_SIZE,
+        ..SimulationConfig::default()
+    }
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(20);
+    for n in SIZES {
+        let (bodies, space) = bodies(n);
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::new("pointer", n), &bodies, |b, bodies| {
+            b.iter_batched(
+                || bodies.clone(),
+                |bodies| BodyTree::build_bucketed(bodies, space, BUCKET_SIZE),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("linear", n), &bodies, |b, bodies| {
+            b.iter_batched(
+                || bodies.clone(),
+                |bodies| LinearOctree::build_bucketed(bodies, space, BUCKET_SIZE),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn moments(c: &mut Criterion) {
+    let mut group = c.benchmark_group("moments");
+    group.sample_size(20);
+    for n in SIZES {
+        let (bodies, space) = bodies(n);
+        let mut tree = BodyTree::build_bucketed(bodies, space, BUCKET_SIZE);
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_function(BenchmarkId::new("mass", n), |b| {
+            b.iter(|| tree.compute_mass_distribution())
+        });
+        group.bench_function(BenchmarkId::new("quadrupole", n), |b| {
+            b.iter(|| tree.compute_quadrupoles())
+        });
+    }
+    group.finish();
+}
+
+fn forces(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forces");
+    group.sample_size(10);
+    for n in SIZES {
+        let (bodies, space) = bodies(n);
+        group.throughput(Throughput::Elements(n as u64));
+        for theta in THETAS {
+            let simulation = Simulation::with_config(bodies.clone(), space, config(theta));
+            group.bench_function(BenchmarkId::new(format!("theta {}", theta), n), |b| {
+                b.iter(|| black_box(simulation.compute_accelerations()))
+            });
+        }
+    }
+    group.finish();
+}
+
+// one walk per body on the pointer tree, recursing or on a walk stack kept across the walks
+fn walk(c: &mut Criterion) {
+    let mut group = c.benchmark_group("walk");
+    group.sample_size(10);
+    for n in SIZES {
+        let (bodies, space) = bodies(n);
+        let tree = BodyTree::build_bucketed(bodies.clone(), space, BUCKET_SIZE);
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_function(BenchmarkId::new("recursive", n), |b| {
+            b.iter(|| {
+                for body in &bodies {
+                    black_box(tree.acceleration_at(&body.location, 0.5, 0.01));
+                }
+            })
+        });
+        let mut stack = WalkStack::new();
+        group.bench_function(BenchmarkId::new("stack", n), |b| {
+            b.iter(|| {
+                for body in &bodies {
+                    black_box(tree.acceleration_at_with(&body.location, 0.5, 0.01, &mut stack));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// the force pass over the plummer bodies in the random order they were drawn in, and sorted by morton key
+fn order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order");
+    group.sample_size(10);
+    for n in SIZES {
+        let (bodies, space) = bodies(n);
+        group.throughput(Throughput::Elements(n as u64));
+        for (name, morton_order) in [("drawn", false), ("morton", true)] {
+            let config = SimulationConfig {
+                morton_order,
+                ..config(0.5)
+            };
+            let mut simulation = Simulation::with_config(bodies.clone(), space, config);
+            // sorts the bodies and builds the tree on them
+            simulation.step(1e-6);
+            group.bench_function(BenchmarkId::new(name, n), |b| {
+                b.iter(|| black_box(simulation.compute_accelerations()))
+            });
+        }
+    }
+    group.finish();
+}
+
+// a plummer sphere with a tenth of its bodies in a clump a fiftieth its size off to one side, walked once per
+// body at theta 0.7 and in pairs at 0.4, which are about as accurate, and at 0.6
+fn traversal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("traversal");
+    group.sample_size(10);
+    for n in SIZES {
+        let mut rng = StdRng::seed_from_u64(n as u64);
+        let mut bodies = ic::plummer(n - n / 10, 1., 1., &mut rng);
+        let offset = Point::from([2., 0., 0.]);
+        bodies.extend(
+            ic::plummer(n / 10, 0.1, 0.02, &mut rng)
+                .into_iter()
+                .map(|body| Body {
+                    location: body.location + offset,
+                    ..body
+                }),
+        );
+        let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+        group.throughput(Throughput::Elements(n as u64));
+        for (name, traversal, theta) in [
+            ("single 0.7", Traversal::Single, 0.7),
+            ("dual 0.4", Traversal::Dual, 0.4),
+            ("dual 0.6", Traversal::Dual, 0.6),
+        ] {
+            let config = SimulationConfig {
+                traversal,
+                ..config(theta)
+            };
+            let simulation = Simulation::with_config(bodies.clone(), space, config);
+            group.bench_function(BenchmarkId::new(name, n), |b| {
+                b.iter(|| black_box(simulation.compute_accelerations()))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.sample_size(10);
+    for n in SIZES {
+        let (bodies, space) = bodies(n);
+        let mut simulation = Simulation::with_config(bodies, space, config(0.5));
+        // the first step also computes the starting accelerations
+        simulation.step(1e-3);
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter(|| simulation.step(1e-3))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, build, moments, forces, walk, order, traversal, step);
+criterion_main!(benches);
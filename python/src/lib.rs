@@ -0,0 +1,575 @@
+//! the `barneshutt3d` python module: `Simulation`, the initial condition generators and snapshot writing, with
+//! bodies going in and out as numpy arrays so results go straight into astropy or matplotlib. build it into
+//! the current environment with `maturin develop --release` from this directory.
+//!
+//! ```python
+//! import barneshutt3d as bh
+//!
+//! positions, velocities, masses = bh.plummer(10_000, seed=1)
+//! sim = bh.Simulation(positions, velocities, masses, theta=0.5, softening=0.01, backend="linear")
+//! sim.run(1e-3, 100)
+//! print(sim.time, sim.diagnostics()["total_energy"])
+//! x, y, z = sim.positions.T
+//! ```
+//!
+//! positions and velocities are (n, 3) float64 arrays and masses an (n,) one; the precision is always f64 and
+//! the force law newtonian gravity. the simulation's properties return copies, so changing them does not move
+//! the bodies
+use barneshutt3d::{
+    ic, load, Body, Cuboid, Diagnostics, MultipoleOrder, OutputFilter, Point, PotentialMethod,
+    Range, Scheme, SimulationConfig, SnapshotFormat, SnapshotLayout, TreeBackend, Units,
+};
+use numpy::ndarray::{Array1, Array2};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// the positions, velocities and masses of `bodies`
+type Arrays<'py> = (
+    Bound<'py, PyArray2<f64>>,
+    Bound<'py, PyArray2<f64>>,
+    Bound<'py, PyArray1<f64>>,
+);
+
+fn to_arrays<'py>(py: Python<'py>, bodies: &[Body]) -> Arrays<'py> {
+    let points = |point: fn(&Body) -> Point| {
+        let flat = bodies
+            .iter()
+            .map(point)
+            .flat_map(|p| [p.x, p.y, p.z])
+            .collect();
+        Array2::from_shape_vec((bodies.len(), 3), flat)
+            .expect("three values per body")
+            .into_pyarray(py)
+    };
+    let masses = Array1::from_iter(bodies.iter().map(|body| body.mass)).into_pyarray(py);
+    (
+        points(|body| body.location),
+        points(|body| body.velocity),
+        masses,
+    )
+}
+
+fn from_arrays(
+    positions: PyReadonlyArray2<f64>,
+    velocities: PyReadonlyArray2<f64>,
+    masses: PyReadonlyArray1<f64>,
+) -> PyResult<Vec<Body>> {
+    let (positions, velocities, masses) = (
+        positions.as_array(),
+        velocities.as_array(),
+        masses.as_array(),
+    );
+    let n = masses.len();
+    if positions.shape() != [n, 3] || velocities.shape() != [n, 3] {
+        return Err(PyValueError::new_err(format!(
+            "positions and velocities must be ({}, 3) for {} masses, got {:?} and {:?}",
+            n,
+            n,
+            positions.shape(),
+            velocities.shape()
+        )));
+    }
+    let point = |row: numpy::ndarray::ArrayView1<f64>| Point {
+        x: row[0],
+        y: row[1],
+        z: row[2],
+    };
+    let bodies: Vec<Body> = (0..n)
+        .map(|i| Body {
+            mass: masses[i],
+            location: point(positions.row(i)),
+            velocity: point(velocities.row(i)),
+            ..Body::default()
+        })
+        .collect();
+    if let Some(i) = bodies.iter().position(|body| !body.is_finite()) {
+        return Err(PyValueError::new_err(format!("body {} is not finite", i)));
+    }
+    Ok(bodies)
+}
+
+fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+fn io_error(err: impl std::fmt::Display) -> PyErr {
+    PyIOError::new_err(err.to_string())
+}
+
+fn parse<T>(what: &str, value: &str, options: &[(&str, T)]) -> PyResult<T>
+where
+    T: Copy,
+{
+    match options.iter().find(|(name, _)| *name == value) {
+        Some(&(_, option)) => Ok(option),
+        None => {
+            let names: Vec<&str> = options.iter().map(|(name, _)| *name).collect();
+            Err(PyValueError::new_err(format!(
+                "unknown {} {:?}, expected one of {}",
+                what,
+                value,
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+fn diagnostics_dict<'py>(
+    py: Python<'py>,
+    diagnostics: &Diagnostics,
+) -> PyResult<Bound<'py, PyDict>> {
+    let vector = |p: Point| (p.x, p.y, p.z);
+    let dict = PyDict::new(py);
+    dict.set_item("kinetic_energy", diagnostics.kinetic_energy)?;
+    dict.set_item("potential_energy", diagnostics.potential_energy)?;
+    dict.set_item("total_energy", diagnostics.total_energy())?;
+    dict.set_item("virial_ratio", diagnostics.virial_ratio())?;
+    dict.set_item("linear_momentum", vector(diagnostics.linear_momentum))?;
+    dict.set_item("angular_momentum", vector(diagnostics.angular_momentum))?;
+    Ok(dict)
+}
+
+/// Simulation(positions, velocities, masses, *, theta=0.5, softening=0.0, bucket_size=1, backend="pointer",
+/// multipole="monopole", integrator="leapfrog", units="dimensionless", gravitational_constant=None)
+///
+/// bodies advanced over a barnes-hut tree, in a power-of-two cube fitted around them. backend is "pointer",
+/// "linear" or "gpu"; integrator is "leapfrog", "yoshida4" or "hermite"; units is "dimensionless", "si",
+/// "astronomical" or "solar_system", unless gravitational_constant gives G directly
+#[pyclass(name = "Simulation", module = "barneshutt3d")]
+struct PySimulation {
+    inner: barneshutt3d::Simulation,
+}
+
+#[pymethods]
+impl PySimulation {
+    #[new]
+    #[pyo3(signature = (
+        positions,
+        velocities,
+        masses,
+        *,
+        theta = 0.5,
+        softening = 0.,
+        bucket_size = 1,
+        backend = "pointer",
+        multipole = "monopole",
+        integrator = "leapfrog",
+        units = "dimensionless",
+        gravitational_constant = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        positions: PyReadonlyArray2<f64>,
+        velocities: PyReadonlyArray2<f64>,
+        masses: PyReadonlyArray1<f64>,
+        theta: f64,
+        softening: f64,
+        bucket_size: usize,
+        backend: &str,
+        multipole: &str,
+        integrator: &str,
+        units: &str,
+        gravitational_constant: Option<f64>,
+    ) -> PyResult<Self> {
+        let bodies = from_arrays(positions, velocities, masses)?;
+        let valid = |value: f64| value >= 0. && value.is_finite();
+        if !valid(theta) || !valid(softening) {
+            return Err(PyValueError::new_err(
+                "theta and softening must be finite and not negative",
+            ));
+        }
+        if bucket_size == 0 {
+            return Err(PyValueError::new_err("bucket_size must be at least 1"));
+        }
+        let units = match gravitational_constant {
+            Some(g) if g > 0. && g.is_finite() => Units::Custom {
+                gravitational_constant: g,
+            },
+            Some(_) => {
+                return Err(PyValueError::new_err(
+                    "gravitational_constant must be positive",
+                ))
+            }
+            None => parse(
+                "units",
+                units,
+                &[
+                    ("dimensionless", Units::Dimensionless),
+                    ("si", Units::Si),
+                    ("astronomical", Units::Astronomical),
+                    ("solar_system", Units::SolarSystem),
+                ],
+            )?,
+        };
+        let config = SimulationConfig {
+            theta,
+            softening,
+            bucket_size,
+            backend: parse(
+                "backend",
+                backend,
+                &[
+                    ("pointer", TreeBackend::Pointer),
+                    ("linear", TreeBackend::Linear),
+                    ("gpu", TreeBackend::Gpu),
+                ],
+            )?,
+            multipole: parse(
+                "multipole",
+                multipole,
+                &[
+                    ("monopole", MultipoleOrder::Monopole),
+                    ("quadrupole", MultipoleOrder::Quadrupole),
+                ],
+            )?,
+            integrator: parse(
+                "integrator",
+                integrator,
+                &[
+                    ("leapfrog", Scheme::Leapfrog),
+                    ("yoshida4", Scheme::Yoshida4),
+                    ("hermite", Scheme::Hermite),
+                ],
+            )?,
+            units,
+            ..SimulationConfig::default()
+        };
+        let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+        Ok(PySimulation {
+            inner: barneshutt3d::Simulation::with_config(bodies, space, config),
+        })
+    }
+
+    /// reads bodies from a csv, tsv or json file and fits the root box around them
+    #[staticmethod]
+    fn from_file(path: std::path::PathBuf) -> PyResult<Self> {
+        let inner = barneshutt3d::Simulation::from_file(path).map_err(io_error)?;
+        Ok(PySimulation { inner })
+    }
+
+    /// a simulation saved with `checkpoint`
+    #[staticmethod]
+    fn resume(path: std::path::PathBuf) -> PyResult<Self> {
+        let inner = barneshutt3d::Simulation::resume(path).map_err(io_error)?;
+        Ok(PySimulation { inner })
+    }
+
+    /// the state to resume from; a .json path is written as json, anything else in binary
+    fn checkpoint(&self, path: std::path::PathBuf) -> PyResult<()> {
+        self.inner.checkpoint(path).map_err(io_error)
+    }
+
+    /// one step of dt, returning the length of the step taken
+    fn step(&mut self, py: Python<'_>, dt: f64) -> f64 {
+        py.detach(|| self.inner.step(dt).dt)
+    }
+
+    /// `steps` steps of dt, without holding the gil
+    fn run(&mut self, py: Python<'_>, dt: f64, steps: u64) {
+        py.detach(|| {
+            for _ in 0..steps {
+                self.inner.step(dt);
+            }
+        })
+    }
+
+    /// adds bodies given as arrays like the constructor's, returning the ids they were given
+    fn add_bodies<'py>(
+        &mut self,
+        py: Python<'py>,
+        positions: PyReadonlyArray2<f64>,
+        velocities: PyReadonlyArray2<f64>,
+        masses: PyReadonlyArray1<f64>,
+    ) -> PyResult<Bound<'py, PyArray1<u64>>> {
+        let bodies = from_arrays(positions, velocities, masses)?;
+        let ids: Vec<u64> = self.inner.add_bodies(bodies).collect();
+        Ok(ids.into_pyarray(py))
+    }
+
+    /// each body's stable id, in the order of the other arrays
+    #[getter]
+    fn ids<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u64>> {
+        let ids: Vec<u64> = self.inner.bodies().iter().map(|body| body.id).collect();
+        ids.into_pyarray(py)
+    }
+
+    /// names the body with `id` in json snapshots and checkpoints
+    fn set_label(&mut self, id: u64, label: String) {
+        self.inner.set_label(id, label);
+    }
+
+    fn label(&self, id: u64) -> Option<String> {
+        self.inner.label(id).map(str::to_string)
+    }
+
+    #[getter]
+    fn positions<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f64>> {
+        to_arrays(py, self.inner.bodies()).0
+    }
+
+    #[getter]
+    fn velocities<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f64>> {
+        to_arrays(py, self.inner.bodies()).1
+    }
+
+    #[getter]
+    fn masses<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        to_arrays(py, self.inner.bodies()).2
+    }
+
+    /// the tree accelerations at the current positions, as an (n, 3) array
+    fn accelerations<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f64>> {
+        let accelerations = py.detach(|| self.inner.compute_accelerations());
+        let flat = accelerations.iter().flat_map(|a| [a.x, a.y, a.z]).collect();
+        Array2::from_shape_vec((accelerations.len(), 3), flat)
+            .expect("three values per body")
+            .into_pyarray(py)
+    }
+
+    #[getter]
+    fn time(&self) -> f64 {
+        self.inner.time()
+    }
+
+    #[getter]
+    fn steps(&self) -> u64 {
+        self.inner.steps()
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.bodies().len()
+    }
+
+    /// energies and momenta as a dict, with the potential from the tree or, with direct=True, the exact sum
+    #[pyo3(signature = (direct = false))]
+    fn diagnostics<'py>(&self, py: Python<'py>, direct: bool) -> PyResult<Bound<'py, PyDict>> {
+        let method = if direct {
+            PotentialMethod::Direct
+        } else {
+            PotentialMethod::Tree
+        };
+        let diagnostics = py.detach(|| self.inner.diagnostics(method));
+        diagnostics_dict(py, &diagnostics)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Simulation(bodies={}, time={}, steps={})",
+            self.inner.bodies().len(),
+            self.inner.time(),
+            self.inner.steps()
+        )
+    }
+}
+
+/// SnapshotWriter(path, format="csv", single_file=False, every=1, *, region=None, sphere=None,
+/// every_nth_body=None, sample=None, sample_seed=0)
+///
+/// writes the state of a simulation every `every` steps when `record` is called after each step. format is
+/// "csv", "json", "vtk", "xyz" or "binary"; `path` is a directory getting one file per snapshot, or with
+/// single_file one file appended to, which binary needs. the rest pick the bodies written: those inside a
+/// region of (xmin, ymin, zmin, xmax, ymax, zmax), within a sphere of (x, y, z, r), every nth one by id, and a
+/// random sample of that fraction of them, the same for the same seed
+#[pyclass(name = "SnapshotWriter", module = "barneshutt3d")]
+struct PySnapshotWriter {
+    inner: barneshutt3d::SnapshotWriter,
+}
+
+#[pymethods]
+impl PySnapshotWriter {
+    #[new]
+    #[pyo3(signature = (
+        path,
+        format = "csv",
+        single_file = false,
+        every = 1,
+        *,
+        region = None,
+        sphere = None,
+        every_nth_body = None,
+        sample = None,
+        sample_seed = 0,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        path: std::path::PathBuf,
+        format: &str,
+        single_file: bool,
+        every: u64,
+        region: Option<[f64; 6]>,
+        sphere: Option<[f64; 4]>,
+        every_nth_body: Option<u64>,
+        sample: Option<f64>,
+        sample_seed: u64,
+    ) -> PyResult<Self> {
+        let format = parse(
+            "format",
+            format,
+            &[
+                ("csv", SnapshotFormat::Csv),
+                ("json", SnapshotFormat::Json),
+                ("vtk", SnapshotFormat::Vtk),
+                ("xyz", SnapshotFormat::Xyz),
+                ("binary", SnapshotFormat::Binary),
+            ],
+        )?;
+        if every == 0 {
+            return Err(PyValueError::new_err("every must be at least 1"));
+        }
+        if format == SnapshotFormat::Vtk && single_file {
+            return Err(PyValueError::new_err("a vtk file holds a single snapshot"));
+        }
+        if format == SnapshotFormat::Binary && !single_file {
+            return Err(PyValueError::new_err(
+                "a binary snapshot file holds every snapshot, so it needs single_file",
+            ));
+        }
+        let layout = if single_file {
+            SnapshotLayout::SingleFile
+        } else {
+            SnapshotLayout::FilePerSnapshot
+        };
+        if every_nth_body == Some(0) {
+            return Err(PyValueError::new_err("every_nth_body must be at least 1"));
+        }
+        if sample.is_some_and(|fraction| !(0. ..=1.).contains(&fraction)) {
+            return Err(PyValueError::new_err("sample must be between 0 and 1"));
+        }
+        let mut inner = barneshutt3d::SnapshotWriter::new(path, format, layout, every);
+        if let Some([x0, y0, z0, x1, y1, z1]) = region {
+            inner = inner.with_filter(OutputFilter::Region(Cuboid {
+                x: Range { start: x0, end: x1 },
+                y: Range { start: y0, end: y1 },
+                z: Range { start: z0, end: z1 },
+            }));
+        }
+        if let Some([x, y, z, radius]) = sphere {
+            inner = inner.with_filter(OutputFilter::Sphere {
+                center: Point { x, y, z },
+                radius,
+            });
+        }
+        if let Some(k) = every_nth_body {
+            inner = inner.with_filter(OutputFilter::EveryNth(k));
+        }
+        if let Some(fraction) = sample {
+            inner = inner.with_filter(OutputFilter::Sample {
+                fraction,
+                seed: sample_seed,
+            });
+        }
+        Ok(PySnapshotWriter { inner })
+    }
+
+    /// writes a snapshot if the step count is a multiple of `every`, returning whether it did
+    fn record(&mut self, simulation: &PySimulation) -> PyResult<bool> {
+        self.inner.record(&simulation.inner).map_err(io_error)
+    }
+
+    /// writes a snapshot regardless of `every`
+    fn write(&mut self, simulation: &PySimulation) -> PyResult<()> {
+        self.inner.write(&simulation.inner).map_err(io_error)
+    }
+}
+
+/// a plummer sphere of n bodies in virial equilibrium, as (positions, velocities, masses)
+#[pyfunction]
+#[pyo3(signature = (n, total_mass = 1., scale_radius = 1., seed = None))]
+fn plummer(
+    py: Python<'_>,
+    n: usize,
+    total_mass: f64,
+    scale_radius: f64,
+    seed: Option<u64>,
+) -> Arrays<'_> {
+    to_arrays(
+        py,
+        &ic::plummer(n, total_mass, scale_radius, &mut rng(seed)),
+    )
+}
+
+/// a uniform-density sphere with velocities scaled to the given virial ratio
+#[pyfunction]
+#[pyo3(signature = (n, total_mass = 1., radius = 1., virial_ratio = 1., seed = None))]
+fn uniform_sphere(
+    py: Python<'_>,
+    n: usize,
+    total_mass: f64,
+    radius: f64,
+    virial_ratio: f64,
+    seed: Option<u64>,
+) -> Arrays<'_> {
+    to_arrays(
+        py,
+        &ic::uniform_sphere(n, total_mass, radius, virial_ratio, &mut rng(seed)),
+    )
+}
+
+/// a central mass, as the first body, with a cold disk of n bodies on circular orbits around it
+#[pyfunction]
+#[pyo3(signature = (n, central_mass = 1., disk_mass = 0.01, inner_radius = 0.1, outer_radius = 1., seed = None))]
+fn kepler_disk(
+    py: Python<'_>,
+    n: usize,
+    central_mass: f64,
+    disk_mass: f64,
+    inner_radius: f64,
+    outer_radius: f64,
+    seed: Option<u64>,
+) -> Arrays<'_> {
+    let bodies = ic::kepler_disk(
+        n,
+        central_mass,
+        disk_mass,
+        inner_radius,
+        outer_radius,
+        &mut rng(seed),
+    );
+    to_arrays(py, &bodies)
+}
+
+/// n bodies at rest, spread uniformly over the box from `low` to `high`, with masses uniform in [0, 1)
+#[pyfunction]
+#[pyo3(signature = (n, low = (0., 0., 0.), high = (1., 1., 1.), seed = None))]
+fn uniform_box(
+    py: Python<'_>,
+    n: usize,
+    low: (f64, f64, f64),
+    high: (f64, f64, f64),
+    seed: Option<u64>,
+) -> Arrays<'_> {
+    let range = |start, end| Range { start, end };
+    let space = Cuboid {
+        x: range(low.0, high.0),
+        y: range(low.1, high.1),
+        z: range(low.2, high.2),
+    };
+    to_arrays(py, &ic::uniform_box(n, &space, &mut rng(seed)))
+}
+
+/// bodies from a csv, tsv or json file, such as a csv snapshot, as (positions, velocities, masses)
+#[pyfunction]
+fn read_bodies(py: Python<'_>, path: std::path::PathBuf) -> PyResult<Arrays<'_>> {
+    let bodies = load::read_bodies(path).map_err(io_error)?;
+    Ok(to_arrays(py, &bodies))
+}
+
+#[pymodule]
+#[pyo3(name = "barneshutt3d")]
+fn bindings(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PySimulation>()?;
+    m.add_class::<PySnapshotWriter>()?;
+    m.add_function(wrap_pyfunction!(plummer, m)?)?;
+    m.add_function(wrap_pyfunction!(uniform_sphere, m)?)?;
+    m.add_function(wrap_pyfunction!(kepler_disk, m)?)?;
+    m.add_function(wrap_pyfunction!(uniform_box, m)?)?;
+    m.add_function(wrap_pyfunction!(read_bodies, m)?)?;
+    Ok(())
+}
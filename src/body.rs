@@ -0,0 +1,69 @@
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use rand::distributions::Standard;
+use rand::prelude::Distribution;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Body<S = f64> {
+    /// which body this is, for telling rows apart across steps and snapshots. a `Simulation` numbers its bodies
+    /// from 0 in the order it is given them, whatever ids they came with, and goes on counting for
+    /// `add_bodies`. an id follows its body through removals, and a merged body keeps the heavier one's, so
+    /// per-body data of your own can be kept in a map by id
+    pub id: u64,
+    pub mass: S,
+    pub location: Point<S>,
+    pub velocity: Point<S>,
+    pub species: Species,
+}
+
+/// what part a body plays in the forces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Species {
+    /// pulls on the other bodies and is pulled by them
+    #[default]
+    Live,
+    /// a test particle, pulled like any other body but pulling on nothing whatever its mass. tracers are left
+    /// out of the tree, the direct sums and the diagnostics, and never collide, so each costs one force
+    /// evaluation and leaves the live bodies exactly as they would be without it
+    Tracer,
+}
+// there are no rules between pairs of species beyond this one. scaling the pull of one kind of live body on
+// another would take a summary per species in every node and a filter in every walk, on the cpu and the gpu
+// alike, so a mixture of, say, dark matter and gas pulls as one population
+
+impl<S: Scalar> Body<S> {
+    /// whether the body pulls on others, i.e. is not a tracer
+    pub fn is_source(&self) -> bool {
+        self.species == Species::Live
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.mass.is_finite() && self.location.is_finite() && self.velocity.is_finite()
+    }
+
+    /// the same body in another precision
+    pub fn cast<T: Scalar>(&self) -> Body<T> {
+        Body {
+            id: self.id,
+            mass: T::from_f64(self.mass.as_f64()),
+            location: self.location.cast(),
+            velocity: self.velocity.cast(),
+            species: self.species,
+        }
+    }
+}
+
+impl<S: Scalar> Distribution<Body<S>> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Body<S> {
+        Body {
+            id: 0,
+            mass: S::from_f64(rng.gen()),
+            location: rng.gen(),
+            // random clouds start at rest
+            velocity: Point::default(),
+            species: Species::Live,
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! saving a simulation mid-run and picking it up again. a checkpoint holds the bodies with their ids and
+//! labels, the root box, the config, the force model, the clock, the seed of random bodies and the precision;
+//! the tree and accelerations are rebuilt on resume, which gives back exactly the state that was saved.
+//! external potentials are not part of it
+
+use crate::body::Body;
+use crate::force::ForceModel;
+use crate::geometry::Cuboid;
+use crate::scalar::{Precision, Scalar};
+use crate::sim::{Simulation, SimulationConfig};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+// bumped whenever the layout below changes, so old files are refused instead of misread
+const VERSION: u32 = 21;
+
+// what a checkpoint holds: the header, then the state. the binary encoding concatenates the two, so the
+// header can be read and checked on its own before the bodies are parsed in what may be the wrong precision
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "S: Scalar, F: ForceModel + Serialize + DeserializeOwned")]
+struct Checkpoint<'a, S: Scalar, F: ForceModel> {
+    header: Header,
+    state: State<'a, S, F>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    precision: Precision,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "S: Scalar, F: ForceModel + Serialize + DeserializeOwned")]
+struct State<'a, S: Scalar, F: ForceModel> {
+    bodies: Cow<'a, [Body<S>]>,
+    space: Cuboid<S>,
+    config: SimulationConfig,
+    model: Cow<'a, F>,
+    time: f64,
+    steps: u64,
+    next_id: u64,
+    labels: Cow<'a, BTreeMap<u64, String>>,
+    seed: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    /// the file was written by a different layout version than this build reads
+    Version(u32),
+    /// the file holds bodies in a different precision than the one asked to resume in
+    Precision {
+        found: Precision,
+        expected: Precision,
+    },
+}
+
+impl std::fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{}", err),
+            CheckpointError::Json(err) => write!(f, "invalid json checkpoint: {}", err),
+            CheckpointError::Binary(err) => write!(f, "invalid binary checkpoint: {}", err),
+            CheckpointError::Version(version) => write!(
+                f,
+                "checkpoint is version {}, this build reads version {}",
+                version, VERSION
+            ),
+            CheckpointError::Precision { found, expected } => {
+                write!(f, "checkpoint is in {} precision, not {}", found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io(err) => Some(err),
+            CheckpointError::Json(err) => Some(err),
+            CheckpointError::Binary(err) => Some(err),
+            CheckpointError::Version(_) | CheckpointError::Precision { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CheckpointError {
+    fn from(err: std::io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+impl<S: Scalar, F: ForceModel + Serialize + DeserializeOwned> Serialize for Simulation<S, F> {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        Checkpoint::of(self).serialize(serializer)
+    }
+}
+
+impl<'de, S: Scalar, F: ForceModel + Serialize + DeserializeOwned> Deserialize<'de>
+    for Simulation<S, F>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let checkpoint = Checkpoint::deserialize(deserializer)?;
+        checkpoint
+            .header
+            .check::<S>()
+            .map_err(serde::de::Error::custom)?;
+        Ok(checkpoint.state.into_simulation())
+    }
+}
+
+impl<'a, S: Scalar, F: ForceModel> Checkpoint<'a, S, F> {
+    fn of(simulation: &'a Simulation<S, F>) -> Self {
+        Checkpoint {
+            header: Header {
+                version: VERSION,
+                precision: S::PRECISION,
+            },
+            state: State {
+                bodies: Cow::Borrowed(simulation.bodies()),
+                space: *simulation.bounds(),
+                config: *simulation.config(),
+                model: Cow::Borrowed(simulation.model()),
+                time: simulation.time(),
+                steps: simulation.steps(),
+                next_id: simulation.next_id(),
+                labels: Cow::Borrowed(simulation.labels()),
+                seed: simulation.seed(),
+            },
+        }
+    }
+}
+
+impl Header {
+    // whether this build can resume the checkpoint as an `S` simulation
+    fn check<S: Scalar>(&self) -> Result<(), CheckpointError> {
+        if self.version != VERSION {
+            return Err(CheckpointError::Version(self.version));
+        }
+        if self.precision != S::PRECISION {
+            return Err(CheckpointError::Precision {
+                found: self.precision,
+                expected: S::PRECISION,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<S: Scalar, F: ForceModel> State<'_, S, F> {
+    fn into_simulation(self) -> Simulation<S, F> {
+        let mut simulation = Simulation::from_parts(
+            self.bodies.into_owned(),
+            self.space,
+            self.config,
+            self.model.into_owned(),
+            self.next_id,
+            self.labels.into_owned(),
+        );
+        simulation.set_clock(self.time, self.steps);
+        if let Some(seed) = self.seed {
+            simulation.set_seed(seed);
+        }
+        simulation
+    }
+}
+
+// json for a `.json` path, the compact binary encoding for anything else
+fn is_json(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("json")
+}
+
+impl<S: Scalar, F: ForceModel + Serialize + DeserializeOwned> Simulation<S, F> {
+    /// saves the state to `path`, as json if it ends in `.json` and in a compact binary encoding otherwise.
+    /// the file is written next to `path` first and renamed over it, so a crash mid-write leaves the previous
+    /// checkpoint intact
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let mut out = BufWriter::new(File::create(&partial)?);
+        let checkpoint = Checkpoint::of(self);
+        if is_json(path) {
+            serde_json::to_writer(&mut out, &checkpoint).map_err(CheckpointError::Json)?;
+        } else {
+            bincode::serialize_into(&mut out, &checkpoint).map_err(CheckpointError::Binary)?;
+        }
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// loads a simulation saved by `checkpoint`, picking the format from the extension the same way. the
+    /// checkpoint must be in this simulation's precision
+    pub fn resume(path: impl AsRef<Path>) -> Result<Simulation<S, F>, CheckpointError> {
+        let path = path.as_ref();
+        let mut input = BufReader::new(File::open(path)?);
+        let state: State<S, F> = if is_json(path) {
+            let value: serde_json::Value =
+                serde_json::from_reader(input).map_err(CheckpointError::Json)?;
+            Header::deserialize(&value["header"])
+                .map_err(CheckpointError::Json)?
+                .check::<S>()?;
+            State::deserialize(&value["state"]).map_err(CheckpointError::Json)?
+        } else {
+            let header: Header =
+                bincode::deserialize_from(&mut input).map_err(CheckpointError::Binary)?;
+            header.check::<S>()?;
+            bincode::deserialize_from(input).map_err(CheckpointError::Binary)?
+        };
+        Ok(state.into_simulation())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("barneshutt3d-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn a_stored_seed_draws_the_same_bodies() {
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let mut simulation = Simulation::<f64>::new_seeded(100, space, 42);
+        simulation.step(0.01);
+        let path = temp_path("seed.bin");
+        simulation.checkpoint(&path).unwrap();
+        let resumed: Simulation = Simulation::resume(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resumed.seed(), Some(42));
+        let original = Simulation::<f64>::new_seeded(100, space, 42);
+        let redrawn = Simulation::<f64>::new_seeded(100, space, resumed.seed().unwrap());
+        let bytes = |simulation: &Simulation| bincode::serialize(simulation.bodies()).unwrap();
+        assert_eq!(bytes(&original), bytes(&redrawn));
+    }
+
+    #[test]
+    fn resuming_gives_back_the_saved_state() {
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let mut simulation = Simulation::<f64>::new_seeded(50, space, 7);
+        simulation.set_label(3, "three");
+        simulation.step(0.01);
+        let path = temp_path("state.json");
+        simulation.checkpoint(&path).unwrap();
+        let mut resumed: Simulation = Simulation::resume(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(resumed.diff(&simulation).is_identical());
+        assert_eq!(
+            (resumed.time(), resumed.steps()),
+            (simulation.time(), simulation.steps())
+        );
+        assert_eq!(resumed.label(3), Some("three"));
+        resumed.step(0.01);
+        simulation.step(0.01);
+        assert!(resumed.diff(&simulation).is_identical());
+    }
+
+    #[test]
+    fn another_precision_is_refused() {
+        let simulation = Simulation::<f64>::new_seeded(10, Cuboid::from(([0.; 3], [1.; 3])), 1);
+        let path = temp_path("precision.bin");
+        simulation.checkpoint(&path).unwrap();
+        let resumed = Simulation::<f32>::resume(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(resumed, Err(CheckpointError::Precision { .. })));
+    }
+}
@@ -0,0 +1,207 @@
+//! close encounters between bodies: finding pairs within a radius and merging or bouncing them
+
+use crate::body::Body;
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use serde::{Deserialize, Serialize};
+
+/// what `Simulation::step` does with two bodies that end a drift closer than `radius`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum CollisionPolicy {
+    /// let them pass through each other, leaving it to the softening
+    #[default]
+    Ignore,
+    /// replace the pair with one body at their center of mass, conserving mass and momentum. it keeps the id of
+    /// the heavier of the two
+    Merge { radius: f64 },
+    /// reflect their velocities along the line between them as in an elastic collision of hard spheres,
+    /// conserving momentum and kinetic energy. pairs already moving apart are left alone
+    Bounce { radius: f64 },
+}
+
+/// one pair that collided during a step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collision {
+    /// indices into `bodies()` as they stood when the pair was found, lower first. with `Merge` the lower one
+    /// becomes the merged body and the other is removed, shifting later bodies down
+    pub pair: (usize, usize),
+    /// the `Body::id`s of the same two bodies
+    pub ids: (u64, u64),
+    /// combined mass and center of mass of the pair at contact, in f64 whatever the simulation's precision
+    pub mass: f64,
+    pub location: Point,
+}
+
+/// every pair of bodies closer than `radius`, as (lower index, higher index) in ascending order. sweeps the
+/// bodies sorted along x, so it stays near o(n log n) while the radius is small next to the spacing
+pub fn close_pairs<S: Scalar>(bodies: &[Body<S>], radius: S) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..bodies.len()).collect();
+    order.sort_unstable_by(|&a, &b| bodies[a].location.x.total_cmp(&bodies[b].location.x));
+    let mut pairs = vec![];
+    for (k, &i) in order.iter().enumerate() {
+        for &j in &order[k + 1..] {
+            if bodies[j].location.x - bodies[i].location.x > radius {
+                break;
+            }
+            if bodies[i].location.distance_squared(&bodies[j].location) < radius * radius {
+                pairs.push((i.min(j), i.max(j)));
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// applies `policy` to the bodies, returning the collisions and, if any bodies were merged away, which ones
+/// are kept. a body merges at most once per call: the merged body sits out the rest of the pairs, so a clump
+/// of several comes together over as many steps as it takes
+pub(crate) fn resolve<S: Scalar>(
+    bodies: &mut Vec<Body<S>>,
+    policy: CollisionPolicy,
+) -> (Vec<Collision>, Option<Vec<bool>>) {
+    let radius = match policy {
+        CollisionPolicy::Ignore => return (vec![], None),
+        CollisionPolicy::Merge { radius } | CollisionPolicy::Bounce { radius } => {
+            S::from_f64(radius)
+        }
+    };
+    let two = S::from_f64(2.);
+    let mut collisions = vec![];
+    let mut kept = vec![true; bodies.len()];
+    // bodies taken up in a merge this call, on either side of it
+    let mut merged = vec![false; bodies.len()];
+    for (i, j) in close_pairs(bodies, radius) {
+        let (a, b) = (bodies[i], bodies[j]);
+        // tracers pass through everything
+        if merged[i] || merged[j] || !a.is_source() || !b.is_source() {
+            continue;
+        }
+        let (ma, mb) = (a.mass, b.mass);
+        let mass = ma + mb;
+        let location = if !mass.is_zero() {
+            (a.location * ma + b.location * mb) / mass
+        } else {
+            (a.location + b.location) / two
+        };
+        match policy {
+            CollisionPolicy::Ignore => unreachable!(),
+            CollisionPolicy::Merge { .. } => {
+                let velocity = if !mass.is_zero() {
+                    (a.velocity * ma + b.velocity * mb) / mass
+                } else {
+                    (a.velocity + b.velocity) / two
+                };
+                bodies[i] = Body {
+                    id: if mb > ma { b.id } else { a.id },
+                    mass: a.mass + b.mass,
+                    location,
+                    velocity,
+                    species: a.species,
+                };
+                kept[j] = false;
+                merged[i] = true;
+                merged[j] = true;
+            }
+            CollisionPolicy::Bounce { .. } => {
+                let offset = b.location - a.location;
+                let distance = offset.length();
+                if distance.is_zero() || mass.is_zero() {
+                    continue;
+                }
+                let normal = offset / distance;
+                // closing speed along the line from a to b
+                let closing = (a.velocity - b.velocity).dot(&normal);
+                if closing <= S::zero() {
+                    continue;
+                }
+                bodies[i].velocity -= normal * (two * mb / mass * closing);
+                bodies[j].velocity += normal * (two * ma / mass * closing);
+            }
+        }
+        collisions.push(Collision {
+            pair: (i, j),
+            ids: (a.id, b.id),
+            mass: mass.as_f64(),
+            location: location.cast(),
+        });
+    }
+    if kept.iter().all(|&keep| keep) {
+        return (collisions, None);
+    }
+    let mut keep = kept.iter();
+    bodies.retain(|_| *keep.next().unwrap());
+    (collisions, Some(kept))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics;
+    use crate::ic;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn body(x: f64, mass: f64, vx: f64) -> Body {
+        Body { mass, location: Point { x, y: 0., z: 0. }, velocity: Point { x: vx, y: 0., z: 0. }, ..Body::default() }
+    }
+
+    #[test]
+    fn close_pairs_match_a_brute_force_search() {
+        let space = crate::geometry::Cuboid::from(([0.; 3], [1.; 3]));
+        let bodies = ic::uniform_box(300, &space, &mut StdRng::seed_from_u64(1));
+        let mut expected = vec![];
+        for i in 0..bodies.len() {
+            for j in i + 1..bodies.len() {
+                if bodies[i].location.distance_squared(&bodies[j].location) < 0.05 * 0.05 {
+                    expected.push((i, j));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(close_pairs(&bodies, 0.05), expected);
+    }
+
+    #[test]
+    fn a_body_merges_once_per_call() {
+        let mut bodies = vec![body(0., 1., 1.), body(0.01, 2., 0.), body(0.02, 3., -1.)];
+        for (id, body) in bodies.iter_mut().enumerate() {
+            body.id = id as u64;
+        }
+        let momentum = diagnostics::linear_momentum(&bodies);
+        let (collisions, kept) = resolve(&mut bodies, CollisionPolicy::Merge { radius: 0.1 });
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(kept, Some(vec![true, false, true]));
+        assert_eq!(bodies.len(), 2);
+        // the merged pair keeps the heavier id, and the third is left for the next call
+        assert_eq!(bodies[0].id, 1);
+        assert_eq!(bodies[0].mass, 3.);
+        assert!((diagnostics::linear_momentum(&bodies) - momentum).length() < 1e-12);
+        let (collisions, _) = resolve(&mut bodies, CollisionPolicy::Merge { radius: 0.1 });
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0].mass, 6.);
+        assert!((diagnostics::linear_momentum(&bodies) - momentum).length() < 1e-12);
+    }
+
+    #[test]
+    fn bounces_conserve_momentum_and_energy() {
+        let mut bodies = vec![body(0., 1., 1.), body(0.05, 3., -0.5)];
+        let momentum = diagnostics::linear_momentum(&bodies);
+        let energy = diagnostics::kinetic_energy(&bodies);
+        let (collisions, kept) = resolve(&mut bodies, CollisionPolicy::Bounce { radius: 0.1 });
+        assert_eq!((collisions.len(), kept), (1, None));
+        assert!(bodies[0].velocity.x < 0. && bodies[1].velocity.x > -0.5);
+        assert!((diagnostics::linear_momentum(&bodies) - momentum).length() < 1e-12);
+        assert!((diagnostics::kinetic_energy(&bodies) - energy).abs() < 1e-12);
+        // now moving apart, so a second call leaves them be
+        let (collisions, _) = resolve(&mut bodies, CollisionPolicy::Bounce { radius: 0.1 });
+        assert!(collisions.is_empty());
+    }
+
+    #[test]
+    fn tracers_pass_through() {
+        let mut bodies = vec![body(0., 1., 0.), Body { species: crate::body::Species::Tracer, ..body(0.01, 1., 0.) }];
+        let (collisions, kept) = resolve(&mut bodies, CollisionPolicy::Merge { radius: 0.1 });
+        assert!(collisions.is_empty() && kept.is_none());
+    }
+}
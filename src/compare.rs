@@ -0,0 +1,494 @@
+//! how far two runs of the same bodies have come apart, e.g. one at a smaller theta or softening than the
+//! other. bodies are matched by id, so a series with merged or removed bodies compares on those left in both
+
+use crate::body::Body;
+use crate::geometry::Point;
+use crate::scalar::{Precision, Scalar};
+use crate::snapshot_file::{SnapshotFile, SnapshotFileError};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum CompareError {
+    Io(io::Error),
+    Snapshot(SnapshotFileError),
+    /// a csv or json snapshot that could not be read
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    /// the path holds no csv, json or binary snapshots
+    NoSnapshots(PathBuf),
+}
+
+impl std::fmt::Display for CompareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompareError::Io(err) => write!(f, "{}", err),
+            CompareError::Snapshot(err) => write!(f, "{}", err),
+            CompareError::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+            CompareError::NoSnapshots(path) => {
+                write!(f, "{}: no csv, json or binary snapshots", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompareError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompareError::Io(err) => Some(err),
+            CompareError::Snapshot(err) => Some(err),
+            CompareError::Parse { .. } | CompareError::NoSnapshots(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for CompareError {
+    fn from(err: io::Error) -> Self {
+        CompareError::Io(err)
+    }
+}
+
+impl From<SnapshotFileError> for CompareError {
+    fn from(err: SnapshotFileError) -> Self {
+        CompareError::Snapshot(err)
+    }
+}
+
+/// how the bodies of one step differ between two runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepDifference {
+    pub step: u64,
+    /// the time of the step in the first run
+    pub time: f64,
+    /// the bodies in both, which the rest is over
+    pub matched: usize,
+    /// root mean square of the distances between where each body is in the two runs
+    pub rms_position: f64,
+    pub rms_velocity: f64,
+    /// the largest of the distances
+    pub max_position: f64,
+    /// the body that is that far apart, if any matched
+    pub max_body: Option<u64>,
+    pub max_velocity: f64,
+}
+
+/// the difference between the same `step` of two runs, `a` at `time`. bodies are matched by id, and those in
+/// only one of them are left out
+pub fn difference<S: Scalar>(step: u64, time: f64, a: &[Body<S>], b: &[Body<S>]) -> StepDifference {
+    let others: HashMap<u64, &Body<S>> = b.iter().map(|body| (body.id, body)).collect();
+    let mut result = StepDifference {
+        step,
+        time,
+        matched: 0,
+        rms_position: 0.,
+        rms_velocity: 0.,
+        max_position: 0.,
+        max_body: None,
+        max_velocity: 0.,
+    };
+    let (mut position_squares, mut velocity_squares) = (0., 0.);
+    for body in a {
+        let Some(other) = others.get(&body.id) else {
+            continue;
+        };
+        let location: Point = body.location.cast();
+        let velocity: Point = body.velocity.cast();
+        let position = location.distance_squared(&other.location.cast());
+        let speed = velocity.distance_squared(&other.velocity.cast());
+        position_squares += position;
+        velocity_squares += speed;
+        if result.max_body.is_none() || position > result.max_position * result.max_position {
+            result.max_position = position.sqrt();
+            result.max_body = Some(body.id);
+        }
+        result.max_velocity = result.max_velocity.max(speed.sqrt());
+        result.matched += 1;
+    }
+    if result.matched > 0 {
+        result.rms_position = (position_squares / result.matched as f64).sqrt();
+        result.rms_velocity = (velocity_squares / result.matched as f64).sqrt();
+    }
+    result
+}
+
+/// how far one body is apart between two states
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyDifference {
+    pub id: u64,
+    /// the distance between its two locations
+    pub position: f64,
+    /// the length of the difference of its two velocities
+    pub velocity: f64,
+}
+
+/// how the bodies of two states differ, body by body, from `Simulation::diff` or `DiffReport::between`.
+/// bodies are matched by id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiffReport {
+    /// the bodies in both, in the order of the first state
+    pub bodies: Vec<BodyDifference>,
+    /// the largest and mean of the position differences, with the body that is farthest apart
+    pub max_position: f64,
+    pub mean_position: f64,
+    pub max_body: Option<u64>,
+    pub max_velocity: f64,
+    pub mean_velocity: f64,
+    /// ids of the bodies only the first state has, in its order
+    pub only_in_first: Vec<u64>,
+    /// and of those only the second has
+    pub only_in_second: Vec<u64>,
+}
+
+impl DiffReport {
+    pub fn between<S: Scalar, T: Scalar>(a: &[Body<S>], b: &[Body<T>]) -> Self {
+        let others: HashMap<u64, &Body<T>> = b.iter().map(|body| (body.id, body)).collect();
+        let mut report = DiffReport::default();
+        for body in a {
+            let Some(other) = others.get(&body.id) else {
+                report.only_in_first.push(body.id);
+                continue;
+            };
+            let (location, velocity): (Point, Point) = (body.location.cast(), body.velocity.cast());
+            let difference = BodyDifference {
+                id: body.id,
+                position: location.distance_squared(&other.location.cast()).sqrt(),
+                velocity: velocity.distance_squared(&other.velocity.cast()).sqrt(),
+            };
+            if report.max_body.is_none() || difference.position > report.max_position {
+                report.max_position = difference.position;
+                report.max_body = Some(body.id);
+            }
+            report.max_velocity = report.max_velocity.max(difference.velocity);
+            report.bodies.push(difference);
+        }
+        let first: HashSet<u64> = a.iter().map(|body| body.id).collect();
+        report.only_in_second = b
+            .iter()
+            .map(|body| body.id)
+            .filter(|id| !first.contains(id))
+            .collect();
+        if !report.bodies.is_empty() {
+            let matched = report.bodies.len() as f64;
+            report.mean_position =
+                report.bodies.iter().map(|body| body.position).sum::<f64>() / matched;
+            report.mean_velocity =
+                report.bodies.iter().map(|body| body.velocity).sum::<f64>() / matched;
+        }
+        report
+    }
+
+    /// every body is in both states, at the same place and with the same velocity
+    pub fn is_identical(&self) -> bool {
+        self.only_in_first.is_empty()
+            && self.only_in_second.is_empty()
+            && self.max_position == 0.
+            && self.max_velocity == 0.
+    }
+}
+
+/// the difference at every step both snapshot series have, in step order. a series is a directory of
+/// `snapshot_<step>.csv` or `.json` files, or a binary `snapshots.bin`, as a run writes to its output
+/// directory, or a binary snapshot file itself. one snapshot of each is held in memory at a time
+pub fn compare_series(
+    a: impl AsRef<Path>,
+    b: impl AsRef<Path>,
+) -> Result<Vec<StepDifference>, CompareError> {
+    let (a, b) = (Series::open(a.as_ref())?, Series::open(b.as_ref())?);
+    let steps: Vec<u64> = a.steps().into_iter().filter(|step| b.has(*step)).collect();
+    steps
+        .into_iter()
+        .map(|step| {
+            let (time, first) = a.load(step)?;
+            let (_, second) = b.load(step)?;
+            Ok(difference(step, time, &first, &second))
+        })
+        .collect()
+}
+
+/// writes `differences` as csv with a header line, one row per step and an empty max_body where none matched
+pub fn write_csv(differences: &[StepDifference], mut out: impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "step,time,matched,rms_position,rms_velocity,max_position,max_body,max_velocity"
+    )?;
+    for d in differences {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            d.step,
+            d.time,
+            d.matched,
+            d.rms_position,
+            d.rms_velocity,
+            d.max_position,
+            d.max_body.map(|id| id.to_string()).unwrap_or_default(),
+            d.max_velocity
+        )?;
+    }
+    out.flush()
+}
+
+// a run's snapshots, in whichever form they were written
+enum Series {
+    // a file per snapshot, by step
+    Files(Vec<(u64, PathBuf)>),
+    Double(SnapshotFile<f64>),
+    Single(SnapshotFile<f32>),
+}
+
+impl Series {
+    fn open(path: &Path) -> Result<Self, CompareError> {
+        let binary = if path.is_dir() {
+            path.join("snapshots.bin")
+        } else {
+            path.to_path_buf()
+        };
+        if binary.is_file() {
+            return match SnapshotFile::<f64>::open(&binary) {
+                Ok(file) => Ok(Series::Double(file)),
+                Err(SnapshotFileError::Precision {
+                    found: Precision::Single,
+                    ..
+                }) => Ok(Series::Single(SnapshotFile::open(&binary)?)),
+                Err(err) => Err(err.into()),
+            };
+        }
+        if !path.is_dir() {
+            return Err(CompareError::NoSnapshots(path.to_path_buf()));
+        }
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let file = entry?.path();
+            let step = file
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| {
+                    name.strip_suffix(".csv")
+                        .or_else(|| name.strip_suffix(".json"))
+                })
+                .and_then(|stem| stem.strip_prefix("snapshot_"))
+                .and_then(|step| step.parse().ok());
+            if let Some(step) = step {
+                files.push((step, file));
+            }
+        }
+        if files.is_empty() {
+            return Err(CompareError::NoSnapshots(path.to_path_buf()));
+        }
+        files.sort();
+        Ok(Series::Files(files))
+    }
+
+    fn steps(&self) -> Vec<u64> {
+        match self {
+            Series::Files(files) => files.iter().map(|(step, _)| *step).collect(),
+            Series::Double(file) => file.steps().collect(),
+            Series::Single(file) => file.steps().collect(),
+        }
+    }
+
+    fn has(&self, step: u64) -> bool {
+        match self {
+            Series::Files(files) => files.binary_search_by_key(&step, |(step, _)| *step).is_ok(),
+            Series::Double(file) => file.position(step).is_some(),
+            Series::Single(file) => file.position(step).is_some(),
+        }
+    }
+
+    // the time and bodies at `step`, which the series has
+    fn load(&self, step: u64) -> Result<(f64, Vec<Body>), CompareError> {
+        match self {
+            Series::Files(files) => {
+                let i = files
+                    .binary_search_by_key(&step, |(step, _)| *step)
+                    .expect("a step of the series");
+                let path = &files[i].1;
+                let text = std::fs::read_to_string(path)?;
+                let fail = |message: String| CompareError::Parse {
+                    path: path.clone(),
+                    message,
+                };
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "json")
+                {
+                    parse_json(&text).map_err(fail)
+                } else {
+                    parse_csv(&text).map_err(fail)
+                }
+            }
+            Series::Double(file) => {
+                let snapshot = file.step(file.position(step).expect("a step of the series"));
+                let snapshot = snapshot.expect("an indexed snapshot");
+                Ok((snapshot.time, snapshot.bodies()))
+            }
+            Series::Single(file) => {
+                let snapshot = file.step(file.position(step).expect("a step of the series"));
+                let snapshot = snapshot.expect("an indexed snapshot");
+                let bodies = snapshot.bodies().iter().map(Body::cast).collect();
+                Ok((snapshot.time, bodies))
+            }
+        }
+    }
+}
+
+// the time and bodies of a snapshot writer csv, whose header names the columns. an empty snapshot has no
+// time, so it gets nan
+fn parse_csv(text: &str) -> Result<(f64, Vec<Body>), String> {
+    const COLUMNS: [&str; 8] = ["time", "body", "x", "y", "z", "vx", "vy", "vz"];
+    let mut rows = text
+        .lines()
+        .enumerate()
+        .filter(|(_, row)| !row.trim().is_empty() && !row.starts_with('#'));
+    let (_, header) = rows.next().ok_or("no header line")?;
+    let names: Vec<&str> = header.split(',').map(str::trim).collect();
+    let mut layout = [0; 8];
+    for (slot, column) in layout.iter_mut().zip(COLUMNS) {
+        *slot = names
+            .iter()
+            .position(|name| *name == column)
+            .ok_or_else(|| format!("no `{}` column", column))?;
+    }
+    let mut time = f64::NAN;
+    let mut bodies = Vec::new();
+    for (i, row) in rows {
+        let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+        let field = |column: usize| {
+            fields
+                .get(layout[column])
+                .ok_or_else(|| format!("line {}: too few fields", i + 1))
+        };
+        let number = |column: usize| {
+            field(column)?
+                .parse::<f64>()
+                .map_err(|err| format!("line {}: {}", i + 1, err))
+        };
+        time = number(0)?;
+        let id = field(1)?
+            .parse()
+            .map_err(|err| format!("line {}: {}", i + 1, err))?;
+        bodies.push(Body {
+            id,
+            location: Point {
+                x: number(2)?,
+                y: number(3)?,
+                z: number(4)?,
+            },
+            velocity: Point {
+                x: number(5)?,
+                y: number(6)?,
+                z: number(7)?,
+            },
+            ..Body::default()
+        });
+    }
+    Ok((time, bodies))
+}
+
+// the time and bodies of a snapshot writer json object
+fn parse_json(text: &str) -> Result<(f64, Vec<Body>), String> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let time = value
+        .get("time")
+        .and_then(|time| time.as_f64())
+        .ok_or("missing or non-numeric `time`")?;
+    let entries = value
+        .get("bodies")
+        .and_then(|bodies| bodies.as_array())
+        .ok_or("missing `bodies` array")?;
+    let bodies = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let vector = |name: &str| {
+                let v: Option<Vec<f64>> = entry
+                    .get(name)
+                    .and_then(|field| field.as_array())
+                    .and_then(|v| v.iter().map(|c| c.as_f64()).collect());
+                match v.as_deref() {
+                    Some(&[x, y, z]) => Ok(Point { x, y, z }),
+                    _ => Err(format!(
+                        "body {}: `{}` must be an array of three numbers",
+                        index, name
+                    )),
+                }
+            };
+            let id = entry
+                .get("id")
+                .and_then(|id| id.as_u64())
+                .ok_or_else(|| format!("body {}: missing or non-integer `id`", index))?;
+            Ok(Body {
+                id,
+                location: vector("location")?,
+                velocity: vector("velocity")?,
+                ..Body::default()
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok((time, bodies))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bodies(n: u64) -> Vec<Body> {
+        (0..n)
+            .map(|id| Body {
+                id,
+                mass: 1.,
+                location: Point {
+                    x: id as f64,
+                    y: 0.,
+                    z: 0.,
+                },
+                velocity: Point {
+                    x: 0.,
+                    y: 1.,
+                    z: 0.,
+                },
+                ..Body::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_states_do_not_differ() {
+        let report = DiffReport::between(&bodies(5), &bodies(5));
+        assert!(report.is_identical());
+        assert_eq!(report.bodies.len(), 5);
+        assert_eq!(report.mean_position, 0.);
+    }
+
+    #[test]
+    fn a_perturbed_body_stands_out() {
+        let mut perturbed = bodies(4);
+        perturbed[2].location.z += 0.3;
+        perturbed[2].velocity.x -= 0.4;
+        let report = DiffReport::between(&bodies(4), &perturbed);
+        assert!(!report.is_identical());
+        assert_eq!(report.max_body, Some(2));
+        assert!((report.max_position - 0.3).abs() < 1e-15);
+        assert!((report.mean_position - 0.075).abs() < 1e-15);
+        assert!((report.max_velocity - 0.4).abs() < 1e-15);
+        assert_eq!(report.bodies[2].id, 2);
+    }
+
+    #[test]
+    fn bodies_in_one_state_only_are_listed() {
+        let mut other = bodies(5);
+        other.remove(1);
+        other.push(Body {
+            id: 9,
+            ..Body::default()
+        });
+        let report = DiffReport::between(&bodies(5), &other);
+        assert_eq!(report.only_in_first, vec![1]);
+        assert_eq!(report.only_in_second, vec![9]);
+        assert_eq!(report.bodies.len(), 4);
+        assert_eq!(report.max_position, 0.);
+    }
+}
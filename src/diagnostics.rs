@@ -0,0 +1,478 @@
+//! conserved quantities for checking an integration: energy, linear and angular momentum. every reduction is
+//! a compensated sum, so a million bodies' worth of small terms adds up to within a rounding or two of the
+//! exact total rather than drifting by the rounding of each addition, which a conservation check would take
+//! for the integration's error
+
+use crate::body::Body;
+use crate::geometry::{Cuboid, Point};
+use crate::force::{ForceModel, Gravity};
+use crate::scalar::Scalar;
+
+/// how the potential energy is found
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PotentialMethod {
+    /// from the simulation's tree at its theta; o(n log n) but approximate
+    #[default]
+    Tree,
+    /// exact pairwise sum; o(n^2)
+    Direct,
+}
+
+/// one measurement of the system, in the simulation's units. always summed in f64 and compensated, so drifts in a
+/// single precision run show the integration's error rather than the sum's
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Diagnostics {
+    pub kinetic_energy: f64,
+    pub potential_energy: f64,
+    pub linear_momentum: Point,
+    /// about the origin
+    pub angular_momentum: Point,
+    /// the work done by drag up to the measurement, negative when it took energy out. `measure` leaves it at
+    /// 0 for the caller to fill in
+    pub drag_work: f64,
+}
+
+impl Diagnostics {
+    /// measures the bodies given their potential energy, which the caller works out by whatever method
+    pub fn measure<S: Scalar>(bodies: &[Body<S>], potential_energy: f64) -> Self {
+        Diagnostics {
+            kinetic_energy: kinetic_energy(bodies),
+            potential_energy,
+            linear_momentum: linear_momentum(bodies),
+            angular_momentum: angular_momentum(bodies),
+            drag_work: 0.,
+        }
+    }
+
+    pub fn total_energy(&self) -> f64 {
+        self.kinetic_energy + self.potential_energy
+    }
+
+    /// the total energy less the work done by drag, which only the integration's errors change
+    pub fn conserved_energy(&self) -> f64 {
+        self.total_energy() - self.drag_work
+    }
+
+    /// -2 T / W, which is 1 for a system in virial equilibrium
+    pub fn virial_ratio(&self) -> f64 {
+        -2. * self.kinetic_energy / self.potential_energy
+    }
+}
+
+/// a running sum with neumaier's compensation: the low-order bits each addition rounds off are kept apart
+/// and added back at the end, so the error does not grow with the number of terms
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    pub fn add(&mut self, value: f64) {
+        let sum = self.sum + value;
+        // whichever of the two is larger in size keeps its bits, the smaller one loses what `sum` cannot hold
+        self.compensation += if self.sum.abs() >= value.abs() {
+            (self.sum - sum) + value
+        } else {
+            (value - sum) + self.sum
+        };
+        self.sum = sum;
+    }
+
+    pub fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+impl std::iter::Sum<f64> for CompensatedSum {
+    fn sum<I: Iterator<Item = f64>>(iter: I) -> Self {
+        let mut total = CompensatedSum::default();
+        iter.for_each(|value| total.add(value));
+        total
+    }
+}
+
+// a compensated sum of each component
+#[derive(Default)]
+struct CompensatedPoint([CompensatedSum; 3]);
+
+impl CompensatedPoint {
+    fn add(&mut self, value: Point) {
+        for (sum, component) in self.0.iter_mut().zip([value.x, value.y, value.z]) {
+            sum.add(component);
+        }
+    }
+
+    fn value(&self) -> Point {
+        let [x, y, z] = self.0.map(|sum| sum.value());
+        Point { x, y, z }
+    }
+}
+
+pub fn kinetic_energy<S: Scalar>(bodies: &[Body<S>]) -> f64 {
+    bodies
+        .iter()
+        .map(|body| {
+            let velocity: Point = body.velocity.cast();
+            0.5 * body.mass.as_f64() * velocity.dot(&velocity)
+        })
+        .sum::<CompensatedSum>()
+        .value()
+}
+
+/// exact softened potential energy with G = 1, each pair counted once
+pub fn potential_energy_direct<S: Scalar>(bodies: &[Body<S>], softening: f64) -> f64 {
+    model_potential_energy_direct(&Gravity, bodies, softening, None)
+}
+
+/// `potential_energy_direct` in a periodic `space`, with every pair at its minimum-image separation
+pub fn periodic_potential_energy_direct<S: Scalar>(
+    bodies: &[Body<S>],
+    softening: f64,
+    space: &Cuboid<S>,
+) -> f64 {
+    model_potential_energy_direct(&Gravity, bodies, softening, Some(space))
+}
+
+/// `potential_energy_direct` under any force law, in the periodic `space` if there is one. the pairs of each
+/// body with the ones after it are summed as a row, in parallel with the `parallel` feature, and the rows are
+/// added up in order, so the result does not depend on the number of threads
+pub fn model_potential_energy_direct<S: Scalar, F: ForceModel>(
+    model: &F,
+    bodies: &[Body<S>],
+    softening: f64,
+    space: Option<&Cuboid<S>>,
+) -> f64 {
+    match space {
+        Some(space) => potential_energy_along(model, bodies, softening, space, [true; 3]),
+        None => potential_energy_along(model, bodies, softening, &Cuboid::default(), [false; 3]),
+    }
+}
+
+// `model_potential_energy_direct` in a `space` that repeats along only the axes, x, y and z, that `axes` holds
+pub(crate) fn potential_energy_along<S: Scalar, F: ForceModel>(
+    model: &F,
+    bodies: &[Body<S>],
+    softening: f64,
+    space: &Cuboid<S>,
+    axes: [bool; 3],
+) -> f64 {
+    let space: Cuboid = space.cast();
+    let row = |i: usize| {
+        let a = &bodies[i];
+        let location: Point = a.location.cast();
+        let mut energy = CompensatedSum::default();
+        for b in &bodies[i + 1..] {
+            let image = space.nearest_image_along(&b.location.cast(), &location, axes);
+            energy.add(model.pair_potential(&location, &image, b.mass.as_f64(), softening));
+        }
+        a.mass.as_f64() * energy.value()
+    };
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        let rows: Vec<f64> = (0..bodies.len()).into_par_iter().map(row).collect();
+        rows.into_iter().sum::<CompensatedSum>().value()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        (0..bodies.len()).map(row).sum::<CompensatedSum>().value()
+    }
+}
+
+/// the mass-weighted mean location and velocity, or none when the bodies have no mass between them
+pub fn center_of_mass<S: Scalar>(bodies: &[Body<S>]) -> Option<(Point, Point)> {
+    let mut mass = 0.;
+    let mut location = Point::default();
+    let mut velocity = Point::default();
+    for body in bodies {
+        let m = body.mass.as_f64();
+        mass += m;
+        location += body.location.cast() * m;
+        velocity += body.velocity.cast() * m;
+    }
+    (mass != 0.).then(|| (location / mass, velocity / mass))
+}
+
+pub fn linear_momentum<S: Scalar>(bodies: &[Body<S>]) -> Point {
+    let mut momentum = CompensatedPoint::default();
+    for body in bodies {
+        momentum.add(body.velocity.cast() * body.mass.as_f64());
+    }
+    momentum.value()
+}
+
+pub fn angular_momentum<S: Scalar>(bodies: &[Body<S>]) -> Point {
+    let mut momentum = CompensatedPoint::default();
+    for body in bodies {
+        let location: Point = body.location.cast();
+        momentum.add(location.cross(&body.velocity.cast()) * body.mass.as_f64());
+    }
+    momentum.value()
+}
+
+/// how far approximate accelerations are from exact ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForceError {
+    /// |a - a_exact| / |a_exact| for each body, 0 where both vanish
+    pub relative: Vec<f64>,
+    /// root mean square of `relative`
+    pub rms: f64,
+    pub max: f64,
+}
+
+impl ForceError {
+    /// compares two equally long, equally ordered acceleration lists
+    pub fn between<S: Scalar>(approximate: &[Point<S>], exact: &[Point<S>]) -> Self {
+        assert_eq!(approximate.len(), exact.len(), "accelerations must pair up");
+        let relative: Vec<f64> = approximate
+            .iter()
+            .zip(exact)
+            .map(|(approximate, exact)| {
+                let error = (*approximate - *exact).length().as_f64();
+                let magnitude = exact.length().as_f64();
+                if error == 0. && magnitude == 0. {
+                    0.
+                } else {
+                    error / magnitude
+                }
+            })
+            .collect();
+        let rms = if relative.is_empty() {
+            0.
+        } else {
+            (relative.iter().map(|e| e * e).sum::<f64>() / relative.len() as f64).sqrt()
+        };
+        let max = relative.iter().cloned().fold(0., f64::max);
+        ForceError { relative, rms, max }
+    }
+}
+
+/// what a run ought to keep, as `Simulation::check_conservation` holds it to: the conserved energy, less the
+/// work done by drag, and the linear and angular momenta of one measurement
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConservedQuantities {
+    pub energy: f64,
+    pub linear_momentum: Point,
+    pub angular_momentum: Point,
+}
+
+impl ConservedQuantities {
+    pub fn of(diagnostics: &Diagnostics) -> Self {
+        ConservedQuantities {
+            energy: diagnostics.conserved_energy(),
+            linear_momentum: diagnostics.linear_momentum,
+            angular_momentum: diagnostics.angular_momentum,
+        }
+    }
+
+    // the first quantity of `current` to have moved from these by more than `tolerance`, checked in the order
+    // of `ConservationError`'s variants
+    pub(crate) fn check(&self, current: &ConservedQuantities, tolerance: f64) -> Result<(), ConservationError> {
+        let energy = relative_change(current.energy - self.energy, self.energy.abs());
+        if !within(energy, tolerance) {
+            return Err(ConservationError::Energy { drift: energy });
+        }
+        let change = (current.linear_momentum - self.linear_momentum).length();
+        let linear = relative_change(change, self.linear_momentum.length());
+        if !within(linear, tolerance) {
+            return Err(ConservationError::LinearMomentum { drift: linear });
+        }
+        let change = (current.angular_momentum - self.angular_momentum).length();
+        let angular = relative_change(change, self.angular_momentum.length());
+        if !within(angular, tolerance) {
+            return Err(ConservationError::AngularMomentum { drift: angular });
+        }
+        Ok(())
+    }
+}
+
+// false for a nan drift, which no tolerance holds
+fn within(drift: f64, tolerance: f64) -> bool {
+    drift.abs() <= tolerance
+}
+
+// `change` relative to `scale`, or as it is when there is nothing to scale it by, as `DriftMonitor` takes it
+fn relative_change(change: f64, scale: f64) -> f64 {
+    if scale > 0. {
+        change / scale
+    } else {
+        change
+    }
+}
+
+/// the quantity `Simulation::check_conservation` found moved past its tolerance, with how far: relative to its
+/// reference value, or absolute when that was zero. a nan counts as past any tolerance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConservationError {
+    /// (E - E0) / |E0|, signed
+    Energy { drift: f64 },
+    /// |P - P0| / |P0|
+    LinearMomentum { drift: f64 },
+    /// |L - L0| / |L0|
+    AngularMomentum { drift: f64 },
+}
+
+impl std::fmt::Display for ConservationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConservationError::Energy { drift } => write!(f, "energy drifted by {:e}", drift),
+            ConservationError::LinearMomentum { drift } => write!(f, "linear momentum drifted by {:e}", drift),
+            ConservationError::AngularMomentum { drift } => write!(f, "angular momentum drifted by {:e}", drift),
+        }
+    }
+}
+
+impl std::error::Error for ConservationError {}
+
+/// remembers the first measurement of a run and reports how far later ones have wandered from it
+#[derive(Debug, Clone, Copy)]
+pub struct DriftMonitor {
+    initial: Diagnostics,
+}
+
+impl DriftMonitor {
+    pub fn new(initial: Diagnostics) -> Self {
+        DriftMonitor { initial }
+    }
+
+    pub fn initial(&self) -> &Diagnostics {
+        &self.initial
+    }
+
+    /// (E - E0) / |E0|, of the `conserved_energy`, so the work done by drag does not count as drift. the
+    /// absolute change E - E0 if the system started with none, as a marginally bound one can
+    pub fn energy_drift(&self, current: &Diagnostics) -> f64 {
+        let initial = self.initial.conserved_energy();
+        let change = current.conserved_energy() - initial;
+        if initial != 0. {
+            change / initial.abs()
+        } else {
+            change
+        }
+    }
+
+    /// change in linear momentum, which stays at rounding level for a symmetric force calculation but not
+    /// for barnes-hut, where forces between two bodies are not exactly equal and opposite
+    pub fn momentum_drift(&self, current: &Diagnostics) -> Point {
+        current.linear_momentum - self.initial.linear_momentum
+    }
+
+    /// |L - L0| / |L0|, or the absolute change if the system started with none
+    pub fn angular_momentum_drift(&self, current: &Diagnostics) -> f64 {
+        let change = (current.angular_momentum - self.initial.angular_momentum).length();
+        let initial = self.initial.angular_momentum.length();
+        if initial > 0. {
+            change / initial
+        } else {
+            change
+        }
+    }
+
+    /// emits a tracing warning if the energy has drifted by more than `threshold` either way since the start,
+    /// returning whether it had
+    pub fn warn_above(&self, step: u64, current: &Diagnostics, threshold: f64) -> bool {
+        let drift = self.energy_drift(current);
+        let above = drift.abs() > threshold;
+        if above {
+            tracing::warn!(step, energy_drift = drift, threshold, "energy drifted past the threshold");
+        }
+        above
+    }
+
+    /// emits the drifts at `step` as a tracing info event
+    pub fn log(&self, step: u64, current: &Diagnostics) {
+        tracing::info!(
+            step,
+            energy_drift = self.energy_drift(current),
+            momentum_drift = self.momentum_drift(current).length(),
+            angular_momentum_drift = self.angular_momentum_drift(current),
+            "drift"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ic;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn bodies(n: usize, seed: u64) -> Vec<Body> {
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        ic::uniform_box(n, &space, &mut StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn direct_potential_matches_a_pairwise_sum() {
+        let bodies = bodies(300, 1);
+        let softening = 0.01;
+        let mut expected = 0.;
+        for (i, a) in bodies.iter().enumerate() {
+            for b in &bodies[i + 1..] {
+                let distance_squared = a.location.distance_squared(&b.location);
+                expected -= a.mass * b.mass / (distance_squared + softening * softening).sqrt();
+            }
+        }
+        let energy = potential_energy_direct(&bodies, softening);
+        assert!((energy - expected).abs() < 1e-12 * expected.abs(), "{} against {}", energy, expected);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn direct_potential_is_the_same_on_any_number_of_threads() {
+        let bodies = bodies(500, 2);
+        let on = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| potential_energy_direct(&bodies, 0.01))
+        };
+        assert_eq!(on(1).to_bits(), on(4).to_bits());
+    }
+
+    #[test]
+    fn energy_drift_is_absolute_from_zero() {
+        let at = |kinetic_energy, potential_energy| Diagnostics {
+            kinetic_energy,
+            potential_energy,
+            ..Diagnostics::default()
+        };
+        let monitor = DriftMonitor::new(at(1., -1.));
+        assert_eq!(monitor.energy_drift(&at(1.5, -1.)), 0.5);
+        let monitor = DriftMonitor::new(at(2., -4.));
+        assert_eq!(monitor.energy_drift(&at(2., -5.)), -0.5);
+    }
+
+    #[test]
+    fn a_million_tiny_terms_add_up_where_a_plain_sum_drops_them() {
+        // one body with half a unit of each, then a million that each carry 1e-17, which is under half an ulp
+        // of 0.5 and so vanishes from a plain running sum
+        let moving = |mass| Body { mass, velocity: Point { x: 1., y: 0., z: 0. }, ..Body::default() };
+        let mut bodies = vec![moving(1.)];
+        bodies.extend(std::iter::repeat_n(moving(2e-17), 1_000_000));
+        let exact = 0.5 + 1e-11;
+        let plain: f64 = bodies.iter().map(|body| 0.5 * body.mass * body.velocity.x * body.velocity.x).sum();
+        assert!((plain - exact).abs() > 1e-12, "{}", plain);
+        assert!((kinetic_energy(&bodies) - exact).abs() < 1e-15, "{}", kinetic_energy(&bodies));
+        let momentum = linear_momentum(&bodies).x;
+        assert!((momentum - (1. + 2e-11)).abs() < 1e-15, "{}", momentum);
+        let total: CompensatedSum = [1e16, 1., -1e16].into_iter().sum();
+        assert_eq!(total.value(), 1.);
+    }
+
+    #[test]
+    fn virial_ratio_of_a_plummer_sphere_is_near_one() {
+        let bodies = ic::plummer(2000, 1., 1., &mut StdRng::seed_from_u64(3));
+        let diagnostics = Diagnostics::measure(&bodies, potential_energy_direct(&bodies, 0.));
+        assert!((diagnostics.virial_ratio() - 1.).abs() < 0.1, "{}", diagnostics.virial_ratio());
+    }
+
+    #[test]
+    fn virial_ratio_of_a_cold_sphere_is_near_zero() {
+        let bodies = ic::uniform_sphere(2000, 1., 1., 0., &mut StdRng::seed_from_u64(4));
+        let diagnostics = Diagnostics::measure(&bodies, potential_energy_direct(&bodies, 0.));
+        assert!(diagnostics.virial_ratio().abs() < 1e-12, "{}", diagnostics.virial_ratio());
+        // and still bound, near the -3/5 M^2 / R of a uniform ball
+        assert!((diagnostics.potential_energy + 0.6).abs() < 0.03, "{}", diagnostics.potential_energy);
+    }
+}
@@ -0,0 +1,490 @@
+//! one simulation spread over several processes that talk over tcp, for runs too big for one machine. the
+//! root box is cut into one contiguous range of morton keys per rank, with about as many bodies in each, and
+//! every rank steps the bodies in its range. for the forces on them, each other rank sends its locally
+//! essential tree: the nodes of its own tree that pass the opening test from anywhere in this rank's domain,
+//! as point masses at their centers of mass, and the bodies of the leaves that do not. the ranges are
+//! recomputed after every drift and the bodies that left theirs move to their new owner
+//!
+//! ranks connect in a star around rank 0, which relays everything between the others, so this scales to a
+//! handful of machines rather than a supercomputer. forces are newtonian gravity to monopole order in an open
+//! box that expands to hold the bodies, stepped with leapfrog at a fixed dt, with no external potentials. the
+//! other options of `SimulationConfig` are not supported
+
+use crate::body::Body;
+use crate::collision::CollisionPolicy;
+use crate::force::Gravity;
+use crate::geometry::{Cuboid, Point};
+use crate::integrator::Scheme;
+use crate::linear::morton_key;
+use crate::sim::{BoundaryCondition, EscapePolicy, Recentering, Simulation, SimulationConfig, Timestep};
+use crate::tree::{BodyTree, MultipoleOrder};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+// how long a rank keeps trying to reach rank 0 before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum ClusterError {
+    Io(io::Error),
+    /// a message could not be encoded or decoded
+    Encoding(bincode::Error),
+    /// a rank that connected gave a rank number that is out of range or taken
+    Rank(usize),
+}
+
+impl std::fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClusterError::Io(err) => write!(f, "{}", err),
+            ClusterError::Encoding(err) => write!(f, "invalid message between ranks: {}", err),
+            ClusterError::Rank(rank) => {
+                write!(f, "a second or out of range rank {} connected", rank)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClusterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClusterError::Io(err) => Some(err),
+            ClusterError::Encoding(err) => Some(err),
+            ClusterError::Rank(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClusterError {
+    fn from(err: io::Error) -> Self {
+        ClusterError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for ClusterError {
+    fn from(err: bincode::Error) -> Self {
+        ClusterError::Encoding(err)
+    }
+}
+
+// one end of a connection between two ranks
+struct Link {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Link {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Link {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    fn send<T: Serialize>(&mut self, value: &T) -> Result<(), ClusterError> {
+        bincode::serialize_into(&mut self.writer, value)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn receive<T: DeserializeOwned>(&mut self) -> Result<T, ClusterError> {
+        Ok(bincode::deserialize_from(&mut self.reader)?)
+    }
+}
+
+/// the processes of a distributed run, as one of them sees it
+pub struct Cluster {
+    rank: usize,
+    size: usize,
+    // on rank 0 a link to every other rank, indexed by rank; elsewhere just the one to rank 0
+    links: Vec<Option<Link>>,
+}
+
+impl Cluster {
+    /// joins a cluster of `size` processes as `rank`. rank 0 listens on `address` until the others have
+    /// connected, and they keep trying to reach it for up to a minute, so the processes can start in any
+    /// order. panics unless `rank < size`
+    pub fn connect(
+        address: impl ToSocketAddrs,
+        rank: usize,
+        size: usize,
+    ) -> Result<Self, ClusterError> {
+        assert!(rank < size, "rank {} is not among {} ranks", rank, size);
+        let mut links: Vec<Option<Link>> = (0..size).map(|_| None).collect();
+        if rank == 0 {
+            let listener = TcpListener::bind(address)?;
+            for _ in 1..size {
+                let (stream, _) = listener.accept()?;
+                let mut link = Link::new(stream)?;
+                let other: usize = link.receive()?;
+                if other == 0 || other >= size || links[other].is_some() {
+                    return Err(ClusterError::Rank(other));
+                }
+                links[other] = Some(link);
+            }
+        } else {
+            let start = Instant::now();
+            let stream = loop {
+                match TcpStream::connect(&address) {
+                    Ok(stream) => break stream,
+                    Err(_) if start.elapsed() < CONNECT_TIMEOUT => {
+                        std::thread::sleep(Duration::from_millis(100))
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            };
+            let mut link = Link::new(stream)?;
+            link.send(&rank)?;
+            links[0] = Some(link);
+        }
+        Ok(Cluster { rank, size, links })
+    }
+
+    /// a cluster of just this process, for running the distributed code without a network
+    pub fn single() -> Self {
+        Cluster {
+            rank: 0,
+            size: 1,
+            links: vec![None],
+        }
+    }
+
+    pub fn rank(&self) -> usize {
+        self.rank
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn link(&mut self, rank: usize) -> &mut Link {
+        self.links[rank]
+            .as_mut()
+            .expect("ranks only talk through rank 0")
+    }
+
+    /// every rank's `value` on rank 0, in rank order, and none elsewhere
+    pub fn gather<T: Serialize + DeserializeOwned>(
+        &mut self,
+        value: T,
+    ) -> Result<Option<Vec<T>>, ClusterError> {
+        if self.rank != 0 {
+            self.link(0).send(&value)?;
+            return Ok(None);
+        }
+        let mut values = vec![value];
+        for rank in 1..self.size {
+            values.push(self.link(rank).receive()?);
+        }
+        Ok(Some(values))
+    }
+
+    /// rank 0's `value` on every rank. the others' are ignored and can be none
+    pub fn broadcast<T: Serialize + DeserializeOwned>(
+        &mut self,
+        value: Option<T>,
+    ) -> Result<T, ClusterError> {
+        if self.rank != 0 {
+            return self.link(0).receive();
+        }
+        let value = value.expect("rank 0 has to give the value to broadcast");
+        for rank in 1..self.size {
+            self.link(rank).send(&value)?;
+        }
+        Ok(value)
+    }
+
+    /// every rank's `value` on every rank, in rank order
+    pub fn all_gather<T: Serialize + DeserializeOwned>(
+        &mut self,
+        value: T,
+    ) -> Result<Vec<T>, ClusterError> {
+        let values = self.gather(value)?;
+        self.broadcast(values)
+    }
+
+    /// sends `outgoing[r]` to rank r and returns what every rank sent this one, by sender. panics unless
+    /// there is one entry per rank
+    pub fn all_to_all<T: Serialize + DeserializeOwned>(
+        &mut self,
+        outgoing: Vec<T>,
+    ) -> Result<Vec<T>, ClusterError> {
+        assert_eq!(
+            outgoing.len(),
+            self.size,
+            "all_to_all needs one message per rank"
+        );
+        if self.rank != 0 {
+            self.link(0).send(&outgoing)?;
+            return self.link(0).receive();
+        }
+        // rows by sender, then handed out by column
+        let mut rows: Vec<Vec<Option<T>>> = vec![outgoing.into_iter().map(Some).collect()];
+        for rank in 1..self.size {
+            let row: Vec<T> = self.link(rank).receive()?;
+            rows.push(row.into_iter().map(Some).collect());
+        }
+        for rank in (0..self.size).rev() {
+            let column: Vec<T> = rows
+                .iter_mut()
+                .map(|row| row[rank].take().unwrap())
+                .collect();
+            if rank == 0 {
+                return Ok(column);
+            }
+            self.link(rank).send(&column)?;
+        }
+        unreachable!()
+    }
+}
+
+/// a simulation whose bodies are spread over the ranks of a `Cluster`, each stepping its own share. every
+/// rank has to make the same calls in the same order, as each of them waits on the others
+pub struct DistributedSimulation {
+    cluster: Cluster,
+    // the bodies in this rank's range of morton keys
+    bodies: Vec<Body>,
+    accelerations: Vec<Point>,
+    // the root box, the same on every rank
+    space: Cuboid,
+    config: SimulationConfig,
+    next_id: u64,
+    time: f64,
+    steps: u64,
+}
+
+impl DistributedSimulation {
+    /// spreads `bodies` from rank 0 over the cluster, numbered from 0 like `Simulation` does, in `space` grown
+    /// to a power-of-two cube around them. all of it is taken from rank 0, so the other ranks can pass no
+    /// bodies, any box and the default config. panics on parts of `config` that are not supported, or if
+    /// another rank is given bodies
+    pub fn new(
+        mut cluster: Cluster,
+        mut bodies: Vec<Body>,
+        space: Cuboid,
+        config: SimulationConfig,
+    ) -> Result<Self, ClusterError> {
+        let config = cluster.broadcast(Some(config))?;
+        assert!(
+            config.boundary == [BoundaryCondition::Open; 3]
+                && config.escape == EscapePolicy::Expand
+                && config.collisions == CollisionPolicy::Ignore
+                && config.timestep == Timestep::Fixed
+                && config.integrator == Scheme::Leapfrog
+                && config.multipole == MultipoleOrder::Monopole
+                && config.recentering == Recentering::Off,
+            "distributed runs take monopole forces in an open, expanding box with fixed leapfrog steps and no collisions or recentering"
+        );
+        assert!(
+            cluster.rank() == 0 || bodies.is_empty(),
+            "only rank 0 brings bodies"
+        );
+        for (id, body) in bodies.iter_mut().enumerate() {
+            body.id = id as u64;
+        }
+        let next_id = cluster.broadcast(Some(bodies.len() as u64))?;
+        let space = cluster.broadcast(Some(space))?;
+        let mut simulation = DistributedSimulation {
+            cluster,
+            bodies,
+            accelerations: Vec::new(),
+            space,
+            config,
+            next_id,
+            time: 0.,
+            steps: 0,
+        };
+        simulation.redistribute()?;
+        Ok(simulation)
+    }
+
+    pub fn rank(&self) -> usize {
+        self.cluster.rank()
+    }
+
+    pub fn ranks(&self) -> usize {
+        self.cluster.size()
+    }
+
+    /// the bodies this rank steps
+    pub fn bodies(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// a kick-drift-kick leapfrog step of `dt` on every rank
+    pub fn step(&mut self, dt: f64) -> Result<(), ClusterError> {
+        let _span = tracing::info_span!("distributed_step", step = self.steps + 1).entered();
+        if self.accelerations.len() != self.bodies.len() {
+            self.update_forces()?;
+        }
+        self.kick(dt / 2.);
+        for body in &mut self.bodies {
+            body.location += body.velocity * dt;
+        }
+        self.redistribute()?;
+        self.update_forces()?;
+        self.kick(dt / 2.);
+        self.time += dt;
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn kick(&mut self, dt: f64) {
+        for (body, acceleration) in self.bodies.iter_mut().zip(&self.accelerations) {
+            body.velocity += *acceleration * dt;
+        }
+    }
+
+    /// every body at rank 0, in id order, as a `Simulation` at the same time and step, for snapshots and
+    /// diagnostics. none on the other ranks
+    pub fn gather(&mut self) -> Result<Option<Simulation>, ClusterError> {
+        let Some(shares) = self.cluster.gather(self.bodies.clone())? else {
+            return Ok(None);
+        };
+        let mut bodies: Vec<Body> = shares.into_iter().flatten().collect();
+        bodies.sort_unstable_by_key(|body| body.id);
+        let mut simulation = Simulation::from_parts(
+            bodies,
+            self.space,
+            self.config,
+            Gravity,
+            self.next_id,
+            BTreeMap::new(),
+        );
+        simulation.set_clock(self.time, self.steps);
+        Ok(Some(simulation))
+    }
+
+    // grows the root box around every body, recuts the morton ranges to even the counts out, and moves the
+    // bodies to their owners
+    fn redistribute(&mut self) -> Result<(), ClusterError> {
+        let local = (!self.bodies.is_empty()).then(|| Cuboid::bounding(&self.bodies));
+        let bounds = self.cluster.all_gather(local)?;
+        let grown = bounds
+            .into_iter()
+            .flatten()
+            .fold(self.space, |space, bounds| space.union(&bounds));
+        if grown != self.space {
+            self.space = grown.to_power_of_two_cube();
+        }
+        let mut keys: Vec<u64> = self
+            .bodies
+            .iter()
+            .map(|body| morton_key(&self.space, &body.location))
+            .collect();
+        keys.sort_unstable();
+        let splits = self.cluster.gather(keys)?.map(|keys| {
+            let mut keys: Vec<u64> = keys.into_iter().flatten().collect();
+            keys.sort_unstable();
+            // the first key of every rank after the first
+            (1..self.cluster.size())
+                .map(|rank| {
+                    keys.get(rank * keys.len() / self.cluster.size())
+                        .copied()
+                        .unwrap_or(u64::MAX)
+                })
+                .collect::<Vec<u64>>()
+        });
+        let splits: Vec<u64> = self.cluster.broadcast(splits)?;
+        let mut outgoing: Vec<Vec<Body>> = vec![Vec::new(); self.cluster.size()];
+        for body in self.bodies.drain(..) {
+            let key = morton_key(&self.space, &body.location);
+            outgoing[splits.partition_point(|&split| split <= key)].push(body);
+        }
+        self.bodies = self
+            .cluster
+            .all_to_all(outgoing)?
+            .into_iter()
+            .flatten()
+            .collect();
+        self.accelerations.clear();
+        Ok(())
+    }
+
+    // accelerations of this rank's bodies from its own and the essential trees of the others
+    fn update_forces(&mut self) -> Result<(), ClusterError> {
+        let _span = tracing::debug_span!("forces", bodies = self.bodies.len()).entered();
+        let sources = self.bodies.iter().filter(|body| body.is_source()).copied();
+        let tree = BodyTree::build_bucketed(sources, self.space, self.config.bucket_size);
+        let local = (!self.bodies.is_empty()).then(|| Cuboid::bounding(&self.bodies));
+        let domains = self.cluster.all_gather(local)?;
+        let outgoing = domains
+            .iter()
+            .enumerate()
+            .map(|(rank, domain)| match domain {
+                Some(domain) if rank != self.cluster.rank() => {
+                    essential(&tree, domain, self.config.theta)
+                }
+                _ => Vec::new(),
+            })
+            .collect();
+        let ghosts = self.cluster.all_to_all(outgoing)?;
+        let sources = self
+            .bodies
+            .iter()
+            .filter(|body| body.is_source())
+            .copied()
+            .chain(ghosts.into_iter().flatten());
+        let tree = BodyTree::build_bucketed(sources, self.space, self.config.bucket_size);
+        let (theta, softening) = (self.config.theta, self.config.softening);
+        let gravity = self.config.units.gravitational_constant();
+        let acceleration =
+            |body: &Body| tree.acceleration_at(&body.location, theta, softening) * gravity;
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            self.accelerations = self.bodies.par_iter().map(acceleration).collect();
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            self.accelerations = self.bodies.iter().map(acceleration).collect();
+        }
+        Ok(())
+    }
+}
+
+// the part of `tree` a rank whose bodies lie in `domain` needs: nodes that pass the opening test from the
+// nearest point of the domain, and so from all of it, as point masses, and the bodies of the leaves reached
+fn essential(tree: &BodyTree, domain: &Cuboid, theta: f64) -> Vec<Body> {
+    let mut found = vec![];
+    let mut stack = vec![tree.root()];
+    while let Some(node) = stack.pop() {
+        if node.mass() == 0. {
+            continue;
+        }
+        if node.is_leaf() {
+            found.extend_from_slice(node.items());
+            continue;
+        }
+        let center = node.center_of_mass();
+        let size = node.bounding_box().size();
+        if size * size < theta * theta * domain.distance_squared_to(center) {
+            found.push(Body {
+                id: u64::MAX,
+                mass: node.mass(),
+                location: *center,
+                ..Body::default()
+            });
+        } else {
+            stack.extend(node.children().map(|(_, child)| &tree.nodes()[child]));
+        }
+    }
+    found
+}
@@ -0,0 +1,162 @@
+//! a velocity-dependent pull towards the velocity of a background medium, for bodies moving through gas such
+//! as planetesimals in a disk. drag is set per species with `Simulation::set_drag` and applied as its own
+//! half steps before and after every step of the integrator, each solved exactly, so it stays stable however
+//! strong it is. the work it does is kept, and `Diagnostics::conserved_energy` adds it back
+//!
+//! drag does not conserve momentum, and is not saved in a checkpoint, so set it again after resuming
+
+use crate::geometry::Point;
+
+/// the velocity of the medium at a location, in f64 whatever the simulation's precision. any `Fn(&Point) ->
+/// Point` is one, and a `Point` is a wind blowing the same everywhere
+pub trait WindField: Send + Sync {
+    fn velocity(&self, location: &Point) -> Point;
+}
+
+impl WindField for Point {
+    fn velocity(&self, _: &Point) -> Point {
+        *self
+    }
+}
+
+impl<T: Fn(&Point) -> Point + Send + Sync> WindField for T {
+    fn velocity(&self, location: &Point) -> Point {
+        self(location)
+    }
+}
+
+/// how the drag acceleration grows with the velocity u of a body relative to the wind
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DragLaw {
+    /// -rate u, as for small bodies in the epstein or stokes regime. 1 / rate is the stopping time
+    Linear { rate: f64 },
+    /// -coefficient |u| u, as for large bodies at high reynolds number
+    Quadratic { coefficient: f64 },
+}
+
+/// a drag law with the wind it drags towards
+pub struct Drag {
+    pub law: DragLaw,
+    /// the medium's velocity, still when none
+    pub wind: Option<Box<dyn WindField>>,
+}
+
+impl Drag {
+    /// drag towards still gas
+    pub fn new(law: DragLaw) -> Self {
+        Drag { law, wind: None }
+    }
+
+    pub fn with_wind(self, wind: impl WindField + 'static) -> Self {
+        Drag {
+            wind: Some(Box::new(wind)),
+            ..self
+        }
+    }
+
+    // the velocity at `location` after `dt` of drag alone, from `velocity`. the wind is taken where the body
+    // starts
+    pub(crate) fn advance(&self, location: &Point, velocity: Point, dt: f64) -> Point {
+        let wind = self
+            .wind
+            .as_ref()
+            .map_or(Point::default(), |wind| wind.velocity(location));
+        let relative = velocity - wind;
+        let kept = match self.law {
+            DragLaw::Linear { rate } => (-rate * dt).exp(),
+            // d|u|/dt = -c |u|², so |u| falls as |u0| / (1 + c |u0| t) with the direction unchanged
+            DragLaw::Quadratic { coefficient } => 1. / (1. + coefficient * relative.length() * dt),
+        };
+        wind + relative * kept
+    }
+}
+
+impl std::fmt::Debug for Drag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Drag")
+            .field("law", &self.law)
+            .field("wind", &self.wind.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::{Body, Species};
+    use crate::geometry::Cuboid;
+    use crate::sim::{Simulation, SimulationConfig};
+
+    #[test]
+    fn linear_drag_alone_decays_the_velocity_exponentially() {
+        let bodies = vec![Body {
+            mass: 1.,
+            velocity: Point {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            ..Body::default()
+        }];
+        let config = SimulationConfig {
+            self_gravity: false,
+            ..SimulationConfig::default()
+        };
+        let mut simulation =
+            Simulation::with_config(bodies, Cuboid::from(([-2.; 3], [2.; 3])), config);
+        simulation.set_drag(Species::Live, Drag::new(DragLaw::Linear { rate: 2. }));
+        let energy = simulation.total_energy();
+        for step in 1..=10 {
+            simulation.step(0.05);
+            let speed = simulation.bodies()[0].velocity.length();
+            let expected = (-2. * simulation.time()).exp();
+            assert!(
+                (speed - expected).abs() < 1e-12,
+                "step {}: {} vs {}",
+                step,
+                speed,
+                expected
+            );
+        }
+        // all the kinetic energy lost went into the drag's work
+        let lost = simulation.total_energy() - energy;
+        assert!(
+            (simulation.drag_work() - lost).abs() < 1e-12,
+            "{} vs {}",
+            simulation.drag_work(),
+            lost
+        );
+    }
+
+    #[test]
+    fn drag_work_is_the_kinetic_energy_taken_out() {
+        // a mass of 2 at unit speed slowed to exp(-0.5) of it loses 1 - exp(-1) of its kinetic energy
+        let bodies = vec![Body {
+            mass: 2.,
+            velocity: Point {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            ..Body::default()
+        }];
+        let config = SimulationConfig {
+            self_gravity: false,
+            ..SimulationConfig::default()
+        };
+        let mut simulation =
+            Simulation::with_config(bodies, Cuboid::from(([-2.; 3], [2.; 3])), config);
+        simulation.set_drag(Species::Live, Drag::new(DragLaw::Linear { rate: 1. }));
+        for _ in 0..5 {
+            simulation.step(0.1);
+        }
+        let expected = (-1f64).exp() - 1.;
+        assert!(
+            (simulation.drag_work() - expected).abs() < 1e-12,
+            "{} vs {}",
+            simulation.drag_work(),
+            expected
+        );
+        assert!((simulation.drag_work() + 0.632).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,443 @@
+//! dual-tree walks: pairs of nodes are walked together instead of the tree once per body. a source node far
+//! enough from a whole target node acts on it once, through its field expanded to second order about the
+//! target's center of mass, which every body beneath then reads off at its own offset. neighbours in a cluster
+//! all see the same far nodes, so they share those terms instead of each summing them. the field is the
+//! monopole of plummer-softened gravity with G = 1, and pairs of leaves too close for that are summed body by
+//! body
+//!
+//! a pair is accepted when the target's and source's radii about their centers of mass add up to less than
+//! theta · the distance between those centers. both ends of the pair count against the same bound, so a dual
+//! walk wants a smaller theta than a single walk for the same error: on a plummer sphere 0.4 matches about 0.7
+//! there, and 0.6 about 0.9
+
+use crate::body::Body;
+use crate::geometry::{Cuboid, Point};
+use crate::kernel::Sources;
+use crate::linear::LinearOctree;
+use crate::scalar::Scalar;
+use crate::tree::BodyTree;
+
+// what a dual walk needs of a tree
+pub(crate) trait NodePairs<S: Scalar> {
+    // bodies in the tree, which is how long the result is
+    fn len(&self) -> usize;
+    fn node_count(&self) -> usize;
+    fn bounding_box(&self, node: usize) -> &Cuboid<S>;
+    fn mass(&self, node: usize) -> S;
+    // zero only when nothing beneath pulls, unlike a net mass negative masses can cancel
+    fn absolute_mass(&self, node: usize) -> S;
+    fn center_of_mass(&self, node: usize) -> &Point<S>;
+    // the node's bounding-sphere radius, infinite unless the tree computed radii
+    fn radius(&self, node: usize) -> S;
+    // none for a leaf
+    fn children(&self, node: usize) -> impl Iterator<Item = usize> + '_;
+    // the bodies of a leaf, each with its place in the result
+    fn leaf(&self, node: usize) -> impl Iterator<Item = (usize, &Body<S>)> + '_;
+    fn is_leaf(&self, node: usize) -> bool;
+}
+
+impl<S: Scalar> NodePairs<S> for LinearOctree<S> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn node_count(&self) -> usize {
+        self.nodes().len()
+    }
+
+    fn bounding_box(&self, node: usize) -> &Cuboid<S> {
+        &self.nodes()[node].bounding_box
+    }
+
+    fn mass(&self, node: usize) -> S {
+        self.nodes()[node].mass
+    }
+
+    fn absolute_mass(&self, node: usize) -> S {
+        self.nodes()[node].absolute_mass
+    }
+
+    fn center_of_mass(&self, node: usize) -> &Point<S> {
+        &self.nodes()[node].center_of_mass
+    }
+
+    fn radius(&self, node: usize) -> S {
+        self.nodes()[node].radius
+    }
+
+    fn children(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        let node = &self.nodes()[node];
+        node.first_child..node.first_child + node.child_count
+    }
+
+    fn leaf(&self, node: usize) -> impl Iterator<Item = (usize, &Body<S>)> + '_ {
+        let node = &self.nodes()[node];
+        self.order()[node.start..node.end]
+            .iter()
+            .copied()
+            .zip(&self.bodies()[node.start..node.end])
+    }
+
+    fn is_leaf(&self, node: usize) -> bool {
+        self.nodes()[node].is_leaf()
+    }
+}
+
+impl<S: Scalar> NodePairs<S> for BodyTree<S> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn node_count(&self) -> usize {
+        self.nodes().len()
+    }
+
+    fn bounding_box(&self, node: usize) -> &Cuboid<S> {
+        &self.nodes()[node].bounding_box
+    }
+
+    fn mass(&self, node: usize) -> S {
+        self.nodes()[node].mass()
+    }
+
+    fn absolute_mass(&self, node: usize) -> S {
+        self.nodes()[node].absolute_mass()
+    }
+
+    fn center_of_mass(&self, node: usize) -> &Point<S> {
+        self.nodes()[node].center_of_mass()
+    }
+
+    fn radius(&self, node: usize) -> S {
+        self.nodes()[node].radius()
+    }
+
+    fn children(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        self.nodes()[node].children().map(|(_, child)| child)
+    }
+
+    fn leaf(&self, node: usize) -> impl Iterator<Item = (usize, &Body<S>)> + '_ {
+        let node = &self.nodes()[node];
+        node.ids.iter().map(|&id| id as usize).zip(&node.items)
+    }
+
+    fn is_leaf(&self, node: usize) -> bool {
+        self.nodes()[node].children().next().is_none()
+    }
+}
+
+// the field of the far sources of a node about its center of mass to second order: the acceleration there, its
+// gradient as the symmetric tidal tensor xx, xy, xz, yy, yz, zz, and the gradient of that as the symmetric
+// tensor xxx, xxy, xxz, xyy, xyz, xzz, yyy, yyz, yzz, zzz
+#[derive(Debug, Clone, Copy)]
+struct Local<S> {
+    acceleration: Point<S>,
+    tidal: [S; 6],
+    curvature: [S; 10],
+}
+
+impl<S: Scalar> Local<S> {
+    fn zero() -> Self {
+        Local {
+            acceleration: Point::default(),
+            tidal: [S::zero(); 6],
+            curvature: [S::zero(); 10],
+        }
+    }
+
+    // adds the softened pull of `mass` at `source` on the expansion center `center`
+    fn add_point_mass(&mut self, center: &Point<S>, source: &Point<S>, mass: S, softening: S) {
+        let d = *source - *center;
+        let inverse_squared = S::one() / (d.dot(&d) + softening * softening);
+        let inverse_cubed = mass * inverse_squared * inverse_squared.sqrt();
+        let inverse_fifth = S::from_f64(3.) * inverse_cubed * inverse_squared;
+        let inverse_seventh = S::from_f64(5.) * inverse_fifth * inverse_squared;
+        self.acceleration += d * inverse_cubed;
+        let Point { x, y, z } = d;
+        let outer = [x * x, x * y, x * z, y * y, y * z, z * z];
+        for (k, (tidal, outer)) in self.tidal.iter_mut().zip(outer).enumerate() {
+            *tidal += inverse_fifth * outer;
+            // the diagonal
+            if k == 0 || k == 3 || k == 5 {
+                *tidal -= inverse_cubed;
+            }
+        }
+        // 15 m d_i d_j d_k / r^7 - 3 m (δ_ij d_k + δ_ik d_j + δ_jk d_i) / r^5
+        let three = S::from_f64(3.);
+        let curvature = [
+            x * (inverse_seventh * x * x - three * inverse_fifth),
+            y * (inverse_seventh * x * x - inverse_fifth),
+            z * (inverse_seventh * x * x - inverse_fifth),
+            x * (inverse_seventh * y * y - inverse_fifth),
+            inverse_seventh * x * y * z,
+            x * (inverse_seventh * z * z - inverse_fifth),
+            y * (inverse_seventh * y * y - three * inverse_fifth),
+            z * (inverse_seventh * y * y - inverse_fifth),
+            y * (inverse_seventh * z * z - inverse_fifth),
+            z * (inverse_seventh * z * z - three * inverse_fifth),
+        ];
+        for (own, added) in self.curvature.iter_mut().zip(curvature) {
+            *own += added;
+        }
+    }
+
+    // the tidal tensor `offset` away from the center
+    fn tidal_at(&self, offset: &Point<S>) -> [S; 6] {
+        let [xxx, xxy, xxz, xyy, xyz, xzz, yyy, yyz, yzz, zzz] = self.curvature;
+        let Point { x, y, z } = *offset;
+        let change = [
+            xxx * x + xxy * y + xxz * z,
+            xxy * x + xyy * y + xyz * z,
+            xxz * x + xyz * y + xzz * z,
+            xyy * x + yyy * y + yyz * z,
+            xyz * x + yyz * y + yzz * z,
+            xzz * x + yzz * y + zzz * z,
+        ];
+        let mut tidal = self.tidal;
+        for (tidal, change) in tidal.iter_mut().zip(change) {
+            *tidal += change;
+        }
+        tidal
+    }
+
+    // the acceleration `offset` away from the center
+    fn at(&self, offset: &Point<S>) -> Point<S> {
+        // the tidal tensor halfway out, applied to the whole offset, is exact to second order
+        let half = S::from_f64(0.5);
+        let [xx, xy, xz, yy, yz, zz] = self.tidal_at(&(*offset * half));
+        self.acceleration
+            + Point {
+                x: xx * offset.x + xy * offset.y + xz * offset.z,
+                y: xy * offset.x + yy * offset.y + yz * offset.z,
+                z: xz * offset.x + yz * offset.y + zz * offset.z,
+            }
+    }
+
+    // the same field about a center `offset` away, plus `other`
+    fn shifted(&self, offset: &Point<S>, other: &Local<S>) -> Self {
+        let mut tidal = self.tidal_at(offset);
+        for (tidal, other) in tidal.iter_mut().zip(other.tidal) {
+            *tidal += other;
+        }
+        let mut curvature = other.curvature;
+        for (curvature, own) in curvature.iter_mut().zip(self.curvature) {
+            *curvature += own;
+        }
+        Local {
+            acceleration: self.at(offset) + other.acceleration,
+            tidal,
+            curvature,
+        }
+    }
+}
+
+// coordinates and masses of the source leaf of a leaf pair, laid out for `Scalar::direct_sum`
+#[derive(Default)]
+struct Gathered<S> {
+    x: Vec<S>,
+    y: Vec<S>,
+    z: Vec<S>,
+    mass: Vec<S>,
+}
+
+impl<S: Scalar> Gathered<S> {
+    fn gather<'a>(&mut self, bodies: impl Iterator<Item = &'a Body<S>>) {
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+        self.mass.clear();
+        for body in bodies {
+            self.x.push(body.location.x);
+            self.y.push(body.location.y);
+            self.z.push(body.location.z);
+            self.mass.push(body.mass);
+        }
+    }
+
+    fn sources(&self) -> Sources<'_, S> {
+        Sources {
+            x: &self.x,
+            y: &self.y,
+            z: &self.z,
+            mass: &self.mass,
+        }
+    }
+}
+
+/// the acceleration on every body of `tree` from all the others by a dual walk at `theta`, with G = 1, indexed
+/// like the bodies the tree was built from
+pub(crate) fn accelerations<S: Scalar, T: NodePairs<S>>(
+    tree: &T,
+    theta: S,
+    softening: S,
+) -> Vec<Point<S>> {
+    let mut accelerations = vec![Point::default(); tree.len()];
+    if tree.node_count() == 0 {
+        return accelerations;
+    }
+    let radii = radii(tree);
+    let mut locals = vec![Local::zero(); tree.node_count()];
+    let mut gathered = Gathered::default();
+    let mut pairs = vec![(0, 0)];
+    while let Some((target, source)) = pairs.pop() {
+        if tree.absolute_mass(source).is_zero() || tree.absolute_mass(target).is_zero() {
+            continue;
+        }
+        let (target_leaf, source_leaf) = (tree.is_leaf(target), tree.is_leaf(source));
+        if target == source {
+            if target_leaf {
+                near(
+                    tree,
+                    target,
+                    source,
+                    softening,
+                    &mut gathered,
+                    &mut accelerations,
+                );
+            } else {
+                for a in tree.children(target) {
+                    pairs.extend(tree.children(target).map(|b| (a, b)));
+                }
+            }
+            continue;
+        }
+        let center = tree.center_of_mass(target);
+        let distance_squared = center.distance_squared(tree.center_of_mass(source));
+        let reach = radii[target] + radii[source];
+        if reach * reach < theta * theta * distance_squared {
+            locals[target].add_point_mass(
+                center,
+                tree.center_of_mass(source),
+                tree.mass(source),
+                softening,
+            );
+        } else if target_leaf && source_leaf {
+            near(
+                tree,
+                target,
+                source,
+                softening,
+                &mut gathered,
+                &mut accelerations,
+            );
+        } else if !target_leaf && (source_leaf || radii[target] >= radii[source]) {
+            pairs.extend(tree.children(target).map(|child| (child, source)));
+        } else {
+            pairs.extend(tree.children(source).map(|child| (target, child)));
+        }
+    }
+
+    // hand each node's field down to its children and out to the bodies of the leaves
+    let mut nodes = vec![(0, locals[0])];
+    while let Some((node, local)) = nodes.pop() {
+        let center = tree.center_of_mass(node);
+        if tree.is_leaf(node) {
+            for (index, body) in tree.leaf(node) {
+                accelerations[index] += local.at(&(body.location - *center));
+            }
+            continue;
+        }
+        for child in tree.children(node) {
+            let offset = *tree.center_of_mass(child) - *center;
+            nodes.push((child, local.shifted(&offset, &locals[child])));
+        }
+    }
+    accelerations
+}
+
+// how far every node's bodies reach from its center of mass, children before parents: a leaf measures its
+// bodies, and a parent takes the nearer of its children's reach and its box's farthest corner
+pub(crate) fn radii<S: Scalar, T: NodePairs<S>>(tree: &T) -> Vec<S> {
+    let mut order = Vec::with_capacity(tree.node_count());
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        order.push(node);
+        stack.extend(tree.children(node));
+    }
+    let mut radii = vec![S::zero(); tree.node_count()];
+    for &node in order.iter().rev() {
+        let center = tree.center_of_mass(node);
+        radii[node] = if tree.is_leaf(node) {
+            tree.leaf(node)
+                .map(|(_, body)| body.location.distance_squared(center))
+                .fold(S::zero(), S::max)
+                .sqrt()
+        } else {
+            let reach = tree
+                .children(node)
+                .filter(|&child| !tree.absolute_mass(child).is_zero())
+                .map(|child| {
+                    tree.center_of_mass(child).distance_squared(center).sqrt() + radii[child]
+                })
+                .fold(S::zero(), S::max);
+            let bounds = tree.bounding_box(node);
+            let corner = Point {
+                x: (center.x - bounds.x.start).max(bounds.x.end - center.x),
+                y: (center.y - bounds.y.start).max(bounds.y.end - center.y),
+                z: (center.z - bounds.z.start).max(bounds.z.end - center.z),
+            };
+            reach.min(corner.dot(&corner).sqrt())
+        };
+    }
+    radii
+}
+
+// the direct sum over the bodies of leaf `source` for every body of leaf `target`
+fn near<S: Scalar, T: NodePairs<S>>(
+    tree: &T,
+    target: usize,
+    source: usize,
+    softening: S,
+    gathered: &mut Gathered<S>,
+    accelerations: &mut [Point<S>],
+) {
+    gathered.gather(tree.leaf(source).map(|(_, body)| body));
+    let sources = gathered.sources();
+    for (index, body) in tree.leaf(target) {
+        accelerations[index] += S::direct_sum(&body.location, &sources, softening);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::ForceError;
+    use crate::geometry::Cuboid;
+    use crate::ic;
+    use crate::sim::{Simulation, SimulationConfig, Traversal, TreeBackend};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // a plummer sphere walked as `traversal` at `theta` on `backend`
+    fn cluster(backend: TreeBackend, traversal: Traversal, theta: f64) -> Simulation {
+        let bodies = ic::plummer(1000, 1., 0.2, &mut StdRng::seed_from_u64(21));
+        let config = SimulationConfig { backend, traversal, theta, softening: 0.01, ..SimulationConfig::default() };
+        Simulation::with_config(bodies, Cuboid::from(([-64.; 3], [64.; 3])), config)
+    }
+
+    #[test]
+    fn opening_every_pair_is_the_direct_sum() {
+        for backend in [TreeBackend::Pointer, TreeBackend::Linear] {
+            let simulation = cluster(backend, Traversal::Dual, 0.);
+            let direct = simulation.compute_accelerations_direct();
+            let error = ForceError::between(&simulation.compute_accelerations(), &direct);
+            assert!(error.max < 1e-10, "{:?}: {} max", backend, error.max);
+        }
+    }
+
+    #[test]
+    fn a_dual_walk_is_about_as_close_as_a_single_walk_at_a_wider_angle() {
+        for backend in [TreeBackend::Pointer, TreeBackend::Linear] {
+            let direct = cluster(backend, Traversal::Single, 0.).compute_accelerations_direct();
+            let dual = cluster(backend, Traversal::Dual, 0.4).compute_accelerations();
+            let single = cluster(backend, Traversal::Single, 0.7).compute_accelerations();
+            let dual_error = ForceError::between(&dual, &direct);
+            let single_error = ForceError::between(&single, &direct);
+            for (dual, single) in [(dual_error.rms, single_error.rms), (dual_error.max, single_error.max)] {
+                assert!(dual < 1.5 * single, "{:?}: {} against {}", backend, dual, single);
+            }
+            // and the two walks land within their errors of each other
+            let apart = ForceError::between(&dual, &single);
+            assert!(apart.rms < 2. * single_error.rms, "{:?}: {}", backend, apart.rms);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! a log of what happened to bodies beyond their motion: merges and bounces from the `CollisionPolicy`, and
+//! bodies leaving the root box, whichever way the `EscapePolicy` or a periodic boundary deals with them. it is
+//! kept once `Simulation::set_event_log` turns it on, for `Simulation::events` to read back or
+//! `Simulation::take_events` to drain on long runs
+
+use serde::{Deserialize, Serialize};
+
+/// what happened in an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    /// the two bodies became one, which kept the id of the heavier
+    Merge,
+    /// the two bodies bounced off each other
+    Bounce,
+    /// the bodies left the root box and were dropped
+    Removed,
+    /// the bodies left the root box and were moved back onto its boundary
+    Clamped,
+    /// the bodies left the root box and the box grew around them
+    Expanded,
+    /// the bodies crossed a face of a periodic box and came back through the opposite one
+    Wrapped,
+}
+
+/// one event, stamped with the step it happened in and the time that step ended at. events from outside a
+/// step, like bodies added outside the box, carry the step and time they were added at
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimEvent {
+    pub step: u64,
+    pub time: f64,
+    pub kind: EventKind,
+    /// the `Body::id`s involved: the pair for a collision, lower index first, or every body that crossed the
+    /// boundary
+    pub body_ids: Vec<u64>,
+}
@@ -0,0 +1,126 @@
+//! fixed background fields acting on every body next to their own gravity, such as a massive central object
+//! the bodies are test particles around or a dark matter halo they sit in. the fields are evaluated in f64
+//! whatever the simulation's precision, and do not feel the bodies back
+
+use crate::geometry::Point;
+use std::f64::consts::PI;
+
+/// a static potential added to the self-gravity of a simulation. `gravitational_constant` is the G of the
+/// simulation's units, for fields given by a mass; fields given as an acceleration can ignore it
+pub trait ExternalPotential: std::fmt::Debug + Send + Sync {
+    fn acceleration(&self, location: &Point, gravitational_constant: f64) -> Point;
+
+    /// the potential whose negative gradient is `acceleration`, for the energy
+    fn potential(&self, location: &Point, gravitational_constant: f64) -> f64;
+}
+
+/// a point mass fixed at `center`, plummer-softened like the bodies are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Kepler {
+    pub mass: f64,
+    pub center: Point,
+    pub softening: f64,
+}
+
+impl Kepler {
+    pub fn new(mass: f64, center: Point) -> Self {
+        Kepler {
+            mass,
+            center,
+            softening: 0.,
+        }
+    }
+}
+
+impl ExternalPotential for Kepler {
+    fn acceleration(&self, location: &Point, gravitational_constant: f64) -> Point {
+        let offset = self.center - *location;
+        let distance_squared = offset.dot(&offset);
+        if distance_squared == 0. {
+            return Point::default();
+        }
+        let softened = distance_squared + self.softening * self.softening;
+        offset * (gravitational_constant * self.mass / (softened * softened.sqrt()))
+    }
+
+    fn potential(&self, location: &Point, gravitational_constant: f64) -> f64 {
+        let softened = location.distance_squared(&self.center) + self.softening * self.softening;
+        if softened == 0. {
+            return 0.;
+        }
+        -gravitational_constant * self.mass / softened.sqrt()
+    }
+}
+
+/// a navarro-frenk-white halo, with density ρ0 / ((r / rs) (1 + r / rs)²) around `center`. the mass inside r
+/// grows without bound, as ln r, so set it up to be much larger than the region the bodies move in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Nfw {
+    /// ρ0
+    pub scale_density: f64,
+    /// rs, where the density slope turns from -1 to -3
+    pub scale_radius: f64,
+    pub center: Point,
+}
+
+impl Nfw {
+    // 4π ρ0 rs³, the mass scale of the profile
+    fn mass_scale(&self) -> f64 {
+        4. * PI * self.scale_density * self.scale_radius.powi(3)
+    }
+}
+
+impl ExternalPotential for Nfw {
+    fn acceleration(&self, location: &Point, gravitational_constant: f64) -> Point {
+        let offset = self.center - *location;
+        let r = offset.length();
+        if r == 0. {
+            return Point::default();
+        }
+        let x = r / self.scale_radius;
+        let enclosed = self.mass_scale() * (x.ln_1p() - x / (1. + x));
+        offset * (gravitational_constant * enclosed / (r * r * r))
+    }
+
+    fn potential(&self, location: &Point, gravitational_constant: f64) -> f64 {
+        let x = location.distance_squared(&self.center).sqrt() / self.scale_radius;
+        // ln(1 + x) / x goes to 1 at the center
+        let shape = if x == 0. { 1. } else { x.ln_1p() / x };
+        -gravitational_constant * self.mass_scale() / self.scale_radius * shape
+    }
+}
+
+/// an isotropic harmonic trap around `center`, pulling with ω² times the distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Harmonic {
+    /// ω, the angular frequency every body oscillates at on its own
+    pub frequency: f64,
+    pub center: Point,
+}
+
+impl ExternalPotential for Harmonic {
+    fn acceleration(&self, location: &Point, _: f64) -> Point {
+        (self.center - *location) * (self.frequency * self.frequency)
+    }
+
+    fn potential(&self, location: &Point, _: f64) -> f64 {
+        0.5 * self.frequency * self.frequency * location.distance_squared(&self.center)
+    }
+}
+
+/// the same acceleration everywhere, e.g. a cluster falling through a larger potential. its potential is 0 at
+/// the origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UniformField {
+    pub acceleration: Point,
+}
+
+impl ExternalPotential for UniformField {
+    fn acceleration(&self, _: &Point, _: f64) -> Point {
+        self.acceleration
+    }
+
+    fn potential(&self, location: &Point, _: f64) -> f64 {
+        -self.acceleration.dot(location)
+    }
+}
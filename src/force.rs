@@ -0,0 +1,135 @@
+//! the force law a tree sums. the trees only decide which bodies are summed one by one and which nodes stand
+//! in for theirs; a `ForceModel` says what each of those terms is, so other pairwise laws reuse the traversal.
+//! every source is weighed by its mass, and nodes carry the total mass and the center of mass of their
+//! bodies, so a model sees mass as the strength of a source: a charge for coulomb, a well depth scale for
+//! lennard-jones. strengths have to be positive, as nodes of no mass are skipped. whatever the law, the
+//! simulation scales it by the gravitational constant of its `Units`, which is 1 unless set otherwise
+
+use crate::geometry::Point;
+use crate::kernel::Sources;
+use crate::scalar::Scalar;
+use crate::tree::{
+    point_mass_acceleration, point_mass_potential, quadrupole_acceleration, quadrupole_potential,
+};
+use serde::{Deserialize, Serialize};
+
+/// a pairwise interaction, in any precision. only the pair terms are required; the node terms default to a
+/// single source of the node's mass at its center of mass
+pub trait ForceModel: std::fmt::Debug + Clone + Send + Sync {
+    /// plain softened newtonian gravity, which the gpu backend can compute in place of the model's own terms
+    const NEWTONIAN: bool = false;
+
+    /// acceleration at `target` from a source of `strength` at `source`; zero when they coincide, so a body
+    /// can be asked for its own position
+    fn pair_acceleration<S: Scalar>(
+        &self,
+        target: &Point<S>,
+        source: &Point<S>,
+        strength: S,
+        softening: S,
+    ) -> Point<S>;
+
+    /// potential at `target` from a source of `strength` at `source`, with the acceleration as its negative
+    /// gradient; zero when they coincide
+    fn pair_potential<S: Scalar>(&self, target: &Point<S>, source: &Point<S>, strength: S, softening: S) -> S;
+
+    /// acceleration at `target` from an accepted node. `quadrupole` is the node's traceless quadrupole when the
+    /// tree keeps them, which a law without a multipole expansion can ignore
+    fn node_acceleration<S: Scalar>(
+        &self,
+        target: &Point<S>,
+        center: &Point<S>,
+        strength: S,
+        quadrupole: Option<&[S; 6]>,
+        softening: S,
+    ) -> Point<S> {
+        let _ = quadrupole;
+        self.pair_acceleration(target, center, strength, softening)
+    }
+
+    /// potential at `target` from an accepted node, matching `node_acceleration`
+    fn node_potential<S: Scalar>(
+        &self,
+        target: &Point<S>,
+        center: &Point<S>,
+        strength: S,
+        quadrupole: Option<&[S; 6]>,
+        softening: S,
+    ) -> S {
+        let _ = quadrupole;
+        self.pair_potential(target, center, strength, softening)
+    }
+
+    /// acceleration at `target` from a batch of leaf bodies, one `pair_acceleration` each unless overridden
+    /// with something faster
+    fn direct_sum<S: Scalar>(&self, target: &Point<S>, sources: &Sources<S>, softening: S) -> Point<S> {
+        let mut acceleration = Point::default();
+        for i in 0..sources.len() {
+            let source = Point {
+                x: sources.x[i],
+                y: sources.y[i],
+                z: sources.z[i],
+            };
+            acceleration += self.pair_acceleration(target, &source, sources.mass[i], softening);
+        }
+        acceleration
+    }
+}
+
+/// plummer-softened newtonian gravity with G = 1, the law a simulation uses unless given another. node terms
+/// include the quadrupole when there is one, and leaves go through `Scalar::direct_sum`, so the `simd`
+/// kernels apply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Gravity;
+
+impl ForceModel for Gravity {
+    const NEWTONIAN: bool = true;
+
+    fn pair_acceleration<S: Scalar>(
+        &self,
+        target: &Point<S>,
+        source: &Point<S>,
+        strength: S,
+        softening: S,
+    ) -> Point<S> {
+        point_mass_acceleration(target, source, strength, softening)
+    }
+
+    fn pair_potential<S: Scalar>(&self, target: &Point<S>, source: &Point<S>, strength: S, softening: S) -> S {
+        point_mass_potential(target, source, strength, softening)
+    }
+
+    fn node_acceleration<S: Scalar>(
+        &self,
+        target: &Point<S>,
+        center: &Point<S>,
+        strength: S,
+        quadrupole: Option<&[S; 6]>,
+        softening: S,
+    ) -> Point<S> {
+        let acceleration = point_mass_acceleration(target, center, strength, softening);
+        match quadrupole {
+            Some(quadrupole) => acceleration + quadrupole_acceleration(target, center, quadrupole, softening),
+            None => acceleration,
+        }
+    }
+
+    fn node_potential<S: Scalar>(
+        &self,
+        target: &Point<S>,
+        center: &Point<S>,
+        strength: S,
+        quadrupole: Option<&[S; 6]>,
+        softening: S,
+    ) -> S {
+        let potential = point_mass_potential(target, center, strength, softening);
+        match quadrupole {
+            Some(quadrupole) => potential + quadrupole_potential(target, center, quadrupole, softening),
+            None => potential,
+        }
+    }
+
+    fn direct_sum<S: Scalar>(&self, target: &Point<S>, sources: &Sources<S>, softening: S) -> Point<S> {
+        S::direct_sum(target, sources, softening)
+    }
+}
@@ -0,0 +1,511 @@
+use crate::body::Body;
+use crate::scalar::Scalar;
+use rand::distributions::Standard;
+use rand::prelude::Distribution;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Range<T> {
+    pub start: T,
+    pub end: T,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Point<S = f64> {
+    pub x: S,
+    pub y: S,
+    pub z: S,
+}
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cuboid<S = f64> {
+    // since range is an iterator and thus lazy, this does not use much memory
+    pub x: Range<S>,
+    pub y: Range<S>,
+    pub z: Range<S>,
+}
+
+/// the axis a projection looks down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl<S: Scalar> Point<S> {
+    pub fn distance_squared(&self, other: &Point<S>) -> S {
+        let dx = self.x - other.x;
+        let dy = self.y - other.y;
+        let dz = self.z - other.z;
+        dx * dx + dy * dy + dz * dz
+    }
+
+    pub fn dot(&self, other: &Point<S>) -> S {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: &Point<S>) -> Point<S> {
+        Point {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(&self) -> S {
+        self.dot(self).sqrt()
+    }
+
+    pub fn as_array(&self) -> [S; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    /// the same point in another precision, rounded to the nearest representable one
+    pub fn cast<T: Scalar>(&self) -> Point<T> {
+        Point {
+            x: T::from_f64(self.x.as_f64()),
+            y: T::from_f64(self.y.as_f64()),
+            z: T::from_f64(self.z.as_f64()),
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+}
+
+impl<S: Scalar> Add for Point<S> {
+    type Output = Point<S>;
+
+    fn add(self, other: Point<S>) -> Point<S> {
+        Point { x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
+    }
+}
+
+impl<S: Scalar> Sub for Point<S> {
+    type Output = Point<S>;
+
+    fn sub(self, other: Point<S>) -> Point<S> {
+        Point { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
+    }
+}
+
+impl<S: Scalar> Mul<S> for Point<S> {
+    type Output = Point<S>;
+
+    fn mul(self, scale: S) -> Point<S> {
+        Point { x: self.x * scale, y: self.y * scale, z: self.z * scale }
+    }
+}
+
+impl<S: Scalar> Div<S> for Point<S> {
+    type Output = Point<S>;
+
+    fn div(self, scale: S) -> Point<S> {
+        Point { x: self.x / scale, y: self.y / scale, z: self.z / scale }
+    }
+}
+
+impl<S: Scalar> Neg for Point<S> {
+    type Output = Point<S>;
+
+    fn neg(self) -> Point<S> {
+        Point { x: -self.x, y: -self.y, z: -self.z }
+    }
+}
+
+impl<S: Scalar> AddAssign for Point<S> {
+    fn add_assign(&mut self, other: Point<S>) {
+        *self = *self + other;
+    }
+}
+
+impl<S: Scalar> SubAssign for Point<S> {
+    fn sub_assign(&mut self, other: Point<S>) {
+        *self = *self - other;
+    }
+}
+
+impl<S> From<[S; 3]> for Point<S> {
+    fn from([x, y, z]: [S; 3]) -> Self {
+        Point { x, y, z }
+    }
+}
+
+impl<S> From<Point<S>> for [S; 3] {
+    fn from(point: Point<S>) -> Self {
+        [point.x, point.y, point.z]
+    }
+}
+
+impl<S> From<(S, S, S)> for Point<S> {
+    fn from((x, y, z): (S, S, S)) -> Self {
+        Point { x, y, z }
+    }
+}
+
+impl<S> From<Point<S>> for (S, S, S) {
+    fn from(point: Point<S>) -> Self {
+        (point.x, point.y, point.z)
+    }
+}
+
+impl<T> From<(T, T)> for Range<T> {
+    fn from((start, end): (T, T)) -> Self {
+        Range { start, end }
+    }
+}
+
+impl<T> From<Range<T>> for (T, T) {
+    fn from(range: Range<T>) -> Self {
+        (range.start, range.end)
+    }
+}
+
+/// a cuboid from its (min, max) corners
+impl<S: Copy> From<([S; 3], [S; 3])> for Cuboid<S> {
+    fn from((min, max): ([S; 3], [S; 3])) -> Self {
+        Cuboid {
+            x: Range { start: min[0], end: max[0] },
+            y: Range { start: min[1], end: max[1] },
+            z: Range { start: min[2], end: max[2] },
+        }
+    }
+}
+
+impl<S> From<Cuboid<S>> for ([S; 3], [S; 3]) {
+    fn from(cuboid: Cuboid<S>) -> Self {
+        (
+            [cuboid.x.start, cuboid.y.start, cuboid.z.start],
+            [cuboid.x.end, cuboid.y.end, cuboid.z.end],
+        )
+    }
+}
+
+impl<T> Range<T> {
+    pub fn midpoint(&self) -> T
+    where
+        T: std::ops::Add<Output = T> + std::ops::Div<Output = T> + From<u8> + Copy,
+    {
+        (self.start + self.end) / T::from(2u8)
+    }
+}
+
+impl<S: Scalar> Cuboid<S> {
+    pub fn center(&self) -> Point<S> {
+        Point {
+            x: self.x.midpoint(),
+            y: self.y.midpoint(),
+            z: self.z.midpoint(),
+        }
+    }
+
+    /// the same box in another precision
+    pub fn cast<T: Scalar>(&self) -> Cuboid<T> {
+        let cast = |range: &Range<S>| Range {
+            start: T::from_f64(range.start.as_f64()),
+            end: T::from_f64(range.end.as_f64()),
+        };
+        Cuboid { x: cast(&self.x), y: cast(&self.y), z: cast(&self.z) }
+    }
+
+    /// length of the longest edge, the `s` in the opening criterion
+    pub fn size(&self) -> S {
+        (self.x.end - self.x.start)
+            .max(self.y.end - self.y.start)
+            .max(self.z.end - self.z.start)
+    }
+
+    /// the tightest box around every body; the default box at the origin for none
+    pub fn bounding(bodies: &[Body<S>]) -> Cuboid<S> {
+        let Some(first) = bodies.first() else {
+            return Cuboid::default();
+        };
+        let mut min = first.location;
+        let mut max = first.location;
+        for body in &bodies[1..] {
+            let p = &body.location;
+            min = Point { x: min.x.min(p.x), y: min.y.min(p.y), z: min.z.min(p.z) };
+            max = Point { x: max.x.max(p.x), y: max.y.max(p.y), z: max.z.max(p.z) };
+        }
+        Cuboid::from((min.as_array(), max.as_array()))
+    }
+
+    /// the smallest box covering both
+    pub fn union(&self, other: &Cuboid<S>) -> Cuboid<S> {
+        let join = |a: &Range<S>, b: &Range<S>| Range {
+            start: a.start.min(b.start),
+            end: a.end.max(b.end),
+        };
+        Cuboid {
+            x: join(&self.x, &other.x),
+            y: join(&self.y, &other.y),
+            z: join(&self.z, &other.z),
+        }
+    }
+
+    /// a cube covering this box whose side is a power of two and whose corners sit on multiples of half that
+    /// side. boxes that change a little map to the same cube, so trees built over it keep their cell boundaries
+    pub fn to_power_of_two_cube(&self) -> Cuboid<S> {
+        let size = self.size().as_f64();
+        // at least the box's size, so starting on the multiple of it at or below the box covers the box
+        let half = S::from_f64(if size > 0. { 2f64.powi(size.log2().ceil() as i32) } else { 1. });
+        let align = |range: &Range<S>| {
+            let start = (range.start / half).floor() * half;
+            Range { start, end: start + half + half }
+        };
+        Cuboid { x: align(&self.x), y: align(&self.y), z: align(&self.z) }
+    }
+
+    pub fn contains(&self, point: &Point<S>) -> bool {
+        (self.x.start..=self.x.end).contains(&point.x)
+            && (self.y.start..=self.y.end).contains(&point.y)
+            && (self.z.start..=self.z.end).contains(&point.z)
+    }
+
+    /// `point` moved back into the box as if its opposite faces were glued together
+    pub fn wrap(&self, point: &Point<S>) -> Point<S> {
+        self.wrap_along(point, [true; 3])
+    }
+
+    /// `wrap` along only the axes, x, y and z, that `axes` holds, leaving the others as they are
+    pub fn wrap_along(&self, point: &Point<S>, axes: [bool; 3]) -> Point<S> {
+        let wrap = |value: S, range: &Range<S>, periodic: bool| {
+            if !periodic {
+                return value;
+            }
+            range.start + (value - range.start).rem_euclid(&(range.end - range.start))
+        };
+        Point {
+            x: wrap(point.x, &self.x, axes[0]),
+            y: wrap(point.y, &self.y, axes[1]),
+            z: wrap(point.z, &self.z, axes[2]),
+        }
+    }
+
+    /// the copy of `point` nearest `target` when the box repeats periodically in every direction; no axis of
+    /// the offset from `target` is longer than half the box
+    pub fn nearest_image(&self, point: &Point<S>, target: &Point<S>) -> Point<S> {
+        self.nearest_image_along(point, target, [true; 3])
+    }
+
+    /// `nearest_image` when the box repeats along only the axes, x, y and z, that `axes` holds
+    pub fn nearest_image_along(&self, point: &Point<S>, target: &Point<S>, axes: [bool; 3]) -> Point<S> {
+        let image = |value: S, target: S, range: &Range<S>, periodic: bool| {
+            if !periodic {
+                return value;
+            }
+            let period = range.end - range.start;
+            let offset = value - target;
+            target + offset - period * (offset / period).round()
+        };
+        Point {
+            x: image(point.x, target.x, &self.x, axes[0]),
+            y: image(point.y, target.y, &self.y, axes[1]),
+            z: image(point.z, target.z, &self.z, axes[2]),
+        }
+    }
+
+    /// grows every range symmetrically by `fraction` of its length, e.g. 0.1 turns [0, 10] into [-0.5, 10.5]
+    pub fn pad(&self, fraction: S) -> Cuboid<S> {
+        let pad = |range: &Range<S>| {
+            let margin = (range.end - range.start) * fraction / S::from_f64(2.);
+            Range {
+                start: range.start - margin,
+                end: range.end + margin,
+            }
+        };
+        Cuboid {
+            x: pad(&self.x),
+            y: pad(&self.y),
+            z: pad(&self.z),
+        }
+    }
+
+    /// the same box moved by `offset`
+    pub fn translated(&self, offset: &Point<S>) -> Cuboid<S> {
+        let shift = |range: &Range<S>, by: S| Range {
+            start: range.start + by,
+            end: range.end + by,
+        };
+        Cuboid {
+            x: shift(&self.x, offset.x),
+            y: shift(&self.y, offset.y),
+            z: shift(&self.z, offset.z),
+        }
+    }
+
+    /// nearest point of the box to `point`; points inside come back unchanged
+    pub fn clamp(&self, point: &Point<S>) -> Point<S> {
+        Point {
+            x: point.x.clamp(self.x.start, self.x.end),
+            y: point.y.clamp(self.y.start, self.y.end),
+            z: point.z.clamp(self.z.start, self.z.end),
+        }
+    }
+
+    /// separating-axis test; boxes sharing only a face, edge or corner count as intersecting
+    pub fn intersects(&self, other: &Cuboid<S>) -> bool {
+        self.x.start <= other.x.end
+            && other.x.start <= self.x.end
+            && self.y.start <= other.y.end
+            && other.y.start <= self.y.end
+            && self.z.start <= other.z.end
+            && other.z.start <= self.z.end
+    }
+
+    /// squared distance from the point to the nearest point of the box, zero if inside
+    pub fn distance_squared_to(&self, point: &Point<S>) -> S {
+        let gap = |range: &Range<S>, v: S| {
+            if v < range.start {
+                range.start - v
+            } else if v > range.end {
+                v - range.end
+            } else {
+                S::zero()
+            }
+        };
+        let dx = gap(&self.x, point.x);
+        let dy = gap(&self.y, point.y);
+        let dz = gap(&self.z, point.z);
+        dx * dx + dy * dy + dz * dz
+    }
+
+    /// distance from the point to the nearest point of the box, zero if inside
+    pub fn distance_to_point(&self, point: &Point<S>) -> S {
+        self.distance_squared_to(point).sqrt()
+    }
+
+    pub fn split(&self) -> [Cuboid<S>; 8] {
+        let x_mid = self.x.midpoint();
+        let y_mid = self.y.midpoint();
+        let z_mid = self.z.midpoint();
+
+        let mut octants = [Cuboid::default(); 8];
+        let (zero, one) = (S::zero(), S::one());
+
+        for (i, &(x_sign, y_sign, z_sign)) in [
+            (zero, zero, zero),
+            (one, zero, zero),
+            (zero, one, zero),
+            (one, one, zero),
+            (zero, zero, one),
+            (one, zero, one),
+            (zero, one, one),
+            (one, one, one),
+        ]
+        .iter()
+        .enumerate()
+        {
+            octants[i] = Cuboid {
+                x: Range {
+                    start: self.x.start + x_sign * (x_mid - self.x.start),
+                    end: self.x.start + (x_sign + one) * (x_mid - self.x.start),
+                },
+                y: Range {
+                    start: self.y.start + y_sign * (y_mid - self.y.start),
+                    end: self.y.start + (y_sign + one) * (y_mid - self.y.start),
+                },
+                z: Range {
+                    start: self.z.start + z_sign * (z_mid - self.z.start),
+                    end: self.z.start + (z_sign + one) * (z_mid - self.z.start),
+                },
+            };
+        }
+
+        octants
+    }
+
+    pub fn octant_contains_point(&self, point: &Point<S>) -> Option<usize> {
+        let x_mid = self.x.midpoint();
+        let y_mid = self.y.midpoint();
+        let z_mid = self.z.midpoint();
+
+        let x_octant = if point.x < x_mid { 0 } else { 1 };
+        let y_octant = if point.y < y_mid { 0 } else { 1 };
+        let z_octant = if point.z < z_mid { 0 } else { 1 };
+
+        match (x_octant, y_octant, z_octant) {
+            (0, 0, 0) => Some(0),
+            (1, 0, 0) => Some(1),
+            (0, 1, 0) => Some(2),
+            (1, 1, 0) => Some(3),
+            (0, 0, 1) => Some(4),
+            (1, 0, 1) => Some(5),
+            (0, 1, 1) => Some(6),
+            (1, 1, 1) => Some(7),
+            _ => None,
+        }
+    }
+}
+
+impl<S: Scalar> Distribution<Point<S>> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Point<S> {
+        let mut coordinate = || S::from_f64(rng.gen());
+        Point { x: coordinate(), y: coordinate(), z: coordinate() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(min: f64, max: f64) -> Cuboid {
+        Cuboid::from(([min; 3], [max; 3]))
+    }
+
+    #[test]
+    fn boxes_overlapping_or_touching_intersect_and_disjoint_ones_do_not() {
+        let unit = cube(0., 1.);
+        assert!(unit.intersects(&cube(0.5, 1.5)));
+        assert!(unit.intersects(&cube(0.25, 0.75)));
+        // a shared face, then a shared corner
+        assert!(unit.intersects(&Cuboid::from(([1., 0., 0.], [2., 1., 1.]))));
+        assert!(unit.intersects(&cube(1., 2.)));
+        assert!(!unit.intersects(&cube(1.01, 2.)));
+        // apart along z alone
+        assert!(!unit.intersects(&Cuboid::from(([0., 0., 1.5], [1., 1., 2.]))));
+        for other in [cube(0.5, 1.5), cube(1.01, 2.)] {
+            assert_eq!(unit.intersects(&other), other.intersects(&unit));
+        }
+    }
+
+    #[test]
+    fn a_point_inside_is_no_distance_away_and_one_off_a_face_is_the_gap() {
+        let unit = cube(0., 1.);
+        assert_eq!(unit.distance_to_point(&Point::from([0.5, 0.25, 0.75])), 0.);
+        assert_eq!(unit.distance_to_point(&Point::from([1., 1., 1.])), 0.);
+        assert!((unit.distance_to_point(&Point::from([0.5, 0.5, 3.])) - 2.).abs() < 1e-12);
+        assert!((unit.distance_to_point(&Point::from([-0.25, 0.5, 0.5])) - 0.25).abs() < 1e-12);
+        // past a corner the gaps add in quadrature
+        assert!((unit.distance_to_point(&Point::from([4., 5., 0.5])) - 5.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn conversions_to_arrays_and_tuples_come_back_unchanged() {
+        let point = Point::from([1., -2., 3.5]);
+        assert_eq!(<[f64; 3]>::from(point), [1., -2., 3.5]);
+        assert_eq!(Point::from(<[f64; 3]>::from(point)), point);
+        assert_eq!(Point::from(<(f64, f64, f64)>::from(point)), point);
+        assert_eq!(<(f64, f64, f64)>::from(Point::from((1., -2., 3.5))), (1., -2., 3.5));
+        let range = Range::from((-1., 4.));
+        assert_eq!((range.start, range.end), (-1., 4.));
+        assert_eq!(Range::from(<(f64, f64)>::from(range)), range);
+        let cuboid = Cuboid::from(([0., -1., 2.], [1., 3., 5.]));
+        assert_eq!(cuboid.y, Range { start: -1., end: 3. });
+        assert_eq!(<([f64; 3], [f64; 3])>::from(cuboid), ([0., -1., 2.], [1., 3., 5.]));
+        assert_eq!(Cuboid::from(<([f64; 3], [f64; 3])>::from(cuboid)), cuboid);
+    }
+
+    #[test]
+    fn padding_grows_each_range_about_its_middle() {
+        let padded = cube(0., 10.).pad(0.1);
+        assert_eq!(padded, cube(-0.5, 10.5));
+        let uneven = Cuboid::from(([0., -2., 1.], [4., 2., 1.])).pad(0.5);
+        assert_eq!(uneven, Cuboid::from(([-1., -3., 1.], [5., 3., 1.])));
+        assert_eq!(uneven.center(), Point::from([2., 0., 1.]));
+    }
+}
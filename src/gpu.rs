@@ -0,0 +1,491 @@
+//! barnes-hut forces on the gpu through a wgpu compute shader, for the `TreeBackend::Gpu` backend. the cpu
+//! still builds the linear tree; its nodes and bodies are uploaded every call and one thread walks the tree per
+//! target. the shader works in f32 and sums monopoles only, so it matches `LinearOctree::acceleration_at` to
+//! single precision whatever `S` is. `GpuForces::direct_accelerations` sums every pair instead, for checking
+//! the tree at medium n without waiting on the cpu
+use crate::body::Body;
+use crate::geometry::Point;
+use crate::linear::LinearOctree;
+use crate::scalar::Scalar;
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+// threads per workgroup, as in the shader
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Debug)]
+pub enum GpuError {
+    /// no adapter that can run compute shaders was found
+    Adapter(wgpu::RequestAdapterError),
+    Device(wgpu::RequestDeviceError),
+    /// a buffer would be larger than the device allows
+    TooLarge {
+        bytes: u64,
+        limit: u64,
+    },
+    /// the accelerations could not be read back
+    Readback(wgpu::BufferAsyncError),
+    Poll(wgpu::PollError),
+}
+
+impl std::fmt::Display for GpuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GpuError::Adapter(err) => write!(f, "no gpu adapter: {}", err),
+            GpuError::Device(err) => write!(f, "could not open the gpu: {}", err),
+            GpuError::TooLarge { bytes, limit } => write!(
+                f,
+                "a {} byte buffer is over the gpu's limit of {} bytes",
+                bytes, limit
+            ),
+            GpuError::Readback(err) => write!(f, "could not read back from the gpu: {}", err),
+            GpuError::Poll(err) => write!(f, "waiting on the gpu failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GpuError::Adapter(err) => Some(err),
+            GpuError::Device(err) => Some(err),
+            GpuError::TooLarge { .. } => None,
+            GpuError::Readback(err) => Some(err),
+            GpuError::Poll(err) => Some(err),
+        }
+    }
+}
+
+// a `LinearNode` as the shader reads it
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct Node {
+    center_of_mass: [f32; 3],
+    mass: f32,
+    // the walk skips nodes with none, as on the cpu, and opens those whose masses cancel
+    absolute_mass: f32,
+    size: f32,
+    first_child: u32,
+    child_count: u32,
+    start: u32,
+    end: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct Params {
+    theta: f32,
+    softening_squared: f32,
+    targets: u32,
+    row: u32,
+}
+
+/// an open gpu device with the force pipeline compiled on it. making one is slow, so keep it around for the
+/// whole run rather than per step
+pub struct GpuForces {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    // the tree walk
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    // the all-pairs sum, which binds no nodes
+    direct: wgpu::ComputePipeline,
+    direct_layout: wgpu::BindGroupLayout,
+    adapter: String,
+}
+
+impl std::fmt::Debug for GpuForces {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GpuForces")
+            .field("adapter", &self.adapter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GpuForces {
+    /// opens the default adapter, honoring the `WGPU_BACKEND` and `WGPU_ADAPTER_NAME` environment variables,
+    /// and compiles the shaders
+    pub fn new() -> Result<Self, GpuError> {
+        let instance =
+            wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+        let options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::from_env()
+                .unwrap_or(wgpu::PowerPreference::HighPerformance),
+            ..Default::default()
+        };
+        let adapter =
+            pollster::block_on(instance.request_adapter(&options)).map_err(GpuError::Adapter)?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("barneshutt3d"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        }))
+        .map_err(GpuError::Device)?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("forces"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let (direct, pipeline) = (pipeline("direct"), pipeline("main"));
+        let (layout, direct_layout) = (
+            pipeline.get_bind_group_layout(0),
+            direct.get_bind_group_layout(0),
+        );
+        let info = adapter.get_info();
+        tracing::info!(adapter = %info.name, backend = %info.backend, "gpu forces ready");
+        Ok(GpuForces {
+            device,
+            queue,
+            pipeline,
+            layout,
+            direct,
+            direct_layout,
+            adapter: info.name,
+        })
+    }
+
+    /// the name of the device in use
+    pub fn adapter(&self) -> &str {
+        &self.adapter
+    }
+
+    /// the monopole tree acceleration at each of `targets` with G = 1, in their order. blocks until the gpu
+    /// is done
+    pub fn accelerations<S: Scalar>(
+        &self,
+        tree: &LinearOctree<S>,
+        targets: &[Point<S>],
+        theta: S,
+        softening: S,
+    ) -> Result<Vec<Point<S>>, GpuError> {
+        if targets.is_empty() {
+            return Ok(vec![]);
+        }
+        let _span = tracing::debug_span!("gpu_forces", targets = targets.len()).entered();
+        let nodes: Vec<Node> = tree
+            .nodes()
+            .iter()
+            .map(|node| Node {
+                center_of_mass: to_f32(&node.center_of_mass),
+                mass: node.mass.as_f64() as f32,
+                absolute_mass: node.absolute_mass.as_f64() as f32,
+                size: node.bounding_box.size().as_f64() as f32,
+                first_child: node.first_child as u32,
+                child_count: node.child_count as u32,
+                start: node.start as u32,
+                end: node.end as u32,
+            })
+            .collect();
+        let sources: Vec<[f32; 4]> = tree
+            .bodies()
+            .iter()
+            .map(|body| with_w(&body.location, body.mass.as_f64() as f32))
+            .collect();
+        let limits = self.device.limits();
+        let nodes = self.storage(
+            "nodes",
+            bytemuck::cast_slice(&nodes),
+            limits.max_storage_buffer_binding_size,
+        )?;
+        self.dispatch(Kernel::Tree(&nodes), &sources, targets, theta, softening)
+    }
+
+    /// the softened pull of every one of `sources` on each of `targets` with G = 1, summed pair by pair in
+    /// f32, in the order of `targets`. tracers among `sources` pull on nothing, as on the cpu, and a source
+    /// at a target leaves it alone. blocks until the gpu is done
+    pub fn direct_accelerations<S: Scalar>(
+        &self,
+        sources: &[Body<S>],
+        targets: &[Point<S>],
+        softening: S,
+    ) -> Result<Vec<Point<S>>, GpuError> {
+        if targets.is_empty() {
+            return Ok(vec![]);
+        }
+        let _span = tracing::debug_span!("gpu_direct", targets = targets.len()).entered();
+        let sources: Vec<[f32; 4]> = sources
+            .iter()
+            .filter(|body| body.is_source())
+            .map(|body| with_w(&body.location, body.mass.as_f64() as f32))
+            .collect();
+        self.dispatch(Kernel::Direct, &sources, targets, S::zero(), softening)
+    }
+
+    // runs `kernel` with one thread per target over `sources` and reads back the accelerations
+    fn dispatch<S: Scalar>(
+        &self,
+        kernel: Kernel<'_>,
+        sources: &[[f32; 4]],
+        targets: &[Point<S>],
+        theta: S,
+        softening: S,
+    ) -> Result<Vec<Point<S>>, GpuError> {
+        let positions: Vec<[f32; 4]> = targets.iter().map(|target| with_w(target, 0.)).collect();
+        let output_size = std::mem::size_of_val(positions.as_slice()) as u64;
+
+        let limits = self.device.limits();
+        let groups = (targets.len() as u32).div_ceil(WORKGROUP_SIZE);
+        let width = groups.min(limits.max_compute_workgroups_per_dimension);
+        let softening = softening.as_f64() as f32;
+        let params = Params {
+            theta: theta.as_f64() as f32,
+            softening_squared: softening * softening,
+            targets: targets.len() as u32,
+            row: width * WORKGROUP_SIZE,
+        };
+
+        let uniform = self.buffer(
+            "params",
+            bytemuck::bytes_of(&params),
+            wgpu::BufferUsages::UNIFORM,
+        );
+        let storage = wgpu::BufferUsages::STORAGE;
+        // an empty tree still has its root, but a buffer cannot be bound empty
+        let sources = if sources.is_empty() {
+            self.buffer("sources", &[0; 16], storage)
+        } else {
+            self.storage(
+                "sources",
+                bytemuck::cast_slice(sources),
+                limits.max_storage_buffer_binding_size,
+            )?
+        };
+        let positions = self.storage(
+            "targets",
+            bytemuck::cast_slice(&positions),
+            limits.max_storage_buffer_binding_size,
+        )?;
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("accelerations"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (pipeline, layout, nodes) = match kernel {
+            Kernel::Tree(nodes) => (&self.pipeline, &self.layout, Some(nodes)),
+            Kernel::Direct => (&self.direct, &self.direct_layout, None),
+        };
+        let mut entries = vec![entry(0, &uniform)];
+        entries.extend(nodes.map(|nodes| entry(1, nodes)));
+        entries.extend([entry(2, &sources), entry(3, &positions), entry(4, &output)]);
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("forces"),
+            layout,
+            entries: &entries,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(width, groups.div_ceil(width), 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, output_size);
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        readback.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(GpuError::Poll)?;
+        // the callback has run once the poll is back
+        receiver
+            .recv()
+            .expect("map callback ran")
+            .map_err(GpuError::Readback)?;
+        let view = readback.get_mapped_range(..).expect("buffer is mapped");
+        let accelerations = bytemuck::cast_slice::<u8, [f32; 4]>(&view)
+            .iter()
+            .map(|&[x, y, z, _]| Point {
+                x: S::from_f64(x as f64),
+                y: S::from_f64(y as f64),
+                z: S::from_f64(z as f64),
+            })
+            .collect();
+        Ok(accelerations)
+    }
+
+    fn buffer(&self, label: &str, contents: &[u8], usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage,
+            })
+    }
+
+    // a read-only storage buffer, if the device can bind one that large
+    fn storage(&self, label: &str, contents: &[u8], limit: u64) -> Result<wgpu::Buffer, GpuError> {
+        let bytes = contents.len() as u64;
+        if bytes > limit {
+            return Err(GpuError::TooLarge { bytes, limit });
+        }
+        Ok(self.buffer(label, contents, wgpu::BufferUsages::STORAGE))
+    }
+}
+
+// which shader `GpuForces::dispatch` runs
+enum Kernel<'a> {
+    // the walk of the tree with these nodes
+    Tree(&'a wgpu::Buffer),
+    // every source on every target
+    Direct,
+}
+
+fn entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: buffer.as_entire_binding(),
+    }
+}
+
+fn to_f32<S: Scalar>(point: &Point<S>) -> [f32; 3] {
+    [
+        point.x.as_f64() as f32,
+        point.y.as_f64() as f32,
+        point.z.as_f64() as f32,
+    ]
+}
+
+fn with_w<S: Scalar>(point: &Point<S>, w: f32) -> [f32; 4] {
+    let [x, y, z] = to_f32(point);
+    [x, y, z, w]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::ForceError;
+    use crate::geometry::Cuboid;
+    use crate::ic;
+    use crate::sim::{Simulation, SimulationConfig, TreeBackend};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // the device, or none on a machine without one, where the tests pass without checking anything
+    fn device() -> Option<GpuForces> {
+        GpuForces::new()
+            .map_err(|err| eprintln!("skipping, {}", err))
+            .ok()
+    }
+
+    #[test]
+    fn the_all_pairs_sum_matches_the_cpu_one_to_single_precision() {
+        let Some(gpu) = device() else {
+            return;
+        };
+        let bodies = ic::plummer(1000, 1., 0.2, &mut StdRng::seed_from_u64(7));
+        let config = SimulationConfig {
+            softening: 0.01,
+            ..SimulationConfig::default()
+        };
+        let softening = config.softening;
+        let simulation =
+            Simulation::with_config(bodies, Cuboid::from(([-16.; 3], [16.; 3])), config);
+        let targets: Vec<Point> = simulation
+            .bodies()
+            .iter()
+            .map(|body| body.location)
+            .collect();
+        let accelerations = gpu
+            .direct_accelerations(simulation.bodies(), &targets, softening)
+            .unwrap();
+        let error = ForceError::between(&accelerations, &simulation.compute_accelerations_direct());
+        assert!(error.max < 1e-5, "{} rms, {} max", error.rms, error.max);
+        assert!(gpu
+            .direct_accelerations(simulation.bodies(), &[], softening)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn the_tree_walk_matches_the_cpu_walk_of_the_same_tree() {
+        let Some(gpu) = device() else {
+            return;
+        };
+        let bodies = ic::plummer(2000, 1., 0.2, &mut StdRng::seed_from_u64(8));
+        let space = Cuboid::from(([-32.; 3], [32.; 3]));
+        let tree = LinearOctree::build(bodies.clone(), space);
+        let targets: Vec<Point> = bodies.iter().map(|body| body.location).collect();
+        let accelerations = gpu.accelerations(&tree, &targets, 0.5, 0.01).unwrap();
+        let cpu: Vec<Point> = targets
+            .iter()
+            .map(|target| tree.acceleration_at(target, 0.5, 0.01))
+            .collect();
+        let error = ForceError::between(&accelerations, &cpu);
+        assert!(error.max < 1e-5, "{} rms, {} max", error.rms, error.max);
+        // the backend a simulation picks walks the same tree
+        let config = |backend| SimulationConfig {
+            theta: 0.5,
+            softening: 0.01,
+            backend,
+            ..SimulationConfig::default()
+        };
+        let on_gpu = Simulation::with_config(bodies.clone(), space, config(TreeBackend::Gpu));
+        let on_cpu = Simulation::with_config(bodies, space, config(TreeBackend::Linear));
+        let error = ForceError::between(
+            &on_gpu.compute_accelerations(),
+            &on_cpu.compute_accelerations(),
+        );
+        assert!(error.max < 1e-5, "{} rms, {} max", error.rms, error.max);
+    }
+
+    #[test]
+    fn nodes_whose_masses_cancel_are_opened_as_on_the_cpu() {
+        let Some(gpu) = device() else {
+            return;
+        };
+        // pairs of opposite masses close enough to share leaves, so the root and many nodes weigh nothing
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let mut bodies = vec![];
+        for body in ic::uniform_box(100, &space, &mut StdRng::seed_from_u64(6)) {
+            let partner = Point {
+                x: body.location.x + 1e-3,
+                ..body.location
+            };
+            bodies.push(Body { mass: 1., ..body });
+            bodies.push(Body {
+                mass: -1.,
+                location: partner,
+                ..body
+            });
+        }
+        let tree = LinearOctree::build_bucketed(bodies.clone(), space, 2);
+        assert_eq!(tree.nodes()[0].mass(), 0.);
+        let targets: Vec<Point> = bodies.iter().map(|body| body.location).collect();
+        for theta in [0., 0.7] {
+            let accelerations = gpu.accelerations(&tree, &targets, theta, 0.01).unwrap();
+            let cpu: Vec<Point> = targets
+                .iter()
+                .map(|target| tree.acceleration_at(target, theta, 0.01))
+                .collect();
+            let error = ForceError::between(&accelerations, &cpu);
+            // the dipoles leave little pull, so f32 keeps fewer of its digits than for one sign of mass
+            assert!(
+                error.max < 1e-4,
+                "theta {}: {} rms, {} max",
+                theta,
+                error.rms,
+                error.max
+            );
+        }
+    }
+}
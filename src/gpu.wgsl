@@ -0,0 +1,113 @@
+// barnes-hut walk of a linear octree, one thread per target. leaves are summed body by body and accepted nodes
+// count as a point mass at their center of mass, as in LinearOctree::acceleration_at with monopoles. `direct`
+// sums every source on each target instead, and binds no nodes
+
+struct Node {
+    x: f32,
+    y: f32,
+    z: f32,
+    mass: f32,
+    absolute_mass: f32,
+    size: f32,
+    first_child: u32,
+    child_count: u32,
+    start: u32,
+    end: u32,
+}
+
+struct Params {
+    theta: f32,
+    softening_squared: f32,
+    targets: u32,
+    // threads per row of workgroups, for dispatches too wide for one dimension
+    row: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> nodes: array<Node>;
+// xyz and mass
+@group(0) @binding(2) var<storage, read> sources: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read> targets: array<vec4<f32>>;
+@group(0) @binding(4) var<storage, read_write> accelerations: array<vec4<f32>>;
+
+// one more than seven children for each of the 21 levels, the deepest a linear tree goes
+const STACK: u32 = 148u;
+
+// softened pull of a point mass; zero when it sits at `position`
+fn pull(position: vec3<f32>, source: vec3<f32>, mass: f32) -> vec3<f32> {
+    let offset = source - position;
+    let distance_squared = dot(offset, offset);
+    if distance_squared == 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let softened = distance_squared + params.softening_squared;
+    return offset * (mass / (softened * sqrt(softened)));
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x + id.y * params.row;
+    if i >= params.targets {
+        return;
+    }
+    let position = targets[i].xyz;
+    var acceleration = vec3<f32>(0.0);
+    var stack: array<u32, STACK>;
+    stack[0] = 0u;
+    var depth = 1u;
+    while depth > 0u {
+        depth -= 1u;
+        let node = nodes[stack[depth]];
+        if node.absolute_mass == 0.0 {
+            continue;
+        }
+        if node.child_count == 0u {
+            for (var j = node.start; j < node.end; j++) {
+                let source = sources[j];
+                acceleration += pull(position, source.xyz, source.w);
+            }
+            continue;
+        }
+        let center = vec3<f32>(node.x, node.y, node.z);
+        let offset = center - position;
+        if node.size * node.size < params.theta * params.theta * dot(offset, offset) {
+            acceleration += pull(position, center, node.mass);
+        } else {
+            for (var c = 0u; c < node.child_count; c++) {
+                stack[depth] = node.first_child + c;
+                depth += 1u;
+            }
+        }
+    }
+    accelerations[i] = vec4<f32>(acceleration, 0.0);
+}
+
+// every source on the target, for the direct sum. the sources are staged through workgroup memory a tile at a
+// time, so each is read from storage once per workgroup rather than once per thread
+var<workgroup> tile: array<vec4<f32>, 64>;
+
+@compute @workgroup_size(64)
+fn direct(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    let i = id.x + id.y * params.row;
+    // threads past the last target still load their share of every tile
+    let position = targets[min(i, params.targets - 1u)].xyz;
+    var acceleration = vec3<f32>(0.0);
+    let count = arrayLength(&sources);
+    for (var start = 0u; start < count; start += 64u) {
+        let j = start + local;
+        if j < count {
+            tile[local] = sources[j];
+        } else {
+            tile[local] = vec4<f32>(0.0);
+        }
+        workgroupBarrier();
+        for (var k = 0u; k < 64u; k++) {
+            let source = tile[k];
+            acceleration += pull(position, source.xyz, source.w);
+        }
+        workgroupBarrier();
+    }
+    if i < params.targets {
+        accelerations[i] = vec4<f32>(acceleration, 0.0);
+    }
+}
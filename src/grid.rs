@@ -0,0 +1,168 @@
+//! fields sampled on a regular grid over the simulation's box, for volume renderings and slice plots. see
+//! `Simulation::sample_grid`
+
+use crate::body::Body;
+use crate::geometry::{Cuboid, Point};
+use crate::scalar::Scalar;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// what `Simulation::sample_grid` puts in each cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridQuantity {
+    /// mass per volume, each body's mass going whole to the cell it is in (nearest grid point)
+    DensityNgp,
+    /// mass per volume, each body's mass shared between the eight cells around it by how close their centers
+    /// are (cloud in cell), for a smoother field
+    DensityCic,
+    /// the gravitational potential at each cell center from the tree, with the external potentials added
+    Potential,
+}
+
+impl GridQuantity {
+    /// the name the field gets in a vtk file
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridQuantity::DensityNgp | GridQuantity::DensityCic => "density",
+            GridQuantity::Potential => "potential",
+        }
+    }
+}
+
+/// N³ samples of a field, with what places them in space. cell (i, j, k) is centered at
+/// `origin + (i, j, k) * spacing` and its value is at `values[i + n * (j + n * k)]`, x running fastest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    pub quantity: GridQuantity,
+    /// cells along each axis
+    pub resolution: usize,
+    /// the center of cell (0, 0, 0)
+    pub origin: Point,
+    /// the size of a cell along each axis
+    pub spacing: Point,
+    pub values: Vec<f64>,
+}
+
+impl Grid {
+    // an all-zero grid of `resolution` cells a side over `space`
+    pub(crate) fn over(space: &Cuboid, resolution: usize, quantity: GridQuantity) -> Self {
+        assert!(resolution > 0, "a grid needs at least one cell");
+        let n = resolution as f64;
+        let spacing = Point {
+            x: (space.x.end - space.x.start) / n,
+            y: (space.y.end - space.y.start) / n,
+            z: (space.z.end - space.z.start) / n,
+        };
+        let corner = Point {
+            x: space.x.start,
+            y: space.y.start,
+            z: space.z.start,
+        };
+        let origin = corner + spacing * 0.5;
+        Grid {
+            quantity,
+            resolution,
+            origin,
+            spacing,
+            values: vec![0.; resolution.pow(3)],
+        }
+    }
+
+    pub fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        i + self.resolution * (j + self.resolution * k)
+    }
+
+    pub fn value(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.values[self.index(i, j, k)]
+    }
+
+    pub fn cell_center(&self, i: usize, j: usize, k: usize) -> Point {
+        Point {
+            x: self.origin.x + i as f64 * self.spacing.x,
+            y: self.origin.y + j as f64 * self.spacing.y,
+            z: self.origin.z + k as f64 * self.spacing.z,
+        }
+    }
+
+    /// the cell centers in the order of `values`
+    pub fn cell_centers(&self) -> impl Iterator<Item = Point> + '_ {
+        let n = self.resolution;
+        (0..n.pow(3)).map(move |index| self.cell_center(index % n, index / n % n, index / (n * n)))
+    }
+
+    // adds the mass of `bodies` to the cells as a density, wrapping around the edges along the axes that are
+    // `periodic` and leaving out what falls outside along the others
+    pub(crate) fn deposit<S: Scalar>(&mut self, bodies: &[Body<S>], periodic: [bool; 3]) {
+        let n = self.resolution as isize;
+        let volume = self.spacing.x * self.spacing.y * self.spacing.z;
+        let cell = |index: isize, axis: usize| {
+            if periodic[axis] {
+                Some(index.rem_euclid(n) as usize)
+            } else {
+                (0..n).contains(&index).then_some(index as usize)
+            }
+        };
+        for body in bodies {
+            let density = body.mass.as_f64() / volume;
+            // the position in cells from the center of cell 0
+            let p: Point = body.location.cast();
+            let u = [
+                (p.x - self.origin.x) / self.spacing.x,
+                (p.y - self.origin.y) / self.spacing.y,
+                (p.z - self.origin.z) / self.spacing.z,
+            ];
+            match self.quantity {
+                GridQuantity::DensityNgp => {
+                    let [i, j, k] = u.map(|u| u.round() as isize);
+                    if let (Some(i), Some(j), Some(k)) = (cell(i, 0), cell(j, 1), cell(k, 2)) {
+                        let index = self.index(i, j, k);
+                        self.values[index] += density;
+                    }
+                }
+                GridQuantity::DensityCic => {
+                    let lower = u.map(|u| u.floor());
+                    for corner in 0..8 {
+                        let mut weight = 1.;
+                        let mut indices = [0; 3];
+                        for axis in 0..3 {
+                            let upper = corner >> axis & 1 == 1;
+                            let fraction = u[axis] - lower[axis];
+                            weight *= if upper { fraction } else { 1. - fraction };
+                            indices[axis] = lower[axis] as isize + upper as isize;
+                        }
+                        let [i, j, k] = indices;
+                        if let (Some(i), Some(j), Some(k)) = (cell(i, 0), cell(j, 1), cell(k, 2)) {
+                            let index = self.index(i, j, k);
+                            self.values[index] += weight * density;
+                        }
+                    }
+                }
+                GridQuantity::Potential => unreachable!("a potential is evaluated, not deposited"),
+            }
+        }
+    }
+
+    /// writes the grid as a legacy ascii vtk structured grid, with the values as point data named after the
+    /// quantity, for paraview or visit
+    pub fn write_vtk(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let n = self.resolution;
+        writeln!(out, "# vtk DataFile Version 3.0")?;
+        writeln!(out, "barneshutt3d {} grid", self.quantity.name())?;
+        writeln!(out, "ASCII")?;
+        writeln!(out, "DATASET STRUCTURED_GRID")?;
+        writeln!(out, "DIMENSIONS {} {} {}", n, n, n)?;
+        writeln!(out, "POINTS {} double", self.values.len())?;
+        for p in self.cell_centers() {
+            writeln!(out, "{} {} {}", p.x, p.y, p.z)?;
+        }
+        writeln!(out, "POINT_DATA {}", self.values.len())?;
+        writeln!(out, "SCALARS {} double 1", self.quantity.name())?;
+        writeln!(out, "LOOKUP_TABLE default")?;
+        for value in &self.values {
+            writeln!(out, "{}", value)?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,198 @@
+//! initial-condition samplers. the systems come out centered on the origin, in units where G = 1
+
+use crate::body::Body;
+use crate::diagnostics;
+use crate::geometry::{Cuboid, Point};
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use std::f64::consts::PI;
+
+/// isotropic maxwell-boltzmann velocities: every component is gaussian with mean 0 and standard deviation sigma.
+/// panics if sigma is negative or not finite
+pub fn velocities_maxwellian<R: Rng + ?Sized>(n: usize, sigma: f64, rng: &mut R) -> Vec<Point> {
+    let normal = Normal::new(0., sigma).expect("sigma must be finite and non-negative");
+    (0..n)
+        .map(|_| Point {
+            x: normal.sample(rng),
+            y: normal.sample(rng),
+            z: normal.sample(rng),
+        })
+        .collect()
+}
+
+/// bodies scattered uniformly through `space` with masses in [0, 1), at rest. unlike the other samplers
+/// this keeps the box's own coordinates
+pub fn uniform_box<R: Rng + ?Sized>(n: usize, space: &Cuboid, rng: &mut R) -> Vec<Body> {
+    (0..n)
+        .map(|_| {
+            let mut body: Body = rng.gen();
+            let t = body.location;
+            body.location = Point {
+                x: space.x.start + t.x * (space.x.end - space.x.start),
+                y: space.y.start + t.y * (space.y.end - space.y.start),
+                z: space.z.start + t.z * (space.z.end - space.z.start),
+            };
+            body
+        })
+        .collect()
+}
+
+/// a plummer sphere in virial equilibrium, sampled as in aarseth, henon & wielen (1974): radii from the
+/// cumulative mass profile and speeds by rejection from the isotropic distribution function. the profile
+/// has a long tail, so a few bodies land tens of scale radii out. the sample is shifted to its center of
+/// mass frame
+pub fn plummer<R: Rng + ?Sized>(
+    n: usize,
+    total_mass: f64,
+    scale_radius: f64,
+    rng: &mut R,
+) -> Vec<Body> {
+    let mass = total_mass / n as f64;
+    let mut bodies: Vec<Body> = (0..n)
+        .map(|_| {
+            // the open upper bound keeps the mass fraction below 1 and the radius finite
+            let fraction: f64 = rng.gen_range(f64::EPSILON..1.);
+            let r = scale_radius / (fraction.powf(-2. / 3.) - 1.).sqrt();
+            let escape = (2. * total_mass / scale_radius).sqrt()
+                * (1. + r * r / (scale_radius * scale_radius)).powf(-0.25);
+            // q = v / v_escape is distributed as q^2 (1 - q^2)^(7/2), whose peak is below 0.1
+            let q = loop {
+                let q: f64 = rng.gen();
+                if rng.gen::<f64>() * 0.1 < q * q * (1. - q * q).powf(3.5) {
+                    break q;
+                }
+            };
+            Body {
+                mass,
+                location: isotropic(rng) * r,
+                velocity: isotropic(rng) * (q * escape),
+                ..Body::default()
+            }
+        })
+        .collect();
+    to_center_of_mass_frame(&mut bodies);
+    bodies
+}
+
+/// a homogeneous ball with gaussian velocities scaled to the given virial ratio 2T / |W|: 0 starts a cold
+/// collapse, 1 is equilibrium. panics if the ratio is negative or not finite
+pub fn uniform_sphere<R: Rng + ?Sized>(
+    n: usize,
+    total_mass: f64,
+    radius: f64,
+    virial_ratio: f64,
+    rng: &mut R,
+) -> Vec<Body> {
+    assert!(
+        virial_ratio >= 0. && virial_ratio.is_finite(),
+        "virial ratio must be finite and non-negative"
+    );
+    let mass = total_mass / n as f64;
+    // |W| = 3 M^2 / 5 R for a uniform ball, and T = 3/2 M sigma^2
+    let sigma = (virial_ratio * total_mass / (5. * radius)).sqrt();
+    let velocities = velocities_maxwellian(n, sigma, rng);
+    let mut bodies: Vec<Body> = velocities
+        .into_iter()
+        .map(|velocity| Body {
+            mass,
+            location: isotropic(rng) * (radius * rng.gen::<f64>().cbrt()),
+            velocity,
+            ..Body::default()
+        })
+        .collect();
+    to_center_of_mass_frame(&mut bodies);
+    bodies
+}
+
+/// a central mass at rest at the origin, first in the list, orbited counterclockwise about +z by `n` disk
+/// bodies. the disk is flat in the xy plane with uniform surface density between the two radii, and every
+/// body is on the circular orbit for the central mass plus the disk mass inside its radius
+pub fn kepler_disk<R: Rng + ?Sized>(
+    n: usize,
+    central_mass: f64,
+    disk_mass: f64,
+    inner_radius: f64,
+    outer_radius: f64,
+    rng: &mut R,
+) -> Vec<Body> {
+    let mass = disk_mass / n as f64;
+    let (inner_squared, outer_squared) = (inner_radius * inner_radius, outer_radius * outer_radius);
+    let mut bodies = Vec::with_capacity(n + 1);
+    bodies.push(Body {
+        mass: central_mass,
+        ..Body::default()
+    });
+    for _ in 0..n {
+        // uniform in area
+        let r_squared = inner_squared + rng.gen::<f64>() * (outer_squared - inner_squared);
+        let r = r_squared.sqrt();
+        let phi = rng.gen_range(0. ..2. * PI);
+        let enclosed = central_mass
+            + disk_mass * (r_squared - inner_squared) / (outer_squared - inner_squared);
+        let speed = (enclosed / r).sqrt();
+        let (sin, cos) = phi.sin_cos();
+        bodies.push(Body {
+            mass,
+            location: Point {
+                x: r * cos,
+                y: r * sin,
+                z: 0.,
+            },
+            velocity: Point {
+                x: -speed * sin,
+                y: speed * cos,
+                z: 0.,
+            },
+            ..Body::default()
+        });
+    }
+    bodies
+}
+
+// a uniformly random unit vector
+fn isotropic<R: Rng + ?Sized>(rng: &mut R) -> Point {
+    let z: f64 = rng.gen_range(-1. ..=1.);
+    let phi = rng.gen_range(0. ..2. * PI);
+    let s = (1. - z * z).sqrt();
+    Point {
+        x: s * phi.cos(),
+        y: s * phi.sin(),
+        z,
+    }
+}
+
+fn to_center_of_mass_frame(bodies: &mut [Body]) {
+    let Some((location, velocity)) = diagnostics::center_of_mass(bodies) else {
+        return;
+    };
+    for body in bodies {
+        body.location -= location;
+        body.velocity -= velocity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn maxwellian_components_have_zero_mean_and_sigma_spread() {
+        let sigma = 2.5;
+        let velocities = velocities_maxwellian(20_000, sigma, &mut StdRng::seed_from_u64(5));
+        let n = velocities.len() as f64;
+        let components = |v: &Point| [v.x, v.y, v.z];
+        for axis in 0..3 {
+            let mean = velocities.iter().map(|v| components(v)[axis]).sum::<f64>() / n;
+            let spread = (velocities.iter().map(|v| (components(v)[axis] - mean).powi(2)).sum::<f64>() / n).sqrt();
+            // the standard error of the mean is sigma / sqrt(n), about 0.018 here
+            assert!(mean.abs() < 0.06, "axis {} mean {}", axis, mean);
+            assert!((spread / sigma - 1.).abs() < 0.03, "axis {} spread {}", axis, spread);
+        }
+        // the 3d speeds follow, with a mean square of 3 sigma²
+        let mean_square = velocities.iter().map(|v| v.x * v.x + v.y * v.y + v.z * v.z).sum::<f64>() / n;
+        assert!((mean_square / (3. * sigma * sigma) - 1.).abs() < 0.03, "{}", mean_square);
+        assert!(velocities_maxwellian(10, 0., &mut StdRng::seed_from_u64(5)).iter().all(|v| *v == Point::default()));
+    }
+}
@@ -0,0 +1,488 @@
+//! time integrators. a step is written against a `Stage`, which moves the bodies of a simulation and refreshes
+//! their forces, and leaves escapes, collisions and the tree to the simulation in between. `Scheme` picks one
+//! of the built-in integrators from the config, and `Simulation::step_with` takes any other
+//!
+//! on a two-body kepler orbit of eccentricity 0.5 with exact forces, 200 steps a period for 20 periods,
+//! leapfrog peaks at an energy error of 3e-3 around each pericenter, and a body ends up 6e-2 of the
+//! semi-major axis from where it should be. yoshida4 peaks at 2e-5 and ends 2e-3 off for three times the force
+//! evaluations. hermite ends 3e-3 off for one force and one jerk evaluation a step, but its energy error of
+//! 1e-4 grows steadily instead of staying bounded, as it is not symplectic. halving the step cuts the
+//! leapfrog errors by 4 and the others by 16 or more
+
+use crate::body::Body;
+use crate::collision::Collision;
+use crate::dual::NodePairs;
+use crate::force::ForceModel;
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use crate::sim::{retain_kept, Simulation};
+use crate::tree::{image_of, within_half_period, Acceptance, Period};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// advances the bodies of a simulation by one step
+pub trait Integrator {
+    /// moves `stage` forward by `dt`. the accelerations in `stage` are current when this is called, and
+    /// should be again when it returns, with the tree holding the bodies where they are
+    fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64);
+}
+
+/// the integrators built in, as picked by `SimulationConfig::integrator`. block timesteps always take
+/// leapfrog sub-steps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Scheme {
+    /// `Leapfrog`
+    #[default]
+    Leapfrog,
+    /// `Yoshida4`
+    Yoshida4,
+    /// `Hermite`
+    Hermite,
+}
+
+impl Integrator for Scheme {
+    fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64) {
+        match self {
+            Scheme::Leapfrog => Leapfrog.advance(stage, dt),
+            Scheme::Yoshida4 => Yoshida4.advance(stage, dt),
+            Scheme::Hermite => Hermite.advance(stage, dt),
+        }
+    }
+}
+
+/// kick-drift-kick leapfrog, i.e. velocity verlet: half a kick from the current accelerations, a full drift,
+/// new forces at the new positions, then the closing half kick, which brings the velocities back in step
+/// with the positions. second order and symplectic, so energy errors stay bounded instead of drifting, for
+/// one force evaluation per step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Leapfrog;
+
+impl Integrator for Leapfrog {
+    fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64) {
+        stage.kick(dt / 2.);
+        stage.drift(dt);
+        stage.settle();
+        stage.update_forces();
+        stage.kick(dt / 2.);
+    }
+}
+
+/// yoshida's fourth-order composition of three leapfrog steps, the middle one backwards in time. fourth order
+/// and still symplectic, for three force evaluations per step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Yoshida4;
+
+impl Integrator for Yoshida4 {
+    fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64) {
+        let outer = 1. / (2. - 2f64.cbrt());
+        let inner = 1. - 2. * outer;
+        for weight in [outer, inner, outer] {
+            Leapfrog.advance(stage, weight * dt);
+        }
+    }
+}
+
+/// the fourth-order hermite predictor-corrector: positions and velocities are predicted from the
+/// accelerations and their time derivatives, the jerks, then corrected with the forces and jerks at the
+/// prediction. one force and one jerk evaluation per step, but not symplectic, so energy errors slowly
+/// grow. the jerk is that of the bodies' softened newtonian pull, from node monopoles, so other force laws
+/// and external potentials fall back to second order. bodies that collided during the step keep their
+/// predicted state
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hermite;
+
+impl Integrator for Hermite {
+    fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64) {
+        let mut accelerations = stage.accelerations().to_vec();
+        let mut jerks = stage.jerks().to_vec();
+        let mut start: Vec<(Point<S>, Point<S>)> = stage
+            .bodies()
+            .iter()
+            .map(|body| (body.location, body.velocity))
+            .collect();
+        let (step, half) = (S::from_f64(dt), S::from_f64(dt / 2.));
+        let (second, third) = (S::from_f64(dt * dt / 2.), S::from_f64(dt * dt * dt / 6.));
+        for ((body, a), j) in stage
+            .bodies_mut()
+            .iter_mut()
+            .zip(&accelerations)
+            .zip(&jerks)
+        {
+            body.location += body.velocity * step + *a * second + *j * third;
+            body.velocity += *a * step + *j * second;
+        }
+        if let Some(kept) = stage.settle() {
+            retain_kept(&mut start, &kept);
+            retain_kept(&mut accelerations, &kept);
+            retain_kept(&mut jerks, &kept);
+        }
+        stage.update_forces();
+        let collided: HashSet<u64> = stage
+            .collisions()
+            .iter()
+            .flat_map(|collision| [collision.ids.0, collision.ids.1])
+            .collect();
+        let twelfth = S::from_f64(dt * dt / 12.);
+        let new_jerks = stage.jerks().to_vec();
+        let new_accelerations = stage.accelerations().to_vec();
+        for (i, body) in stage.bodies_mut().iter_mut().enumerate() {
+            if collided.contains(&body.id) {
+                continue;
+            }
+            let (location, velocity) = start[i];
+            let (a0, a1) = (accelerations[i], new_accelerations[i]);
+            let (j0, j1) = (jerks[i], new_jerks[i]);
+            body.velocity = velocity + (a0 + a1) * half + (j0 - j1) * twelfth;
+            body.location = location + (velocity + body.velocity) * half + (a0 - a1) * twelfth;
+        }
+        // the forces stay those of the prediction, which is as close to the corrected state as the scheme
+        // needs, but the tree has to hold the bodies where they are
+        stage.settle();
+        stage.refresh_tree();
+    }
+}
+
+/// a simulation in the middle of a step, as an `Integrator` sees it
+pub struct Stage<'a, S: Scalar, F: ForceModel> {
+    simulation: &'a mut Simulation<S, F>,
+    force_evaluations: usize,
+    collisions: Vec<Collision>,
+}
+
+impl<'a, S: Scalar, F: ForceModel> Stage<'a, S, F> {
+    pub(crate) fn new(simulation: &'a mut Simulation<S, F>, force_evaluations: usize) -> Self {
+        Stage {
+            simulation,
+            force_evaluations,
+            collisions: Vec::new(),
+        }
+    }
+
+    // the force evaluations and collisions of the step
+    pub(crate) fn finish(self) -> (usize, Vec<Collision>) {
+        (self.force_evaluations, self.collisions)
+    }
+
+    pub fn bodies(&self) -> &[Body<S>] {
+        self.simulation.bodies()
+    }
+
+    /// the bodies to move by hand. call `settle` before `update_forces` after moving them
+    pub fn bodies_mut(&mut self) -> &mut [Body<S>] {
+        self.simulation.bodies_mut()
+    }
+
+    /// accelerations at the positions of the last `update_forces`, in the order of `bodies`
+    pub fn accelerations(&self) -> &[Point<S>] {
+        self.simulation.current_accelerations()
+    }
+
+    /// jerks, the time derivatives of the accelerations, at the positions and velocities of the last
+    /// `update_forces`. worked out on the first call after it, as they cost about another force evaluation
+    pub fn jerks(&mut self) -> &[Point<S>] {
+        self.simulation.current_jerks()
+    }
+
+    /// the accelerations `dt` on from the last `update_forces`, predicted to first order from the accelerations
+    /// and jerks there, a + j dt, e.g. as the starting guess of a corrector. the error grows as dt², so the
+    /// closer forces come out of the next `update_forces` on a smooth orbit, the less there is to correct
+    pub fn predicted_accelerations(&mut self, dt: f64) -> Vec<Point<S>> {
+        let step = S::from_f64(dt);
+        let jerks = self.simulation.current_jerks().to_vec();
+        let accelerations = self.simulation.current_accelerations();
+        accelerations
+            .iter()
+            .zip(&jerks)
+            .map(|(&acceleration, &jerk)| acceleration + jerk * step)
+            .collect()
+    }
+
+    /// the collisions `settle` has found so far this step
+    pub fn collisions(&self) -> &[Collision] {
+        &self.collisions
+    }
+
+    /// adds `dt` times the accelerations to the velocities
+    pub fn kick(&mut self, dt: f64) {
+        self.simulation.kick(dt);
+    }
+
+    /// adds `dt` times the velocities to the positions
+    pub fn drift(&mut self, dt: f64) {
+        self.simulation.drift(dt);
+    }
+
+    /// deals with bodies that left the root box and with close pairs, per the config. returns which bodies
+    /// were kept when some were removed or merged away; the accelerations and jerks are filtered to match
+    pub fn settle(&mut self) -> Option<Vec<bool>> {
+        let (collisions, kept) = self.simulation.settle();
+        self.collisions.extend(collisions);
+        kept
+    }
+
+    /// brings the tree up to date and computes the accelerations at the current positions
+    pub fn update_forces(&mut self) {
+        self.force_evaluations += self.simulation.update_forces();
+    }
+
+    /// brings the tree up to date with the current positions, keeping the accelerations. a step that moves
+    /// the bodies after its last `update_forces` has to end with this, so the tree holds them where they are
+    pub fn refresh_tree(&mut self) {
+        self.simulation.refresh_tree();
+    }
+}
+
+/// how far a run retraced its steps, from `reversal_error`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReversalError {
+    /// the largest distance of a body from where it started
+    pub location: f64,
+    /// the largest difference between a body's velocity and its starting one
+    pub velocity: f64,
+}
+
+/// runs `simulation` forward `steps` steps of `dt` with `integrator`, then as many of -dt back, and measures
+/// how far the bodies ended up from where they started. a time-symmetric scheme like leapfrog comes back to
+/// rounding, so an error well above that points at an asymmetry in the forces or the boundary handling. only
+/// meaningful with a fixed timestep and without drag, removals or merges, which are not undone by going back;
+/// panics if bodies were lost along the way
+pub fn reversal_error<S: Scalar, F: ForceModel>(
+    simulation: &mut Simulation<S, F>,
+    integrator: &impl Integrator,
+    dt: f64,
+    steps: u64,
+) -> ReversalError {
+    let start: Vec<Body<S>> = simulation.bodies().to_vec();
+    for _ in 0..steps {
+        simulation.step_with(dt, integrator);
+    }
+    for _ in 0..steps {
+        simulation.step_with(-dt, integrator);
+    }
+    assert_eq!(simulation.len(), start.len(), "bodies were lost on the way");
+    let mut error = ReversalError::default();
+    for (body, start) in simulation.bodies().iter().zip(&start) {
+        error.location = error.location.max(body.location.distance_squared(&start.location).sqrt().as_f64());
+        error.velocity = error.velocity.max((body.velocity - start.velocity).length().as_f64());
+    }
+    error
+}
+
+// jerk on every one of `targets` from the bodies of `tree` with G = 1: the time derivative of their softened
+// pull, with accepted nodes standing in as a point mass moving with their center of mass. nodes are accepted
+// as in the single walk
+pub(crate) fn jerks<S: Scalar, T: NodePairs<S> + Sync>(
+    tree: &T,
+    targets: &[Body<S>],
+    acceptance: Acceptance<S>,
+    softening: S,
+    period: Option<&Period<S>>,
+) -> Vec<Point<S>> {
+    let velocities = velocities(tree);
+    let jerk = |target: &Body<S>| {
+        let mut jerk = Point::default();
+        if tree.len() == 0 {
+            return jerk;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let mass = tree.mass(node);
+            if mass.is_zero() {
+                continue;
+            }
+            if tree.is_leaf(node) {
+                for (_, body) in tree.leaf(node) {
+                    let source = image_of(period, &body.location, &target.location);
+                    jerk += pair_jerk(target, &source, &body.velocity, body.mass, softening);
+                }
+                continue;
+            }
+            let center = image_of(period, tree.center_of_mass(node), &target.location);
+            let distance_squared = center.distance_squared(&target.location);
+            let bounds = tree.bounding_box(node);
+            if acceptance.accepts(bounds.size(), tree.radius(node), tree.absolute_mass(node), distance_squared)
+                && within_half_period(period, bounds, &target.location)
+            {
+                jerk += pair_jerk(target, &center, &velocities[node], mass, softening);
+            } else {
+                stack.extend(tree.children(node));
+            }
+        }
+        jerk
+    };
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        targets.par_iter().map(jerk).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        targets.iter().map(jerk).collect()
+    }
+}
+
+// the mass-weighted mean velocity beneath every node, filled in bottom up
+fn velocities<S: Scalar, T: NodePairs<S>>(tree: &T) -> Vec<Point<S>> {
+    let mut order = Vec::with_capacity(tree.node_count());
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        order.push(node);
+        stack.extend(tree.children(node));
+    }
+    let mut velocities = vec![Point::default(); tree.node_count()];
+    for &node in order.iter().rev() {
+        let mass = tree.mass(node);
+        if mass.is_zero() {
+            continue;
+        }
+        let mut momentum = Point::default();
+        for (_, body) in tree.leaf(node) {
+            momentum += body.velocity * body.mass;
+        }
+        for child in tree.children(node) {
+            momentum += velocities[child] * tree.mass(child);
+        }
+        velocities[node] = momentum / mass;
+    }
+    velocities
+}
+
+// d/dt of m r / (r² + ε²)^(3/2), with r the offset to a source moving at `velocity`; zero at `target` itself
+fn pair_jerk<S: Scalar>(
+    target: &Body<S>,
+    source: &Point<S>,
+    velocity: &Point<S>,
+    mass: S,
+    softening: S,
+) -> Point<S> {
+    let offset = *source - target.location;
+    let distance_squared = offset.dot(&offset);
+    if distance_squared.is_zero() {
+        return Point::default();
+    }
+    let relative = *velocity - target.velocity;
+    let softened = distance_squared + softening * softening;
+    let inverse_cube = mass / (softened * softened.sqrt());
+    let radial = S::from_f64(3.) * offset.dot(&relative) / softened;
+    (relative - offset * radial) * inverse_cube
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenarios::{Scenario, ScenarioCheck};
+
+    // explicit euler: a drift and a kick both from the state at the start of the step, which is first order
+    // and does not retrace its steps
+    struct Euler;
+
+    impl Integrator for Euler {
+        fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64) {
+            stage.drift(dt);
+            stage.kick(dt);
+            stage.settle();
+            stage.update_forces();
+        }
+    }
+
+    // leapfrog steps, noting for each how far the predicted accelerations were from those computed at its end
+    // and how far those moved from the ones at its start, each the largest over the bodies
+    #[derive(Default)]
+    struct Predicting {
+        corrections: std::cell::RefCell<Vec<(f64, f64)>>,
+    }
+
+    impl Integrator for Predicting {
+        fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64) {
+            let start = stage.accelerations().to_vec();
+            let predicted = stage.predicted_accelerations(dt);
+            Leapfrog.advance(stage, dt);
+            let largest = |from: &[Point<S>]| {
+                stage
+                    .accelerations()
+                    .iter()
+                    .zip(from)
+                    .map(|(a, b)| a.distance_squared(b).sqrt().as_f64())
+                    .fold(0., f64::max)
+            };
+            self.corrections
+                .borrow_mut()
+                .push((largest(&predicted), largest(&start)));
+        }
+    }
+
+    #[test]
+    fn predictions_on_a_smooth_orbit_leave_little_to_correct() {
+        let scenario = Scenario::FigureEight;
+        // the largest correction over the same stretch of orbit in steps of dt, and the largest of its share of
+        // the change over a step
+        let run = |dt: f64, steps: usize| {
+            let mut simulation = scenario.simulation();
+            let predicting = Predicting::default();
+            for _ in 0..steps {
+                simulation.step_with(dt, &predicting);
+            }
+            let corrections = predicting.corrections.into_inner();
+            let largest = corrections.iter().map(|c| c.0).fold(0., f64::max);
+            let share = corrections.iter().map(|c| c.0 / c.1).fold(0., f64::max);
+            (largest, share)
+        };
+        let (coarse, share) = run(scenario.dt(), 200);
+        let (fine, finer_share) = run(scenario.dt() / 2., 400);
+        assert!(share < 0.1 && finer_share < 0.6 * share, "{} then {}", share, finer_share);
+        assert!(coarse / fine > 3. && coarse / fine < 5., "{} then {}", coarse, fine);
+    }
+
+    #[test]
+    fn leapfrog_retraces_its_steps() {
+        let scenario = Scenario::FigureEight;
+        let error = reversal_error(&mut scenario.simulation(), &Leapfrog, scenario.dt(), 500);
+        assert!(error.location < 1e-10 && error.velocity < 1e-10, "{:?}", error);
+    }
+
+    #[test]
+    fn yoshida_retraces_its_steps() {
+        let scenario = Scenario::TwoBody;
+        let error = reversal_error(&mut scenario.simulation(), &Yoshida4, scenario.dt(), 500);
+        assert!(error.location < 1e-10 && error.velocity < 1e-10, "{:?}", error);
+    }
+
+    #[test]
+    fn euler_does_not() {
+        let scenario = Scenario::FigureEight;
+        let error = reversal_error(&mut scenario.simulation(), &Euler, scenario.dt(), 500);
+        assert!(error.location > 1e-4, "{:?}", error);
+    }
+
+    #[test]
+    fn the_fourth_order_schemes_keep_a_kepler_orbit_far_better_than_leapfrog() {
+        let scenario = Scenario::TwoBody;
+        // the largest energy drift and the period error over the run, in steps `coarsening` times the scenario's
+        let run = |scheme: Scheme, coarsening: u64| {
+            let mut simulation = scenario.simulation();
+            let mut check = ScenarioCheck::new(scenario, &simulation);
+            let dt = coarsening as f64 * scenario.dt();
+            for _ in 0..scenario.steps() / coarsening {
+                simulation.step_with(dt, &scheme);
+                check.record(&simulation);
+            }
+            let report = check.report();
+            let (expected, measured) = report.periods[0];
+            (report.max_energy_drift, ((measured.expect("the orbit comes round") - expected) / expected).abs())
+        };
+        let (leapfrog, finer) = (run(Scheme::Leapfrog, 4), run(Scheme::Leapfrog, 2));
+        assert!(leapfrog.1 / finer.1 > 3. && leapfrog.1 / finer.1 < 5., "{:?} then {:?}", leapfrog, finer);
+        for scheme in [Scheme::Yoshida4, Scheme::Hermite] {
+            let (coarse, fine) = (run(scheme, 4), run(scheme, 2));
+            assert!(
+                coarse.0 < leapfrog.0 / 10. && coarse.1 < leapfrog.1 / 10.,
+                "{:?}: {:?} against {:?}",
+                scheme,
+                coarse,
+                leapfrog
+            );
+            // halving the step cuts the period error about sixteenfold
+            assert!(coarse.1 / fine.1 > 10. && coarse.1 / fine.1 < 25., "{:?}: {:?} then {:?}", scheme, coarse, fine);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! the near-field kernel: the direct sum over the bodies of the leaves a tree walk opens. the walk gathers them
+//! into batches of separate coordinate and mass arrays, which the `simd` feature sums several bodies at a time
+//! with the `wide` crate, four per lane group in f64 and eight in f32. the result differs from the scalar sum
+//! only by rounding
+
+use crate::force::ForceModel;
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+
+// sources gathered before the kernel is called
+const BATCH: usize = 64;
+
+/// source bodies as one slice per coordinate plus their masses, all the same length
+#[derive(Debug, Clone, Copy)]
+pub struct Sources<'a, S = f64> {
+    pub x: &'a [S],
+    pub y: &'a [S],
+    pub z: &'a [S],
+    pub mass: &'a [S],
+}
+
+impl<S> Sources<'_, S> {
+    pub fn len(&self) -> usize {
+        self.mass.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mass.is_empty()
+    }
+}
+
+/// plummer-softened acceleration at `target` from every source, one body at a time. a source exactly at
+/// `target` is skipped, as in `Octree::acceleration_at`
+pub fn scalar<S: Scalar>(target: &Point<S>, sources: &Sources<S>, softening: S) -> Point<S> {
+    let softening_squared = softening * softening;
+    let mut acceleration = Point::default();
+    for i in 0..sources.len() {
+        let offset = Point {
+            x: sources.x[i] - target.x,
+            y: sources.y[i] - target.y,
+            z: sources.z[i] - target.z,
+        };
+        let distance_squared = offset.dot(&offset);
+        if distance_squared.is_zero() {
+            continue;
+        }
+        let softened = distance_squared + softening_squared;
+        acceleration += offset * (sources.mass[i] / (softened * softened.sqrt()));
+    }
+    acceleration
+}
+
+// one vectorized kernel per lane type; whatever is left over past the last full group goes to `scalar`
+#[cfg(feature = "simd")]
+macro_rules! simd_kernel {
+    ($(#[$doc:meta])* $name:ident, $scalar:ty, $lanes:ty, $width:expr) => {
+        $(#[$doc])*
+        pub fn $name(target: &Point<$scalar>, sources: &Sources<$scalar>, softening: $scalar) -> Point<$scalar> {
+            let (tx, ty, tz) = (<$lanes>::splat(target.x), <$lanes>::splat(target.y), <$lanes>::splat(target.z));
+            let softening_squared = <$lanes>::splat(softening * softening);
+            let zero = <$lanes>::ZERO;
+            let (mut ax, mut ay, mut az) = (zero, zero, zero);
+            let full = sources.len() - sources.len() % $width;
+            for i in (0..full).step_by($width) {
+                let lanes = |values: &[$scalar]| <$lanes>::from(&values[i..i + $width]);
+                let dx = lanes(sources.x) - tx;
+                let dy = lanes(sources.y) - ty;
+                let dz = lanes(sources.z) - tz;
+                let distance_squared = dx.mul_add(dx, dy.mul_add(dy, dz * dz));
+                let softened = distance_squared + softening_squared;
+                let factor = lanes(sources.mass) / (softened * softened.sqrt());
+                // picked rather than multiplied away, since the factor is infinite there without softening
+                let factor = distance_squared.simd_eq(zero).select(zero, factor);
+                ax = dx.mul_add(factor, ax);
+                ay = dy.mul_add(factor, ay);
+                az = dz.mul_add(factor, az);
+            }
+            let rest = Sources {
+                x: &sources.x[full..],
+                y: &sources.y[full..],
+                z: &sources.z[full..],
+                mass: &sources.mass[full..],
+            };
+            Point {
+                x: ax.reduce_add(),
+                y: ay.reduce_add(),
+                z: az.reduce_add(),
+            } + scalar(target, &rest, softening)
+        }
+    };
+}
+
+#[cfg(feature = "simd")]
+simd_kernel!(
+    /// `scalar` four sources at a time
+    simd_f64, f64, wide::f64x4, 4
+);
+#[cfg(feature = "simd")]
+simd_kernel!(
+    /// `scalar` eight sources at a time
+    simd_f32, f32, wide::f32x8, 8
+);
+
+// leaf bodies queued for the model's `direct_sum` during a walk, together with the sum of the batches already
+// done
+pub(crate) struct NearField<'a, S: Scalar, F> {
+    model: &'a F,
+    target: Point<S>,
+    softening: S,
+    x: [S; BATCH],
+    y: [S; BATCH],
+    z: [S; BATCH],
+    mass: [S; BATCH],
+    len: usize,
+    acceleration: Point<S>,
+}
+
+impl<'a, S: Scalar, F: ForceModel> NearField<'a, S, F> {
+    pub(crate) fn new(model: &'a F, target: Point<S>, softening: S) -> Self {
+        NearField {
+            model,
+            target,
+            softening,
+            x: [S::zero(); BATCH],
+            y: [S::zero(); BATCH],
+            z: [S::zero(); BATCH],
+            mass: [S::zero(); BATCH],
+            len: 0,
+            acceleration: Point::default(),
+        }
+    }
+
+    pub(crate) fn model(&self) -> &'a F {
+        self.model
+    }
+
+    pub(crate) fn push(&mut self, source: &Point<S>, mass: S) {
+        if self.len == BATCH {
+            self.flush();
+        }
+        self.x[self.len] = source.x;
+        self.y[self.len] = source.y;
+        self.z[self.len] = source.z;
+        self.mass[self.len] = mass;
+        self.len += 1;
+    }
+
+    fn flush(&mut self) {
+        let sources = Sources {
+            x: &self.x[..self.len],
+            y: &self.y[..self.len],
+            z: &self.z[..self.len],
+            mass: &self.mass[..self.len],
+        };
+        self.acceleration += self
+            .model
+            .direct_sum(&self.target, &sources, self.softening);
+        self.len = 0;
+    }
+
+    /// the pull of every source pushed
+    pub(crate) fn finish(mut self) -> Point<S> {
+        self.flush();
+        self.acceleration
+    }
+}
@@ -0,0 +1,84 @@
+//! barnes-hut n-body simulation over an octree. the binary in main.rs is a thin command line front end to this api
+
+pub mod body;
+pub mod checkpoint;
+pub mod collision;
+pub mod compare;
+pub mod diagnostics;
+pub mod drag;
+pub mod event;
+#[cfg(feature = "distributed")]
+pub mod distributed;
+mod dual;
+pub mod external;
+pub mod force;
+pub mod geometry;
+pub mod grid;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod ic;
+pub mod integrator;
+pub mod kernel;
+pub mod linear;
+pub mod load;
+pub mod ndtree;
+pub mod observer;
+pub mod regularization;
+pub mod run;
+pub mod scalar;
+pub mod scenarios;
+pub mod sim;
+pub mod snapshot;
+pub mod snapshot_file;
+pub mod spring;
+pub mod state;
+pub mod steps;
+pub mod stop;
+pub mod trajectory;
+#[cfg(feature = "interop")]
+pub mod tipsy;
+pub mod tree;
+pub mod units;
+#[cfg(feature = "viz")]
+pub mod viz;
+
+pub use body::{Body, Species};
+pub use checkpoint::CheckpointError;
+pub use collision::{Collision, CollisionPolicy};
+pub use compare::{BodyDifference, CompareError, DiffReport, StepDifference};
+pub use diagnostics::{
+    CompensatedSum, ConservationError, ConservedQuantities, Diagnostics, DriftMonitor, ForceError, PotentialMethod,
+};
+pub use drag::{Drag, DragLaw, WindField};
+pub use event::{EventKind, SimEvent};
+pub use external::{ExternalPotential, Harmonic, Kepler, Nfw, UniformField};
+pub use force::{ForceModel, Gravity};
+pub use geometry::{Axis, Cuboid, Point, Range};
+pub use grid::{Grid, GridQuantity};
+pub use integrator::{Hermite, Integrator, Leapfrog, ReversalError, Scheme, Stage, Yoshida4};
+#[cfg(feature = "gpu")]
+pub use gpu::{GpuError, GpuForces};
+pub use linear::{LinearNode, LinearOctree};
+pub use load::LoadError;
+pub use observer::{DriftGuard, StepObserver};
+pub use regularization::Regularization;
+pub use run::{Instability, InstabilityMonitor, RunReport};
+pub use scalar::{Precision, Scalar};
+pub use sim::{
+    BoundaryCondition, EscapePolicy, ForceConstants, MotionStats, RebuildStrategy, Recentering, Simulation,
+    SimulationConfig, StepReport, StepTiming, ThetaField, Timestep, Traversal, TreeBackend,
+};
+pub use snapshot::{OutputFilter, OutputFrame, SnapshotFormat, SnapshotLayout, SnapshotWriter};
+pub use snapshot_file::{SnapshotFile, SnapshotFileError, SnapshotStep};
+pub use spring::Spring;
+pub use state::{StateHandle, StateSnapshot};
+pub use steps::{IntoSteps, StepSnapshot, Steps};
+pub use stop::{StopCondition, StopEvent};
+#[cfg(feature = "interop")]
+pub use tipsy::Tipsy;
+pub use trajectory::{Interpolation, Trajectory};
+pub use tree::{
+    Aggregate, BodyTree, HasPosition, InsertError, Interactions, LongestAxis, MassMoments, MultipoleOrder, Octants,
+    Octree, OctreeNode, Opening, Subdivision, TraceInfo, TreeError, TreeStats, WalkStack,
+};
+pub use units::Units;
@@ -0,0 +1,546 @@
+//! a pointer-free octree over bodies sorted along a morton (z-order) curve. every node covers a contiguous
+//! run of the sorted bodies, so construction is a sort plus one pass and the nodes live in a single vec
+
+use crate::body::Body;
+use crate::dual;
+use crate::force::{ForceModel, Gravity};
+use crate::geometry::{Cuboid, Point};
+use crate::kernel::NearField;
+use crate::scalar::Scalar;
+use crate::tree::{
+    add_shifted, center_of, image_of, within_half_period, Acceptance, MultipoleOrder, Neighbor, Period,
+    TreeStats, WalkStack,
+};
+use std::collections::BinaryHeap;
+
+// bits per axis in a key; 3 * 21 fits a u64
+const LEVELS: u32 = 21;
+
+#[derive(Debug, Clone)]
+pub struct LinearNode<S = f64> {
+    pub(crate) bounding_box: Cuboid<S>,
+    // indices into LinearOctree::bodies
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    // the children are nodes[first_child..first_child + child_count]; none for a leaf
+    pub(crate) first_child: usize,
+    pub(crate) child_count: usize,
+    pub(crate) mass: S,
+    // see `MassMoments::absolute_mass`
+    pub(crate) absolute_mass: S,
+    pub(crate) center_of_mass: Point<S>,
+    // zero until LinearOctree::compute_quadrupoles
+    pub(crate) quadrupole: [S; 6],
+    // infinite until LinearOctree::compute_radii
+    pub(crate) radius: S,
+}
+
+impl<S: Scalar> LinearNode<S> {
+    pub fn bounding_box(&self) -> &Cuboid<S> {
+        &self.bounding_box
+    }
+
+    pub fn mass(&self) -> S {
+        self.mass
+    }
+
+    /// same as `OctreeNode::absolute_mass`
+    pub fn absolute_mass(&self) -> S {
+        self.absolute_mass
+    }
+
+    pub fn center_of_mass(&self) -> &Point<S> {
+        &self.center_of_mass
+    }
+
+    /// same as `OctreeNode::quadrupole`
+    pub fn quadrupole(&self) -> &[S; 6] {
+        &self.quadrupole
+    }
+
+    /// same as `OctreeNode::radius`
+    pub fn radius(&self) -> S {
+        self.radius
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.child_count == 0
+    }
+}
+
+/// the same subdivision as `Octree` with its default octants, down to 21 levels. a leaf holds up to a
+/// bucket of bodies; only at the deepest level can it hold more, so coincident bodies cannot recurse
+/// forever
+#[derive(Debug, Clone)]
+pub struct LinearOctree<S = f64> {
+    nodes: Vec<LinearNode<S>>,
+    // sorted by morton key
+    bodies: Vec<Body<S>>,
+    // the place each of `bodies` had in the input
+    order: Vec<usize>,
+    multipole: MultipoleOrder,
+}
+
+impl<S: Scalar> LinearOctree<S> {
+    /// sorts the bodies by morton key within `space` and builds the nodes and their mass distribution.
+    /// bodies outside `space` are filed under the nearest boundary cell
+    pub fn build(bodies: impl IntoIterator<Item = Body<S>>, space: Cuboid<S>) -> Self {
+        LinearOctree::build_bucketed(bodies, space, 1)
+    }
+
+    /// `build` with leaves of up to `bucket_size` bodies. panics if it is 0
+    pub fn build_bucketed(
+        bodies: impl IntoIterator<Item = Body<S>>,
+        space: Cuboid<S>,
+        bucket_size: usize,
+    ) -> Self {
+        assert!(bucket_size > 0, "a leaf must hold at least one body");
+        let mut keyed: Vec<(u64, usize, Body<S>)> = bodies
+            .into_iter()
+            .enumerate()
+            .map(|(i, body)| (morton_key(&space, &body.location), i, body))
+            .collect();
+        keyed.sort_unstable_by_key(|(key, _, _)| *key);
+        let keys: Vec<u64> = keyed.iter().map(|(key, _, _)| *key).collect();
+        let order: Vec<usize> = keyed.iter().map(|(_, i, _)| *i).collect();
+        let bodies: Vec<Body<S>> = keyed.into_iter().map(|(_, _, body)| body).collect();
+
+        let mut tree = LinearOctree {
+            nodes: Vec::with_capacity(2 * bodies.len() + 1),
+            bodies,
+            order,
+            multipole: MultipoleOrder::Monopole,
+        };
+        tree.nodes.push(LinearNode {
+            bounding_box: space,
+            start: 0,
+            end: keys.len(),
+            first_child: 0,
+            child_count: 0,
+            mass: S::zero(),
+            absolute_mass: S::zero(),
+            center_of_mass: space.center(),
+            quadrupole: [S::zero(); 6],
+            radius: S::infinity(),
+        });
+        tree.subdivide(0, &keys, 0, bucket_size);
+        tree
+    }
+
+    // splits nodes[index], whose bodies all share the key bits above `level`, then fills in its moments.
+    // returns its first moment, the sum of mass times location, which its parent's center is found from
+    fn subdivide(
+        &mut self,
+        index: usize,
+        keys: &[u64],
+        level: u32,
+        bucket_size: usize,
+    ) -> Point<S> {
+        let LinearNode {
+            bounding_box,
+            start,
+            end,
+            ..
+        } = self.nodes[index];
+        let (mut mass, mut absolute_mass) = (S::zero(), S::zero());
+        let mut weighted = Point::default();
+        if end - start > bucket_size && level < LEVELS {
+            let shift = 3 * (LEVELS - 1 - level);
+            let octants = bounding_box.split();
+            let first_child = self.nodes.len();
+            let mut lo = start;
+            while lo < end {
+                let octant = (keys[lo] >> shift) & 7;
+                let hi = lo + keys[lo..end].partition_point(|key| (key >> shift) & 7 == octant);
+                self.nodes.push(LinearNode {
+                    bounding_box: octants[octant as usize],
+                    start: lo,
+                    end: hi,
+                    first_child: 0,
+                    child_count: 0,
+                    mass: S::zero(),
+                    absolute_mass: S::zero(),
+                    center_of_mass: octants[octant as usize].center(),
+                    quadrupole: [S::zero(); 6],
+                    radius: S::infinity(),
+                });
+                lo = hi;
+            }
+            let child_count = self.nodes.len() - first_child;
+            self.nodes[index].first_child = first_child;
+            self.nodes[index].child_count = child_count;
+            for child in first_child..first_child + child_count {
+                weighted += self.subdivide(child, keys, level + 1, bucket_size);
+                mass += self.nodes[child].mass;
+                absolute_mass += self.nodes[child].absolute_mass;
+            }
+        } else {
+            for body in &self.bodies[start..end] {
+                mass += body.mass;
+                absolute_mass += body.mass.abs();
+                weighted += body.location * body.mass;
+            }
+        }
+        let node = &mut self.nodes[index];
+        node.mass = mass;
+        node.absolute_mass = absolute_mass;
+        node.center_of_mass = center_of(weighted, mass, &bounding_box);
+        weighted
+    }
+
+    /// same as `Octree::compute_quadrupoles`
+    pub fn compute_quadrupoles(&mut self) {
+        // children come after their parent, so walking backwards meets them first
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let center = node.center_of_mass;
+            let mut quadrupole = [S::zero(); 6];
+            if node.is_leaf() {
+                for body in &self.bodies[node.start..node.end] {
+                    add_shifted(
+                        &mut quadrupole,
+                        &[S::zero(); 6],
+                        body.mass,
+                        &(body.location - center),
+                    );
+                }
+            }
+            for child in &self.nodes[node.first_child..node.first_child + node.child_count] {
+                add_shifted(
+                    &mut quadrupole,
+                    &child.quadrupole,
+                    child.mass,
+                    &(child.center_of_mass - center),
+                );
+            }
+            self.nodes[index].quadrupole = quadrupole;
+        }
+        self.multipole = MultipoleOrder::Quadrupole;
+    }
+
+    /// same as `BodyTree::compute_radii`
+    pub fn compute_radii(&mut self) {
+        let radii = dual::radii(self);
+        for (node, radius) in self.nodes.iter_mut().zip(radii) {
+            node.radius = radius;
+        }
+    }
+
+    pub fn multipole(&self) -> MultipoleOrder {
+        self.multipole
+    }
+
+    pub fn len(&self) -> usize {
+        self.bodies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bodies.is_empty()
+    }
+
+    pub fn bounds(&self) -> &Cuboid<S> {
+        &self.nodes[0].bounding_box
+    }
+
+    /// the bodies in morton order, which is not the order they were given in
+    pub fn bodies(&self) -> &[Body<S>] {
+        &self.bodies
+    }
+
+    /// the index each of `bodies` had in the bodies the tree was built from
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn nodes(&self) -> &[LinearNode<S>] {
+        &self.nodes
+    }
+
+    /// same as `Octree::stats`
+    pub fn stats(&self) -> TreeStats {
+        let mut leaves = vec![];
+        let mut stack = vec![(0, 0)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.is_leaf() {
+                leaves.push((depth, node.end - node.start));
+            }
+            stack.extend(
+                (node.first_child..node.first_child + node.child_count)
+                    .map(|child| (child, depth + 1)),
+            );
+        }
+        let memory = self.nodes.capacity() * std::mem::size_of::<LinearNode<S>>()
+            + self.bodies.capacity() * std::mem::size_of::<Body<S>>()
+            + self.order.capacity() * std::mem::size_of::<usize>();
+        TreeStats::from_leaves(self.nodes.len(), leaves, memory)
+    }
+
+    /// same as `Octree::acceleration_at`
+    pub fn acceleration_at(&self, target: &Point<S>, theta: S, softening: S) -> Point<S> {
+        self.acceleration_with(&Gravity, target, Acceptance::theta(theta), softening, None)
+    }
+
+    /// same as `Octree::periodic_acceleration_at`
+    pub fn periodic_acceleration_at(&self, target: &Point<S>, theta: S, softening: S) -> Point<S> {
+        self.acceleration_with(
+            &Gravity,
+            target,
+            Acceptance::theta(theta),
+            softening,
+            Some(&Period::all(*self.bounds())),
+        )
+    }
+
+    // `acceleration_at` under any `model`; `period` is the periodic box, if any
+    pub(crate) fn acceleration_with<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+    ) -> Point<S> {
+        let mut stack = WalkStack::new();
+        self.acceleration_with_stack(model, target, acceptance, softening, period, &mut stack)
+    }
+
+    // `acceleration_with` keeping the nodes still to visit on `stack`, which is left empty for the next walk
+    pub(crate) fn acceleration_with_stack<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+        stack: &mut WalkStack<S>,
+    ) -> Point<S> {
+        let mut acceleration = Point::default();
+        let mut near = NearField::new(model, *target, softening);
+        let (stack, interactions) = (&mut stack.nodes, &mut stack.interactions);
+        stack.clear();
+        stack.push(0);
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.absolute_mass.is_zero() {
+                continue;
+            }
+            if node.is_leaf() {
+                for body in &self.bodies[node.start..node.end] {
+                    near.push(&image_of(period, &body.location, target), body.mass);
+                }
+                interactions.body_body += (node.end - node.start) as u64;
+                continue;
+            }
+            let center = image_of(period, &node.center_of_mass, target);
+            let distance_squared = center.distance_squared(target);
+            if acceptance.accepts(node.bounding_box.size(), node.radius, node.absolute_mass, distance_squared)
+                && within_half_period(period, &node.bounding_box, target)
+            {
+                interactions.body_cell += 1;
+                acceleration += model.node_acceleration(
+                    target,
+                    &center,
+                    node.mass,
+                    self.quadrupole_of(node),
+                    softening,
+                );
+            } else {
+                stack.extend(node.first_child..node.first_child + node.child_count);
+            }
+        }
+        acceleration + near.finish()
+    }
+
+    /// same as `Octree::potential_at`
+    pub fn potential_at(&self, target: &Point<S>, theta: S, softening: S) -> S {
+        self.potential_with(&Gravity, target, Acceptance::theta(theta), softening, None)
+    }
+
+    /// same as `Octree::periodic_potential_at`
+    pub fn periodic_potential_at(&self, target: &Point<S>, theta: S, softening: S) -> S {
+        self.potential_with(
+            &Gravity,
+            target,
+            Acceptance::theta(theta),
+            softening,
+            Some(&Period::all(*self.bounds())),
+        )
+    }
+
+    // `potential_at` under any `model`; `period` is the periodic box, if any
+    pub(crate) fn potential_with<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+    ) -> S {
+        let mut potential = S::zero();
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.absolute_mass.is_zero() {
+                continue;
+            }
+            if node.is_leaf() {
+                for body in &self.bodies[node.start..node.end] {
+                    potential += model.pair_potential(
+                        target,
+                        &image_of(period, &body.location, target),
+                        body.mass,
+                        softening,
+                    );
+                }
+                continue;
+            }
+            let center = image_of(period, &node.center_of_mass, target);
+            let distance_squared = center.distance_squared(target);
+            if acceptance.accepts(node.bounding_box.size(), node.radius, node.absolute_mass, distance_squared)
+                && within_half_period(period, &node.bounding_box, target)
+            {
+                potential += model.node_potential(
+                    target,
+                    &center,
+                    node.mass,
+                    self.quadrupole_of(node),
+                    softening,
+                );
+            } else {
+                stack.extend(node.first_child..node.first_child + node.child_count);
+            }
+        }
+        potential
+    }
+
+    // what the node terms get for `node`'s quadrupole
+    fn quadrupole_of<'a>(&self, node: &'a LinearNode<S>) -> Option<&'a [S; 6]> {
+        (self.multipole == MultipoleOrder::Quadrupole).then_some(&node.quadrupole)
+    }
+
+    /// same as `Octree::k_nearest`
+    pub fn k_nearest(&self, target: &Point<S>, k: usize) -> Vec<&Body<S>> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.collect_nearest(0, target, k, &mut heap);
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|neighbor| neighbor.item)
+            .collect()
+    }
+
+    fn collect_nearest<'a>(
+        &'a self,
+        index: usize,
+        target: &Point<S>,
+        k: usize,
+        heap: &mut BinaryHeap<Neighbor<'a, Body<S>>>,
+    ) {
+        let node = &self.nodes[index];
+        if heap.len() == k
+            && node.bounding_box.distance_squared_to(target) > heap.peek().unwrap().distance_squared
+        {
+            return;
+        }
+        if node.is_leaf() {
+            for body in &self.bodies[node.start..node.end] {
+                let distance_squared = body.location.distance_squared(target);
+                if heap.len() < k {
+                    heap.push(Neighbor {
+                        distance_squared,
+                        item: body,
+                    });
+                } else if distance_squared < heap.peek().unwrap().distance_squared {
+                    heap.pop();
+                    heap.push(Neighbor {
+                        distance_squared,
+                        item: body,
+                    });
+                }
+            }
+            return;
+        }
+        let mut children: Vec<usize> =
+            (node.first_child..node.first_child + node.child_count).collect();
+        children.sort_by(|&a, &b| {
+            self.nodes[a]
+                .bounding_box
+                .distance_squared_to(target)
+                .total_cmp(&self.nodes[b].bounding_box.distance_squared_to(target))
+        });
+        for child in children {
+            self.collect_nearest(child, target, k, heap);
+        }
+    }
+
+    /// same as `Octree::within_radius`
+    pub fn within_radius(&self, center: &Point<S>, radius: S) -> Vec<&Body<S>> {
+        let radius_squared = radius * radius;
+        self.collect(
+            |space| space.distance_squared_to(center) <= radius_squared,
+            |point| point.distance_squared(center) <= radius_squared,
+        )
+    }
+
+    /// same as `Octree::within_box`
+    pub fn within_box(&self, region: &Cuboid<S>) -> Vec<&Body<S>> {
+        self.collect(
+            |space| space.intersects(region),
+            |point| region.contains(point),
+        )
+    }
+
+    // the bodies `keep` picks from the leaves under every node whose box `reaches`
+    fn collect(
+        &self,
+        reaches: impl Fn(&Cuboid<S>) -> bool,
+        keep: impl Fn(&Point<S>) -> bool,
+    ) -> Vec<&Body<S>> {
+        let mut found = vec![];
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !reaches(&node.bounding_box) {
+                continue;
+            }
+            if node.is_leaf() {
+                found.extend(
+                    self.bodies[node.start..node.end]
+                        .iter()
+                        .filter(|body| keep(&body.location)),
+                );
+            } else {
+                stack.extend(node.first_child..node.first_child + node.child_count);
+            }
+        }
+        found
+    }
+}
+
+// interleaves the quantized coordinates as ...zyx so the low three bits of every triple match the octant
+// numbering of Cuboid::split
+pub(crate) fn morton_key<S: Scalar>(space: &Cuboid<S>, point: &Point<S>) -> u64 {
+    let cells = (1u64 << LEVELS) as f64;
+    let quantize = |value: S, start: S, end: S| {
+        let t = ((value - start) / (end - start)).as_f64();
+        // the cast saturates, so anything below the box and nan become cell 0
+        ((t * cells) as u64).min((1 << LEVELS) - 1)
+    };
+    let x = quantize(point.x, space.x.start, space.x.end);
+    let y = quantize(point.y, space.y.start, space.y.end);
+    let z = quantize(point.z, space.z.start, space.z.end);
+    spread(x) | (spread(y) << 1) | (spread(z) << 2)
+}
+
+// puts two zero bits between each of the low 21 bits
+fn spread(value: u64) -> u64 {
+    let mut v = value & 0x1f_ffff;
+    v = (v | (v << 32)) & 0x1f_0000_0000_ffff;
+    v = (v | (v << 16)) & 0x1f_0000_ff00_00ff;
+    v = (v | (v << 8)) & 0x100f_00f0_0f00_f00f;
+    v = (v | (v << 4)) & 0x10c3_0c30_c30c_30c3;
+    v = (v | (v << 2)) & 0x1249_2492_4924_9249;
+    v
+}
@@ -0,0 +1,252 @@
+//! reading initial conditions from disk
+
+use crate::body::{Body, Species};
+use crate::geometry::Point;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// a csv/tsv line that could not be turned into a body; lines count from 1
+    Row {
+        line: usize,
+        message: String,
+    },
+    /// the file is not valid json
+    Json(serde_json::Error),
+    /// a json body that is missing a field or holds the wrong kind of value; bodies count from 0
+    Body {
+        index: usize,
+        message: String,
+    },
+    /// no file matches the pattern given to `Trajectory::load_series`
+    NoMatch(String),
+    /// a binary file, like a tipsy one, that is cut short or does not hold what its header says; `offset`
+    /// is in bytes from the start
+    Binary {
+        offset: usize,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Row { line, message } => write!(f, "line {}: {}", line, message),
+            LoadError::Json(err) => write!(f, "invalid json: {}", err),
+            LoadError::Body { index, message } => write!(f, "body {}: {}", index, message),
+            LoadError::NoMatch(pattern) => write!(f, "no file matches {}", pattern),
+            LoadError::Binary { offset, message } => write!(f, "byte {}: {}", offset, message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            LoadError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+/// reads bodies from `path`, picking the format from the extension: `.json` is json, `.tsv` is tab
+/// separated, `.std` and `.tipsy` are tipsy with the `interop` feature, and anything else comma separated
+pub fn read_bodies(path: impl AsRef<Path>) -> Result<Vec<Body>, LoadError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str());
+    #[cfg(feature = "interop")]
+    if let Some("std" | "tipsy") = extension {
+        return Ok(crate::tipsy::Tipsy::read(path)?.bodies);
+    }
+    let text = std::fs::read_to_string(path)?;
+    match extension {
+        Some("json") => parse_json(&text),
+        Some("tsv") => parse_delimited(&text, '\t'),
+        _ => parse_delimited(&text, ','),
+    }
+}
+
+/// one body per line as mass,x,y,z[,vx,vy,vz]; missing velocities are zero. blank lines and lines starting
+/// with `#` are skipped. an optional header line names the columns instead, in any order and with extra
+/// columns ignored, so a csv written per snapshot by the snapshot writer loads too. a header naming an `id`
+/// column, or the snapshot writer's `body`, gives the bodies their ids, and one naming a `species` column makes
+/// the bodies marked `tracer` in it tracers
+pub fn parse_delimited(text: &str, delimiter: char) -> Result<Vec<Body>, LoadError> {
+    const COLUMNS: [&str; 7] = ["mass", "x", "y", "z", "vx", "vy", "vz"];
+    // which field holds each of COLUMNS, if any
+    let mut layout: Option<[Option<usize>; 7]> = None;
+    let mut ids: Option<usize> = None;
+    let mut species: Option<usize> = None;
+    let mut bodies = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let row = raw.trim();
+        if row.is_empty() || row.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = row.split(delimiter).map(str::trim).collect();
+        let width = fields.len();
+
+        // the first data line is a header if it is not numeric
+        if layout.is_none() && bodies.is_empty() && fields[0].parse::<f64>().is_err() {
+            let mut columns = [None; 7];
+            for (c, name) in COLUMNS.iter().enumerate() {
+                columns[c] = fields
+                    .iter()
+                    .position(|field| field.eq_ignore_ascii_case(name));
+            }
+            if let Some(c) = (0..4).find(|&c| columns[c].is_none()) {
+                return Err(LoadError::Row {
+                    line,
+                    message: format!("header has no `{}` column", COLUMNS[c]),
+                });
+            }
+            layout = Some(columns);
+            ids = fields.iter().position(|field| {
+                field.eq_ignore_ascii_case("id") || field.eq_ignore_ascii_case("body")
+            });
+            species = fields
+                .iter()
+                .position(|field| field.eq_ignore_ascii_case("species"));
+            continue;
+        }
+        let columns = match layout {
+            Some(columns) => columns,
+            None if width == 4 || width == 7 => std::array::from_fn(|c| (c < width).then_some(c)),
+            None => {
+                return Err(LoadError::Row {
+                    line,
+                    message: format!(
+                        "expected 4 or 7 fields (mass, x, y, z[, vx, vy, vz]), found {}",
+                        width
+                    ),
+                })
+            }
+        };
+
+        let mut values = [0f64; 7];
+        for (c, column) in columns.iter().enumerate() {
+            let Some(index) = *column else { continue };
+            let Some(field) = fields.get(index) else {
+                return Err(LoadError::Row {
+                    line,
+                    message: format!("missing `{}` field", COLUMNS[c]),
+                });
+            };
+            values[c] = field.parse().map_err(|_| LoadError::Row {
+                line,
+                message: format!("`{}` is not a number ({} column)", field, COLUMNS[c]),
+            })?;
+        }
+        let [mass, x, y, z, vx, vy, vz] = values;
+        let id = match ids.and_then(|index| fields.get(index)) {
+            Some(field) => field.parse().map_err(|_| LoadError::Row {
+                line,
+                message: format!("`{}` is not an id", field),
+            })?,
+            None => 0,
+        };
+        let body = Body {
+            id,
+            mass,
+            location: Point { x, y, z },
+            velocity: Point {
+                x: vx,
+                y: vy,
+                z: vz,
+            },
+            species: match species.and_then(|index| fields.get(index)) {
+                Some(field) if field.eq_ignore_ascii_case("tracer") => Species::Tracer,
+                _ => Species::Live,
+            },
+        };
+        if !body.is_finite() {
+            return Err(LoadError::Row {
+                line,
+                message: "mass, position or velocity is not finite".to_string(),
+            });
+        }
+        bodies.push(body);
+    }
+    Ok(bodies)
+}
+
+/// either an array of bodies or an object with a `bodies` array, as written by the snapshot writer. each
+/// body is `{"mass": m, "location": [x, y, z], "velocity": [vx, vy, vz]}`, with velocity optional, and an
+/// `id` and a `"tracer": true` kept if there
+pub fn parse_json(text: &str) -> Result<Vec<Body>, LoadError> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(LoadError::Json)?;
+    let entries = match value.get("bodies").unwrap_or(&value).as_array() {
+        Some(entries) => entries,
+        None => {
+            return Err(LoadError::Body {
+                index: 0,
+                message: "expected an array of bodies or an object with a `bodies` array"
+                    .to_string(),
+            })
+        }
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let fail = |message: String| LoadError::Body { index, message };
+            let mass = entry
+                .get("mass")
+                .and_then(|m| m.as_f64())
+                .ok_or_else(|| fail("missing or non-numeric `mass`".to_string()))?;
+            let vector = |name: &str| -> Result<Option<Point>, LoadError> {
+                let Some(field) = entry.get(name) else {
+                    return Ok(None);
+                };
+                match field
+                    .as_array()
+                    .map(|a| a.iter().map(|v| v.as_f64()).collect::<Vec<_>>())
+                {
+                    Some(v) if v.len() == 3 && v.iter().all(|c| c.is_some()) => Ok(Some(Point {
+                        x: v[0].unwrap(),
+                        y: v[1].unwrap(),
+                        z: v[2].unwrap(),
+                    })),
+                    _ => Err(fail(format!(
+                        "`{}` must be an array of three numbers",
+                        name
+                    ))),
+                }
+            };
+            let location =
+                vector("location")?.ok_or_else(|| fail("missing `location`".to_string()))?;
+            let velocity = vector("velocity")?.unwrap_or_default();
+            let id = match entry.get("id") {
+                Some(id) => id
+                    .as_u64()
+                    .ok_or_else(|| fail("`id` must be a whole number".to_string()))?,
+                None => 0,
+            };
+            let species = match entry.get("tracer").and_then(|tracer| tracer.as_bool()) {
+                Some(true) => Species::Tracer,
+                _ => Species::Live,
+            };
+            let body = Body {
+                id,
+                mass,
+                location,
+                velocity,
+                species,
+            };
+            if !body.is_finite() {
+                return Err(fail("mass, position or velocity is not finite".to_string()));
+            }
+            Ok(body)
+        })
+        .collect()
+}
@@ -1,203 +1,1512 @@
-use rand::prelude::Distribution;
-use rand::distributions::Standard;
-use rand::Rng;
+use barneshutt3d::scenarios::{Scenario, ScenarioCheck};
+use barneshutt3d::{
+    ic, Body, BoundaryCondition, CollisionPolicy, Cuboid, Drag, DragLaw, DriftMonitor,
+    EscapePolicy, GridQuantity, Kepler, MultipoleOrder, Opening, OutputFilter, OutputFrame, Point,
+    PotentialMethod, Range, RebuildStrategy, Recentering, Regularization, Scalar, Scheme,
+    Simulation, SimulationConfig, SnapshotFormat, SnapshotLayout, SnapshotWriter, Species,
+    StopCondition, Timestep, Traversal, TreeBackend, Units,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+#[cfg(feature = "indicatif")]
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-#[derive(Debug, Clone, Copy, Default)]
-struct Range<T> {
-    start: T,
-    end: T,
+#[derive(Parser)]
+#[command(version, about = "barnes-hut n-body simulation")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 }
 
-#[derive(Debug)]
-struct Point {
-    x: f64,
-    y: f64,
-    z: f64,
+#[derive(Subcommand)]
+enum Command {
+    /// evolve a system and optionally write snapshots. RUST_LOG=info logs the wall time of every step to
+    /// stderr, and RUST_LOG=debug also times tree builds, force passes and the integration within it
+    Run(Box<RunArgs>),
+    /// evolve a system live in a window. takes the flags of run but writes no snapshots or checkpoints, and
+    /// keeps going until the window is closed instead of stopping after --steps
+    #[cfg(feature = "viz")]
+    View(Box<RunArgs>),
+    /// match the bodies of two snapshot series by id and write how far apart they are at every step both
+    /// have, as csv: the rms and largest position differences and the rms velocity difference
+    Compare(CompareArgs),
 }
 
-#[derive(Debug)]
-struct Body {
-    mass: f32,
-    location: Point,
+#[derive(clap::Args)]
+struct CompareArgs {
+    /// the output directory of the first run, of csv, json or binary snapshots, or a binary snapshot file
+    a: PathBuf,
+    /// the same for the second run
+    b: PathBuf,
+    /// write the csv here instead of to stdout
+    #[arg(long)]
+    out: Option<PathBuf>,
 }
 
-#[derive(Default, Copy, Clone, Debug)]
-struct Cuboid {
-    // since range is an iterator and thus lazy, this does not use much memory
-    x: Range<f64>,
-    y: Range<f64>,
-    z: Range<f64>,
+/// every physics, integration and output setting can also come from a --config file, with flags given here
+/// overriding it
+#[derive(clap::Args)]
+struct RunArgs {
+    /// read the run from a toml file laid out like the `run.toml` written next to the snapshots. relative
+    /// paths in it are taken from the working directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// number of random bodies, ignored with --input [default: 1000]
+    #[arg(long)]
+    bodies: Option<usize>,
+    /// massless tracers scattered in the same cube after the random bodies, which feel their pull without
+    /// pulling back; ignored with --input [default: 0]
+    #[arg(long)]
+    tracers: Option<usize>,
+    /// read initial conditions from a csv, tsv or json file instead
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// run one of the checkable systems instead and report how far its periods and energy end up from the
+    /// expected ones, failing past the tolerances. sets the steps, dt, units and exact unsoftened forces
+    /// the tolerances are for, unless given here
+    #[arg(long, value_enum, conflicts_with_all = ["input", "resume"])]
+    scenario: Option<ScenarioName>,
+    /// continue from a checkpoint, keeping its bodies, clock and config; the physics flags are ignored
+    #[arg(long, conflicts_with = "input")]
+    resume: Option<PathBuf>,
+    /// seed for the random bodies; a fresh one is picked and printed without it
+    #[arg(long)]
+    seed: Option<u64>,
+    /// edge length of the cube random bodies are scattered in [default: 1024]
+    #[arg(long)]
+    size: Option<f64>,
+    /// start in the center-of-mass frame, moving the bodies so they are centered on the origin and at rest
+    /// as a whole; ignored with --resume
+    #[arg(long)]
+    com_frame: bool,
+    /// [default: 100]
+    #[arg(long)]
+    steps: Option<u64>,
+    /// step length, or the longest allowed step with --eta [default: 0.01]
+    #[arg(long)]
+    dt: Option<f64>,
+    /// how bodies are moved between force evaluations: leapfrog is second order with one force evaluation a
+    /// step, yoshida4 fourth order with three, hermite fourth order with one plus the jerks; block steps always
+    /// take leapfrog [default: leapfrog]
+    #[arg(long, value_enum)]
+    integrator: Option<Method>,
+    /// pick each step as eta * sqrt(softening / max acceleration) instead of always taking --dt
+    #[arg(long)]
+    eta: Option<f64>,
+    /// with --eta, split each --dt into block steps of --dt / 2^k per body, for k up to this, instead of
+    /// shortening the whole step
+    #[arg(long)]
+    levels: Option<u32>,
+    /// shortest step the adaptive timestep may take [default: 0]
+    #[arg(long)]
+    min_dt: Option<f64>,
+    /// move back into the center-of-mass frame every this many steps, so the tree's momentum errors do not
+    /// add up to a drift
+    #[arg(long, value_name = "STEPS")]
+    recenter_every: Option<u64>,
+    /// move bound pairs closer than this, each the other's nearest neighbor, along their unsoftened kepler
+    /// orbits, with the tree's forces only for the pull of the rest; not with --levels or --periodic
+    #[arg(long, value_name = "RADIUS")]
+    regularize: Option<f64>,
+    /// barnes-hut opening angle [default: 0.5]
+    #[arg(long)]
+    theta: Option<f64>,
+    /// evaluate accepted tree nodes to quadrupole order
+    #[arg(long)]
+    quadrupole: bool,
+    /// hold theta against the bounding sphere of a node's bodies rather than its box
+    #[arg(long)]
+    bounding_sphere: bool,
+    /// accept a tree node when its mass times its size squared over its distance cubed is below this, in
+    /// place of theta
+    #[arg(long, value_name = "TOL", conflicts_with = "bounding_sphere")]
+    multipole_tolerance: Option<f64>,
+    /// plummer softening length [default: 0]
+    #[arg(long)]
+    softening: Option<f64>,
+    /// truncate the force law at this distance and sum each body's neighbors within it in place of a tree
+    /// walk; no longer than half the box along a periodic axis
+    #[arg(long, value_name = "RADIUS")]
+    cutoff: Option<f64>,
+    /// units the bodies, --dt and --size are in, which fix the gravitational constant [default: dimensionless]
+    #[arg(long, value_enum)]
+    units: Option<UnitPreset>,
+    /// use this gravitational constant instead of the one of a --units preset
+    #[arg(long, value_name = "G", conflicts_with = "units")]
+    gravitational_constant: Option<f64>,
+    /// hold a point mass of this mass fixed at the origin, softened like the bodies
+    #[arg(long, value_name = "MASS")]
+    central_mass: Option<f64>,
+    /// the bodies do not pull on each other, only the --central-mass, springs and drag move them
+    #[arg(long)]
+    no_self_gravity: bool,
+    /// drag the bodies towards the velocity of a background gas, solved exactly each half step
+    #[arg(long, value_enum)]
+    drag: Option<DragKind>,
+    /// the --drag rate of a linear law, or coefficient of a quadratic one [default: 1]
+    #[arg(long)]
+    drag_coefficient: Option<f64>,
+    /// the velocity of the gas --drag pulls towards [default: at rest]
+    #[arg(long, value_delimiter = ',', value_name = "VX,VY,VZ")]
+    drag_wind: Option<Vec<f64>>,
+    /// which bodies feel the --drag [default: all]
+    #[arg(long, value_enum)]
+    drag_species: Option<DragSpecies>,
+    /// tree the forces are computed on [default: pointer]
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+    /// walk the tree in pairs of nodes, sharing far-field terms between neighbouring bodies
+    #[arg(long)]
+    dual_tree: bool,
+    /// sum every pair exactly, each pair's force applied once to both, conserving angular momentum to rounding
+    #[arg(long, conflicts_with = "dual_tree")]
+    pairwise: bool,
+    /// wrap bodies and forces around the faces of the box; --escape is not used
+    #[arg(long)]
+    periodic: bool,
+    /// wrap around only these axes, e.g. x,y for a slab, leaving the others open; bodies leaving through an
+    /// open face need --escape clamp or remove
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "periodic")]
+    periodic_axes: Option<Vec<PeriodicAxis>>,
+    /// what to do with bodies that leave the root box [default: expand]
+    #[arg(long, value_enum)]
+    escape: Option<Escape>,
+    /// what to do with pairs closer than --collision-radius [default: ignore]
+    #[arg(long, value_enum)]
+    collisions: Option<Collisions>,
+    /// [default: 0]
+    #[arg(long)]
+    collision_radius: Option<f64>,
+    /// float width bodies are stored and forces computed in; a checkpoint resumes only in its own
+    /// [default: double]
+    #[arg(long, value_enum)]
+    precision: Option<Precision>,
+    /// move only the bodies that left their leaf instead of rebuilding the tree every step, unless more than
+    /// this fraction of them did; pointer backend only
+    #[arg(long, value_name = "MAX_MOVED")]
+    incremental: Option<f64>,
+    /// refresh the tree only every K steps, refitting it to the moved bodies in between; faster, but the
+    /// forces get less accurate the more the bodies move in between. pointer backend only [default: 1]
+    #[arg(long, value_name = "K")]
+    rebuild_every: Option<usize>,
+    /// sort the bodies by Morton key every step, so bodies near in space are near in memory; the tree is then
+    /// rebuilt every step, whatever --incremental and --rebuild-every say
+    #[arg(long)]
+    morton_order: bool,
+    /// bodies per leaf before it splits [default: 1]
+    #[arg(long)]
+    bucket_size: Option<usize>,
+    /// make bodies lighter than this tracers, which feel the others but pull on none [default: 0]
+    #[arg(long, value_name = "MASS")]
+    tracer_mass: Option<f64>,
+    /// stop once a body is farther than this from the center of mass
+    #[arg(long, value_name = "RADIUS")]
+    stop_escape: Option<f64>,
+    /// stop once two bodies are closer than this
+    #[arg(long, value_name = "DISTANCE")]
+    stop_approach: Option<f64>,
+    /// stop once the total energy is off from the start by more than this fraction of it
+    #[arg(long, value_name = "TOLERANCE")]
+    stop_energy_drift: Option<f64>,
+    /// with more than one --stop-* condition, stop only when all of them hold after the same step instead
+    /// of any one
+    #[arg(long)]
+    stop_all: bool,
+    /// directory for snapshots and the resolved `run.toml`; nothing is written without it
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// write a snapshot every this many steps, and one of the final state [default: 1]
+    #[arg(long)]
+    every: Option<u64>,
+    /// binary writes every snapshot to one `snapshots.bin` for random access, the others a file each
+    /// [default: csv]
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// write only the bodies inside this box
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "XMIN,YMIN,ZMIN,XMAX,YMAX,ZMAX"
+    )]
+    region: Option<Vec<f64>>,
+    /// write only the bodies within R of this point
+    #[arg(long, value_delimiter = ',', value_name = "X,Y,Z,R")]
+    sphere: Option<Vec<f64>>,
+    /// write only every this many-th body, by id
+    #[arg(long, value_name = "K")]
+    every_nth_body: Option<u64>,
+    /// write only about this fraction of the bodies, the same random ones every snapshot
+    #[arg(long, value_name = "FRACTION")]
+    sample: Option<f64>,
+    /// picks which bodies --sample writes [default: 0]
+    #[arg(long)]
+    sample_seed: Option<u64>,
+    /// write only the live bodies or only the tracers
+    #[arg(long, value_enum)]
+    output_species: Option<OutputSpecies>,
+    /// write positions relative to the center of mass at each snapshot, leaving the run where it is, unlike
+    /// --com-frame
+    #[arg(long)]
+    center_output: bool,
+    /// save the state here every --checkpoint-every steps and at the end; json if it ends in .json, binary
+    /// otherwise
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// [default: 1000]
+    #[arg(long)]
+    checkpoint_every: Option<u64>,
+    /// write the boxes of the final tree here for a look at its subdivision: a json list of cells if it
+    /// ends in .json, obj line segments otherwise; pointer backend only
+    #[arg(long)]
+    wireframe: Option<PathBuf>,
+    /// write the --wireframe boxes only down to this depth [default: all of them]
+    #[arg(long)]
+    wireframe_depth: Option<usize>,
+    /// sample --grid-quantity on a regular grid over the box at the end of the run and write it here as a
+    /// vtk structured grid
+    #[arg(long)]
+    grid: Option<PathBuf>,
+    /// cells along each side of the --grid [default: 64]
+    #[arg(long)]
+    grid_resolution: Option<usize>,
+    /// [default: density-cic]
+    #[arg(long, value_enum)]
+    grid_quantity: Option<GridField>,
+    /// log energy and momentum drift to stderr every this many steps; 0 turns it off [default: 0]
+    #[arg(long)]
+    log_every: Option<u64>,
+    /// print the shape of the tree to stderr after every build
+    #[arg(long)]
+    tree_stats: bool,
+    /// print the fastest body's speed, the closest pair's separation and the time it takes to cross it to
+    /// stderr after every step, to see what an adaptive timestep is shrinking for
+    #[arg(long)]
+    motion_stats: bool,
+    /// show a progress bar with the step rate and the time left on stderr. needs the indicatif feature, which
+    /// is on by default
+    #[arg(long)]
+    progress: bool,
+    /// spread the run over this many processes, each started with the same flags and its own --rank. rank 0
+    /// sets up the bodies and writes the snapshots and the summary. forces are monopole gravity in an open box
+    /// with fixed leapfrog steps, and checkpoints, scenarios and stop conditions are not supported
+    #[cfg(feature = "distributed")]
+    #[arg(long, requires = "cluster")]
+    ranks: Option<usize>,
+    /// this process' place among --ranks, from 0 [default: 0]
+    #[cfg(feature = "distributed")]
+    #[arg(long, requires = "ranks")]
+    rank: Option<usize>,
+    /// host:port rank 0 listens on and the other ranks connect to
+    #[cfg(feature = "distributed")]
+    #[arg(long, requires = "ranks")]
+    cluster: Option<String>,
 }
 
-impl<T> Range<T> {
-    fn midpoint(&self) -> T
-    where
-        T: std::ops::Add<Output = T> + std::ops::Div<Output = T> + From<u8> + Copy,
-    {
-        (self.start + self.end) / T::from(2u8)
+/// a whole run as a --config file holds it. anything left out takes the default of its flag
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RunConfig {
+    seed: Option<u64>,
+    precision: Precision,
+    initial: InitialConfig,
+    integrator: IntegratorConfig,
+    forces: ForceConfig,
+    boundary: BoundaryConfig,
+    stop: StopConfig,
+    output: OutputConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct InitialConfig {
+    bodies: usize,
+    tracers: usize,
+    size: f64,
+    com_frame: bool,
+    input: Option<PathBuf>,
+    scenario: Option<ScenarioName>,
+    resume: Option<PathBuf>,
+}
+
+impl Default for InitialConfig {
+    fn default() -> Self {
+        InitialConfig {
+            bodies: 1000,
+            tracers: 0,
+            size: 1024.,
+            com_frame: false,
+            input: None,
+            scenario: None,
+            resume: None,
+        }
     }
 }
 
-impl Cuboid {
-    fn split(&self) -> [Cuboid; 8] {
-        let x_mid = self.x.midpoint();
-        let y_mid = self.y.midpoint();
-        let z_mid = self.z.midpoint();
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IntegratorConfig {
+    steps: u64,
+    dt: f64,
+    method: Method,
+    eta: Option<f64>,
+    levels: Option<u32>,
+    min_dt: f64,
+    recenter_every: Option<u64>,
+    regularize: Option<f64>,
+}
 
-        let mut octants = [Cuboid::default(); 8];
+impl Default for IntegratorConfig {
+    fn default() -> Self {
+        IntegratorConfig {
+            steps: 100,
+            dt: 0.01,
+            method: Method::Leapfrog,
+            eta: None,
+            levels: None,
+            min_dt: 0.,
+            recenter_every: None,
+            regularize: None,
+        }
+    }
+}
 
-        for (i, (x_sign, y_sign, z_sign)) in [
-            (0., 0., 0.),
-            (1., 0., 0.),
-            (0., 1., 0.),
-            (1., 1., 0.),
-            (0., 0., 1.),
-            (1., 0., 1.),
-            (0., 1., 1.),
-            (1., 1., 1.),
-        ]
-        .iter()
-        .enumerate()
-        {
-            octants[i] = Cuboid {
-                x: Range {
-                    start: self.x.start + x_sign * (x_mid - self.x.start),
-                    end: self.x.start + (x_sign + 1.) as f64 * (x_mid - self.x.start),
-                },
-                y: Range {
-                    start: self.y.start + y_sign * (y_mid - self.y.start),
-                    end: self.y.start + (y_sign + 1.) as f64 * (y_mid - self.y.start),
-                },
-                z: Range {
-                    start: self.z.start + z_sign * (z_mid - self.z.start),
-                    end: self.z.start + (z_sign + 1.) as f64 * (z_mid - self.z.start),
-                },
-            };
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ForceConfig {
+    theta: f64,
+    softening: f64,
+    cutoff: Option<f64>,
+    quadrupole: bool,
+    bounding_sphere: bool,
+    multipole_tolerance: Option<f64>,
+    backend: Backend,
+    dual_tree: bool,
+    pairwise: bool,
+    bucket_size: usize,
+    tracer_mass: f64,
+    incremental: Option<f64>,
+    rebuild_every: usize,
+    morton_order: bool,
+    units: UnitPreset,
+    gravitational_constant: Option<f64>,
+    central_mass: Option<f64>,
+    self_gravity: bool,
+    drag: Option<DragKind>,
+    drag_coefficient: f64,
+    drag_wind: Option<[f64; 3]>,
+    drag_species: DragSpecies,
+}
+
+impl Default for ForceConfig {
+    fn default() -> Self {
+        ForceConfig {
+            theta: 0.5,
+            softening: 0.,
+            cutoff: None,
+            quadrupole: false,
+            bounding_sphere: false,
+            multipole_tolerance: None,
+            backend: Backend::Pointer,
+            dual_tree: false,
+            pairwise: false,
+            bucket_size: 1,
+            tracer_mass: 0.,
+            incremental: None,
+            rebuild_every: 1,
+            morton_order: false,
+            units: UnitPreset::Dimensionless,
+            gravitational_constant: None,
+            central_mass: None,
+            self_gravity: true,
+            drag: None,
+            drag_coefficient: 1.,
+            drag_wind: None,
+            drag_species: DragSpecies::All,
         }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BoundaryConfig {
+    periodic: bool,
+    periodic_axes: Vec<PeriodicAxis>,
+    escape: Escape,
+    collisions: Collisions,
+    collision_radius: f64,
+}
 
-        octants
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StopConfig {
+    escape: Option<f64>,
+    approach: Option<f64>,
+    energy_drift: Option<f64>,
+    all: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutputConfig {
+    out: Option<PathBuf>,
+    every: u64,
+    format: Format,
+    region: Option<[f64; 6]>,
+    sphere: Option<[f64; 4]>,
+    every_nth_body: Option<u64>,
+    sample: Option<f64>,
+    sample_seed: u64,
+    output_species: Option<OutputSpecies>,
+    center_output: bool,
+    checkpoint: Option<PathBuf>,
+    checkpoint_every: u64,
+    wireframe: Option<PathBuf>,
+    wireframe_depth: Option<usize>,
+    grid: Option<PathBuf>,
+    grid_resolution: usize,
+    grid_quantity: GridField,
+    log_every: u64,
+    tree_stats: bool,
+    motion_stats: bool,
+    progress: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            out: None,
+            every: 1,
+            format: Format::Csv,
+            region: None,
+            sphere: None,
+            every_nth_body: None,
+            sample: None,
+            sample_seed: 0,
+            output_species: None,
+            center_output: false,
+            checkpoint: None,
+            checkpoint_every: 1000,
+            wireframe: None,
+            wireframe_depth: None,
+            grid: None,
+            grid_resolution: 64,
+            grid_quantity: GridField::DensityCic,
+            log_every: 0,
+            tree_stats: false,
+            motion_stats: false,
+            progress: false,
+        }
     }
-   fn octant_contains_point(&self, point: &Point) -> Option<usize> {
-        let x_mid = self.x.midpoint();
-        let y_mid = self.y.midpoint();
-        let z_mid = self.z.midpoint();
+}
 
-        let x_octant = if point.x < x_mid { 0 } else { 1 };
-        let y_octant = if point.y < y_mid { 0 } else { 1 };
-        let z_octant = if point.z < z_mid { 0 } else { 1 };
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ScenarioName {
+    /// two bodies on circular orbits
+    TwoBody,
+    /// the figure-eight choreography of three equal masses
+    Figure8,
+    /// the sun, earth and moon
+    SunEarthMoon,
+}
 
-        match (x_octant, y_octant, z_octant) {
-            (0, 0, 0) => Some(0),
-            (1, 0, 0) => Some(1),
-            (0, 1, 0) => Some(2),
-            (1, 1, 0) => Some(3),
-            (0, 0, 1) => Some(4),
-            (1, 0, 1) => Some(5),
-            (0, 1, 1) => Some(6),
-            (1, 1, 1) => Some(7),
-            _ => None,
+impl ScenarioName {
+    fn scenario(self) -> Scenario {
+        match self {
+            ScenarioName::TwoBody => Scenario::TwoBody,
+            ScenarioName::Figure8 => Scenario::FigureEight,
+            ScenarioName::SunEarthMoon => Scenario::SunEarthMoon,
         }
     }
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Method {
+    Leapfrog,
+    Yoshida4,
+    Hermite,
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum DragKind {
+    Linear,
+    Quadratic,
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputSpecies {
+    Live,
+    Tracers,
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum DragSpecies {
+    All,
+    Live,
+    Tracers,
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Backend {
+    Pointer,
+    Linear,
+    /// the linear tree with forces on the gpu, needs the gpu feature
+    Gpu,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum PeriodicAxis {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Escape {
+    #[default]
+    Expand,
+    Clamp,
+    Remove,
+}
 
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Collisions {
+    #[default]
+    Ignore,
+    Merge,
+    Bounce,
 }
 
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum UnitPreset {
+    Dimensionless,
+    Si,
+    Astronomical,
+    SolarSystem,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Precision {
+    Single,
+    #[default]
+    Double,
+}
 
-#[derive(Debug)]
-struct OctreeNode {
-    children: [Box<Option<OctreeNode>>; 8],
-    body: Option<Body>,
-    bounding_box: Cuboid,
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Format {
+    Csv,
+    Json,
+    Vtk,
+    Xyz,
+    Binary,
 }
 
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum GridField {
+    DensityNgp,
+    DensityCic,
+    Potential,
+}
 
-impl OctreeNode {
-    fn new(space: Cuboid) -> Self {
-        OctreeNode {
-            children: std::array::from_fn(|_| Box::new(None)),
-            body: None,
-            bounding_box: space
+fn main() {
+    let cli = Cli::parse();
+    // the drift lines of --log-every are info events, so they show unless RUST_LOG says otherwise
+    let filter = match std::env::var_os(EnvFilter::DEFAULT_ENV) {
+        Some(_) => EnvFilter::builder()
+            .with_default_directive(LevelFilter::WARN.into())
+            .from_env_lossy(),
+        None => EnvFilter::new("warn,barneshutt3d::diagnostics=info"),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+    let result = match cli.command {
+        #[cfg(feature = "distributed")]
+        Command::Run(args) if args.ranks.is_some() => {
+            let (ranks, rank) = (args.ranks.unwrap(), args.rank.unwrap_or(0));
+            let address = args.cluster.clone().unwrap();
+            resolve(*args).and_then(|config| run_distributed(config, &address, rank, ranks))
         }
+        Command::Run(args) => resolve(*args).and_then(|config| match config.precision {
+            Precision::Single => run::<f32>(config),
+            Precision::Double => run::<f64>(config),
+        }),
+        #[cfg(feature = "viz")]
+        Command::View(args) => resolve(*args).and_then(|config| match config.precision {
+            Precision::Single => view::<f32>(config),
+            Precision::Double => view::<f64>(config),
+        }),
+        Command::Compare(args) => compare(args),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
     }
-    fn insert(&mut self, body: Body) {
-        if !self.body.is_some() {
-            self.body = Some(body);
-            return;
+}
+
+fn compare(args: CompareArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let differences = barneshutt3d::compare::compare_series(&args.a, &args.b)?;
+    if differences.is_empty() {
+        return Err("the two series have no steps in common".into());
+    }
+    match &args.out {
+        Some(path) => barneshutt3d::compare::write_csv(&differences, std::fs::File::create(path)?)?,
+        None => barneshutt3d::compare::write_csv(&differences, std::io::stdout().lock())?,
+    }
+    let worst = differences
+        .iter()
+        .max_by(|a, b| a.max_position.total_cmp(&b.max_position))
+        .expect("a step in common");
+    eprintln!(
+        "{} steps compared, largest divergence {} at step {}{}",
+        differences.len(),
+        worst.max_position,
+        worst.step,
+        worst
+            .max_body
+            .map(|id| format!(" (body {})", id))
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+// the --config file, or the defaults without one, with the flags that were given put over it. a random run
+// gets its seed picked here, so the resolved config repeats it exactly
+fn resolve(args: RunArgs) -> Result<RunConfig, Box<dyn std::error::Error>> {
+    let mut config: RunConfig = match &args.config {
+        Some(path) => toml::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|err| format!("{}: {}", path.display(), err))?,
+        None => RunConfig::default(),
+    };
+    fn put<T>(slot: &mut T, value: Option<T>) {
+        if let Some(value) = value {
+            *slot = value;
         }
-        let cuboids = self.bounding_box.split();
-        // get which octant the point is in
-        for cube in cuboids.iter() {
-        	if let Some(cuboid_idx) = cube.octant_contains_point(&body.location) {
-        	    let octant = cuboids[cuboid_idx];
-        	    if !self.children[cuboid_idx].is_some() {
-                    // if the octant does not exist, create it
-                    self.children[cuboid_idx] = Box::new(Some(octant.into()));
-                }
-                self.children[cuboid_idx].as_mut().as_mut().unwrap().insert(body);
-                return;
-            }
+    }
+    fn put_some<T>(slot: &mut Option<T>, value: Option<T>) {
+        if value.is_some() {
+            *slot = value;
         }
     }
+    // the scenario's own settings go under the flags, so they can still be changed
+    if let Some(name) = args.scenario {
+        let scenario = name.scenario();
+        config.initial.scenario = Some(name);
+        config.integrator.steps = scenario.steps();
+        config.integrator.dt = scenario.dt();
+        config.forces.theta = scenario.config().theta;
+        config.forces.softening = scenario.config().softening;
+        config.forces.units = match scenario.units() {
+            Units::SolarSystem => UnitPreset::SolarSystem,
+            _ => UnitPreset::Dimensionless,
+        };
+    }
+    put_some(&mut config.seed, args.seed);
+    put(&mut config.precision, args.precision);
+
+    let initial = &mut config.initial;
+    put(&mut initial.bodies, args.bodies);
+    put(&mut initial.tracers, args.tracers);
+    put(&mut initial.size, args.size);
+    initial.com_frame |= args.com_frame;
+    put_some(&mut initial.input, args.input);
+    put_some(&mut initial.resume, args.resume);
+
+    let integrator = &mut config.integrator;
+    put(&mut integrator.steps, args.steps);
+    put(&mut integrator.dt, args.dt);
+    put(&mut integrator.method, args.integrator);
+    put_some(&mut integrator.eta, args.eta);
+    put_some(&mut integrator.levels, args.levels);
+    put(&mut integrator.min_dt, args.min_dt);
+    put_some(&mut integrator.recenter_every, args.recenter_every);
+    put_some(&mut integrator.regularize, args.regularize);
+
+    let forces = &mut config.forces;
+    put(&mut forces.theta, args.theta);
+    put(&mut forces.softening, args.softening);
+    put_some(&mut forces.cutoff, args.cutoff);
+    forces.quadrupole |= args.quadrupole;
+    forces.bounding_sphere |= args.bounding_sphere;
+    put_some(&mut forces.multipole_tolerance, args.multipole_tolerance);
+    put(&mut forces.backend, args.backend);
+    forces.dual_tree |= args.dual_tree;
+    forces.pairwise |= args.pairwise;
+    put(&mut forces.bucket_size, args.bucket_size);
+    put(&mut forces.tracer_mass, args.tracer_mass);
+    put_some(&mut forces.incremental, args.incremental);
+    put(&mut forces.rebuild_every, args.rebuild_every);
+    forces.morton_order |= args.morton_order;
+    // a preset and a constant on the command line replace whichever of the two the file had
+    if args.units.is_some() {
+        forces.gravitational_constant = None;
+    }
+    put(&mut forces.units, args.units);
+    put_some(
+        &mut forces.gravitational_constant,
+        args.gravitational_constant,
+    );
+    put_some(&mut forces.central_mass, args.central_mass);
+    forces.self_gravity &= !args.no_self_gravity;
+    put_some(&mut forces.drag, args.drag);
+    put(&mut forces.drag_coefficient, args.drag_coefficient);
+    if let Some(wind) = args.drag_wind {
+        let wind = wind
+            .try_into()
+            .map_err(|_| "--drag-wind takes three numbers")?;
+        forces.drag_wind = Some(wind);
+    }
+    put(&mut forces.drag_species, args.drag_species);
+
+    let boundary = &mut config.boundary;
+    boundary.periodic |= args.periodic;
+    put(&mut boundary.periodic_axes, args.periodic_axes);
+    put(&mut boundary.escape, args.escape);
+    put(&mut boundary.collisions, args.collisions);
+    put(&mut boundary.collision_radius, args.collision_radius);
+
+    let stop = &mut config.stop;
+    put_some(&mut stop.escape, args.stop_escape);
+    put_some(&mut stop.approach, args.stop_approach);
+    put_some(&mut stop.energy_drift, args.stop_energy_drift);
+    stop.all |= args.stop_all;
+
+    let output = &mut config.output;
+    put_some(&mut output.out, args.out);
+    put(&mut output.every, args.every);
+    put(&mut output.format, args.format);
+    if let Some(region) = args.region {
+        let region = region
+            .try_into()
+            .map_err(|_| "--region takes six numbers")?;
+        output.region = Some(region);
+    }
+    if let Some(sphere) = args.sphere {
+        let sphere = sphere
+            .try_into()
+            .map_err(|_| "--sphere takes four numbers")?;
+        output.sphere = Some(sphere);
+    }
+    put_some(&mut output.every_nth_body, args.every_nth_body);
+    put_some(&mut output.sample, args.sample);
+    put(&mut output.sample_seed, args.sample_seed);
+    put_some(&mut output.output_species, args.output_species);
+    output.center_output |= args.center_output;
+    put_some(&mut output.checkpoint, args.checkpoint);
+    put(&mut output.checkpoint_every, args.checkpoint_every);
+    put_some(&mut output.wireframe, args.wireframe);
+    put_some(&mut output.wireframe_depth, args.wireframe_depth);
+    put_some(&mut output.grid, args.grid);
+    put(&mut output.grid_resolution, args.grid_resolution);
+    put(&mut output.grid_quantity, args.grid_quantity);
+    put(&mut output.log_every, args.log_every);
+    output.tree_stats |= args.tree_stats;
+    output.motion_stats |= args.motion_stats;
+    output.progress |= args.progress;
+
+    if config.initial.input.is_some() && config.initial.resume.is_some() {
+        return Err("--input and --resume cannot both be given".into());
+    }
+    if config.initial.scenario.is_some()
+        && (config.initial.input.is_some() || config.initial.resume.is_some())
+    {
+        return Err("--scenario cannot be given with --input or --resume".into());
+    }
+    if config.integrator.levels.is_some() && config.integrator.eta.is_none() {
+        return Err("--levels needs --eta".into());
+    }
+    if config.seed.is_none()
+        && config.initial.input.is_none()
+        && config.initial.resume.is_none()
+        && config.initial.scenario.is_none()
+    {
+        let seed = rand::random();
+        eprintln!("seed {}", seed);
+        config.seed = Some(seed);
+    }
+    Ok(config)
 }
 
-impl From<Cuboid> for OctreeNode {
-    fn from(value: Cuboid) -> Self {
-        return OctreeNode {
-            body: None,
-            children: std::array::from_fn(|_| Box::new(None)),
-            bounding_box: value
+fn run<S: Scalar>(config: RunConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let output = &config.output;
+    check_output(output)?;
+    if output.checkpoint_every == 0 {
+        return Err("--checkpoint-every must be at least 1".into());
+    }
+    let stop = &config.stop;
+    for (flag, value) in [
+        ("--stop-escape", stop.escape),
+        ("--stop-approach", stop.approach),
+        ("--stop-energy-drift", stop.energy_drift),
+    ] {
+        if value.is_some_and(|value| !(value.is_finite() && value > 0.)) {
+            return Err(format!("{} must be a positive number", flag).into());
         }
     }
-}
-impl Distribution<Body> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Body {
-        Body {
-            mass: rng.gen(),
-            location: rng.gen()
+    let mut simulation = simulation::<S>(&config)?;
+    let conditions = [
+        stop.escape.map(|radius| StopCondition::Escape { radius }),
+        stop.approach
+            .map(|distance| StopCondition::Approach { distance }),
+        stop.energy_drift
+            .map(|tolerance| StopCondition::EnergyDrift { tolerance }),
+    ];
+    let condition =
+        conditions
+            .into_iter()
+            .flatten()
+            .reduce(|a, b| if stop.all { a.and(b) } else { a.or(b) });
+    if let Some(condition) = condition {
+        simulation.set_stop_condition(condition);
+    }
+    if output.wireframe.is_some() && simulation.tree().is_none() {
+        return Err("--wireframe needs the pointer backend".into());
+    }
+    if output.grid.is_some() && output.grid_resolution == 0 {
+        return Err("--grid-resolution must be positive".into());
+    }
+    let mut check = config
+        .initial
+        .scenario
+        .map(|name| ScenarioCheck::new(name.scenario(), &simulation));
+    let mut writer = snapshot_writer(&config)?;
+
+    let (steps, dt) = (config.integrator.steps, config.integrator.dt);
+    let monitor = DriftMonitor::new(simulation.diagnostics(PotentialMethod::Tree));
+    if let Some(writer) = &mut writer {
+        writer.record(&simulation)?;
+    }
+    let progress = progress_bar(output.progress, steps)?;
+    if output.tree_stats {
+        eprintln!("step {}: {}", simulation.steps(), simulation.tree_stats());
+    }
+    if output.motion_stats {
+        eprintln!("step {}: {}", simulation.steps(), simulation.motion_stats());
+    }
+    let mut collisions = 0;
+    let mut taken = 0;
+    let mut stopped = None;
+    let instant = std::time::Instant::now();
+    while taken < steps && stopped.is_none() {
+        let report = simulation.step(dt);
+        collisions += report.collisions.len();
+        stopped = report.stop;
+        taken += 1;
+        progress.inc(1);
+        if let Some(check) = &mut check {
+            check.record(&simulation);
+        }
+        if output.tree_stats {
+            progress
+                .suspend(|| eprintln!("step {}: {}", simulation.steps(), simulation.tree_stats()));
+        }
+        if output.motion_stats {
+            progress.suspend(|| {
+                eprintln!("step {}: {}", simulation.steps(), simulation.motion_stats())
+            });
+        }
+        if let Some(writer) = &mut writer {
+            writer.record(&simulation)?;
+        }
+        if output.log_every > 0 && simulation.steps().is_multiple_of(output.log_every) {
+            // the bar is cleared around the line so it stays below the log
+            progress.suspend(|| {
+                monitor.log(
+                    simulation.steps(),
+                    &simulation.diagnostics(PotentialMethod::Tree),
+                )
+            });
+        }
+        if let Some(path) = &output.checkpoint {
+            if simulation.steps().is_multiple_of(output.checkpoint_every) {
+                simulation.checkpoint(path)?;
+            }
+        }
+    }
+    if let Some(writer) = &mut writer {
+        writer.finish(&simulation)?;
+    }
+    if let Some(path) = &output.checkpoint {
+        simulation.checkpoint(path)?;
+    }
+    if let (Some(path), Some(tree)) = (&output.wireframe, simulation.tree()) {
+        tree.export_wireframe(path, output.wireframe_depth.unwrap_or(usize::MAX))?;
+    }
+    if let Some(path) = &output.grid {
+        let quantity = match output.grid_quantity {
+            GridField::DensityNgp => GridQuantity::DensityNgp,
+            GridField::DensityCic => GridQuantity::DensityCic,
+            GridField::Potential => GridQuantity::Potential,
+        };
+        simulation
+            .sample_grid(output.grid_resolution, quantity)
+            .write_vtk(path)?;
+    }
+    progress.finish_and_clear();
+    let elapsed = instant.elapsed();
+    println!(
+        "{} bodies, {} steps to t = {} in {:?}, {} collisions, relative energy change {:e}",
+        simulation.len(),
+        taken,
+        simulation.time(),
+        elapsed,
+        collisions,
+        monitor.energy_drift(&simulation.diagnostics(PotentialMethod::Tree))
+    );
+    if let Some(event) = stopped {
+        println!("stopped after step {}: {}", simulation.steps(), event);
+    }
+    if let Some(check) = check {
+        let report = check.report();
+        println!("{}", report);
+        let failures = report.failures(&check.scenario().tolerances());
+        if !failures.is_empty() {
+            return Err(failures.join("; ").into());
         }
     }
+    Ok(())
 }
 
-impl Distribution<Point> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Point {
-        Point { x: rng.gen(), y: rng.gen(), z: rng.gen() }
+fn check_output(output: &OutputConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if output.every == 0 {
+        return Err("--every must be at least 1".into());
+    }
+    if output.every_nth_body == Some(0) {
+        return Err("--every-nth-body must be at least 1".into());
+    }
+    if output
+        .sample
+        .is_some_and(|fraction| !(0. ..=1.).contains(&fraction))
+    {
+        return Err("--sample must be between 0 and 1".into());
     }
+    Ok(())
 }
 
-struct Simulation {
-    tree: OctreeNode
+// the writer for the snapshots under --out, if there is one, after writing the resolved config there
+fn snapshot_writer(
+    config: &RunConfig,
+) -> Result<Option<SnapshotWriter>, Box<dyn std::error::Error>> {
+    let output = &config.output;
+    let Some(out) = &output.out else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(out)?;
+    std::fs::write(out.join("run.toml"), toml::to_string_pretty(config)?)?;
+    let format = match output.format {
+        Format::Csv => SnapshotFormat::Csv,
+        Format::Json => SnapshotFormat::Json,
+        Format::Vtk => SnapshotFormat::Vtk,
+        Format::Xyz => SnapshotFormat::Xyz,
+        Format::Binary => SnapshotFormat::Binary,
+    };
+    let mut writer = match format {
+        SnapshotFormat::Binary => SnapshotWriter::new(
+            out.join("snapshots.bin"),
+            format,
+            SnapshotLayout::SingleFile,
+            output.every,
+        ),
+        _ => SnapshotWriter::new(out, format, SnapshotLayout::FilePerSnapshot, output.every),
+    };
+    if let Some([x0, y0, z0, x1, y1, z1]) = output.region {
+        writer = writer.with_filter(OutputFilter::Region(Cuboid {
+            x: Range { start: x0, end: x1 },
+            y: Range { start: y0, end: y1 },
+            z: Range { start: z0, end: z1 },
+        }));
+    }
+    if let Some([x, y, z, radius]) = output.sphere {
+        writer = writer.with_filter(OutputFilter::Sphere {
+            center: Point { x, y, z },
+            radius,
+        });
+    }
+    if let Some(k) = output.every_nth_body {
+        writer = writer.with_filter(OutputFilter::EveryNth(k));
+    }
+    if let Some(fraction) = output.sample {
+        writer = writer.with_filter(OutputFilter::Sample {
+            fraction,
+            seed: output.sample_seed,
+        });
+    }
+    if let Some(species) = output.output_species {
+        writer = writer.with_filter(OutputFilter::Species(match species {
+            OutputSpecies::Live => Species::Live,
+            OutputSpecies::Tracers => Species::Tracer,
+        }));
+    }
+    if output.center_output {
+        writer = writer.with_frame(OutputFrame::CenterOfMass);
+    }
+    Ok(Some(writer))
 }
 
-impl Simulation {
-    fn new(bodies: Vec<Body>, space: Cuboid) -> Self {
-        let mut root = OctreeNode::new(space);
-        for body in bodies {
-            root.insert(body.into());
+// a run spread over `ranks` processes, as the one at `rank`. rank 0 builds the bodies before joining, so a
+// bad config stops it before the others are kept waiting, and is the only one to write anything; the others
+// follow its snapshot and log cadence from their own flags, so they need the same ones
+#[cfg(feature = "distributed")]
+fn run_distributed(
+    config: RunConfig,
+    address: &str,
+    rank: usize,
+    ranks: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use barneshutt3d::distributed::{Cluster, DistributedSimulation};
+    let (initial, output, stop) = (&config.initial, &config.output, &config.stop);
+    if rank >= ranks {
+        return Err("--rank must be below --ranks".into());
+    }
+    if matches!(config.precision, Precision::Single) {
+        return Err("distributed runs are in double precision".into());
+    }
+    if initial.resume.is_some() || output.checkpoint.is_some() {
+        return Err("distributed runs cannot be checkpointed or resumed".into());
+    }
+    if initial.scenario.is_some() || output.wireframe.is_some() || output.grid.is_some() {
+        return Err("--scenario, --wireframe and --grid need a run in one process".into());
+    }
+    if stop.escape.is_some() || stop.approach.is_some() || stop.energy_drift.is_some() {
+        return Err("distributed runs take no stop conditions".into());
+    }
+    if config.forces.drag.is_some() {
+        return Err("distributed runs take no drag".into());
+    }
+    check_output(output)?;
+    let (bodies, space, simulation_config) = if rank == 0 {
+        let simulation = simulation::<f64>(&config)?;
+        let used = simulation.config();
+        if used.integrator != Scheme::Leapfrog
+            || used.boundary != [BoundaryCondition::Open; 3]
+            || used.escape != EscapePolicy::Expand
+            || used.collisions != CollisionPolicy::Ignore
+            || used.timestep != Timestep::Fixed
+            || used.multipole != MultipoleOrder::Monopole
+            || used.opening != Opening::Box
+            || used.traversal == Traversal::Pairwise
+            || used.recentering != Recentering::Off
+            || used.rebuild_every != 1
+            || used.morton_order
+            || used.regularization != Regularization::Off
+            || !used.self_gravity
+            || used.cutoff.is_some()
+            || !simulation.potentials().is_empty()
+        {
+            return Err("distributed runs take monopole gravity in an open box with fixed leapfrog steps: \
+                        no --periodic, --periodic-axes, --escape, --collisions, --eta, --integrator, \
+                        --recenter-every, --quadrupole, --bounding-sphere, --multipole-tolerance, --pairwise, \
+                        --rebuild-every, --morton-order, --regularize, --no-self-gravity, --cutoff or \
+                        --central-mass"
+                .into());
+        }
+        (
+            simulation.bodies().to_vec(),
+            *simulation.bounds(),
+            *simulation.config(),
+        )
+    } else {
+        (Vec::new(), Cuboid::default(), SimulationConfig::default())
+    };
+    let mut writer = if rank == 0 {
+        snapshot_writer(&config)?
+    } else {
+        None
+    };
+    let mut cluster = Cluster::connect(address, rank, ranks)?;
+    // every rank has to step and gather the same number of times, so they all go by rank 0's step count and
+    // cadences, whatever they were given themselves
+    let snapshot_every = output.out.is_some().then_some(output.every);
+    let (steps, dt, snapshot_every, log_every) = cluster.broadcast(Some((
+        config.integrator.steps,
+        config.integrator.dt,
+        snapshot_every,
+        output.log_every,
+    )))?;
+    let mut simulation = DistributedSimulation::new(cluster, bodies, space, simulation_config)?;
+
+    let monitor = simulation
+        .gather()?
+        .map(|gathered| -> Result<_, Box<dyn std::error::Error>> {
+            if let Some(writer) = &mut writer {
+                writer.record(&gathered)?;
+            }
+            Ok(DriftMonitor::new(
+                gathered.diagnostics(PotentialMethod::Tree),
+            ))
+        })
+        .transpose()?;
+    let progress = progress_bar(output.progress && rank == 0, steps)?;
+    let instant = std::time::Instant::now();
+    for _ in 0..steps {
+        simulation.step(dt)?;
+        progress.inc(1);
+        let step = simulation.steps();
+        // every rank has to take part in a gather
+        let snapshot = snapshot_every.is_some_and(|every| step.is_multiple_of(every));
+        let log = log_every > 0 && step.is_multiple_of(log_every);
+        if !(snapshot || log) {
+            continue;
+        }
+        if let Some(gathered) = simulation.gather()? {
+            if let Some(writer) = &mut writer {
+                writer.record(&gathered)?;
+            }
+            if let (true, Some(monitor)) = (log, &monitor) {
+                progress
+                    .suspend(|| monitor.log(step, &gathered.diagnostics(PotentialMethod::Tree)));
+            }
         }
-        
-        Simulation { tree: root }
     }
+    progress.finish_and_clear();
+    let elapsed = instant.elapsed();
+    if let (Some(gathered), Some(monitor)) = (simulation.gather()?, monitor) {
+        println!(
+            "{} bodies, {} steps to t = {} in {:?} on {} ranks, relative energy change {:e}",
+            gathered.len(),
+            steps,
+            gathered.time(),
+            elapsed,
+            ranks,
+            monitor.energy_drift(&gathered.diagnostics(PotentialMethod::Tree))
+        );
+    }
+    Ok(())
 }
 
-fn main() {
-    for step in (0..256).step_by(8) {
-        let mut bodies = vec![];
-        for _ in 0..8 * step {
-            bodies.push(rand::random::<Body>())
-        }
-        
-        let space = Cuboid {
-            x: Range { start: 0.0, end: 1024.0 },
-            y: Range { start: 0.0, end: 1024.0 },
-            z: Range { start: 0.0, end: 1024.0 }
+// the bar for --progress over `steps` steps, hidden unless `shown`
+#[cfg(feature = "indicatif")]
+fn progress_bar(shown: bool, steps: u64) -> Result<ProgressBar, Box<dyn std::error::Error>> {
+    if !shown {
+        return Ok(ProgressBar::hidden());
+    }
+    Ok(
+        ProgressBar::new(steps).with_style(ProgressStyle::with_template(
+            "{bar:40} {pos}/{len} steps, {per_sec}, {eta} left",
+        )?),
+    )
+}
+
+#[cfg(not(feature = "indicatif"))]
+fn progress_bar(shown: bool, _: u64) -> Result<ProgressBar, Box<dyn std::error::Error>> {
+    if shown {
+        return Err("--progress needs the binary built with the indicatif feature".into());
+    }
+    Ok(ProgressBar)
+}
+
+// what the run loops call on the bar when there is none to show
+#[cfg(not(feature = "indicatif"))]
+struct ProgressBar;
+
+#[cfg(not(feature = "indicatif"))]
+impl ProgressBar {
+    fn inc(&self, _: u64) {}
+
+    fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        f()
+    }
+
+    fn finish_and_clear(&self) {}
+}
+
+// the simulation the config describes: resumed, read from a file or scattered at random
+fn simulation<S: Scalar>(config: &RunConfig) -> Result<Simulation<S>, Box<dyn std::error::Error>> {
+    let (initial, integrator, forces, boundary) = (
+        &config.initial,
+        &config.integrator,
+        &config.forces,
+        &config.boundary,
+    );
+    if forces.bucket_size == 0 {
+        return Err("--bucket-size must be at least 1".into());
+    }
+    if integrator.eta.is_some() && forces.softening <= 0. && integrator.min_dt <= 0. {
+        return Err("--eta needs a --softening or --min-dt above 0".into());
+    }
+    if integrator.levels.is_some_and(|levels| levels > 32) {
+        return Err("--levels must be at most 32".into());
+    }
+    if forces
+        .incremental
+        .is_some_and(|max_moved| !(0. ..=1.).contains(&max_moved))
+    {
+        return Err("--incremental must be between 0 and 1".into());
+    }
+    if forces.rebuild_every == 0 {
+        return Err("--rebuild-every must be at least 1".into());
+    }
+    if forces
+        .gravitational_constant
+        .is_some_and(|g| !(g.is_finite() && g > 0.))
+    {
+        return Err("--gravitational-constant must be a positive number".into());
+    }
+    if forces
+        .central_mass
+        .is_some_and(|mass| !(mass.is_finite() && mass > 0.))
+    {
+        return Err("--central-mass must be a positive number".into());
+    }
+    if forces.drag.is_some()
+        && !(forces.drag_coefficient.is_finite() && forces.drag_coefficient > 0.)
+    {
+        return Err("--drag-coefficient must be a positive number".into());
+    }
+    if forces
+        .multipole_tolerance
+        .is_some_and(|tol| !(tol.is_finite() && tol > 0.))
+    {
+        return Err("--multipole-tolerance must be a positive number".into());
+    }
+    if forces
+        .cutoff
+        .is_some_and(|radius| !(radius.is_finite() && radius > 0.))
+    {
+        return Err("--cutoff must be a positive number".into());
+    }
+    if forces.bounding_sphere && forces.multipole_tolerance.is_some() {
+        return Err(
+            "--bounding-sphere and --multipole-tolerance are two opening tests: pick one".into(),
+        );
+    }
+    if integrator.recenter_every == Some(0) {
+        return Err("--recenter-every must be at least 1".into());
+    }
+    if integrator
+        .regularize
+        .is_some_and(|radius| !(radius.is_finite() && radius > 0.))
+    {
+        return Err("--regularize must be a positive number".into());
+    }
+    let periodic_axes = [PeriodicAxis::X, PeriodicAxis::Y, PeriodicAxis::Z]
+        .map(|axis| boundary.periodic || boundary.periodic_axes.contains(&axis));
+    if periodic_axes.contains(&true)
+        && periodic_axes.contains(&false)
+        && boundary.escape == Escape::Expand
+    {
+        return Err("--periodic-axes leaves the box fixed: pick --escape clamp or remove".into());
+    }
+    if integrator.regularize.is_some()
+        && (integrator.levels.is_some() || periodic_axes.contains(&true))
+    {
+        return Err(
+            "--regularize takes a shared step in an open box: no --levels, --periodic or --periodic-axes"
+                .into(),
+        );
+    }
+    if boundary.collisions != Collisions::Ignore && boundary.collision_radius <= 0. {
+        return Err("--collisions needs a --collision-radius above 0".into());
+    }
+    let backend = match forces.backend {
+        Backend::Pointer => TreeBackend::Pointer,
+        Backend::Linear => TreeBackend::Linear,
+        Backend::Gpu => TreeBackend::Gpu,
+    };
+    let simulation_config = SimulationConfig {
+        theta: forces.theta,
+        softening: forces.softening,
+        cutoff: forces.cutoff,
+        backend,
+        traversal: if forces.pairwise {
+            Traversal::Pairwise
+        } else if forces.dual_tree {
+            Traversal::Dual
+        } else {
+            Traversal::Single
+        },
+        bucket_size: forces.bucket_size,
+        tracer_mass: forces.tracer_mass,
+        boundary: periodic_axes.map(|periodic| {
+            if periodic {
+                BoundaryCondition::Periodic
+            } else {
+                BoundaryCondition::Open
+            }
+        }),
+        multipole: if forces.quadrupole {
+            MultipoleOrder::Quadrupole
+        } else {
+            MultipoleOrder::Monopole
+        },
+        opening: match (forces.bounding_sphere, forces.multipole_tolerance) {
+            (_, Some(tol)) => Opening::MultipoleAcceptance { tol },
+            (true, None) => Opening::BoundingSphere,
+            (false, None) => Opening::Box,
+        },
+        escape: match boundary.escape {
+            Escape::Expand => EscapePolicy::Expand,
+            Escape::Clamp => EscapePolicy::Clamp,
+            Escape::Remove => EscapePolicy::Remove,
+        },
+        collisions: match boundary.collisions {
+            Collisions::Ignore => CollisionPolicy::Ignore,
+            Collisions::Merge => CollisionPolicy::Merge {
+                radius: boundary.collision_radius,
+            },
+            Collisions::Bounce => CollisionPolicy::Bounce {
+                radius: boundary.collision_radius,
+            },
+        },
+        timestep: match (integrator.eta, integrator.levels) {
+            (Some(eta), Some(levels)) => Timestep::Block { eta, levels },
+            (Some(eta), None) => Timestep::Adaptive {
+                eta,
+                min: integrator.min_dt,
+            },
+            (None, _) => Timestep::Fixed,
+        },
+        integrator: match integrator.method {
+            Method::Leapfrog => Scheme::Leapfrog,
+            Method::Yoshida4 => Scheme::Yoshida4,
+            Method::Hermite => Scheme::Hermite,
+        },
+        rebuild: match forces.incremental {
+            Some(max_moved) => RebuildStrategy::Incremental { max_moved },
+            None => RebuildStrategy::Always,
+        },
+        rebuild_every: forces.rebuild_every,
+        morton_order: forces.morton_order,
+        regularization: match integrator.regularize {
+            Some(radius) => Regularization::Kepler { radius },
+            None => Regularization::Off,
+        },
+        recentering: match integrator.recenter_every {
+            Some(steps) => Recentering::Every { steps },
+            None => Recentering::Off,
+        },
+        units: match (forces.gravitational_constant, forces.units) {
+            (Some(gravitational_constant), _) => Units::Custom {
+                gravitational_constant,
+            },
+            (None, UnitPreset::Dimensionless) => Units::Dimensionless,
+            (None, UnitPreset::Si) => Units::Si,
+            (None, UnitPreset::Astronomical) => Units::Astronomical,
+            (None, UnitPreset::SolarSystem) => Units::SolarSystem,
+        },
+        self_gravity: forces.self_gravity,
+    };
+    let mut simulation = match (&initial.resume, &initial.input) {
+        (Some(path), _) => Simulation::<S>::resume(path)?,
+        (None, None) if initial.scenario.is_some() => {
+            let scenario = initial.scenario.unwrap().scenario();
+            let bodies: Vec<Body<S>> = scenario.bodies().iter().map(Body::cast).collect();
+            let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+            let mut simulation = Simulation::with_config(bodies, space, simulation_config);
+            for (id, label) in scenario.labels().iter().enumerate() {
+                simulation.set_label(id as u64, *label);
+            }
+            simulation
+        }
+        (None, Some(path)) => {
+            let loaded = Simulation::<S>::from_file(path)?;
+            Simulation::with_config(
+                loaded.bodies().to_vec(),
+                *loaded.bounds(),
+                simulation_config,
+            )
+        }
+        (None, None) => {
+            let space = cube(initial.size);
+            let seed = config.seed.expect("resolve picks a seed for random bodies");
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut bodies = ic::uniform_box(initial.bodies, &space, &mut rng);
+            bodies.extend(
+                ic::uniform_box(initial.tracers, &space, &mut rng)
+                    .into_iter()
+                    .map(|tracer| Body {
+                        mass: 0.,
+                        species: Species::Tracer,
+                        ..tracer
+                    }),
+            );
+            let mut simulation = Simulation::with_config(
+                bodies.iter().map(Body::cast).collect(),
+                space.cast(),
+                simulation_config,
+            );
+            simulation.set_seed(seed);
+            simulation
+        }
+    };
+    if initial.com_frame && initial.resume.is_none() {
+        simulation.to_com_frame();
+    }
+    if let Some(mass) = forces.central_mass {
+        simulation.add_potential(Kepler {
+            softening: simulation.config().softening,
+            ..Kepler::new(mass, Point::default())
+        });
+    }
+    if let Some(kind) = forces.drag {
+        let species: &[Species] = match forces.drag_species {
+            DragSpecies::All => &[Species::Live, Species::Tracer],
+            DragSpecies::Live => &[Species::Live],
+            DragSpecies::Tracers => &[Species::Tracer],
         };
-        
-        let instant = std::time::Instant::now();
-        let simulation = Simulation::new(bodies, space);
-        let after = std::time::Instant::now();
-        //println!("took {:?} constructing tree for {} bodies", after - instant, step * 8);
-        println!("{:?},{:?}", after - instant, step * 8)
+        for &species in species {
+            let law = match kind {
+                DragKind::Linear => DragLaw::Linear {
+                    rate: forces.drag_coefficient,
+                },
+                DragKind::Quadratic => DragLaw::Quadratic {
+                    coefficient: forces.drag_coefficient,
+                },
+            };
+            let mut drag = Drag::new(law);
+            if let Some([x, y, z]) = forces.drag_wind {
+                drag = drag.with_wind(Point { x, y, z });
+            }
+            simulation.set_drag(species, drag);
+        }
     }
-    
+    Ok(simulation)
 }
 
+#[cfg(feature = "viz")]
+fn view<S: Scalar>(config: RunConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let mut simulation = simulation::<S>(&config)?;
+    let options = barneshutt3d::viz::ViewOptions {
+        dt: config.integrator.dt,
+        ..Default::default()
+    };
+    barneshutt3d::viz::show(&mut simulation, &options)?;
+    Ok(())
+}
 
+fn cube(size: f64) -> Cuboid {
+    Cuboid {
+        x: Range {
+            start: 0.0,
+            end: size,
+        },
+        y: Range {
+            start: 0.0,
+            end: size,
+        },
+        z: Range {
+            start: 0.0,
+            end: size,
+        },
+    }
+}
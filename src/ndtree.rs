@@ -0,0 +1,460 @@
+//! a tree over any number of dimensions D, for demos and tests that read better flat, or experiments in more
+//! than three. a node's hypercube splits into 2^D children by the sign of each coordinate about its center, the
+//! bit of axis i picking the upper half along it, and nodes farther than theta · their size stand in for their
+//! bodies as in the single walk of the octree. with D = 3 it builds the same tree as `Octree` with octants. the
+//! pull of a point mass is 1/r² whatever D is, so a flat system moves as it would in the plane z = 0 of a
+//! three-dimensional run. everything is f64, with G = 1 and plummer softening. `Quadtree` and the other flat
+//! names are the two-dimensional case
+
+/// a body in D dimensions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NBody<const D: usize> {
+    pub id: u64,
+    pub mass: f64,
+    pub location: [f64; D],
+    pub velocity: [f64; D],
+}
+
+impl<const D: usize> Default for NBody<D> {
+    fn default() -> Self {
+        NBody { id: 0, mass: 0., location: [0.; D], velocity: [0.; D] }
+    }
+}
+
+/// an axis-aligned hypercube, `min` its lowest corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hypercube<const D: usize> {
+    pub min: [f64; D],
+    pub size: f64,
+}
+
+pub type FlatBody = NBody<2>;
+pub type Square = Hypercube<2>;
+pub type Quadtree = NTree<2>;
+pub type FlatSimulation = NSimulation<2>;
+
+impl<const D: usize> Hypercube<D> {
+    /// the children a hypercube splits into
+    pub const CHILDREN: usize = 1 << D;
+
+    /// the smallest hypercube around `bodies` with its lowest corner at theirs, or a unit one at the origin
+    /// for none
+    pub fn bounding(bodies: &[NBody<D>]) -> Self {
+        let Some(first) = bodies.first() else {
+            return Hypercube { min: [0.; D], size: 1. };
+        };
+        let (mut min, mut max) = (first.location, first.location);
+        for body in bodies {
+            for axis in 0..D {
+                min[axis] = min[axis].min(body.location[axis]);
+                max[axis] = max[axis].max(body.location[axis]);
+            }
+        }
+        let size = (0..D).map(|axis| max[axis] - min[axis]).fold(0., f64::max);
+        // a hypercube of no size has no children to split into
+        Hypercube { min, size: if size > 0. { size } else { 1. } }
+    }
+
+    pub fn center(&self) -> [f64; D] {
+        self.min.map(|min| min + self.size / 2.)
+    }
+
+    /// child `index` of 2^D: bit i picks the upper half along axis i
+    pub fn child(&self, index: usize) -> Self {
+        let half = self.size / 2.;
+        let mut min = self.min;
+        for (axis, min) in min.iter_mut().enumerate() {
+            if index >> axis & 1 == 1 {
+                *min += half;
+            }
+        }
+        Hypercube { min, size: half }
+    }
+
+    /// the child `point` falls in. points outside go to the child nearest them
+    pub fn child_of(&self, point: &[f64; D]) -> usize {
+        let center = self.center();
+        (0..D).filter(|&axis| point[axis] >= center[axis]).map(|axis| 1 << axis).sum()
+    }
+
+    pub fn contains(&self, point: &[f64; D]) -> bool {
+        (0..D).all(|axis| point[axis] >= self.min[axis] && point[axis] <= self.min[axis] + self.size)
+    }
+}
+
+// the depth past which leaves stop splitting and keep every body they get, far below where f64 still tells
+// bodies apart in a square of any size
+const MAX_DEPTH: usize = 48;
+
+#[derive(Debug, Clone)]
+struct Node<const D: usize> {
+    cube: Hypercube<D>,
+    mass: f64,
+    center_of_mass: [f64; D],
+    // arena indices, 2^D of them, none for a child without bodies
+    children: Box<[Option<usize>]>,
+    // only ever filled in a leaf; more than one only at the maximum depth
+    bodies: Vec<NBody<D>>,
+}
+
+impl<const D: usize> Node<D> {
+    fn empty(cube: Hypercube<D>) -> Self {
+        Node {
+            cube,
+            mass: 0.,
+            center_of_mass: cube.center(),
+            children: vec![None; Hypercube::<D>::CHILDREN].into(),
+            bodies: Vec::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.iter().all(Option::is_none)
+    }
+}
+
+/// a tree of bodies in D dimensions, one to a leaf, with the mass and center of mass of every node
+#[derive(Debug, Clone)]
+pub struct NTree<const D: usize> {
+    nodes: Vec<Node<D>>,
+    len: usize,
+}
+
+impl<const D: usize> NTree<D> {
+    /// a tree of `bodies` over `space`. bodies outside it are filed in the children nearest them
+    pub fn build(bodies: &[NBody<D>], space: Hypercube<D>) -> Self {
+        let mut tree = NTree { nodes: vec![Node::empty(space)], len: 0 };
+        for body in bodies {
+            tree.insert(0, *body, 0);
+            tree.len += 1;
+        }
+        tree
+    }
+
+    fn insert(&mut self, index: usize, body: NBody<D>, depth: usize) {
+        let node = &mut self.nodes[index];
+        let mass = node.mass + body.mass;
+        if mass != 0. {
+            for axis in 0..D {
+                node.center_of_mass[axis] =
+                    (node.center_of_mass[axis] * node.mass + body.location[axis] * body.mass) / mass;
+            }
+        }
+        node.mass = mass;
+        if node.is_leaf() && (node.bodies.is_empty() || depth >= MAX_DEPTH) {
+            node.bodies.push(body);
+            return;
+        }
+        // a full leaf becomes internal, so its body moves down too
+        for existing in std::mem::take(&mut node.bodies) {
+            self.insert_into_child(index, existing, depth);
+        }
+        self.insert_into_child(index, body, depth);
+    }
+
+    fn insert_into_child(&mut self, index: usize, body: NBody<D>, depth: usize) {
+        let cube = self.nodes[index].cube;
+        let slot = cube.child_of(&body.location);
+        let child = match self.nodes[index].children[slot] {
+            Some(child) => child,
+            None => {
+                let child = self.nodes.len();
+                self.nodes.push(Node::empty(cube.child(slot)));
+                self.nodes[index].children[slot] = Some(child);
+                child
+            }
+        };
+        self.insert(child, body, depth + 1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bounds(&self) -> &Hypercube<D> {
+        &self.nodes[0].cube
+    }
+
+    /// the total mass of the bodies
+    pub fn mass(&self) -> f64 {
+        self.nodes[0].mass
+    }
+
+    pub fn center_of_mass(&self) -> [f64; D] {
+        self.nodes[0].center_of_mass
+    }
+
+    /// nodes in the tree, leaves included
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// barnes-hut acceleration at `target`: a node is taken as a point mass once its size is below theta times
+    /// the distance from `target` to its center of mass, and opened otherwise. theta 0 gives the direct sum
+    pub fn acceleration(&self, target: &[f64; D], theta: f64, softening: f64) -> [f64; D] {
+        let mut acceleration = [0.; D];
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.mass == 0. {
+                continue;
+            }
+            if node.is_leaf() {
+                for body in &node.bodies {
+                    add_pull(&mut acceleration, target, &body.location, body.mass, softening);
+                }
+                continue;
+            }
+            let separation = distance_squared(target, &node.center_of_mass);
+            if node.cube.size * node.cube.size < theta * theta * separation {
+                add_pull(&mut acceleration, target, &node.center_of_mass, node.mass, softening);
+            } else {
+                stack.extend(node.children.iter().flatten());
+            }
+        }
+        acceleration
+    }
+}
+
+fn distance_squared<const D: usize>(a: &[f64; D], b: &[f64; D]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+// adds the softened pull of `mass` at `source` on `target`; nothing when they coincide
+fn add_pull<const D: usize>(
+    acceleration: &mut [f64; D],
+    target: &[f64; D],
+    source: &[f64; D],
+    mass: f64,
+    softening: f64,
+) {
+    let distance_squared = distance_squared(target, source);
+    if distance_squared == 0. {
+        return;
+    }
+    let softened = distance_squared + softening * softening;
+    let scale = mass / (softened * softened.sqrt());
+    for axis in 0..D {
+        acceleration[axis] += (source[axis] - target[axis]) * scale;
+    }
+}
+
+/// exact accelerations of `bodies` on each other, as a reference for the tree
+pub fn accelerations_direct<const D: usize>(bodies: &[NBody<D>], softening: f64) -> Vec<[f64; D]> {
+    bodies
+        .iter()
+        .map(|target| {
+            let mut acceleration = [0.; D];
+            for source in bodies {
+                add_pull(&mut acceleration, &target.location, &source.location, source.mass, softening);
+            }
+            acceleration
+        })
+        .collect()
+}
+
+/// bodies in D dimensions advanced in time by kick-drift-kick leapfrog on a tree rebuilt every step, over the
+/// hypercube around them
+#[derive(Debug, Clone)]
+pub struct NSimulation<const D: usize> {
+    bodies: Vec<NBody<D>>,
+    theta: f64,
+    softening: f64,
+    // at the current positions, from the closing kick of the last step
+    accelerations: Vec<[f64; D]>,
+    time: f64,
+}
+
+impl<const D: usize> NSimulation<D> {
+    /// `bodies` under barnes-hut with opening angle `theta` and plummer softening `softening`, with their ids
+    /// set to their places in order
+    pub fn new(mut bodies: Vec<NBody<D>>, theta: f64, softening: f64) -> Self {
+        for (id, body) in bodies.iter_mut().enumerate() {
+            body.id = id as u64;
+        }
+        let mut simulation = NSimulation { bodies, theta, softening, accelerations: Vec::new(), time: 0. };
+        simulation.accelerations = simulation.compute_accelerations();
+        simulation
+    }
+
+    pub fn bodies(&self) -> &[NBody<D>] {
+        &self.bodies
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// the tree over the bodies where they are now
+    pub fn tree(&self) -> NTree<D> {
+        NTree::build(&self.bodies, Hypercube::bounding(&self.bodies))
+    }
+
+    pub fn compute_accelerations(&self) -> Vec<[f64; D]> {
+        let tree = self.tree();
+        self.bodies.iter().map(|body| tree.acceleration(&body.location, self.theta, self.softening)).collect()
+    }
+
+    pub fn step(&mut self, dt: f64) {
+        self.kick(dt / 2.);
+        for body in &mut self.bodies {
+            for (location, velocity) in body.location.iter_mut().zip(&body.velocity) {
+                *location += velocity * dt;
+            }
+        }
+        self.accelerations = self.compute_accelerations();
+        self.kick(dt / 2.);
+        self.time += dt;
+    }
+
+    fn kick(&mut self, dt: f64) {
+        for (body, acceleration) in self.bodies.iter_mut().zip(&self.accelerations) {
+            for (velocity, acceleration) in body.velocity.iter_mut().zip(acceleration) {
+                *velocity += acceleration * dt;
+            }
+        }
+    }
+
+    /// kinetic plus the exact softened potential energy
+    pub fn total_energy(&self) -> f64 {
+        let mut energy = 0.;
+        for (i, a) in self.bodies.iter().enumerate() {
+            energy += 0.5 * a.mass * a.velocity.iter().map(|v| v * v).sum::<f64>();
+            for b in &self.bodies[i + 1..] {
+                let softened = distance_squared(&a.location, &b.location) + self.softening * self.softening;
+                energy -= a.mass * b.mass / softened.sqrt();
+            }
+        }
+        energy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cuboid;
+    use crate::ic;
+    use crate::tree::BodyTree;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_bodies(n: usize, seed: u64) -> Vec<FlatBody> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|id| FlatBody {
+                id: id as u64,
+                mass: rng.gen_range(0.5..1.5),
+                location: [rng.gen(), rng.gen()],
+                velocity: [0.; 2],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nodes_carry_the_mass_of_their_bodies() {
+        let bodies = random_bodies(500, 1);
+        let tree = Quadtree::build(&bodies, Square::bounding(&bodies));
+        let mass: f64 = bodies.iter().map(|body| body.mass).sum();
+        let x = bodies.iter().map(|body| body.mass * body.location[0]).sum::<f64>() / mass;
+        let y = bodies.iter().map(|body| body.mass * body.location[1]).sum::<f64>() / mass;
+        assert_eq!(tree.len(), 500);
+        assert!((tree.mass() - mass).abs() < 1e-12 * mass);
+        assert!((tree.center_of_mass()[0] - x).abs() < 1e-12 && (tree.center_of_mass()[1] - y).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tree_forces_approach_the_direct_sum() {
+        let bodies = random_bodies(1000, 2);
+        let tree = Quadtree::build(&bodies, Square::bounding(&bodies));
+        let direct = accelerations_direct(&bodies, 0.01);
+        // over all bodies, since single ones whose pulls nearly cancel can be far off in relative terms
+        let error_at = |theta: f64| {
+            let (mut error, mut total) = (0., 0.);
+            for (body, exact) in bodies.iter().zip(&direct) {
+                error += distance_squared(&tree.acceleration(&body.location, theta, 0.01), exact);
+                total += exact[0].powi(2) + exact[1].powi(2);
+            }
+            (error / total).sqrt()
+        };
+        assert!(error_at(0.) < 1e-12);
+        let coarse = error_at(0.5);
+        assert!(coarse < 2e-2, "{}", coarse);
+        assert!(coarse > error_at(0.2));
+    }
+
+    #[test]
+    fn three_dimensions_make_the_octree() {
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let bodies = ic::uniform_box(500, &space, &mut StdRng::seed_from_u64(3));
+        let octree = BodyTree::build(bodies.iter().copied(), space);
+        let spatial: Vec<NBody<3>> = bodies
+            .iter()
+            .map(|body| NBody {
+                id: body.id,
+                mass: body.mass,
+                location: body.location.as_array(),
+                velocity: body.velocity.as_array(),
+            })
+            .collect();
+        let tree = NTree::build(&spatial, Hypercube { min: [0.; 3], size: 1. });
+        assert_eq!(tree.node_count(), octree.nodes().len());
+        assert!((tree.mass() - octree.root().mass()).abs() < 1e-12 * tree.mass());
+        let center = octree.root().center_of_mass().as_array();
+        assert!(distance_squared(&tree.center_of_mass(), &center).sqrt() < 1e-12);
+        for body in &bodies {
+            let expected = octree.acceleration_at(&body.location, 0.5, 0.01).as_array();
+            let acceleration = tree.acceleration(&body.location.as_array(), 0.5, 0.01);
+            let scale = expected.iter().map(|a| a * a).sum::<f64>().sqrt();
+            assert!(distance_squared(&acceleration, &expected).sqrt() < 1e-12 * scale);
+        }
+    }
+
+    #[test]
+    fn four_dimensions_summarize_their_bodies() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let bodies: Vec<NBody<4>> = (0..300)
+            .map(|id| NBody { id, mass: rng.gen_range(0.5..1.5), location: rng.gen(), velocity: [0.; 4] })
+            .collect();
+        let tree = NTree::build(&bodies, Hypercube::bounding(&bodies));
+        let mass: f64 = bodies.iter().map(|body| body.mass).sum();
+        assert!((tree.mass() - mass).abs() < 1e-12 * mass);
+        for axis in 0..4 {
+            let center = bodies.iter().map(|body| body.mass * body.location[axis]).sum::<f64>() / mass;
+            assert!((tree.center_of_mass()[axis] - center).abs() < 1e-12);
+        }
+        let direct = accelerations_direct(&bodies, 0.);
+        for (body, exact) in bodies.iter().zip(&direct).take(20) {
+            let acceleration = tree.acceleration(&body.location, 0., 0.);
+            assert!(distance_squared(&acceleration, exact).sqrt() < 1e-10, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn a_circular_orbit_comes_round() {
+        // masses 1 and 0.5 a distance 1 apart, in their center-of-mass frame
+        let (heavy, light) = (1., 0.5);
+        let speed = f64::sqrt(heavy + light);
+        let body = |mass: f64, offset: f64| FlatBody {
+            mass,
+            location: [offset, 0.],
+            velocity: [0., speed * offset],
+            ..FlatBody::default()
+        };
+        let bodies = vec![body(heavy, -light / 1.5), body(light, heavy / 1.5)];
+        let period = 2. * std::f64::consts::PI / speed;
+        let mut simulation = FlatSimulation::new(bodies.clone(), 0.5, 0.);
+        let energy = simulation.total_energy();
+        let steps = 1000;
+        for _ in 0..steps {
+            simulation.step(period / steps as f64);
+        }
+        for (body, start) in simulation.bodies().iter().zip(&bodies) {
+            assert!(distance_squared(&body.location, &start.location).sqrt() < 1e-3, "{:?}", body);
+        }
+        assert!((simulation.total_energy() - energy).abs() < 1e-6 * energy.abs());
+    }
+}
@@ -0,0 +1,105 @@
+//! hooks into `Simulation::step` for code embedding the simulator: logging, live plots, or calling a run off
+//! when something happens. every hook has an empty default, so an observer only writes the ones it needs
+
+use crate::diagnostics::{DriftMonitor, PotentialMethod};
+use crate::force::{ForceModel, Gravity};
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use crate::sim::{Simulation, StepReport};
+use std::ops::ControlFlow;
+
+/// called by `Simulation::step` at fixed points of every step, in the order observers were added
+pub trait StepObserver<S: Scalar = f64, F: ForceModel = Gravity>: Send + Sync {
+    /// before anything moves
+    fn on_step_start(&mut self, simulation: &Simulation<S, F>) {
+        let _ = simulation;
+    }
+
+    /// after forces are computed at new positions and before the kick that uses them, so velocities are
+    /// half a step behind. `accelerations` are in the order of `bodies()`. block timesteps recompute only
+    /// some bodies per tick, and this is called after each such tick with the rest left as they were
+    fn on_forces_computed(&mut self, simulation: &Simulation<S, F>, accelerations: &[Point<S>]) {
+        let _ = (simulation, accelerations);
+    }
+
+    /// once the step is done. breaking asks for the run to stop: the step stands, and the report of `step` has
+    /// `stop_requested` set for the caller's loop to act on
+    fn on_step_end(&mut self, simulation: &Simulation<S, F>, report: &StepReport) -> ControlFlow<()> {
+        let _ = (simulation, report);
+        ControlFlow::Continue(())
+    }
+}
+
+/// a guard for unattended runs: every `every` steps it takes the energy with the tree, and warns through tracing
+/// when it has drifted from the first measurement by more than `threshold`, as `DriftMonitor::warn_above` does.
+/// the first measurement is taken at the start of the first step it sees. a halting guard also asks the run to
+/// stop, which catches an integrator blowing up long before the bodies fly apart
+#[derive(Debug, Clone)]
+pub struct DriftGuard {
+    every: u64,
+    threshold: f64,
+    halt: bool,
+    monitor: Option<DriftMonitor>,
+}
+
+impl DriftGuard {
+    /// panics if `every` is 0
+    pub fn new(every: u64, threshold: f64) -> Self {
+        assert!(every > 0, "the energy needs checking at least every step");
+        DriftGuard { every, threshold, halt: false, monitor: None }
+    }
+
+    /// the guard, asking the run to stop the first time it warns
+    pub fn halting(self) -> Self {
+        DriftGuard { halt: true, ..self }
+    }
+}
+
+impl<S: Scalar, F: ForceModel> StepObserver<S, F> for DriftGuard {
+    fn on_step_start(&mut self, simulation: &Simulation<S, F>) {
+        if self.monitor.is_none() {
+            self.monitor = Some(DriftMonitor::new(simulation.diagnostics(PotentialMethod::Tree)));
+        }
+    }
+
+    fn on_step_end(&mut self, simulation: &Simulation<S, F>, _: &StepReport) -> ControlFlow<()> {
+        let Some(monitor) = &self.monitor else {
+            return ControlFlow::Continue(());
+        };
+        let step = simulation.steps();
+        if !step.is_multiple_of(self.every) {
+            return ControlFlow::Continue(());
+        }
+        let current = simulation.diagnostics(PotentialMethod::Tree);
+        if monitor.warn_above(step, &current, self.threshold) && self.halt {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenarios::Scenario;
+
+    // steps until the run asks to stop, or none within `limit`
+    fn steps_to_stop(dt: f64, limit: u64) -> Option<u64> {
+        let mut simulation = Scenario::TwoBody.simulation();
+        simulation.add_observer(DriftGuard::new(5, 1e-4).halting());
+        (0..limit).find(|_| simulation.step(dt).stop_requested).map(|_| simulation.steps())
+    }
+
+    #[test]
+    fn a_blowup_trips_the_guard_at_its_first_check() {
+        // ten steps an orbit, far too few without softening
+        let dt = Scenario::TwoBody.orbits()[0].period / 10.;
+        assert_eq!(steps_to_stop(dt, 100), Some(5));
+    }
+
+    #[test]
+    fn a_stable_run_does_not() {
+        assert_eq!(steps_to_stop(Scenario::TwoBody.dt(), 500), None);
+    }
+}
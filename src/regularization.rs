@@ -0,0 +1,223 @@
+//! two-body regularization of close binaries. a tight pair needs either a softening that changes its orbit or,
+//! unsoftened, steps far shorter than the rest of the bodies need. with `Regularization::Kepler` on, every
+//! step pairs up bound bodies within a radius of each other that each pull hardest on the other. the
+//! integrator's drifts
+//! then carry each pair along its exact, unsoftened kepler orbit about their center of mass, which drifts
+//! on in a straight line. its kicks only apply the pull of everything else, including the tree's softened
+//! pull of each member on the other, taken back out. the rest of the bodies feel the pair through the tree as
+//! usual
+//!
+//! the split is that of a mixed-variable symplectic map, so with leapfrog the pair's energy errors only come
+//! from the tides on it, however few steps an orbit takes. its pull on itself is newtonian whatever the
+//! `ForceModel`
+
+use crate::body::{Body, Species};
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// which close pairs `Simulation::step` integrates as two-body problems. used with integrators that move the
+/// bodies through `Stage::kick` and `Stage::drift`, like leapfrog and yoshida4; hermite moves the pairs like
+/// any other bodies. panics in `step` with block timesteps or a periodic boundary
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Regularization {
+    /// every body moves under the tree's forces
+    #[default]
+    Off,
+    /// pairs closer than `radius`, bound to each other and each pulled hardest by the other of its nearest
+    /// few neighbors, follow kepler orbits between kicks
+    Kepler { radius: f64 },
+}
+
+/// where a body at `offset` from a point mass, moving at `velocity`, is after `dt` of a kepler orbit about it,
+/// and how fast it goes then. `mu` is the gravitational constant times the mass. works for bound and unbound
+/// orbits alike, and backwards for a negative `dt`
+pub fn kepler_drift(offset: Point, velocity: Point, mu: f64, dt: f64) -> (Point, Point) {
+    let r0 = offset.length();
+    if r0 == 0. || mu <= 0. || dt == 0. {
+        return (offset + velocity * dt, velocity);
+    }
+    let alpha = 2. / r0 - velocity.dot(&velocity) / mu;
+    // whole periods of a bound orbit bring it back where it was
+    let dt = if alpha > 0. {
+        dt.rem_euclid(2. * PI / (mu * alpha * alpha * alpha).sqrt())
+    } else {
+        dt
+    };
+    let root_mu = mu.sqrt();
+    let radial = offset.dot(&velocity) / root_mu;
+    // newton's method on the universal anomaly chi, from kepler's equation in universal variables, starting
+    // from vallado's guesses
+    let mut chi = if alpha > 1e-12 {
+        root_mu * alpha * dt
+    } else if alpha < -1e-12 {
+        let a = 1. / alpha;
+        let sign = dt.signum();
+        let guess = -2. * mu * alpha * dt
+            / (offset.dot(&velocity) + sign * (-mu * a).sqrt() * (1. - r0 * alpha));
+        sign * (-a).sqrt() * guess.ln()
+    } else {
+        root_mu * dt / r0
+    };
+    if !chi.is_finite() {
+        chi = root_mu * dt / r0;
+    }
+    for _ in 0..100 {
+        let z = alpha * chi * chi;
+        let (c, s) = (stumpff_c(z), stumpff_s(z));
+        let time = radial * chi * chi * c + (1. - alpha * r0) * chi * chi * chi * s + r0 * chi;
+        let radius = radial * chi * (1. - z * s) + (1. - alpha * r0) * chi * chi * c + r0;
+        let change = (time - root_mu * dt) / radius;
+        chi -= change;
+        if change.abs() <= 1e-15 * chi.abs().max(1e-300) {
+            break;
+        }
+    }
+    let z = alpha * chi * chi;
+    let (c, s) = (stumpff_c(z), stumpff_s(z));
+    let f = 1. - chi * chi / r0 * c;
+    let g = dt - chi * chi * chi * s / root_mu;
+    let moved = offset * f + velocity * g;
+    let r = moved.length();
+    let f_dot = root_mu / (r * r0) * (alpha * chi * chi * chi * s - chi);
+    let g_dot = 1. - chi * chi / r * c;
+    (moved, offset * f_dot + velocity * g_dot)
+}
+
+// the stumpff functions c(z) = (1 - cos √z) / z and s(z) = (√z - sin √z) / √z³, continued to negative z, and
+// by their series near zero where the closed forms cancel
+fn stumpff_c(z: f64) -> f64 {
+    if z.abs() < 1e-3 {
+        1. / 2. - z / 24. + z * z / 720. - z * z * z / 40320.
+    } else if z > 0. {
+        (1. - z.sqrt().cos()) / z
+    } else {
+        ((-z).sqrt().cosh() - 1.) / -z
+    }
+}
+
+fn stumpff_s(z: f64) -> f64 {
+    if z.abs() < 1e-3 {
+        1. / 6. - z / 120. + z * z / 5040. - z * z * z / 362880.
+    } else if z > 0. {
+        let root = z.sqrt();
+        (root - root.sin()) / (root * root * root)
+    } else {
+        let root = (-z).sqrt();
+        (root.sinh() - root) / (root * root * root)
+    }
+}
+
+// the nearest neighbors of a body looked through for the one that pulls on it hardest, so that a light body
+// passing closer than its partner does not break up a pair
+pub(crate) const CANDIDATES: usize = 4;
+
+// the ids of the pairs to regularize, lower index first: live bodies with mass, each pulled hardest by the
+// other of the ids `nearest` gives, which may include the body's own, closer than `radius` and bound with
+// gravitational constant `g`
+pub(crate) fn find_pairs<S: Scalar>(
+    bodies: &[Body<S>],
+    radius: f64,
+    g: f64,
+    nearest: impl Fn(&Body<S>) -> Vec<u64>,
+) -> Vec<(u64, u64)> {
+    let candidate = |body: &Body<S>| body.species == Species::Live && body.mass.as_f64() > 0.;
+    let index: HashMap<u64, usize> = bodies
+        .iter()
+        .enumerate()
+        .filter(|(_, body)| candidate(body))
+        .map(|(i, body)| (body.id, i))
+        .collect();
+    let pull = |body: &Body<S>, other: &Body<S>| {
+        other.mass.as_f64() / body.location.distance_squared(&other.location).as_f64()
+    };
+    let neighbors: HashMap<u64, u64> = bodies
+        .iter()
+        .filter(|body| candidate(body))
+        .filter_map(|body| {
+            let strongest = nearest(body)
+                .into_iter()
+                .filter(|&id| id != body.id)
+                .filter_map(|id| Some(&bodies[*index.get(&id)?]))
+                .max_by(|a, b| pull(body, a).total_cmp(&pull(body, b)))?;
+            Some((body.id, strongest.id))
+        })
+        .collect();
+    let mut pairs = Vec::new();
+    for (i, a) in bodies.iter().enumerate() {
+        let Some(&other) = neighbors.get(&a.id) else {
+            continue;
+        };
+        let Some(&j) = index.get(&other) else {
+            continue;
+        };
+        if j <= i || neighbors.get(&other) != Some(&a.id) {
+            continue;
+        }
+        let b = &bodies[j];
+        let offset: Point = (b.location - a.location).cast();
+        let velocity: Point = (b.velocity - a.velocity).cast();
+        let distance = offset.length();
+        let mu = g * (a.mass.as_f64() + b.mass.as_f64());
+        if distance < radius && velocity.dot(&velocity) / 2. < mu / distance {
+            pairs.push((a.id, b.id));
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64, z: f64) -> Point {
+        Point { x, y, z }
+    }
+
+    #[test]
+    fn a_circular_orbit_turns_a_quarter_in_a_quarter_period() {
+        let (offset, velocity) = kepler_drift(point(2., 0., 0.), point(0., 0.5, 0.), 0.5, 2. * PI);
+        assert!(
+            offset.distance_squared(&point(0., 2., 0.)) < 1e-24,
+            "{:?}",
+            offset
+        );
+        assert!(
+            velocity.distance_squared(&point(-0.5, 0., 0.)) < 1e-24,
+            "{:?}",
+            velocity
+        );
+    }
+
+    #[test]
+    fn eccentric_and_unbound_orbits_keep_their_energy_and_come_back() {
+        let energy = |(offset, velocity): (Point, Point)| {
+            velocity.dot(&velocity) / 2. - 1. / offset.length()
+        };
+        for speed in [0.4, 1.2, 1.414, 1.5, 3.] {
+            let start = (point(0.7, 0.1, -0.2), point(0.1, speed, 0.3));
+            for dt in [1e-3, 0.37, 5., 123.4] {
+                let there = kepler_drift(start.0, start.1, 1., dt);
+                let error = (energy(there) - energy(start)).abs();
+                assert!(
+                    error < 1e-10 * energy(start).abs().max(1.),
+                    "{} at {}, {}",
+                    error,
+                    speed,
+                    dt
+                );
+                let back = kepler_drift(there.0, there.1, 1., -dt);
+                let off = back.0.distance_squared(&start.0).sqrt();
+                assert!(
+                    off < 1e-8 * there.0.length().max(1.),
+                    "{} at {}, {}",
+                    off,
+                    speed,
+                    dt
+                );
+            }
+        }
+    }
+}
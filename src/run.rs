@@ -0,0 +1,184 @@
+//! many steps in one call, with an optional watch for the signs of a step too long for the forces it meets:
+//! the largest acceleration jumping from one step to the next, as when a pair passes closer than the steps
+//! can follow, or a body outrunning the system's escape speed many times over, as it does once a pair has
+//! been flung apart with energy the integrator made up. energy drift is left to `DriftGuard` and
+//! `StopCondition::EnergyDrift`, which cost a tree walk for the potential per check
+
+use crate::diagnostics::PotentialMethod;
+use crate::force::ForceModel;
+use crate::scalar::Scalar;
+use crate::sim::Simulation;
+use std::fmt;
+
+/// what `Simulation::run` holds each step to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstabilityMonitor {
+    /// how many times the largest acceleration of the step before the largest acceleration may be
+    pub acceleration_jump: f64,
+    /// how many times the escape speed of the system, measured at the start of the run, a body may move at
+    pub escape_multiple: f64,
+}
+
+impl Default for InstabilityMonitor {
+    fn default() -> Self {
+        InstabilityMonitor {
+            acceleration_jump: 10.,
+            escape_multiple: 10.,
+        }
+    }
+}
+
+/// a sign that the step is too long, from `InstabilityMonitor`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instability {
+    /// the largest acceleration went up `ratio` times in one step
+    AccelerationSpike { ratio: f64 },
+    /// the body with `id` moves at `speed`, against an escape speed of `escape_speed`
+    Runaway {
+        id: u64,
+        speed: f64,
+        escape_speed: f64,
+    },
+}
+
+impl fmt::Display for Instability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instability::AccelerationSpike { ratio } => {
+                write!(f, "the largest acceleration grew {:.3}x in a step", ratio)
+            }
+            Instability::Runaway {
+                id,
+                speed,
+                escape_speed,
+            } => write!(
+                f,
+                "body {} moves at {:e}, {:.3}x the escape speed",
+                id,
+                speed,
+                speed / escape_speed
+            ),
+        }
+    }
+}
+
+/// what a call to `Simulation::run` did
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunReport {
+    /// the steps taken, the one the run stopped on included
+    pub steps: u64,
+    /// an observer or the stop condition asked for the run to stop
+    pub stop_requested: bool,
+    /// the first sign of instability and the step count it was seen at, after which the run stopped
+    pub instability: Option<(u64, Instability)>,
+}
+
+impl RunReport {
+    pub fn is_stable(&self) -> bool {
+        self.instability.is_none()
+    }
+}
+
+impl<S: Scalar, F: ForceModel> Simulation<S, F> {
+    /// takes up to `steps` steps of `dt`, stopping early when a step asks for it, as `StepReport::stop_requested`
+    /// says, or when `monitor` sees a sign of instability. the escape speed it holds bodies to is that of the
+    /// bound sources at the start, √(4 |W| / M), the mass-weighted mean of √(2 |φ|) from each body's potential
+    pub fn run(&mut self, dt: f64, steps: u64, monitor: Option<InstabilityMonitor>) -> RunReport {
+        let mut report = RunReport::default();
+        // the escape speed and the largest acceleration before the first step
+        let mut watch = monitor.map(|monitor| {
+            let potential = self.diagnostics(PotentialMethod::Tree).potential_energy;
+            let mass: f64 = self
+                .bodies()
+                .iter()
+                .filter(|body| body.is_source())
+                .map(|body| body.mass.as_f64())
+                .sum();
+            let escape_speed = if potential < 0. && mass > 0. {
+                (-4. * potential / mass).sqrt()
+            } else {
+                f64::INFINITY
+            };
+            (monitor, escape_speed, self.max_acceleration())
+        });
+        while report.steps < steps {
+            let step = self.step(dt);
+            report.steps += 1;
+            if let Some((monitor, escape_speed, previous)) = &mut watch {
+                let largest = self.max_acceleration();
+                let fastest = self
+                    .bodies()
+                    .iter()
+                    .map(|body| (body.id, body.velocity.length().as_f64()))
+                    .max_by(|a, b| a.1.total_cmp(&b.1));
+                let instability = if largest > monitor.acceleration_jump * *previous {
+                    Some(Instability::AccelerationSpike {
+                        ratio: largest / *previous,
+                    })
+                } else {
+                    fastest
+                        .filter(|&(_, speed)| speed > monitor.escape_multiple * *escape_speed)
+                        .map(|(id, speed)| Instability::Runaway {
+                            id,
+                            speed,
+                            escape_speed: *escape_speed,
+                        })
+                };
+                *previous = largest;
+                if let Some(instability) = instability {
+                    tracing::warn!(step = self.steps(), %instability, "unstable step");
+                    report.instability = Some((self.steps(), instability));
+                    break;
+                }
+            }
+            if step.stop_requested {
+                report.stop_requested = true;
+                break;
+            }
+        }
+        report
+    }
+
+    // the size of the largest of the accelerations the last step closed on, or of fresh ones before the first
+    fn max_acceleration(&self) -> f64 {
+        self.bodies_with_acceleration()
+            .map(|(_, acceleration)| acceleration.length().as_f64())
+            .fold(0., f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ic;
+    use crate::sim::SimulationConfig;
+    use crate::Cuboid;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn cluster(softening: f64) -> Simulation {
+        let bodies = ic::plummer(100, 1., 0.2, &mut StdRng::seed_from_u64(3));
+        let config = SimulationConfig {
+            softening,
+            ..SimulationConfig::default()
+        };
+        Simulation::with_config(bodies, Cuboid::from(([-4.; 3], [4.; 3])), config)
+    }
+
+    #[test]
+    fn steps_too_long_for_close_passes_are_flagged_and_short_ones_are_not() {
+        let report = cluster(1e-4).run(0.05, 200, Some(InstabilityMonitor::default()));
+        let (step, instability) = report
+            .instability
+            .expect("an unsoftened cluster at a long step");
+        assert_eq!(step, report.steps);
+        assert!(report.steps < 200);
+        assert!(matches!(
+            instability,
+            Instability::AccelerationSpike { .. } | Instability::Runaway { .. }
+        ));
+        let report = cluster(0.05).run(1e-3, 200, Some(InstabilityMonitor::default()));
+        assert!(report.is_stable(), "{:?}", report);
+        assert_eq!(report.steps, 200);
+    }
+}
@@ -0,0 +1,117 @@
+//! the floating point type bodies are stored and forces computed in. everything defaults to f64; f32 halves
+//! the memory per body and doubles the simd width, at the cost of about seven significant digits
+
+use crate::geometry::Point;
+use crate::kernel::{self, Sources};
+use num_traits::float::TotalOrder;
+use num_traits::{Euclid, Float, FloatConst};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display};
+use std::iter::Sum;
+use std::ops::{AddAssign, DivAssign, MulAssign, SubAssign};
+
+/// f32 or f64, and sealed to them. configuration, the clock and conserved-quantity measurements stay in f64
+/// whichever is used, with `SimulationConfig::constants` rounding the force constants to it
+pub trait Scalar:
+    plain::Plain
+    + Float
+    + FloatConst
+    + Euclid
+    + TotalOrder
+    + Default
+    + From<u8>
+    + Debug
+    + Display
+    + Sum
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + DivAssign
+    + Send
+    + Sync
+    + Serialize
+    + DeserializeOwned
+    + 'static
+{
+    const PRECISION: Precision;
+
+    /// `value` rounded to the nearest scalar
+    fn from_f64(value: f64) -> Self;
+
+    fn as_f64(self) -> f64;
+
+    /// the near-field sum, `kernel::scalar` unless the `simd` feature swaps in a vectorized kernel
+    fn direct_sum(target: &Point<Self>, sources: &Sources<Self>, softening: Self) -> Point<Self> {
+        kernel::scalar(target, sources, softening)
+    }
+}
+
+pub(crate) mod plain {
+    /// numbers that any bit pattern is a valid value of, with no padding, so raw bytes can be read as them.
+    /// public only so it can seal `Scalar`; it cannot be named or implemented outside the crate
+    ///
+    /// # Safety
+    ///
+    /// implementors must have no invalid bit patterns and no padding bytes
+    pub unsafe trait Plain: Copy + 'static {}
+
+    // SAFETY: integers and floats of every bit pattern are valid, and none have padding
+    unsafe impl Plain for u64 {}
+    unsafe impl Plain for f32 {}
+    unsafe impl Plain for f64 {}
+    // SAFETY: arrays have no padding between their elements
+    unsafe impl<T: Plain> Plain for [T; 3] {}
+}
+
+impl Scalar for f32 {
+    const PRECISION: Precision = Precision::Single;
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+
+    #[cfg(feature = "simd")]
+    fn direct_sum(target: &Point<f32>, sources: &Sources<f32>, softening: f32) -> Point<f32> {
+        kernel::simd_f32(target, sources, softening)
+    }
+}
+
+impl Scalar for f64 {
+    const PRECISION: Precision = Precision::Double;
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+
+    #[cfg(feature = "simd")]
+    fn direct_sum(target: &Point<f64>, sources: &Sources<f64>, softening: f64) -> Point<f64> {
+        kernel::simd_f64(target, sources, softening)
+    }
+}
+
+/// names the scalar a simulation runs in, e.g. in a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precision {
+    /// f32
+    Single,
+    /// f64
+    Double,
+}
+
+impl Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Precision::Single => write!(f, "single"),
+            Precision::Double => write!(f, "double"),
+        }
+    }
+}
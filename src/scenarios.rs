@@ -0,0 +1,407 @@
+//! small systems with known answers, for checking the force and integrator code end to end: a circular
+//! two-body orbit, the figure-eight choreography of three equal masses, and the sun, earth and moon. each
+//! knows the periods it should show, and a `ScenarioCheck` follows a run of it to measure them along with
+//! the energy drift. `Scenario::run` does a whole run, and `assert_within` on its report panics on a miss, so
+//! a test of the physics is `scenario.run().assert_within(&scenario.tolerances())`
+
+use crate::body::Body;
+use crate::diagnostics::{self, DriftMonitor, PotentialMethod};
+use crate::force::ForceModel;
+use crate::geometry::{Cuboid, Point};
+use crate::scalar::Scalar;
+use crate::sim::{Simulation, SimulationConfig};
+use crate::units::Units;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scenario {
+    /// masses 1 and 0.5 a distance 1 apart on circular orbits, with G = 1
+    TwoBody,
+    /// three unit masses chasing each other around a figure eight, from the initial conditions of chenciner
+    /// and montgomery (2000) as refined by simó, with G = 1
+    FigureEight,
+    /// the sun, and the earth and moon on circular orbits a mean distance apart, in solar masses, AU and
+    /// years. the sun's tide shortens the moon's period by most of a percent from its two-body value
+    SunEarthMoon,
+}
+
+/// a periodic motion a scenario should show: `body` coming back to where it started relative to `around`,
+/// or relative to the center of mass at the origin, once every `period`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orbit {
+    /// the id of the body
+    pub body: u64,
+    pub around: Option<u64>,
+    pub period: f64,
+}
+
+/// how far a run of a scenario may be off
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerances {
+    /// |measured - expected| / expected of every period
+    pub period: f64,
+    /// the largest |E - E0| / |E0| over the run
+    pub energy: f64,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 3] = [Scenario::TwoBody, Scenario::FigureEight, Scenario::SunEarthMoon];
+
+    /// the name on the command line: `two-body`, `figure8` or `sun-earth-moon`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scenario::TwoBody => "two-body",
+            Scenario::FigureEight => "figure8",
+            Scenario::SunEarthMoon => "sun-earth-moon",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Scenario> {
+        Scenario::ALL.into_iter().find(|scenario| scenario.name() == name)
+    }
+
+    /// the bodies in their center-of-mass frame, in the order of their ids
+    pub fn bodies(&self) -> Vec<Body> {
+        let body = |mass, location: [f64; 2], velocity: [f64; 2]| Body {
+            mass,
+            location: Point { x: location[0], y: location[1], z: 0. },
+            velocity: Point { x: velocity[0], y: velocity[1], z: 0. },
+            ..Body::default()
+        };
+        let mut bodies = match self {
+            Scenario::TwoBody => {
+                // the light body moves relative to the heavy one at the circular speed sqrt(G (m1 + m2) / r)
+                let (heavy, light) = (1., 0.5f64);
+                let speed = (heavy + light).sqrt();
+                vec![body(heavy, [0., 0.], [0., 0.]), body(light, [1., 0.], [0., speed])]
+            }
+            Scenario::FigureEight => {
+                let (x, y) = (0.970_004_360_5, -0.243_087_530_8);
+                let (vx, vy) = (-0.932_407_370_7, -0.864_731_460_4);
+                vec![
+                    body(1., [x, y], [-vx / 2., -vy / 2.]),
+                    body(1., [-x, -y], [-vx / 2., -vy / 2.]),
+                    body(1., [0., 0.], [vx, vy]),
+                ]
+            }
+            Scenario::SunEarthMoon => {
+                let g = self.units().gravitational_constant();
+                let (sun, earth, moon) = (1., EARTH_MASS, EARTH_MASS / EARTH_MOON_MASS_RATIO);
+                // the earth-moon barycenter goes round the sun at 1 AU, and the pair round each other
+                let orbit = (g * (sun + earth + moon)).sqrt();
+                let month = (g * (earth + moon) / MOON_DISTANCE).sqrt();
+                let (to_earth, to_moon) = (moon / (earth + moon), earth / (earth + moon));
+                vec![
+                    body(sun, [0., 0.], [0., 0.]),
+                    body(earth, [1. - to_earth * MOON_DISTANCE, 0.], [0., orbit - to_earth * month]),
+                    body(moon, [1. + to_moon * MOON_DISTANCE, 0.], [0., orbit + to_moon * month]),
+                ]
+            }
+        };
+        if let Some((location, velocity)) = diagnostics::center_of_mass(&bodies) {
+            for body in &mut bodies {
+                body.location -= location;
+                body.velocity -= velocity;
+            }
+        }
+        for (id, body) in bodies.iter_mut().enumerate() {
+            body.id = id as u64;
+        }
+        bodies
+    }
+
+    /// labels for the bodies, by id
+    pub fn labels(&self) -> &'static [&'static str] {
+        match self {
+            Scenario::TwoBody => &["primary", "secondary"],
+            Scenario::FigureEight => &["a", "b", "c"],
+            Scenario::SunEarthMoon => &["sun", "earth", "moon"],
+        }
+    }
+
+    pub fn units(&self) -> Units {
+        match self {
+            Scenario::TwoBody | Scenario::FigureEight => Units::Dimensionless,
+            Scenario::SunEarthMoon => Units::SolarSystem,
+        }
+    }
+
+    /// the periods a run should show
+    pub fn orbits(&self) -> Vec<Orbit> {
+        let kepler = |distance: f64, mass: f64| {
+            2. * PI * (distance.powi(3) / (self.units().gravitational_constant() * mass)).sqrt()
+        };
+        let orbit = |body, around, period| Orbit { body, around, period };
+        match self {
+            Scenario::TwoBody => vec![orbit(1, Some(0), kepler(1., 1.5))],
+            Scenario::FigureEight => (0..3).map(|body| orbit(body, None, FIGURE_EIGHT_PERIOD)).collect(),
+            Scenario::SunEarthMoon => {
+                let (earth, moon) = (EARTH_MASS, EARTH_MASS / EARTH_MOON_MASS_RATIO);
+                vec![
+                    orbit(1, Some(0), kepler(1., 1. + earth + moon)),
+                    orbit(2, Some(1), kepler(MOON_DISTANCE, earth + moon)),
+                ]
+            }
+        }
+    }
+
+    /// exact forces without softening, in the scenario's units
+    pub fn config(&self) -> SimulationConfig {
+        SimulationConfig { theta: 0., softening: 0., units: self.units(), ..SimulationConfig::default() }
+    }
+
+    /// a simulation of the bodies under `config`, labeled
+    pub fn simulation(&self) -> Simulation {
+        let bodies = self.bodies();
+        let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+        let mut simulation = Simulation::with_config(bodies, space, self.config());
+        for (id, label) in self.labels().iter().enumerate() {
+            simulation.set_label(id as u64, *label);
+        }
+        simulation
+    }
+
+    /// a 200th of the shortest period
+    pub fn dt(&self) -> f64 {
+        self.orbits().iter().map(|orbit| orbit.period).fold(f64::INFINITY, f64::min) / 200.
+    }
+
+    /// enough steps of `dt` to see every period come round once
+    pub fn steps(&self) -> u64 {
+        let longest = self.orbits().iter().map(|orbit| orbit.period).fold(0., f64::max);
+        (1.25 * longest / self.dt()).ceil() as u64
+    }
+
+    /// bounds that leapfrog meets at `dt` with a few times to spare. a broken force or integrator misses
+    /// them by orders of magnitude
+    pub fn tolerances(&self) -> Tolerances {
+        match self {
+            Scenario::TwoBody => Tolerances { period: 1e-3, energy: 1e-6 },
+            Scenario::FigureEight => Tolerances { period: 1e-3, energy: 3e-3 },
+            // the moon's period is off its two-body value by the sun's tide
+            Scenario::SunEarthMoon => Tolerances { period: 2e-2, energy: 1e-8 },
+        }
+    }
+
+    /// `steps()` steps of `dt()` under `config()`, checked along the way
+    pub fn run(&self) -> ScenarioReport {
+        let mut simulation = self.simulation();
+        let mut check = ScenarioCheck::new(*self, &simulation);
+        for _ in 0..self.steps() {
+            simulation.step(self.dt());
+            check.record(&simulation);
+        }
+        check.report()
+    }
+}
+
+// kg of the earth in solar masses
+const EARTH_MASS: f64 = 5.972_2e24 / crate::units::SOLAR_MASS;
+const EARTH_MOON_MASS_RATIO: f64 = 81.300_57;
+// the moon's mean distance, 384 399 km, in AU
+const MOON_DISTANCE: f64 = 3.843_99e8 / crate::units::AU;
+const FIGURE_EIGHT_PERIOD: f64 = 6.325_913_985;
+
+/// follows a run of a scenario: `record` after every step, then `report`
+#[derive(Debug, Clone)]
+pub struct ScenarioCheck {
+    scenario: Scenario,
+    orbits: Vec<Orbit>,
+    tracks: Vec<Track>,
+    monitor: DriftMonitor,
+    max_energy_drift: f64,
+}
+
+// where an orbit's body started, and the last two squared distances from there with their times, until the
+// period is found
+#[derive(Debug, Clone)]
+struct Track {
+    start: Point,
+    recent: Vec<(f64, f64)>,
+    period: Option<f64>,
+}
+
+impl ScenarioCheck {
+    /// starts from the current state of `simulation`, which should be the scenario's
+    pub fn new<S: Scalar, F: ForceModel>(scenario: Scenario, simulation: &Simulation<S, F>) -> Self {
+        let orbits = scenario.orbits();
+        let tracks = orbits
+            .iter()
+            .map(|orbit| Track { start: offset(simulation, orbit), recent: Vec::new(), period: None })
+            .collect();
+        ScenarioCheck {
+            scenario,
+            orbits,
+            tracks,
+            monitor: DriftMonitor::new(simulation.diagnostics(PotentialMethod::Direct)),
+            max_energy_drift: 0.,
+        }
+    }
+
+    pub fn scenario(&self) -> Scenario {
+        self.scenario
+    }
+
+    pub fn record<S: Scalar, F: ForceModel>(&mut self, simulation: &Simulation<S, F>) {
+        let drift = self.monitor.energy_drift(&simulation.diagnostics(PotentialMethod::Direct));
+        self.max_energy_drift = self.max_energy_drift.max(drift.abs());
+        let time = simulation.time();
+        for (orbit, track) in self.orbits.iter().zip(&mut self.tracks) {
+            if track.period.is_some() {
+                continue;
+            }
+            let away = offset(simulation, orbit) - track.start;
+            let sample = (time, away.dot(&away));
+            // the return is the first minimum of the squared distance past three quarters of a period, found as
+            // the vertex of the parabola through it and its neighbours. the figure eight crosses itself at half
+            // a period, where the body that started there comes by again, going the other way
+            if let [(t0, d0), (t1, d1)] = track.recent[..] {
+                let (t2, d2) = sample;
+                if t1 > 0.75 * orbit.period && d1 < d0 && d1 <= d2 {
+                    let (before, after) = ((d1 - d0) / (t1 - t0), (d2 - d1) / (t2 - t1));
+                    let curvature = (after - before) / (t2 - t0);
+                    track.period = Some(if curvature > 0. { (t0 + t1) / 2. - before / (2. * curvature) } else { t1 });
+                }
+                track.recent.remove(0);
+            }
+            track.recent.push(sample);
+        }
+    }
+
+    pub fn report(&self) -> ScenarioReport {
+        ScenarioReport {
+            scenario: self.scenario,
+            periods: self.orbits.iter().zip(&self.tracks).map(|(orbit, track)| (orbit.period, track.period)).collect(),
+            max_energy_drift: self.max_energy_drift,
+        }
+    }
+}
+
+// where the orbit's body is relative to what it goes round
+fn offset<S: Scalar, F: ForceModel>(simulation: &Simulation<S, F>, orbit: &Orbit) -> Point {
+    let location = |id| simulation.body(id).expect("scenario bodies are never removed").location.cast();
+    match orbit.around {
+        Some(around) => location(orbit.body) - location(around),
+        None => location(orbit.body),
+    }
+}
+
+/// what a `ScenarioCheck` measured
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioReport {
+    pub scenario: Scenario,
+    /// the expected period of every orbit, with the measured one once its body came back
+    pub periods: Vec<(f64, Option<f64>)>,
+    /// the largest |E - E0| / |E0| seen, with the potential summed directly
+    pub max_energy_drift: f64,
+}
+
+impl ScenarioReport {
+    /// what missed `tolerances`, empty when nothing did. an orbit that never came back counts as a miss
+    pub fn failures(&self, tolerances: &Tolerances) -> Vec<String> {
+        let mut failures = vec![];
+        for (i, &(expected, measured)) in self.periods.iter().enumerate() {
+            match measured {
+                Some(measured) if ((measured - expected) / expected).abs() <= tolerances.period => {}
+                Some(measured) => failures.push(format!(
+                    "orbit {} has a period of {} instead of {}, off by {:.2e}",
+                    i,
+                    measured,
+                    expected,
+                    (measured - expected) / expected
+                )),
+                None => failures.push(format!("orbit {} never came back within the run", i)),
+            }
+        }
+        if self.max_energy_drift.is_nan() || self.max_energy_drift > tolerances.energy {
+            failures.push(format!(
+                "energy drifted by up to {:.2e}, more than {:.2e}",
+                self.max_energy_drift, tolerances.energy
+            ));
+        }
+        failures
+    }
+
+    /// panics with the failures if there are any, for tests
+    pub fn assert_within(&self, tolerances: &Tolerances) {
+        let failures = self.failures(tolerances);
+        assert!(failures.is_empty(), "{} failed: {}", self.scenario.name(), failures.join("; "));
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.scenario.name())?;
+        for (i, &(expected, measured)) in self.periods.iter().enumerate() {
+            match measured {
+                Some(measured) => write!(
+                    f,
+                    " orbit {} period {:.6} (expected {:.6}, off by {:.2e}),",
+                    i,
+                    measured,
+                    expected,
+                    (measured - expected) / expected
+                )?,
+                None => write!(f, " orbit {} not back yet (expected {:.6}),", i, expected)?,
+            }
+        }
+        write!(f, " max energy drift {:.2e}", self.max_energy_drift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_come_back_to_their_scenarios() {
+        for scenario in Scenario::ALL {
+            assert_eq!(Scenario::from_name(scenario.name()), Some(scenario));
+            assert_eq!(scenario.labels().len(), scenario.bodies().len());
+        }
+        assert_eq!(Scenario::from_name("figure-eight"), None);
+    }
+
+    #[test]
+    fn the_bodies_start_at_rest_about_the_origin() {
+        for scenario in Scenario::ALL {
+            let bodies = scenario.bodies();
+            let (location, velocity) = diagnostics::center_of_mass(&bodies).unwrap();
+            assert!(location.length() < 1e-12 && velocity.length() < 1e-12, "{}", scenario.name());
+            assert!(bodies.iter().enumerate().all(|(i, body)| body.id == i as u64));
+        }
+    }
+
+    #[test]
+    fn misses_are_reported_and_hits_are_not() {
+        let tolerances = Tolerances { period: 1e-3, energy: 1e-6 };
+        let report = |measured, max_energy_drift| ScenarioReport {
+            scenario: Scenario::TwoBody,
+            periods: vec![(2., measured)],
+            max_energy_drift,
+        };
+        assert!(report(Some(2.001), 1e-7).failures(&tolerances).is_empty());
+        assert_eq!(report(Some(2.1), 1e-7).failures(&tolerances).len(), 1);
+        assert_eq!(report(None, 1e-7).failures(&tolerances), ["orbit 0 never came back within the run"]);
+        assert_eq!(report(Some(2.), 1e-5).failures(&tolerances).len(), 1);
+        assert_eq!(report(Some(2.), f64::NAN).failures(&tolerances).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "two-body failed: orbit 0 never came back")]
+    fn assert_within_panics_on_a_miss() {
+        let report = ScenarioReport { scenario: Scenario::TwoBody, periods: vec![(2., None)], max_energy_drift: 0. };
+        report.assert_within(&Scenario::TwoBody.tolerances());
+    }
+
+    #[test]
+    fn a_circular_orbit_comes_round_on_time() {
+        let report = Scenario::TwoBody.run();
+        let (expected, measured) = report.periods[0];
+        assert!((expected - 2. * PI / 1.5f64.sqrt()).abs() < 1e-12);
+        assert!((measured.unwrap() / expected - 1.).abs() < 1e-3, "{}", report);
+        report.assert_within(&Scenario::TwoBody.tolerances());
+    }
+}
@@ -0,0 +1,3023 @@
+use crate::body::{Body, Species};
+use crate::collision::{self, Collision, CollisionPolicy};
+use crate::compare::DiffReport;
+use crate::diagnostics::{
+    self, CompensatedSum, ConservationError, ConservedQuantities, Diagnostics, ForceError, PotentialMethod,
+};
+use crate::drag::Drag;
+use crate::event::{EventKind, SimEvent};
+use crate::dual;
+use crate::external::ExternalPotential;
+use crate::force::{ForceModel, Gravity};
+use crate::ic;
+use crate::geometry::{Cuboid, Point, Range};
+use crate::grid::{Grid, GridQuantity};
+use crate::integrator::{self, Integrator, Scheme, Stage};
+#[cfg(feature = "gpu")]
+use crate::gpu::GpuForces;
+#[cfg(feature = "png")]
+use crate::geometry::Axis;
+use crate::linear::{morton_key, LinearOctree};
+use crate::load::{self, LoadError};
+use crate::observer::StepObserver;
+use crate::regularization::{self, Regularization};
+use crate::scalar::Scalar;
+use crate::spring::{self, Spring};
+use crate::state::{StateHandle, StateSnapshot};
+use crate::stop::{StopCondition, StopEvent};
+use crate::tree::{Acceptance, BodyTree, Interactions, Period, MultipoleOrder, Opening, TreeStats, WalkStack};
+use crate::units::Units;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+// the bare wasm target has no clock in std, so step timings come from the browser's
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use web_time::Instant;
+
+/// which tree the forces are computed on. both give the same accelerations up to rounding
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TreeBackend {
+    /// the `Octree` with index-linked nodes, which can also take insertions and spatial queries
+    #[default]
+    Pointer,
+    /// a `LinearOctree` rebuilt from a morton sort every time; much faster to construct at large n
+    Linear,
+    /// the linear tree, walked on the gpu in f32 with the `gpu` feature. only newtonian monopole forces with an
+    /// open boundary run there; anything else, a build without the feature, or no usable device leaves the
+    /// forces to the cpu walk of the linear tree
+    Gpu,
+}
+
+/// how the tree is walked for the forces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Traversal {
+    /// once per body, opening nodes until each is far enough from that body
+    #[default]
+    Single,
+    /// pairs of nodes together, letting a far node act once on a whole group of bodies through a field
+    /// expanded about the group's center of mass. theta bounds the sum of both nodes' sizes, so it wants a
+    /// smaller value for the same error: 0.4 does about what 0.7 does for a single walk, in roughly a third of
+    /// the time on a clumped plummer sphere (the `traversal` group of `benches/tree.rs`), but on one thread.
+    /// only plain gravity with monopoles and an open boundary walks this way; anything else, and the partial
+    /// force passes of block timesteps, walk per body
+    Dual,
+    /// no walk at all but the exact o(n^2) sum of `Simulation::compute_accelerations_pairwise`, which works out
+    /// each pair's force once and hands it to both bodies with opposite signs. the forces then cancel pair by
+    /// pair, so momentum and angular momentum are conserved to rounding, which no tree can do: a node's pull on
+    /// a body is not matched by the body's pull on the node's bodies. theta is not used, other than by the
+    /// jerks of the hermite integrators, which still come from the tree
+    Pairwise,
+}
+
+/// knobs for the force calculation and `Simulation::step`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SimulationConfig {
+    /// barnes-hut opening angle. 0 opens every node and gives the exact direct sum; around 0.5 is the
+    /// usual tradeoff
+    pub theta: f64,
+    /// plummer softening length ε, applied to body-body and body-node terms alike. pick it around the
+    /// mean interparticle spacing to stop close encounters from blowing up the integration
+    pub softening: f64,
+    pub backend: TreeBackend,
+    pub traversal: Traversal,
+    /// bodies a leaf holds before it splits. larger buckets make shallower trees and more direct sums;
+    /// a small bucket of 4 to 8 tends to be fastest
+    pub bucket_size: usize,
+    /// live bodies lighter than this, by the size of their mass, are made tracers as they join the
+    /// simulation, so that in a system with a wide range of masses the lightest feel the rest but stop costing
+    /// the tree and the direct sums. they leave the diagnostics and collisions along with it. 0 keeps them all
+    pub tracer_mass: f64,
+    /// what happens to bodies that leave the root box
+    pub escape: EscapePolicy,
+    pub timestep: Timestep,
+    /// how `step` moves the bodies between force evaluations
+    pub integrator: Scheme,
+    /// close bound pairs to move along their kepler orbits instead of by the tree's softened forces
+    pub regularization: Regularization,
+    pub collisions: CollisionPolicy,
+    /// the expansion used for accepted tree nodes. quadrupoles cost a little more per step but allow a
+    /// larger theta for the same accuracy
+    pub multipole: MultipoleOrder,
+    /// how a walk tells whether a node stands in for its bodies: a size of it held against theta, or its mass
+    /// weighed in by a tolerance
+    pub opening: Opening,
+    /// the edges of the root box along x, y and z, e.g. periodic along x and y and open along z for a slab
+    pub boundary: [BoundaryCondition; 3],
+    pub rebuild: RebuildStrategy,
+    /// with the pointer backend, refresh the tree per `rebuild` only every this many force passes, refitting it
+    /// in between: the bodies stay in the leaves they were in while their masses and centers follow them. the
+    /// boxes then lag behind the bodies, so a node can be accepted while its bodies have spread past its box,
+    /// and the forces drift further from theta's accuracy the more the bodies move between refreshes. 1 keeps
+    /// the tree fresh every step; a resumed or undone run starts again on a fresh tree. panics in `step` if 0
+    pub rebuild_every: usize,
+    /// sort the bodies by the Morton key of where they are at the start of every step, so bodies near each other
+    /// in space sit near each other in memory for the force pass. `bodies()` then changes order from step to
+    /// step, though every body keeps its id, and the tree is built from scratch every step whatever `rebuild`
+    /// and `rebuild_every` say, since refits and relocations follow bodies by their place in the order
+    pub morton_order: bool,
+    pub recentering: Recentering,
+    /// the units bodies are given in, which fix the gravitational constant forces and potentials are scaled by
+    pub units: Units,
+    /// whether the bodies pull on each other. without it they are test particles moving only under the external
+    /// potentials, springs and drag, and the tree is kept for the queries but not walked for forces; the
+    /// potential energy then holds only those terms too, and `regularization` is not used
+    pub self_gravity: bool,
+    /// a distance past which bodies stop pulling on each other. this changes the physics: the force law is
+    /// truncated there, and the potential energy is that of each pair within it less its value at the cutoff,
+    /// so that a pair crossing it does not step the energy. each body is then pulled by the bodies the tree
+    /// finds within the cutoff, summed one by one, in place of a walk and whatever `traversal` says, which
+    /// for a short cutoff at a uniform density is o(n). a periodic boundary takes the nearest image of each
+    /// body, and panics in `step` for a cutoff longer than half the box along a periodic axis. the jerks of
+    /// the hermite integrators still come from the whole tree
+    pub cutoff: Option<f64>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            theta: 0.5,
+            softening: 0.,
+            backend: TreeBackend::Pointer,
+            traversal: Traversal::Single,
+            bucket_size: 1,
+            tracer_mass: 0.,
+            escape: EscapePolicy::Expand,
+            timestep: Timestep::Fixed,
+            integrator: Scheme::Leapfrog,
+            regularization: Regularization::Off,
+            collisions: CollisionPolicy::Ignore,
+            multipole: MultipoleOrder::Monopole,
+            opening: Opening::Box,
+            boundary: [BoundaryCondition::Open; 3],
+            rebuild: RebuildStrategy::Always,
+            rebuild_every: 1,
+            morton_order: false,
+            recentering: Recentering::Off,
+            units: Units::Dimensionless,
+            self_gravity: true,
+            cutoff: None,
+        }
+    }
+}
+
+impl SimulationConfig {
+    /// the constants the forces are computed with, in precision `S`
+    pub fn constants<S: Scalar>(&self) -> ForceConstants<S> {
+        ForceConstants {
+            gravitational_constant: S::from_f64(self.units.gravitational_constant()),
+            softening: S::from_f64(self.softening),
+            theta: S::from_f64(self.theta),
+        }
+    }
+}
+
+/// the force constants of a `SimulationConfig` in the precision of a simulation. the config holds them in f64,
+/// so that one config and one checkpoint layout serve either precision, and `Simulation` works out these once
+/// per pass over the bodies, so an f32 simulation does all of its force arithmetic in f32. the `dt` of a step
+/// is rounded to the precision once per kick and drift in the same way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ForceConstants<S> {
+    /// of the configured units
+    pub gravitational_constant: S,
+    pub softening: S,
+    pub theta: S,
+}
+
+/// what `Simulation::step` does with bodies that drift out of the root box
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum EscapePolicy {
+    /// grow the root to the power-of-two cube around the old root and every body, keeping them all
+    #[default]
+    Expand,
+    /// move escaped bodies back onto the nearest point of the boundary, keeping their velocity
+    Clamp,
+    /// drop escaped bodies from the simulation
+    Remove,
+}
+
+/// how `Simulation::step` picks the length of a step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Timestep {
+    /// exactly the `dt` passed to `step`
+    #[default]
+    Fixed,
+    /// η·sqrt(ε / |a|max) from the largest acceleration at the start of the step, with ε the softening length,
+    /// kept between `min` and the `dt` passed to `step`. without softening the criterion is 0, so every step
+    /// takes `min`
+    Adaptive { eta: f64, min: f64 },
+    /// hierarchical block steps: each body takes the longest of dt, dt / 2, dt / 4, ... down to
+    /// dt / 2^levels that the adaptive criterion allows, picked at the start of every `step`. only bodies
+    /// finishing their own sub-step get new forces, so the few bodies in a dense core stop costing a full
+    /// force pass each. without softening every body takes the finest sub-step. panics above 32 levels
+    Block { eta: f64, levels: u32 },
+}
+
+/// what a call to `Simulation::step` did
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepReport {
+    /// the length of the step taken
+    pub dt: f64,
+    /// bodies whose acceleration was computed, counted once per computation
+    pub force_evaluations: usize,
+    /// pairs the `CollisionPolicy` acted on, in the order they were handled
+    pub collisions: Vec<Collision>,
+    /// an observer asked for the run to stop after this step, or the stop condition held
+    pub stop_requested: bool,
+    /// what made the stop condition hold after this step
+    pub stop: Option<StopEvent>,
+}
+
+/// where the wall time of a step went, from `Simulation::step_timed`. the parts add up to the whole step
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StepTiming {
+    /// building or updating the tree, along with the mass and center of mass of every node, which are gathered
+    /// as the bodies go in
+    pub tree: Duration,
+    /// the quadrupole pass over the finished tree; none with monopoles
+    pub moments: Duration,
+    /// the accelerations, and the jerks of integrators that take them
+    pub forces: Duration,
+    /// everything else: kicks, drifts, escapes, collisions, drag and the observers
+    pub integration: Duration,
+    /// the terms the step's force passes summed, with `Simulation::set_interaction_counting` on. the tree
+    /// walks count each accepted node and each body of an opened leaf, the pairwise sum each pair once and a
+    /// cutoff each neighbor; the dual walk, the gpu and the hermite jerks go uncounted
+    pub interactions: Option<Interactions>,
+}
+
+impl StepTiming {
+    pub fn total(&self) -> Duration {
+        self.tree + self.moments + self.forces + self.integration
+    }
+}
+
+/// how fast things happen among the bodies, from `Simulation::motion_stats`: when an adaptive timestep keeps
+/// shrinking, a short crossing time says a close or fast encounter is why
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MotionStats {
+    /// the speed of the fastest body
+    pub max_speed: f64,
+    /// the distance between the closest two bodies, infinite with fewer than two
+    pub min_separation: f64,
+    /// `min_separation` over `max_speed`, the shortest time in which two bodies could meet
+    pub min_crossing_time: f64,
+}
+
+impl std::fmt::Display for MotionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "max speed {:e}, min separation {:e}, min crossing time {:e}",
+            self.max_speed, self.min_separation, self.min_crossing_time
+        )
+    }
+}
+
+/// the edges of the root box along one axis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum BoundaryCondition {
+    /// space goes on past the box, and bodies leaving it through these faces follow the `EscapePolicy`
+    #[default]
+    Open,
+    /// the box repeats along the axis: bodies leaving through one face come back through the opposite one,
+    /// and forces act across the faces by the minimum-image convention. periodic on every axis is meant for
+    /// uniform cosmological-style boxes, with the root box fixed and the escape policy not used. with some axes
+    /// open the box stays fixed too, so `EscapePolicy::Expand` panics in `step`; bodies leaving through an open
+    /// face are clamped or removed
+    Periodic,
+}
+
+/// how the tree follows the bodies from one step to the next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum RebuildStrategy {
+    /// build it from scratch after every drift
+    #[default]
+    Always,
+    /// with the pointer backend, move only the bodies that left their leaf's box and leave the rest of the
+    /// tree standing, via `Octree::relocate`. it is built from scratch instead when more than `max_moved` of
+    /// the bodies, as a fraction, left their leaves in one go, a sign the tree no longer fits them, and
+    /// whenever bodies are removed or the root box grows. the linear backend is always rebuilt
+    Incremental { max_moved: f64 },
+}
+
+/// whether `Simulation::step` keeps moving the bodies back into their center-of-mass frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Recentering {
+    /// leave them be. the tree does not conserve momentum exactly, so the system slowly drifts off
+    #[default]
+    Off,
+    /// `Simulation::to_com_frame` after every `steps` steps, which keeps that drift from adding up over long
+    /// runs. panics in `step` if `steps` is 0
+    Every { steps: u64 },
+}
+
+/// an opening angle that depends on where the body feeling the force is, see `Simulation::set_theta_field`
+pub type ThetaField = dyn Fn(&Point) -> f64 + Send + Sync;
+
+// what `Simulation::undo` goes back to: everything a step changes, less the tree, which is built again
+struct Rewind<S: Scalar> {
+    bodies: Vec<Body<S>>,
+    space: Cuboid<S>,
+    accelerations: Vec<Point<S>>,
+    jerks: Vec<Point<S>>,
+    drag_work: f64,
+    next_id: u64,
+    time: f64,
+    steps: u64,
+}
+
+// the tree behind a simulation, per its backend
+enum ForceTree<S: Scalar> {
+    Pointer(BodyTree<S>),
+    Linear(LinearOctree<S>),
+}
+
+impl<S: Scalar> ForceTree<S> {
+    fn build(config: &SimulationConfig, bodies: &[Body<S>], space: Cuboid<S>) -> Self {
+        let mut tree = ForceTree::build_monopoles(config, bodies, space);
+        tree.compute_moments(config);
+        tree
+    }
+
+    // the tree with the masses and centers of mass of its nodes, but no higher moments yet
+    fn build_monopoles(config: &SimulationConfig, bodies: &[Body<S>], space: Cuboid<S>) -> Self {
+        let _span = tracing::debug_span!("tree_build", bodies = bodies.len(), backend = ?config.backend).entered();
+        let bodies = bodies.iter().filter(|body| body.is_source()).copied();
+        match config.backend {
+            TreeBackend::Pointer => ForceTree::Pointer(BodyTree::build_bucketed(bodies, space, config.bucket_size)),
+            TreeBackend::Linear | TreeBackend::Gpu => {
+                ForceTree::Linear(LinearOctree::build_bucketed(bodies, space, config.bucket_size))
+            }
+        }
+    }
+
+    // the quadrupoles and bounding spheres, if the config asks for them
+    fn compute_moments(&mut self, config: &SimulationConfig) {
+        if config.multipole == MultipoleOrder::Quadrupole {
+            match self {
+                ForceTree::Pointer(tree) => tree.compute_quadrupoles(),
+                ForceTree::Linear(tree) => tree.compute_quadrupoles(),
+            }
+        }
+        if config.opening == Opening::BoundingSphere {
+            match self {
+                ForceTree::Pointer(tree) => tree.compute_radii(),
+                ForceTree::Linear(tree) => tree.compute_radii(),
+            }
+        }
+    }
+
+    // the walk keeps its place on `stack`, so a force pass can hand the same one to every target
+    fn acceleration_at<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        boundary: [BoundaryCondition; 3],
+        stack: &mut WalkStack<S>,
+    ) -> Point<S> {
+        let period = self.period(boundary);
+        let period = period.as_ref();
+        match self {
+            ForceTree::Pointer(tree) => {
+                tree.acceleration_with_stack(model, target, acceptance, softening, period, stack)
+            }
+            ForceTree::Linear(tree) => {
+                tree.acceleration_with_stack(model, target, acceptance, softening, period, stack)
+            }
+        }
+    }
+
+    fn potential_at<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        boundary: [BoundaryCondition; 3],
+    ) -> S {
+        let period = self.period(boundary);
+        let period = period.as_ref();
+        match self {
+            ForceTree::Pointer(tree) => tree.potential_with(model, target, acceptance, softening, period),
+            ForceTree::Linear(tree) => tree.potential_with(model, target, acceptance, softening, period),
+        }
+    }
+
+    fn within_radius(&self, center: &Point<S>, radius: S) -> Vec<&Body<S>> {
+        match self {
+            ForceTree::Pointer(tree) => tree.within_radius(center, radius),
+            ForceTree::Linear(tree) => tree.within_radius(center, radius),
+        }
+    }
+
+    // the periodic box, when there is one
+    fn bounds(&self) -> &Cuboid<S> {
+        match self {
+            ForceTree::Pointer(tree) => tree.bounds(),
+            ForceTree::Linear(tree) => tree.bounds(),
+        }
+    }
+
+    // the box repeating along the periodic axes of `boundary`, none when every axis is open
+    fn period(&self, boundary: [BoundaryCondition; 3]) -> Option<Period<S>> {
+        let axes = periodic_axes(boundary);
+        axes.contains(&true).then(|| Period { space: *self.bounds(), axes })
+    }
+}
+
+/// bodies and their tree, advanced in time by `step`. `S` is the precision bodies are stored and forces
+/// computed in; the config and the clock are f64 either way. `F` is the force law, newtonian gravity unless
+/// the simulation is made with `with_model`
+pub struct Simulation<S: Scalar = f64, F = Gravity> {
+    bodies: Vec<Body<S>>,
+    // the root box every rebuild uses
+    space: Cuboid<S>,
+    // built from copies of `bodies`
+    tree: ForceTree<S>,
+    config: SimulationConfig,
+    // accelerations at the current positions, carried over from the closing kick of the previous step.
+    // empty until the first step or after bodies are added
+    accelerations: Vec<Point<S>>,
+    // jerks to go with `accelerations`, worked out when an integrator asks for them. empty when out of date
+    jerks: Vec<Point<S>>,
+    model: F,
+    // background fields added on top of the bodies' own forces
+    potentials: Vec<Box<dyn ExternalPotential>>,
+    // at most one per species
+    drag: Vec<(Species, Drag)>,
+    // by the drag on the sources since the start, or the resume
+    drag_work: f64,
+    springs: Vec<Spring>,
+    // the opening angle by where the sink body is, in place of the configured one
+    theta_field: Option<Box<ThetaField>>,
+    // kept only while `event_log` is on
+    events: Vec<SimEvent>,
+    event_log: bool,
+    observers: Vec<Box<dyn StepObserver<S, F>>>,
+    // checked after every step, with the total energy when it was set if it needs that
+    stop: Option<(StopCondition, f64)>,
+    // the id the next added body gets
+    next_id: u64,
+    labels: BTreeMap<u64, String>,
+    // what the bodies were drawn with, when they were random and the caller said
+    seed: Option<u64>,
+    // the state at the end of the last step, for other threads
+    published: StateHandle<S>,
+    // the open device when the backend is `Gpu` and one could be had
+    #[cfg(feature = "gpu")]
+    gpu: Option<GpuForces>,
+    // the states before the latest steps, newest last, at most `undo_depth` of them
+    history: VecDeque<Rewind<S>>,
+    undo_depth: usize,
+    // force passes on a refitted tree since it was last refreshed, see `SimulationConfig::rebuild_every`
+    refits: usize,
+    // the ids of the pairs the step under way moves as two-body problems, see `SimulationConfig::regularization`
+    regularized: Vec<(u64, u64)>,
+    // the tree and force parts of the step under way
+    timing: StepTiming,
+    // the terms the force passes have summed since the step began, when they are being counted
+    interactions: Option<InteractionCounter>,
+    time: f64,
+    steps: u64,
+}
+
+impl<S: Scalar> Simulation<S> {
+    pub fn new(bodies: Vec<Body<S>>, space: Cuboid<S>) -> Self {
+        Simulation::with_config(bodies, space, SimulationConfig::default())
+    }
+
+    pub fn with_config(bodies: Vec<Body<S>>, space: Cuboid<S>, config: SimulationConfig) -> Self {
+        Simulation::with_model(bodies, space, config, Gravity)
+    }
+
+    /// `ic::uniform_box` bodies. the same rng state always gives the same bodies, so pass a seeded one for
+    /// reproducible runs
+    pub fn new_random<R: Rng + ?Sized>(n: usize, space: Cuboid<S>, rng: &mut R) -> Self {
+        let bodies = ic::uniform_box(n, &space.cast(), rng).iter().map(Body::cast).collect();
+        Simulation::new(bodies, space)
+    }
+
+    /// `new_random` from a `StdRng` seeded with `seed`, which the simulation keeps and checkpoints carry, so the
+    /// same bodies can be drawn again with `new_seeded(n, space, simulation.seed().unwrap())` later on
+    pub fn new_seeded(n: usize, space: Cuboid<S>, seed: u64) -> Self {
+        let mut simulation = Simulation::new_random(n, space, &mut StdRng::seed_from_u64(seed));
+        simulation.set_seed(seed);
+        simulation
+    }
+
+    /// reads bodies with `load::read_bodies` and fits a power-of-two cube around them
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self, LoadError> {
+        let bodies: Vec<Body<S>> = load::read_bodies(path)?.iter().map(Body::cast).collect();
+        let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+        Ok(Simulation::new(bodies, space))
+    }
+}
+
+impl<S: Scalar, F: ForceModel> Simulation<S, F> {
+    /// a simulation under the force law `model` in place of gravity
+    pub fn with_model(mut bodies: Vec<Body<S>>, space: Cuboid<S>, config: SimulationConfig, model: F) -> Self {
+        for (id, body) in bodies.iter_mut().enumerate() {
+            body.id = id as u64;
+        }
+        let next_id = bodies.len() as u64;
+        Simulation::from_parts(bodies, space, config, model, next_id, BTreeMap::new())
+    }
+
+    // a simulation of bodies that already carry their ids, for resuming a checkpoint
+    pub(crate) fn from_parts(
+        mut bodies: Vec<Body<S>>,
+        space: Cuboid<S>,
+        config: SimulationConfig,
+        model: F,
+        next_id: u64,
+        labels: BTreeMap<u64, String>,
+    ) -> Self {
+        #[cfg(not(feature = "gpu"))]
+        if config.backend == TreeBackend::Gpu {
+            tracing::warn!("built without the gpu feature, computing forces on the cpu");
+        }
+        demote_light(&mut bodies, config.tracer_mass);
+        let tree = ForceTree::build(&config, &bodies, space);
+        let published = StateHandle::new(StateSnapshot {
+            step: 0,
+            time: 0.,
+            bounds: space,
+            bodies: bodies.clone(),
+        });
+        Simulation {
+            bodies,
+            space,
+            tree,
+            config,
+            accelerations: Vec::new(),
+            jerks: Vec::new(),
+            model,
+            potentials: Vec::new(),
+            drag: Vec::new(),
+            drag_work: 0.,
+            springs: Vec::new(),
+            theta_field: None,
+            events: Vec::new(),
+            event_log: false,
+            observers: Vec::new(),
+            stop: None,
+            next_id,
+            labels,
+            seed: None,
+            published,
+            #[cfg(feature = "gpu")]
+            gpu: open_gpu(&config),
+            history: VecDeque::new(),
+            undo_depth: 0,
+            refits: 0,
+            regularized: Vec::new(),
+            timing: StepTiming::default(),
+            interactions: None,
+            time: 0.,
+            steps: 0,
+        }
+    }
+
+    pub fn model(&self) -> &F {
+        &self.model
+    }
+
+    /// walks the tree for each body with the opening angle `theta` gives at its location, in place of the
+    /// configured one, so that a region that needs accuracy can have a small theta and the rest a coarse one.
+    /// it applies to the accelerations only: the potential, the jerks and `force_error` keep the configured
+    /// theta, and the gpu and the dual walk, which take one theta for every body, step aside for the single
+    /// walk. not saved in a checkpoint, so set it again after resuming
+    pub fn set_theta_field(&mut self, theta: impl Fn(&Point) -> f64 + Send + Sync + 'static) {
+        self.theta_field = Some(Box::new(theta));
+        self.accelerations.clear();
+    }
+
+    pub fn clear_theta_field(&mut self) {
+        self.theta_field = None;
+        self.accelerations.clear();
+    }
+
+    pub fn theta_field(&self) -> Option<&ThetaField> {
+        self.theta_field.as_deref()
+    }
+
+    /// adds a fixed background field that acts on every body from the next step on, and counts towards the
+    /// potential energy; momentum is not conserved under them. potentials are not saved in a checkpoint, so add
+    /// them again after resuming
+    pub fn add_potential(&mut self, potential: impl ExternalPotential + 'static) {
+        self.potentials.push(Box::new(potential));
+        self.accelerations.clear();
+    }
+
+    pub fn potentials(&self) -> &[Box<dyn ExternalPotential>] {
+        &self.potentials
+    }
+
+    /// drags the bodies of `species` from the next step on, in place of any drag set for them before. see the
+    /// drag module
+    pub fn set_drag(&mut self, species: Species, drag: Drag) {
+        self.clear_drag(species);
+        self.drag.push((species, drag));
+    }
+
+    pub fn clear_drag(&mut self, species: Species) {
+        self.drag.retain(|(dragged, _)| *dragged != species);
+    }
+
+    pub fn drag(&self, species: Species) -> Option<&Drag> {
+        self.drag.iter().find(|(dragged, _)| *dragged == species).map(|(_, drag)| drag)
+    }
+
+    /// the work drag has done on the live bodies so far, negative when it took energy out. tracers are left
+    /// out, as they are of the energy
+    pub fn drag_work(&self) -> f64 {
+        self.drag_work
+    }
+
+    /// ties two bodies together with a harmonic spring from the next step on, pulling on top of gravity. a
+    /// spring whose bodies are not both there pulls on neither. see the spring module
+    pub fn add_spring(&mut self, spring: Spring) {
+        self.springs.push(spring);
+        self.accelerations.clear();
+    }
+
+    pub fn springs(&self) -> &[Spring] {
+        &self.springs
+    }
+
+    pub fn clear_springs(&mut self) {
+        self.springs.clear();
+        self.accelerations.clear();
+    }
+
+    /// starts or stops logging merges, bounces and bodies leaving the root box. off by default, since a periodic
+    /// box sees bodies cross it all the time. see the event module
+    pub fn set_event_log(&mut self, enabled: bool) {
+        self.event_log = enabled;
+    }
+
+    /// starts or stops counting the terms of every force pass, which `step_timed` hands back in its
+    /// `StepTiming`. off by default; walks on a stack count anyway, so on it costs two atomic adds per body
+    pub fn set_interaction_counting(&mut self, enabled: bool) {
+        self.interactions = enabled.then(InteractionCounter::default);
+    }
+
+    /// everything logged so far, oldest first
+    pub fn events(&self) -> &[SimEvent] {
+        &self.events
+    }
+
+    /// the events logged so far, leaving the log empty
+    pub fn take_events(&mut self) -> Vec<SimEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // logs `kind` for `body_ids` at the current step and time, if the log is on and there are any
+    fn log_event(&mut self, kind: EventKind, body_ids: Vec<u64>) {
+        if self.event_log && !body_ids.is_empty() {
+            self.events.push(SimEvent { step: self.steps, time: self.time, kind, body_ids });
+        }
+    }
+
+    /// keeps the state from before each of the last `depth` steps so that `undo` can go back to it, copying the
+    /// bodies once per step. 0, the default, keeps none; a smaller depth than before forgets the oldest
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        while self.history.len() > depth {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn undo_depth(&self) -> usize {
+        self.undo_depth
+    }
+
+    /// how many steps `undo` can still go back
+    pub fn undoable(&self) -> usize {
+        self.history.len()
+    }
+
+    /// puts the bodies, the root box and the clock back as they were before the latest step still kept, exactly,
+    /// and returns whether there was one. anything done to the simulation since then goes along with it, other
+    /// than its config, forces and observers. stepping again gives the same step as before as long as the tree
+    /// is built from scratch, as it is unless the rebuild strategy is incremental
+    pub fn undo(&mut self) -> bool {
+        let Some(rewind) = self.history.pop_back() else {
+            return false;
+        };
+        self.bodies = rewind.bodies;
+        self.space = rewind.space;
+        self.accelerations = rewind.accelerations;
+        self.jerks = rewind.jerks;
+        self.drag_work = rewind.drag_work;
+        self.next_id = rewind.next_id;
+        self.time = rewind.time;
+        self.steps = rewind.steps;
+        self.tree = ForceTree::build(&self.config, &self.bodies, self.space);
+        self.refits = 0;
+        self.publish_state();
+        true
+    }
+
+    /// adds an observer whose hooks run during every step from now on
+    pub fn add_observer(&mut self, observer: impl StepObserver<S, F> + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// stops the run after any step that ends with `condition` holding, in place of any set before. an energy
+    /// drift is measured from now. the condition is not saved in a checkpoint, so set it again after resuming
+    pub fn set_stop_condition(&mut self, condition: StopCondition) {
+        let watched = condition.watches_energy();
+        let energy = if watched { self.diagnostics(PotentialMethod::Tree).conserved_energy() } else { 0. };
+        self.stop = Some((condition, energy));
+    }
+
+    pub fn stop_condition(&self) -> Option<&StopCondition> {
+        self.stop.as_ref().map(|(condition, _)| condition)
+    }
+
+    // runs `hook` on every observer, which get the simulation as it is now
+    fn notify(&mut self, mut hook: impl FnMut(&mut dyn StepObserver<S, F>, &Self)) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let mut observers = std::mem::take(&mut self.observers);
+        for observer in &mut observers {
+            hook(observer.as_mut(), self);
+        }
+        self.observers = observers;
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    /// the config's force constants in the simulation's precision
+    pub fn constants(&self) -> ForceConstants<S> {
+        self.config.constants()
+    }
+
+    /// simulated time elapsed over all steps so far
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // for resuming a checkpoint
+    pub(crate) fn set_clock(&mut self, time: f64, steps: u64) {
+        self.time = time;
+        self.steps = steps;
+        self.publish_state();
+    }
+
+    /// the bodies and clock as they were at the end of the last step, or when the simulation was made before
+    /// the first. see the state module
+    pub fn published_state(&self) -> Arc<StateSnapshot<S>> {
+        self.published.latest()
+    }
+
+    /// a handle to read `published_state` through from other threads while this one steps
+    pub fn state_handle(&self) -> StateHandle<S> {
+        self.published.clone()
+    }
+
+    fn publish_state(&self) {
+        self.published.publish(StateSnapshot {
+            step: self.steps,
+            time: self.time,
+            bounds: self.space,
+            bodies: self.bodies.clone(),
+        });
+    }
+
+    pub fn bodies(&self) -> &[Body<S>] {
+        &self.bodies
+    }
+
+    /// where the body with `id` is in `bodies()`, if it is still there. a linear search, since removals and
+    /// merges move bodies around
+    pub fn index_of(&self, id: u64) -> Option<usize> {
+        self.bodies.iter().position(|body| body.id == id)
+    }
+
+    /// the body with `id`, if it is still there
+    pub fn body(&self, id: u64) -> Option<&Body<S>> {
+        self.index_of(id).map(|i| &self.bodies[i])
+    }
+
+    /// the bodies in the order of their ids, which stays put as bodies move and the tree or `morton_order`
+    /// shuffles `bodies()`, so that each has the same row or column from one frame of output to the next
+    pub fn bodies_by_id(&self) -> impl Iterator<Item = &Body<S>> + '_ {
+        let mut bodies: Vec<&Body<S>> = self.bodies.iter().collect();
+        bodies.sort_unstable_by_key(|body| body.id);
+        bodies.into_iter()
+    }
+
+    /// each body with its acceleration, in the order of `bodies()`: the one the last step closed on, or, before
+    /// the first step and after anything that changed the forces, one worked out fresh at the current positions
+    pub fn bodies_with_acceleration(&self) -> impl Iterator<Item = (&Body<S>, Point<S>)> + '_ {
+        let accelerations = if self.accelerations.len() == self.bodies.len() {
+            self.accelerations.clone()
+        } else {
+            self.compute_accelerations()
+        };
+        self.bodies.iter().zip(accelerations)
+    }
+
+    /// names the body with `id`, e.g. "jupiter", for snapshots and checkpoints to carry along. the label stays
+    /// after the body is removed or merged away
+    pub fn set_label(&mut self, id: u64, label: impl Into<String>) {
+        self.labels.insert(id, label.into());
+    }
+
+    pub fn label(&self, id: u64) -> Option<&str> {
+        self.labels.get(&id).map(String::as_str)
+    }
+
+    /// every label set so far, by id
+    pub fn labels(&self) -> &BTreeMap<u64, String> {
+        &self.labels
+    }
+
+    /// records the seed the bodies were generated from, for `seed` to hand back and checkpoints to keep. the
+    /// simulation draws no random numbers itself, so this changes nothing about the run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    /// the seed given to `new_seeded` or `set_seed`, if any
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    // the id the next added body will get
+    pub(crate) fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    pub fn bounds(&self) -> &Cuboid<S> {
+        &self.space
+    }
+
+    /// the pointer tree, if that is the backend
+    pub fn tree(&self) -> Option<&BodyTree<S>> {
+        match &self.tree {
+            ForceTree::Pointer(tree) => Some(tree),
+            ForceTree::Linear(_) => None,
+        }
+    }
+
+    /// the linear tree, if that or the gpu is the backend
+    pub fn linear_tree(&self) -> Option<&LinearOctree<S>> {
+        match &self.tree {
+            ForceTree::Linear(tree) => Some(tree),
+            ForceTree::Pointer(_) => None,
+        }
+    }
+
+    /// the shape of the current tree, whichever the backend
+    pub fn tree_stats(&self) -> TreeStats {
+        match &self.tree {
+            ForceTree::Pointer(tree) => tree.stats(),
+            ForceTree::Linear(tree) => tree.stats(),
+        }
+    }
+
+    /// the fastest body and closest pair, the separation found by asking the tree for each body's nearest
+    /// neighbor. tracers count for speed, but are measured only against the sources in the tree
+    pub fn motion_stats(&self) -> MotionStats {
+        let max_speed = self.bodies.iter().map(|body| body.velocity.length().as_f64()).fold(0., f64::max);
+        let min_separation = self
+            .bodies
+            .iter()
+            .filter_map(|body| {
+                let neighbors = match &self.tree {
+                    ForceTree::Pointer(tree) => tree.k_nearest(&body.location, 2),
+                    ForceTree::Linear(tree) => tree.k_nearest(&body.location, 2),
+                };
+                let neighbor = neighbors.into_iter().find(|neighbor| neighbor.id != body.id)?;
+                Some(neighbor.location.distance_squared(&body.location).sqrt().as_f64())
+            })
+            .fold(f64::INFINITY, f64::min);
+        MotionStats { max_speed, min_separation, min_crossing_time: min_separation / max_speed }
+    }
+
+    /// how the bodies here differ from those of `other`, matched by id, e.g. between a run on the pointer tree
+    /// and one on the linear tree, or on one thread and several; see `DiffReport`
+    pub fn diff<T: Scalar, G: ForceModel>(&self, other: &Simulation<T, G>) -> DiffReport {
+        DiffReport::between(&self.bodies, other.bodies())
+    }
+
+    /// adds bodies mid-run, e.g. for matter falling in, returning the ids they were given in order. bodies
+    /// outside the root box are handled as if they had drifted out of it, per the `EscapePolicy` or wrapped
+    /// around a periodic box, so under `Remove` some of the ids may be gone again. the pointer tree takes the
+    /// bodies as insertions when they all land inside the box; otherwise, and with the linear tree, it is
+    /// rebuilt. bodies lighter than the configured `tracer_mass` join as tracers
+    pub fn add_bodies(&mut self, mut bodies: Vec<Body<S>>) -> std::ops::Range<u64> {
+        demote_light(&mut bodies, self.config.tracer_mass);
+        let first = self.next_id;
+        for body in &mut bodies {
+            body.id = self.next_id;
+            self.next_id += 1;
+        }
+        let inside = bodies.iter().all(|body| self.space.contains(&body.location));
+        self.bodies.extend(bodies.iter().copied());
+        self.accelerations.clear();
+        self.jerks.clear();
+        if !inside {
+            self.handle_escapes();
+            self.tree = ForceTree::build(&self.config, &self.bodies, self.space);
+            return first..self.next_id;
+        }
+        match &mut self.tree {
+            ForceTree::Pointer(tree) => {
+                for body in bodies.into_iter().filter(Body::is_source) {
+                    tree.insert(body);
+                }
+            }
+            ForceTree::Linear(_) => self.tree = ForceTree::build(&self.config, &self.bodies, self.space),
+        }
+        self.tree.compute_moments(&self.config);
+        first..self.next_id
+    }
+
+    /// advances every body by `dt` with the configured `integrator`, kick-drift-kick leapfrog unless set
+    /// otherwise. bodies that leave the root box after a drift are handled per the configured `EscapePolicy`,
+    /// then close pairs per the `CollisionPolicy`. an adaptive `Timestep` may take a shorter step than `dt`;
+    /// the report says how long it was. each step runs in a `step` tracing span and ends with a debug event
+    /// carrying its wall time and counts. observers are called along the way, see `StepObserver`
+    pub fn step(&mut self, dt: f64) -> StepReport {
+        let integrator = self.config.integrator;
+        self.step_with(dt, &integrator)
+    }
+
+    /// `step` along with where its time went. the clock is read around every tree build and force pass, which
+    /// costs next to nothing next to them
+    pub fn step_timed(&mut self, dt: f64) -> (StepReport, StepTiming) {
+        let instant = Instant::now();
+        let report = self.step(dt);
+        let elapsed = instant.elapsed();
+        let mut timing = self.timing;
+        timing.integration = elapsed.saturating_sub(timing.tree + timing.moments + timing.forces);
+        timing.interactions = self.interactions.as_ref().map(InteractionCounter::take);
+        (report, timing)
+    }
+
+    /// `step` with `integrator` in place of the configured one. block timesteps take leapfrog sub-steps
+    /// whatever it is
+    pub fn step_with(&mut self, dt: f64, integrator: &impl Integrator) -> StepReport {
+        let _span = tracing::info_span!("step", step = self.steps + 1).entered();
+        let instant = Instant::now();
+        self.timing = StepTiming::default();
+        if let Some(counter) = &self.interactions {
+            counter.take();
+        }
+        if self.undo_depth > 0 {
+            if self.history.len() == self.undo_depth {
+                self.history.pop_front();
+            }
+            self.history.push_back(Rewind {
+                bodies: self.bodies.clone(),
+                space: self.space,
+                accelerations: self.accelerations.clone(),
+                jerks: self.jerks.clone(),
+                drag_work: self.drag_work,
+                next_id: self.next_id,
+                time: self.time,
+                steps: self.steps,
+            });
+        }
+        self.notify(|observer, simulation| observer.on_step_start(simulation));
+        let logged = self.events.len();
+        let mut report = self.advance(dt, integrator);
+        let kind = match self.config.collisions {
+            CollisionPolicy::Merge { .. } => EventKind::Merge,
+            _ => EventKind::Bounce,
+        };
+        for collision in &report.collisions {
+            self.log_event(kind, vec![collision.ids.0, collision.ids.1]);
+        }
+        // events from the middle of the step go down at its end
+        for event in &mut self.events[logged..] {
+            event.step = self.steps;
+            event.time = self.time;
+        }
+        if let Recentering::Every { steps } = self.config.recentering {
+            assert!(steps > 0, "recentering needs a cadence of at least one step");
+            if self.steps.is_multiple_of(steps) {
+                self.to_com_frame();
+            }
+        }
+        tracing::debug!(
+            dt = report.dt,
+            bodies = self.bodies.len(),
+            force_evaluations = report.force_evaluations,
+            collisions = report.collisions.len(),
+            seconds = instant.elapsed().as_secs_f64(),
+            "step done"
+        );
+        let mut stop_requested = false;
+        self.notify(|observer, simulation| {
+            stop_requested |= observer.on_step_end(simulation, &report).is_break();
+        });
+        report.stop = self.stop.as_ref().and_then(|(condition, energy)| condition.check(self, *energy));
+        report.stop_requested = stop_requested || report.stop.is_some();
+        self.publish_state();
+        report
+    }
+
+    fn advance(&mut self, dt: f64, integrator: &impl Integrator) -> StepReport {
+        if self.config.morton_order {
+            self.sort_by_morton_key();
+        }
+        self.regularized.clear();
+        if let (Regularization::Kepler { radius }, true) = (self.config.regularization, self.config.self_gravity) {
+            assert!(
+                !matches!(self.config.timestep, Timestep::Block { .. }),
+                "regularized pairs need a shared timestep"
+            );
+            assert_eq!(self.config.boundary, [BoundaryCondition::Open; 3], "regularized pairs need an open boundary");
+            self.regularized = self.close_pairs(radius);
+        }
+        if let Some(cutoff) = self.config.cutoff {
+            let bounds = self.tree.bounds();
+            let widths = [bounds.x, bounds.y, bounds.z].map(|range| (range.end - range.start).as_f64());
+            let periodic = periodic_axes(self.config.boundary);
+            assert!(
+                (0..3).all(|axis| !periodic[axis] || cutoff <= widths[axis] / 2.),
+                "a cutoff can reach no further than half the periodic box"
+            );
+        }
+        let mut force_evaluations = 0;
+        if self.accelerations.len() != self.bodies.len() {
+            let instant = Instant::now();
+            self.accelerations = self.compute_accelerations();
+            self.timing.forces += instant.elapsed();
+            self.jerks.clear();
+            force_evaluations += self.bodies.len();
+        }
+        if let Timestep::Block { eta, levels } = self.config.timestep {
+            self.apply_drag(dt / 2.);
+            let report = self.block_step(dt, eta, levels, force_evaluations);
+            self.apply_drag(dt / 2.);
+            return report;
+        }
+        let dt = self.timestep(dt);
+        // drag is split off symmetrically around the rest of the step, which keeps it second order
+        self.apply_drag(dt / 2.);
+        let mut stage = Stage::new(self, force_evaluations);
+        integrator.advance(&mut stage, dt);
+        let (force_evaluations, collisions) = stage.finish();
+        self.apply_drag(dt / 2.);
+        self.time += dt;
+        self.steps += 1;
+        StepReport {
+            dt,
+            force_evaluations,
+            collisions,
+            stop_requested: false,
+            stop: None,
+        }
+    }
+
+    // `dt` in 2^levels ticks, each body kick-drift-kicking on its own power-of-two sub-step. everyone drifts
+    // every tick, since forces need current positions, but only bodies closing a sub-step on a tick get new
+    // forces and the tree is only rebuilt for them
+    fn block_step(&mut self, dt: f64, eta: f64, levels: u32, mut force_evaluations: usize) -> StepReport {
+        assert!(levels <= 32, "block timesteps go at most 32 levels deep");
+        let ticks = 1u64 << levels;
+        let tick = dt / ticks as f64;
+        let softening = self.config.softening;
+        // each body's sub-step in ticks, halved from the whole step until the criterion is met
+        let mut spans: Vec<u64> = self
+            .accelerations
+            .iter()
+            .map(|acceleration| {
+                let wanted = wanted_timestep(eta, softening, acceleration.length().as_f64());
+                let mut span = ticks;
+                while span > 1 && span as f64 * tick > wanted {
+                    span /= 2;
+                }
+                span
+            })
+            .collect();
+        let mut collisions = vec![];
+        for t in 0..ticks {
+            for ((body, acceleration), &span) in self.bodies.iter_mut().zip(&self.accelerations).zip(&spans) {
+                if t.is_multiple_of(span) {
+                    body.velocity += *acceleration * S::from_f64(span as f64 * tick / 2.);
+                }
+                body.location += body.velocity * S::from_f64(tick);
+            }
+            if let Some(kept) = self.handle_escapes() {
+                retain_kept(&mut spans, &kept);
+            }
+            let (found, kept) = collision::resolve(&mut self.bodies, self.config.collisions);
+            collisions.extend(found);
+            if let Some(kept) = kept {
+                retain_kept(&mut spans, &kept);
+                retain_kept(&mut self.accelerations, &kept);
+            }
+            let active: Vec<usize> = (0..self.bodies.len()).filter(|&i| (t + 1).is_multiple_of(spans[i])).collect();
+            if active.is_empty() {
+                continue;
+            }
+            self.refresh_tree();
+            let instant = Instant::now();
+            let accelerations = self.accelerations_of(&active);
+            self.timing.forces += instant.elapsed();
+            force_evaluations += active.len();
+            for (&i, acceleration) in active.iter().zip(accelerations) {
+                self.accelerations[i] = acceleration;
+            }
+            self.notify(|observer, simulation| observer.on_forces_computed(simulation, &simulation.accelerations));
+            for &i in &active {
+                self.bodies[i].velocity += self.accelerations[i] * S::from_f64(spans[i] as f64 * tick / 2.);
+            }
+        }
+        self.time += dt;
+        self.steps += 1;
+        StepReport {
+            dt,
+            force_evaluations,
+            collisions,
+            stop_requested: false,
+            stop: None,
+        }
+    }
+
+    // `dt` of drag alone on every dragged body, adding up its work on the sources
+    fn apply_drag(&mut self, dt: f64) {
+        if self.drag.is_empty() {
+            return;
+        }
+        for body in &mut self.bodies {
+            let Some((_, drag)) = self.drag.iter().find(|(species, _)| *species == body.species) else {
+                continue;
+            };
+            let velocity: Point = body.velocity.cast();
+            let dragged = drag.advance(&body.location.cast(), velocity, dt);
+            if body.is_source() {
+                self.drag_work += 0.5 * body.mass.as_f64() * (dragged.dot(&dragged) - velocity.dot(&velocity));
+            }
+            body.velocity = dragged.cast();
+        }
+        // the jerks depend on the velocities
+        self.jerks.clear();
+    }
+
+    pub(crate) fn bodies_mut(&mut self) -> &mut [Body<S>] {
+        &mut self.bodies
+    }
+
+    pub(crate) fn current_accelerations(&self) -> &[Point<S>] {
+        &self.accelerations
+    }
+
+    pub(crate) fn current_jerks(&mut self) -> &[Point<S>] {
+        if self.jerks.len() != self.bodies.len() {
+            let instant = Instant::now();
+            self.jerks = self.compute_jerks();
+            self.timing.forces += instant.elapsed();
+        }
+        &self.jerks
+    }
+
+    pub(crate) fn kick(&mut self, dt: f64) {
+        let _span = tracing::debug_span!("kick").entered();
+        let dt = S::from_f64(dt);
+        for (body, acceleration) in self.bodies.iter_mut().zip(&self.accelerations) {
+            body.velocity += *acceleration * dt;
+        }
+        // the pair's pull on itself is left to the drift
+        let ForceConstants { softening, gravitational_constant: gravity, .. } = self.constants();
+        for (a, b) in self.regularized_indices() {
+            let (first, second) = (self.bodies[a], self.bodies[b]);
+            let on_first = self.model.pair_acceleration(&first.location, &second.location, second.mass, softening);
+            let on_second = self.model.pair_acceleration(&second.location, &first.location, first.mass, softening);
+            self.bodies[a].velocity -= on_first * gravity * dt;
+            self.bodies[b].velocity -= on_second * gravity * dt;
+        }
+    }
+
+    pub(crate) fn drift(&mut self, dt: f64) {
+        let _span = tracing::debug_span!("drift").entered();
+        let pairs = self.regularized_indices();
+        let g = self.config.units.gravitational_constant();
+        let moved: Vec<_> = pairs
+            .iter()
+            .map(|&(a, b)| {
+                let (first, second) = (&self.bodies[a], &self.bodies[b]);
+                let (m1, m2) = (first.mass.as_f64(), second.mass.as_f64());
+                let mass = m1 + m2;
+                let (r1, r2): (Point, Point) = (first.location.cast(), second.location.cast());
+                let (v1, v2): (Point, Point) = (first.velocity.cast(), second.velocity.cast());
+                let velocity = (v1 * m1 + v2 * m2) / mass;
+                let center = (r1 * m1 + r2 * m2) / mass + velocity * dt;
+                let (offset, relative) = regularization::kepler_drift(r2 - r1, v2 - v1, g * mass, dt);
+                let (w1, w2) = (m2 / mass, m1 / mass);
+                [(center - offset * w1, velocity - relative * w1), (center + offset * w2, velocity + relative * w2)]
+            })
+            .collect();
+        let dt = S::from_f64(dt);
+        for body in &mut self.bodies {
+            body.location += body.velocity * dt;
+        }
+        for (&(a, b), [first, second]) in pairs.iter().zip(moved) {
+            for (i, (location, velocity)) in [(a, first), (b, second)] {
+                self.bodies[i].location = location.cast();
+                self.bodies[i].velocity = velocity.cast();
+            }
+        }
+    }
+
+    // the bodies of each regularized pair that is still whole, as indices into `bodies`
+    fn regularized_indices(&self) -> Vec<(usize, usize)> {
+        if self.regularized.is_empty() {
+            return Vec::new();
+        }
+        let index: HashMap<u64, usize> = self.bodies.iter().enumerate().map(|(i, body)| (body.id, i)).collect();
+        self.regularized.iter().filter_map(|(a, b)| Some((*index.get(a)?, *index.get(b)?))).collect()
+    }
+
+    // the pairs to regularize this step, each body's nearest neighbors asked of the tree
+    fn close_pairs(&self, radius: f64) -> Vec<(u64, u64)> {
+        let g = self.config.units.gravitational_constant();
+        regularization::find_pairs(&self.bodies, radius, g, |body| {
+            let neighbors = match &self.tree {
+                ForceTree::Pointer(tree) => tree.k_nearest(&body.location, regularization::CANDIDATES + 1),
+                ForceTree::Linear(tree) => tree.k_nearest(&body.location, regularization::CANDIDATES + 1),
+            };
+            neighbors.into_iter().map(|neighbor| neighbor.id).collect()
+        })
+    }
+
+    // escapes, then collisions, returning the collisions and which bodies were kept through both when any
+    // were dropped. the accelerations and jerks are filtered along
+    pub(crate) fn settle(&mut self) -> (Vec<Collision>, Option<Vec<bool>>) {
+        let escaped = self.handle_escapes();
+        let (collisions, merged) = collision::resolve(&mut self.bodies, self.config.collisions);
+        if let Some(merged) = &merged {
+            if self.accelerations.len() == merged.len() {
+                retain_kept(&mut self.accelerations, merged);
+            }
+        }
+        let kept = match (escaped, merged) {
+            (Some(mut escaped), Some(merged)) => {
+                let mut merged = merged.into_iter();
+                for kept in escaped.iter_mut().filter(|kept| **kept) {
+                    *kept = merged.next().expect("one flag per body left after the escapes");
+                }
+                Some(escaped)
+            }
+            (kept, None) | (None, kept) => kept,
+        };
+        if let Some(kept) = &kept {
+            if self.jerks.len() == kept.len() {
+                retain_kept(&mut self.jerks, kept);
+            }
+        }
+        (collisions, kept)
+    }
+
+    // puts the bodies in the order of their Morton keys in the root box, taking their accelerations and jerks
+    // along. equal keys keep their order, so sorted bodies stay put
+    fn sort_by_morton_key(&mut self) {
+        let keys: Vec<u64> = self.bodies.iter().map(|body| morton_key(&self.space, &body.location)).collect();
+        let mut order: Vec<usize> = (0..self.bodies.len()).collect();
+        order.sort_by_key(|&i| keys[i]);
+        if order.iter().enumerate().all(|(at, &i)| at == i) {
+            return;
+        }
+        fn permute<T: Copy>(items: &mut Vec<T>, order: &[usize]) {
+            if items.len() == order.len() {
+                *items = order.iter().map(|&i| items[i]).collect();
+            }
+        }
+        permute(&mut self.bodies, &order);
+        permute(&mut self.accelerations, &order);
+        permute(&mut self.jerks, &order);
+    }
+
+    // a fresh tree and accelerations at the current positions, returning the force evaluations it took
+    pub(crate) fn update_forces(&mut self) -> usize {
+        self.refresh_tree();
+        let instant = Instant::now();
+        self.accelerations = self.compute_accelerations();
+        self.timing.forces += instant.elapsed();
+        self.jerks.clear();
+        self.notify(|observer, simulation| observer.on_forces_computed(simulation, &simulation.accelerations));
+        self.bodies.len()
+    }
+
+    // brings the tree up to date with the moved bodies per the `RebuildStrategy`, or just refits it if the last
+    // refresh was recent enough for `rebuild_every`
+    pub(crate) fn refresh_tree(&mut self) {
+        let instant = Instant::now();
+        assert!(self.config.rebuild_every > 0, "the tree needs rebuilding at least every step");
+        if self.refits + 1 < self.config.rebuild_every && !self.config.morton_order {
+            if let ForceTree::Pointer(tree) = &mut self.tree {
+                let _span = tracing::debug_span!("tree_refit", bodies = self.bodies.len()).entered();
+                // a grown root box leaves bodies outside every node, so it needs a rebuild
+                if *tree.bounds() == self.space && tree.refit(&sources(&self.bodies)) {
+                    self.refits += 1;
+                    self.timing.tree += instant.elapsed();
+                    self.time_moments();
+                    return;
+                }
+            }
+        }
+        self.refits = 0;
+        if let (RebuildStrategy::Incremental { max_moved }, ForceTree::Pointer(tree), false) =
+            (self.config.rebuild, &mut self.tree, self.config.morton_order)
+        {
+            let limit = (max_moved * self.bodies.len() as f64) as usize;
+            let _span = tracing::debug_span!("tree_update", bodies = self.bodies.len()).entered();
+            // a grown root box means every leaf box is stale
+            let sources = sources(&self.bodies);
+            if *tree.bounds() == self.space && tree.relocate(&sources, limit).is_some() {
+                self.timing.tree += instant.elapsed();
+                self.time_moments();
+                return;
+            }
+        }
+        self.tree = ForceTree::build_monopoles(&self.config, &self.bodies, self.space);
+        self.timing.tree += instant.elapsed();
+        self.time_moments();
+    }
+
+    fn time_moments(&mut self) {
+        let instant = Instant::now();
+        self.tree.compute_moments(&self.config);
+        self.timing.moments += instant.elapsed();
+    }
+
+    // the step length the configured `Timestep` allows, from the accelerations at the start of the step
+    fn timestep(&self, dt: f64) -> f64 {
+        match self.config.timestep {
+            Timestep::Fixed => dt,
+            Timestep::Adaptive { eta, min } => {
+                let max_acceleration =
+                    self.accelerations.iter().map(|a| a.length().as_f64()).fold(0., f64::max);
+                wanted_timestep(eta, self.config.softening, max_acceleration).max(min).min(dt)
+            }
+            // block steps pick their own sub-steps
+            Timestep::Block { .. } => dt,
+        }
+    }
+
+    // returns which bodies were kept when some were removed; the accelerations are filtered to match. along
+    // periodic axes the box wraps bodies around instead of letting them escape
+    fn handle_escapes(&mut self) -> Option<Vec<bool>> {
+        let space = self.space;
+        let outside = |bodies: &[Body<S>]| {
+            bodies.iter().filter(|body| !space.contains(&body.location)).map(|body| body.id).collect::<Vec<_>>()
+        };
+        let axes = periodic_axes(self.config.boundary);
+        if axes.contains(&true) {
+            if self.event_log {
+                let crossed: Vec<u64> = self
+                    .bodies
+                    .iter()
+                    .filter(|body| space.wrap_along(&body.location, axes) != body.location)
+                    .map(|body| body.id)
+                    .collect();
+                self.log_event(EventKind::Wrapped, crossed);
+            }
+            for body in &mut self.bodies {
+                body.location = space.wrap_along(&body.location, axes);
+            }
+        }
+        if self.bodies.iter().all(|body| space.contains(&body.location)) {
+            return None;
+        }
+        assert!(
+            !(axes.contains(&true) && self.config.escape == EscapePolicy::Expand),
+            "a box periodic along some axes cannot grow along the others"
+        );
+        if self.event_log {
+            let kind = match self.config.escape {
+                EscapePolicy::Expand => EventKind::Expanded,
+                EscapePolicy::Clamp => EventKind::Clamped,
+                EscapePolicy::Remove => EventKind::Removed,
+            };
+            let crossed = outside(&self.bodies);
+            self.log_event(kind, crossed);
+        }
+        match self.config.escape {
+            EscapePolicy::Expand => {
+                self.space = space.union(&Cuboid::bounding(&self.bodies)).to_power_of_two_cube();
+            }
+            EscapePolicy::Clamp => {
+                for body in &mut self.bodies {
+                    body.location = space.clamp(&body.location);
+                }
+            }
+            EscapePolicy::Remove => {
+                let kept: Vec<bool> = self.bodies.iter().map(|body| space.contains(&body.location)).collect();
+                retain_kept(&mut self.bodies, &kept);
+                if self.accelerations.len() == kept.len() {
+                    retain_kept(&mut self.accelerations, &kept);
+                }
+                return Some(kept);
+            }
+        }
+        None
+    }
+
+    /// moves into the center-of-mass frame of the live bodies: every body, tracers too, is shifted by their
+    /// center of mass and slowed by its velocity, and the root box moves along. returns the location and
+    /// velocity taken off, or none for bodies without mass. external potentials stay where they are, so with
+    /// any added this changes the motion rather than just the frame
+    pub fn to_com_frame(&mut self) -> Option<(Point, Point)> {
+        let (location, velocity) = diagnostics::center_of_mass(&sources(&self.bodies))?;
+        let (offset, drift) = (location.cast::<S>(), velocity.cast::<S>());
+        for body in &mut self.bodies {
+            body.location -= offset;
+            body.velocity -= drift;
+        }
+        self.space = self.space.translated(&-offset);
+        self.tree = ForceTree::build(&self.config, &self.bodies, self.space);
+        // the bodies' pull on each other only depends on where they are relative to one another
+        if !self.potentials.is_empty() {
+            self.accelerations.clear();
+        }
+        Some((location, velocity))
+    }
+
+    /// barnes-hut acceleration on every body with the configured theta and softening, plus the pull of any
+    /// external potentials and springs, in the order of `bodies()`. bodies run in parallel with the `parallel`
+    /// feature
+    pub fn compute_accelerations(&self) -> Vec<Point<S>> {
+        let mut accelerations = match self.config.traversal {
+            _ if !self.config.self_gravity => vec![Point::default(); self.bodies.len()],
+            _ if self.config.cutoff.is_some() => {
+                let indices: Vec<usize> = (0..self.bodies.len()).collect();
+                self.accelerations_within_cutoff(&indices)
+            }
+            Traversal::Pairwise => self.compute_accelerations_pairwise(),
+            _ => self.accelerations_at_theta(self.config.theta, self.theta_field.as_deref()),
+        };
+        if !self.potentials.is_empty() {
+            for (acceleration, body) in accelerations.iter_mut().zip(&self.bodies) {
+                *acceleration += self.external_acceleration(&body.location);
+            }
+        }
+        if !self.springs.is_empty() {
+            let pulls = spring::accelerations(&self.springs, &self.bodies);
+            for (acceleration, pull) in accelerations.iter_mut().zip(pulls) {
+                *acceleration += pull;
+            }
+        }
+        accelerations
+    }
+
+    /// exact o(n^2) accelerations with the configured softening, as a reference for the tree. external
+    /// potentials are left out, here and in `force_error`
+    pub fn compute_accelerations_direct(&self) -> Vec<Point<S>> {
+        let ForceConstants { softening, gravitational_constant: gravity, .. } = self.constants();
+        let sources = sources(&self.bodies);
+        let axes = periodic_axes(self.config.boundary);
+        let direct = |target: &Body<S>| {
+            let mut acceleration = Point::default();
+            for source in sources.iter() {
+                let location = self.space.nearest_image_along(&source.location, &target.location, axes);
+                acceleration += self.model.pair_acceleration(&target.location, &location, source.mass, softening);
+            }
+            acceleration * gravity
+        };
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            self.bodies.par_iter().map(direct).collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            self.bodies.iter().map(direct).collect()
+        }
+    }
+
+    /// the exact sum of `compute_accelerations_direct`, with the force between each pair of sources worked out
+    /// once and added to one and taken from the other, so that the forces on the sources add up to nothing and
+    /// their torques cancel pair by pair. tracers are pulled as in the direct sum. o(n^2) on one thread
+    pub fn compute_accelerations_pairwise(&self) -> Vec<Point<S>> {
+        let ForceConstants { softening, gravitational_constant: gravity, .. } = self.constants();
+        let axes = periodic_axes(self.config.boundary);
+        let separation = |target: &Body<S>, source: &Body<S>| {
+            self.space.nearest_image_along(&source.location, &target.location, axes)
+        };
+        let sources: Vec<usize> = (0..self.bodies.len()).filter(|&i| self.bodies[i].is_source()).collect();
+        let (n, tracers) = (sources.len() as u64, (self.bodies.len() - sources.len()) as u64);
+        self.count(Interactions { body_body: n * n.saturating_sub(1) / 2 + tracers * n, body_cell: 0 });
+        let mut forces = vec![Point::default(); self.bodies.len()];
+        let mut accelerations = vec![Point::default(); self.bodies.len()];
+        for (k, &i) in sources.iter().enumerate() {
+            let target = &self.bodies[i];
+            for &j in &sources[k + 1..] {
+                let source = &self.bodies[j];
+                let location = separation(target, source);
+                let unit = self.model.pair_acceleration(&target.location, &location, S::one(), softening);
+                if target.mass.is_zero() || source.mass.is_zero() {
+                    // a massless body takes its pull without giving any back
+                    accelerations[i] += unit * source.mass;
+                    accelerations[j] -= unit * target.mass;
+                    continue;
+                }
+                let force = unit * (target.mass * source.mass);
+                forces[i] += force;
+                forces[j] -= force;
+            }
+        }
+        for (i, target) in self.bodies.iter().enumerate() {
+            if !target.is_source() {
+                for &j in &sources {
+                    let source = &self.bodies[j];
+                    accelerations[i] += self.model.pair_acceleration(
+                        &target.location,
+                        &separation(target, source),
+                        source.mass,
+                        softening,
+                    );
+                }
+            } else if !target.mass.is_zero() {
+                accelerations[i] += forces[i] / target.mass;
+            }
+            accelerations[i] = accelerations[i] * gravity;
+        }
+        accelerations
+    }
+
+    /// error of the tree at `theta` against the direct sum, per body and overall
+    pub fn force_error(&self, theta: f64) -> ForceError {
+        ForceError::between(&self.accelerations_at_theta(theta, None), &self.compute_accelerations_direct())
+    }
+
+    // the tree accelerations at `theta`, or at what `field` gives for each body when there is one
+    fn accelerations_at_theta(&self, theta: f64, field: Option<&ThetaField>) -> Vec<Point<S>> {
+        let _span = tracing::debug_span!("forces", bodies = self.bodies.len()).entered();
+        let (acceptance, softening, boundary) = self.force_parameters(theta);
+        let gravity = self.gravity();
+        let locations = || self.bodies.iter().map(|body| body.location).collect::<Vec<_>>();
+        if field.is_none() {
+            if let Some(accelerations) = self.gpu_accelerations(locations, acceptance, softening) {
+                return accelerations;
+            }
+            if let Some(accelerations) = self.dual_accelerations(acceptance, softening) {
+                return accelerations;
+            }
+        }
+        let acceleration = |stack: &mut WalkStack<S>, body: &Body<S>| {
+            let acceptance = regional(acceptance, field, &body.location);
+            let acceleration =
+                self.tree.acceleration_at(&self.model, &body.location, acceptance, softening, boundary, stack);
+            self.count(stack.take_interactions());
+            acceleration * gravity
+        };
+        // one walk stack per thread, reused for each of its bodies. rayon hands the bodies out in halves split down
+        // as threads go idle, stealing from the busy ones, so the deep walks of a clump spread over every thread
+        // rather than leaving one of them with a fixed share of it; each body's walk, and so its acceleration, is
+        // the same whichever thread takes it
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            self.bodies.par_iter().map_init(WalkStack::new, acceleration).collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            let mut stack = WalkStack::new();
+            self.bodies.iter().map(|body| acceleration(&mut stack, body)).collect()
+        }
+    }
+
+    // the pull on each of the bodies at `indices` of the sources within the cutoff, scaled by G, in parallel
+    // with the `parallel` feature
+    fn accelerations_within_cutoff(&self, indices: &[usize]) -> Vec<Point<S>> {
+        let _span = tracing::debug_span!("cutoff_forces", bodies = indices.len()).entered();
+        let ForceConstants { softening, gravitational_constant: gravity, .. } = self.constants();
+        let acceleration = |&i: &usize| {
+            let target = &self.bodies[i];
+            let (mut acceleration, mut neighbors) = (Point::default(), 0);
+            self.for_each_within_cutoff(target, |source, mass| {
+                acceleration += self.model.pair_acceleration(&target.location, source, mass, softening);
+                neighbors += 1;
+            });
+            self.count(Interactions { body_body: neighbors, body_cell: 0 });
+            acceleration * gravity
+        };
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            indices.par_iter().map(acceleration).collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            indices.iter().map(acceleration).collect()
+        }
+    }
+
+    // hands `visit` the location and mass of every source but `target` itself within the cutoff of it, at the
+    // image nearest it along the periodic axes. the tree is searched about each image of `target` one box
+    // over, which the cutoff of half a box keeps from finding any source twice
+    fn for_each_within_cutoff(&self, target: &Body<S>, mut visit: impl FnMut(&Point<S>, S)) {
+        let Some(cutoff) = self.config.cutoff else {
+            return;
+        };
+        let radius = S::from_f64(cutoff);
+        let axes = periodic_axes(self.config.boundary);
+        let bounds = *self.tree.bounds();
+        let shifts = |periodic: bool, range: Range<S>| {
+            let width = range.end - range.start;
+            if periodic {
+                vec![-width, S::zero(), width]
+            } else {
+                vec![S::zero()]
+            }
+        };
+        for &x in &shifts(axes[0], bounds.x) {
+            for &y in &shifts(axes[1], bounds.y) {
+                for &z in &shifts(axes[2], bounds.z) {
+                    let center = target.location + Point { x, y, z };
+                    for source in self.tree.within_radius(&center, radius) {
+                        if source.id != target.id {
+                            let image = bounds.nearest_image_along(&source.location, &target.location, axes);
+                            visit(&image, source.mass);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // accelerations_at_theta for just the bodies at `indices`
+    fn accelerations_of(&self, indices: &[usize]) -> Vec<Point<S>> {
+        let _span = tracing::debug_span!("forces", bodies = indices.len()).entered();
+        if self.config.self_gravity && self.config.cutoff.is_some() {
+            let springs = (!self.springs.is_empty()).then(|| spring::accelerations(&self.springs, &self.bodies));
+            let accelerations = self.accelerations_within_cutoff(indices);
+            return accelerations
+                .into_iter()
+                .zip(indices)
+                .map(|(acceleration, &i)| {
+                    let pull = springs.as_ref().map_or(Point::default(), |springs| springs[i]);
+                    acceleration + self.external_acceleration(&self.bodies[i].location) + pull
+                })
+                .collect();
+        }
+        if self.config.traversal == Traversal::Pairwise {
+            // the sum is only exact taken over every pair at once
+            let accelerations = self.compute_accelerations();
+            return indices.iter().map(|&i| accelerations[i]).collect();
+        }
+        let (acceptance, softening, boundary) = self.force_parameters(self.config.theta);
+        let gravity = self.gravity();
+        let locations = || indices.iter().map(|&i| self.bodies[i].location).collect::<Vec<_>>();
+        // springs are cheap enough to take for every body, whichever are asked for
+        let springs = (!self.springs.is_empty()).then(|| spring::accelerations(&self.springs, &self.bodies));
+        let pull = |i: usize| springs.as_ref().map_or(Point::default(), |springs| springs[i]);
+        if !self.config.self_gravity {
+            return indices.iter().map(|&i| self.external_acceleration(&self.bodies[i].location) + pull(i)).collect();
+        }
+        let field = self.theta_field.as_deref();
+        if field.is_none() {
+            if let Some(mut accelerations) = self.gpu_accelerations(locations, acceptance, softening) {
+                for (acceleration, &i) in accelerations.iter_mut().zip(indices) {
+                    *acceleration += self.external_acceleration(&self.bodies[i].location) + pull(i);
+                }
+                return accelerations;
+            }
+        }
+        let acceleration = |stack: &mut WalkStack<S>, &i: &usize| {
+            let target = &self.bodies[i].location;
+            let acceptance = regional(acceptance, field, target);
+            let acceleration = self.tree.acceleration_at(&self.model, target, acceptance, softening, boundary, stack);
+            self.count(stack.take_interactions());
+            acceleration * gravity + self.external_acceleration(target) + pull(i)
+        };
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            indices.par_iter().map_init(WalkStack::new, acceleration).collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            let mut stack = WalkStack::new();
+            indices.iter().map(|i| acceleration(&mut stack, i)).collect()
+        }
+    }
+
+    // the tree accelerations at `targets` from the gpu, scaled by G, or none when the cpu has to do it
+    #[cfg(feature = "gpu")]
+    fn gpu_accelerations(
+        &self,
+        targets: impl FnOnce() -> Vec<Point<S>>,
+        acceptance: Acceptance<S>,
+        softening: S,
+    ) -> Option<Vec<Point<S>>> {
+        let (Some(gpu), ForceTree::Linear(tree)) = (&self.gpu, &self.tree) else {
+            return None;
+        };
+        if !F::NEWTONIAN
+            || self.config.boundary != [BoundaryCondition::Open; 3]
+            || self.config.multipole != MultipoleOrder::Monopole
+            || acceptance.opening != Opening::Box
+        {
+            return None;
+        }
+        match gpu.accelerations(tree, &targets(), acceptance.theta, softening) {
+            Ok(accelerations) => {
+                let gravity = self.gravity();
+                Some(accelerations.into_iter().map(|acceleration| acceleration * gravity).collect())
+            }
+            Err(err) => {
+                tracing::warn!(%err, "gpu forces failed, computing them on the cpu");
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "gpu"))]
+    fn gpu_accelerations(
+        &self,
+        _: impl FnOnce() -> Vec<Point<S>>,
+        _: Acceptance<S>,
+        _: S,
+    ) -> Option<Vec<Point<S>>> {
+        None
+    }
+
+    // the accelerations on every body from a dual walk, scaled by G, or none when the config rules one out
+    fn dual_accelerations(&self, acceptance: Acceptance<S>, softening: S) -> Option<Vec<Point<S>>> {
+        if self.config.traversal != Traversal::Dual
+            || !F::NEWTONIAN
+            || self.config.boundary != [BoundaryCondition::Open; 3]
+            || self.config.multipole != MultipoleOrder::Monopole
+        {
+            return None;
+        }
+        let _span = tracing::debug_span!("dual_walk").entered();
+        let mut accelerations = match &self.tree {
+            ForceTree::Pointer(tree) => dual::accelerations(tree, acceptance.theta, softening),
+            ForceTree::Linear(tree) => dual::accelerations(tree, acceptance.theta, softening),
+        };
+        if accelerations.len() != self.bodies.len() {
+            // tracers are not in the tree, so they get walks of their own between the sources' results
+            let mut walked = accelerations.into_iter();
+            let boundary = self.config.boundary;
+            let mut stack = WalkStack::new();
+            accelerations = self
+                .bodies
+                .iter()
+                .map(|body| {
+                    if body.is_source() {
+                        walked.next().expect("one result per source")
+                    } else {
+                        let target = &body.location;
+                        self.tree.acceleration_at(&self.model, target, acceptance, softening, boundary, &mut stack)
+                    }
+                })
+                .collect();
+        }
+        let gravity = self.gravity();
+        Some(accelerations.into_iter().map(|acceleration| acceleration * gravity).collect())
+    }
+
+    // jerks of every body with the configured theta and softening, see `integrator::jerks`
+    fn compute_jerks(&self) -> Vec<Point<S>> {
+        let _span = tracing::debug_span!("jerks", bodies = self.bodies.len()).entered();
+        if !self.config.self_gravity {
+            return vec![Point::default(); self.bodies.len()];
+        }
+        let (acceptance, softening, boundary) = self.force_parameters(self.config.theta);
+        let period = self.tree.period(boundary);
+        let period = period.as_ref();
+        let jerks = match &self.tree {
+            ForceTree::Pointer(tree) => integrator::jerks(tree, &self.bodies, acceptance, softening, period),
+            ForceTree::Linear(tree) => integrator::jerks(tree, &self.bodies, acceptance, softening, period),
+        };
+        let gravity = self.gravity();
+        jerks.into_iter().map(|jerk| jerk * gravity).collect()
+    }
+
+    // adds `interactions` to the step's count, if it is being kept
+    fn count(&self, interactions: Interactions) {
+        if let Some(counter) = &self.interactions {
+            counter.body_body.fetch_add(interactions.body_body, Ordering::Relaxed);
+            counter.body_cell.fetch_add(interactions.body_cell, Ordering::Relaxed);
+        }
+    }
+
+    // the opening test at `theta` and the softening in the simulation's precision, with the boundary they
+    // apply under
+    fn force_parameters(&self, theta: f64) -> (Acceptance<S>, S, [BoundaryCondition; 3]) {
+        let acceptance = Acceptance { theta: S::from_f64(theta), opening: self.config.opening };
+        (acceptance, self.constants().softening, self.config.boundary)
+    }
+
+    // the gravitational constant of the configured units; the trees and direct sums all work in G = 1
+    fn gravity(&self) -> S {
+        self.constants().gravitational_constant
+    }
+
+    // the summed pull of the external potentials at `location`
+    fn external_acceleration(&self, location: &Point<S>) -> Point<S> {
+        if self.potentials.is_empty() {
+            return Point::default();
+        }
+        let (location, g) = (location.cast(), self.config.units.gravitational_constant());
+        let acceleration = self.potentials.iter().fold(Point::default(), |acceleration, potential| {
+            acceleration + potential.acceleration(&location, g)
+        });
+        acceleration.cast()
+    }
+
+    /// kinetic plus potential energy, with the potential taken from the tree at the configured theta and
+    /// softening
+    pub fn total_energy(&self) -> f64 {
+        self.diagnostics(PotentialMethod::Tree).total_energy()
+    }
+
+    /// the energy and momenta to hold later states to with `check_conservation`, measured with the tree
+    pub fn conserved_quantities(&self) -> ConservedQuantities {
+        ConservedQuantities::of(&self.diagnostics(PotentialMethod::Tree))
+    }
+
+    /// whether the energy and momenta are still within a relative `tolerance` of `reference`, e.g. from
+    /// `conserved_quantities` at the start of a run; the first that is not is the error, with how far it went.
+    /// the tree does not conserve momentum exactly, so a tolerance near rounding only holds for direct sums
+    pub fn check_conservation(
+        &self,
+        reference: &ConservedQuantities,
+        tolerance: f64,
+    ) -> Result<(), ConservationError> {
+        reference.check(&self.conserved_quantities(), tolerance)
+    }
+
+    // the potential energy of the pairs of `bodies` within the cutoff, each shifted by its value at the cutoff,
+    // with G = 1
+    fn potential_energy_within_cutoff(&self, bodies: &[Body<S>]) -> f64 {
+        let Some(cutoff) = self.config.cutoff else {
+            return 0.;
+        };
+        let softening = self.constants().softening;
+        let at_cutoff = Point { x: S::from_f64(cutoff), y: S::zero(), z: S::zero() };
+        let shift = self.model.pair_potential(&Point::default(), &at_cutoff, S::one(), softening).as_f64();
+        // each pair is seen from both ends, hence the half
+        let energy = |body: &Body<S>| {
+            let mut potential = CompensatedSum::default();
+            self.for_each_within_cutoff(body, |source, mass| {
+                let pair = self.model.pair_potential(&body.location, source, mass, softening).as_f64();
+                potential.add(pair - mass.as_f64() * shift);
+            });
+            0.5 * body.mass.as_f64() * potential.value()
+        };
+        #[cfg(feature = "parallel")]
+        let energies: Vec<f64> = {
+            use rayon::prelude::*;
+            bodies.par_iter().map(energy).collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let energies: Vec<f64> = bodies.iter().map(energy).collect();
+        energies.into_iter().sum::<CompensatedSum>().value()
+    }
+
+    /// energies and momenta of the current state, with the external potentials and springs counted in the
+    /// potential energy; cheap enough with the tree to take every step. tracers are left out, since they exchange
+    /// no energy or momentum with the rest, along with any spring tied to one
+    pub fn diagnostics(&self, method: PotentialMethod) -> Diagnostics {
+        let (acceptance, softening, boundary) = self.force_parameters(self.config.theta);
+        let bodies = sources(&self.bodies);
+        let potential: f64 = match method {
+            _ if !self.config.self_gravity => 0.,
+            // the truncated law has the one potential, which the neighbor search finds exactly
+            _ if self.config.cutoff.is_some() => self.potential_energy_within_cutoff(&bodies),
+            PotentialMethod::Tree => {
+                // each pair is seen from both ends, hence the half
+                let energy = |body: &Body<S>| {
+                    let potential =
+                        self.tree.potential_at(&self.model, &body.location, acceptance, softening, boundary);
+                    0.5 * body.mass.as_f64() * potential.as_f64()
+                };
+                // collected before summing, so the total is the same on any number of threads
+                #[cfg(feature = "parallel")]
+                let energies: Vec<f64> = {
+                    use rayon::prelude::*;
+                    bodies.par_iter().map(energy).collect()
+                };
+                #[cfg(not(feature = "parallel"))]
+                let energies: Vec<f64> = bodies.iter().map(energy).collect();
+                energies.into_iter().sum::<CompensatedSum>().value()
+            }
+            PotentialMethod::Direct => diagnostics::potential_energy_along(
+                &self.model,
+                &bodies,
+                self.config.softening,
+                &self.space,
+                periodic_axes(boundary),
+            ),
+        };
+        let g = self.config.units.gravitational_constant();
+        let external: f64 = bodies
+            .iter()
+            .map(|body| {
+                let location = body.location.cast();
+                let potential: f64 = self.potentials.iter().map(|field| field.potential(&location, g)).sum();
+                body.mass.as_f64() * potential
+            })
+            .sum();
+        let springs = spring::potential_energy(&self.springs, &self.bodies, Body::is_source);
+        Diagnostics {
+            drag_work: self.drag_work,
+            ..Diagnostics::measure(&bodies, potential * g + external + springs)
+        }
+    }
+
+    /// kinetic plus self-potential energy of the sources inside `region`, found with the tree's box query, for
+    /// telling whether a clump is bound: below zero it is. the kinetic energy is taken about the clump's own
+    /// center of mass, so a bound clump flying past still counts as bound, and the potential is the softened
+    /// direct sum over its pairs, leaving out the other bodies, the external potentials and the springs
+    pub fn binding_energy(&self, region: &Cuboid<S>) -> f64 {
+        // the tree's copies lag a closing kick behind, so only their ids are taken from it
+        let ids: HashSet<u64> = match &self.tree {
+            ForceTree::Pointer(tree) => tree.within_box(region).iter().map(|body| body.id).collect(),
+            ForceTree::Linear(tree) => tree.within_box(region).iter().map(|body| body.id).collect(),
+        };
+        let inside: Vec<Body<S>> = self.bodies.iter().filter(|body| ids.contains(&body.id)).copied().collect();
+        let Some((_, velocity)) = diagnostics::center_of_mass(&inside) else {
+            return 0.;
+        };
+        let kinetic: f64 = inside
+            .iter()
+            .map(|body| {
+                let relative = body.velocity.cast() - velocity;
+                0.5 * body.mass.as_f64() * relative.dot(&relative)
+            })
+            .sum();
+        let potential = diagnostics::model_potential_energy_direct(&self.model, &inside, self.config.softening, None);
+        kinetic + potential * self.config.units.gravitational_constant()
+    }
+
+    /// density around each body: mass of its k nearest other sources over the volume of the sphere reaching
+    /// the farthest of them, in the order of `bodies()`. the body itself is left out, so k = 1 already gives a
+    /// finite estimate, though bodies sharing a position still come out infinitely dense. a body with no other
+    /// source to measure by, such as the only one, has a density of 0. panics if k is 0
+    pub fn local_density(&self, k: usize) -> Vec<f64> {
+        assert!(k > 0, "a density needs at least one neighbor");
+        self.bodies
+            .iter()
+            .map(|body| {
+                let mut neighbors = match &self.tree {
+                    ForceTree::Pointer(tree) => tree.k_nearest(&body.location, k + 1),
+                    ForceTree::Linear(tree) => tree.k_nearest(&body.location, k + 1),
+                };
+                // a tracer is not in the tree, so there is no self to drop and one neighbor too many
+                match neighbors.iter().position(|neighbor| neighbor.id == body.id) {
+                    Some(own) => {
+                        neighbors.remove(own);
+                    }
+                    None => neighbors.truncate(k),
+                }
+                let Some(farthest) = neighbors.last() else {
+                    return 0.;
+                };
+                let mass: f64 = neighbors.iter().map(|n| n.mass.as_f64()).sum();
+                let radius = farthest.location.distance_squared(&body.location).sqrt().as_f64();
+                mass / (4. / 3. * std::f64::consts::PI * radius.powi(3))
+            })
+            .collect()
+    }
+
+    /// `quantity` on a grid of `resolution` cells a side over the simulation's box. densities come from the
+    /// mass of the sources, wrapped around the box when it is periodic and cut off at its faces otherwise; the
+    /// potential is in the configured units and walks the tree once per cell, in parallel with the `parallel`
+    /// feature. panics if `resolution` is 0
+    pub fn sample_grid(&self, resolution: usize, quantity: GridQuantity) -> Grid {
+        let _span = tracing::debug_span!("sample_grid", resolution).entered();
+        let mut grid = Grid::over(&self.space.cast(), resolution, quantity);
+        let boundary = self.config.boundary;
+        if quantity != GridQuantity::Potential {
+            grid.deposit(&sources(&self.bodies), periodic_axes(boundary));
+            return grid;
+        }
+        let (acceptance, softening, _) = self.force_parameters(self.config.theta);
+        let g = self.config.units.gravitational_constant();
+        let potential = |location: Point| {
+            let tree = self.tree.potential_at(&self.model, &location.cast(), acceptance, softening, boundary);
+            let external: f64 = self.potentials.iter().map(|field| field.potential(&location, g)).sum();
+            tree.as_f64() * g + external
+        };
+        let centers: Vec<_> = grid.cell_centers().collect();
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            grid.values = centers.into_par_iter().map(potential).collect();
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            grid.values = centers.into_iter().map(potential).collect();
+        }
+        grid
+    }
+
+    pub fn len(&self) -> usize {
+        self.bodies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bodies.is_empty()
+    }
+
+    /// second mass moment sum(m * x_i * x_j) about the origin; its second time derivative drives gravitational-wave emission.
+    /// tracers do not count
+    pub fn mass_quadrupole(&self) -> [[f64; 3]; 3] {
+        let mut moment = [[0.; 3]; 3];
+        for body in self.bodies.iter().filter(|body| body.is_source()) {
+            let m = body.mass.as_f64();
+            let r = body.location.cast::<f64>().as_array();
+            for i in 0..3 {
+                for j in 0..3 {
+                    moment[i][j] += m * r[i] * r[j];
+                }
+            }
+        }
+        moment
+    }
+
+    /// bins body mass onto the plane perpendicular to `axis` and writes it as a log-scaled greyscale png. the
+    /// scale runs from the lightest pixel with any mass to the heaviest, whatever the units, so a lone light
+    /// body still shows next to a heavy one. a width or height of 0 is a parameter error, with nothing written
+    #[cfg(feature = "png")]
+    pub fn render_projection(
+        &self,
+        axis: Axis,
+        width: u32,
+        height: u32,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), image::ImageError> {
+        if width == 0 || height == 0 {
+            use image::error::{ParameterError, ParameterErrorKind};
+            let error = ParameterError::from_kind(ParameterErrorKind::DimensionMismatch);
+            return Err(image::ImageError::Parameter(error));
+        }
+        let b: Cuboid = self.space.cast();
+        let (u_range, v_range) = match axis {
+            Axis::X => (b.y, b.z),
+            Axis::Y => (b.x, b.z),
+            Axis::Z => (b.x, b.y),
+        };
+        let pixel = |value: f64, range: &Range<f64>, n: u32| {
+            let t = (value - range.start) / (range.end - range.start);
+            ((t * n as f64) as u32).min(n - 1)
+        };
+
+        let mut density = vec![0f64; width as usize * height as usize];
+        for body in &self.bodies {
+            let p: Point = body.location.cast();
+            let (u, v) = match axis {
+                Axis::X => (p.y, p.z),
+                Axis::Y => (p.x, p.z),
+                Axis::Z => (p.x, p.y),
+            };
+            let col = pixel(u, &u_range, width);
+            // image rows grow downwards
+            let row = height - 1 - pixel(v, &v_range, height);
+            density[row as usize * width as usize + col as usize] += body.mass.as_f64();
+        }
+
+        let max = density.iter().cloned().fold(0., f64::max);
+        let min = density.iter().cloned().filter(|&mass| mass > 0.).fold(max, f64::min);
+        // ln(1 + 3) at the lightest pixel with mass keeps it off black however heavy the heaviest is
+        let level = |mass: f64| (mass / min * 3.).ln_1p();
+        let scale = if max > 0. { 255. / level(max) } else { 0. };
+        let img = image::GrayImage::from_fn(width, height, |col, row| {
+            let value = density[row as usize * width as usize + col as usize].max(0.);
+            image::Luma([(level(value) * scale).round() as u8])
+        });
+        img.save(path)
+    }
+}
+
+// the terms of the force passes, added up from whichever threads walk
+#[derive(Default)]
+struct InteractionCounter {
+    body_body: AtomicU64,
+    body_cell: AtomicU64,
+}
+
+impl InteractionCounter {
+    // the count so far, starting it again from none
+    fn take(&self) -> Interactions {
+        Interactions {
+            body_body: self.body_body.swap(0, Ordering::Relaxed),
+            body_cell: self.body_cell.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+// the device for the `Gpu` backend, or none to stay on the cpu
+#[cfg(feature = "gpu")]
+fn open_gpu(config: &SimulationConfig) -> Option<GpuForces> {
+    if config.backend != TreeBackend::Gpu {
+        return None;
+    }
+    GpuForces::new()
+        .map_err(|err| tracing::warn!(%err, "no gpu, computing forces on the cpu"))
+        .ok()
+}
+
+// the step length η·sqrt(ε / |a|) asks for; unbounded without any acceleration
+fn wanted_timestep(eta: f64, softening: f64, acceleration: f64) -> f64 {
+    if acceleration == 0. {
+        f64::INFINITY
+    } else {
+        eta * (softening / acceleration).sqrt()
+    }
+}
+
+// the bodies that pull on others, borrowed unless there are tracers to leave out
+pub(crate) fn sources<S: Scalar>(bodies: &[Body<S>]) -> Cow<'_, [Body<S>]> {
+    if bodies.iter().all(Body::is_source) {
+        Cow::Borrowed(bodies)
+    } else {
+        Cow::Owned(bodies.iter().filter(|body| body.is_source()).copied().collect())
+    }
+}
+
+// makes tracers of the live bodies lighter than `threshold`
+fn demote_light<S: Scalar>(bodies: &mut [Body<S>], threshold: f64) {
+    if threshold <= 0. {
+        return;
+    }
+    for body in bodies.iter_mut().filter(|body| body.mass.as_f64().abs() < threshold) {
+        body.species = Species::Tracer;
+    }
+}
+
+// `acceptance` with the theta `field` gives at `location`, if there is a field
+fn regional<S: Scalar>(acceptance: Acceptance<S>, field: Option<&ThetaField>, location: &Point<S>) -> Acceptance<S> {
+    match field {
+        Some(field) => Acceptance { theta: S::from_f64(field(&location.cast())), ..acceptance },
+        None => acceptance,
+    }
+}
+
+// which of x, y and z repeat under `boundary`
+fn periodic_axes(boundary: [BoundaryCondition; 3]) -> [bool; 3] {
+    boundary.map(|axis| axis == BoundaryCondition::Periodic)
+}
+
+// drops the items whose entry in `kept` is false
+pub(crate) fn retain_kept<T>(items: &mut Vec<T>, kept: &[bool]) {
+    let mut keep = kept.iter();
+    items.retain(|_| *keep.next().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenarios::Scenario;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn unit_box() -> Cuboid {
+        Cuboid::from(([0.; 3], [1.; 3]))
+    }
+
+    fn random_bodies(n: usize, seed: u64) -> Vec<Body> {
+        ic::uniform_box(n, &unit_box(), &mut StdRng::seed_from_u64(seed))
+    }
+
+    fn config(escape: EscapePolicy) -> SimulationConfig {
+        SimulationConfig { softening: 0.05, escape, ..SimulationConfig::default() }
+    }
+
+    #[test]
+    fn bodies_come_with_their_own_accelerations() {
+        let mut simulation = Simulation::with_config(random_bodies(50, 3), unit_box(), config(EscapePolicy::Remove));
+        for _ in 0..5 {
+            simulation.step(0.01);
+        }
+        // the new body leaves the forces of the last step out of date
+        simulation.add_bodies(random_bodies(1, 4));
+        let fresh = simulation.compute_accelerations();
+        let paired = |simulation: &Simulation| -> Vec<_> {
+            simulation.bodies_with_acceleration().map(|(body, a)| (body.id, a)).collect()
+        };
+        let ids = |simulation: &Simulation| simulation.bodies().iter().map(|body| body.id).collect::<Vec<_>>();
+        assert_eq!(paired(&simulation), ids(&simulation).into_iter().zip(fresh).collect::<Vec<_>>());
+        // after a step, the accelerations it closed on
+        simulation.step(0.01);
+        assert_eq!(simulation.bodies_with_acceleration().count(), simulation.len());
+        let stored = simulation.current_accelerations().to_vec();
+        assert_eq!(paired(&simulation), ids(&simulation).into_iter().zip(stored).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn bodies_by_id_keep_their_order_through_rebuilds_and_reordering() {
+        let morton = SimulationConfig { morton_order: true, ..config(EscapePolicy::Clamp) };
+        let mut simulation = Simulation::with_config(random_bodies(200, 16), unit_box(), morton);
+        let order = |bodies: Vec<&Body>| bodies.iter().map(|body| body.id).collect::<Vec<_>>();
+        let first = order(simulation.bodies_by_id().collect());
+        assert_eq!(first, (0..200).collect::<Vec<_>>());
+        let shuffled = order(simulation.bodies().iter().collect());
+        for _ in 0..3 {
+            simulation.step(0.01);
+            assert_eq!(order(simulation.bodies_by_id().collect()), first);
+        }
+        assert_ne!(order(simulation.bodies().iter().collect()), shuffled);
+        for body in simulation.bodies_by_id() {
+            assert_eq!(simulation.body(body.id), Some(body));
+        }
+    }
+
+    #[test]
+    fn an_f32_simulation_takes_its_constants_and_forces_in_f32() {
+        let bodies = random_bodies(200, 18);
+        let config = SimulationConfig { units: Units::SolarSystem, ..config(EscapePolicy::Clamp) };
+        let cast = bodies.iter().map(Body::cast).collect();
+        let single = Simulation::<f32>::with_config(cast, unit_box().cast(), config);
+        let double = Simulation::<f64>::with_config(bodies, unit_box(), config);
+        let ForceConstants { gravitational_constant, softening, theta }: ForceConstants<f32> = single.constants();
+        assert_eq!((softening, theta), (0.05, 0.5));
+        assert_eq!(gravitational_constant, Units::SolarSystem.gravitational_constant() as f32);
+        assert_eq!(double.constants().softening, 0.05f64);
+        // the same forces to f32's rounding, and not to f64's
+        let single: Vec<Point<f32>> = single.compute_accelerations();
+        let double: Vec<Point<f64>> = double.compute_accelerations();
+        let single: Vec<Point> = single.iter().map(Point::cast).collect();
+        let error = ForceError::between(&single, &double);
+        assert!(error.max < 1e-4 && error.max > 1e-12, "{:?}", error);
+    }
+
+    #[test]
+    fn added_bodies_join_the_tree_and_the_step() {
+        let mut simulation = Simulation::with_config(random_bodies(10, 1), unit_box(), config(EscapePolicy::Expand));
+        simulation.step(0.01);
+        let ids = simulation.add_bodies(random_bodies(5, 2));
+        assert_eq!(ids, 10..15);
+        assert_eq!(simulation.len(), 15);
+        assert_eq!(simulation.tree().unwrap().len(), 15);
+        assert_eq!(simulation.tree().unwrap().validate(), Ok(()));
+        let before: Vec<Point> = simulation.bodies().iter().map(|body| body.location).collect();
+        simulation.step(0.01);
+        assert!(simulation.bodies().iter().zip(&before).all(|(body, before)| body.location != *before));
+    }
+
+    #[test]
+    fn bodies_added_outside_grow_the_box() {
+        let mut simulation = Simulation::with_config(random_bodies(10, 3), unit_box(), config(EscapePolicy::Expand));
+        let mut outside = random_bodies(5, 4);
+        outside[0].location = Point { x: 3.5, y: 0.5, z: -1.2 };
+        simulation.add_bodies(outside);
+        assert!(simulation.bounds().contains(&Point { x: 3.5, y: 0.5, z: -1.2 }));
+        let tree = simulation.tree().unwrap();
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.len(), 15);
+        // opening every node makes the tree the direct sum, unless a body is filed where it pulls on itself
+        let error = simulation.force_error(0.);
+        assert!(error.max < 1e-12, "{}", error.max);
+    }
+
+    #[test]
+    fn bodies_added_outside_follow_the_escape_policy() {
+        let mut outside = random_bodies(5, 5);
+        outside[2].location = Point { x: -2., y: 0.5, z: 0.5 };
+
+        let mut clamped = Simulation::with_config(random_bodies(10, 6), unit_box(), config(EscapePolicy::Clamp));
+        let ids = clamped.add_bodies(outside.clone());
+        assert_eq!(clamped.len(), 15);
+        assert_eq!(clamped.body(ids.start + 2).unwrap().location.x, 0.);
+        assert_eq!(*clamped.bounds(), unit_box());
+        assert_eq!(clamped.tree().unwrap().validate(), Ok(()));
+
+        let mut removed = Simulation::with_config(random_bodies(10, 6), unit_box(), config(EscapePolicy::Remove));
+        let ids = removed.add_bodies(outside);
+        assert_eq!(removed.len(), 14);
+        assert!(removed.body(ids.start + 2).is_none());
+        assert_eq!(removed.tree().unwrap().len(), 14);
+        removed.step(0.01);
+        assert_eq!(removed.len(), 14);
+    }
+
+    #[test]
+    fn tree_potential_opening_everything_is_the_direct_one() {
+        let config = SimulationConfig { theta: 0., softening: 0.01, ..SimulationConfig::default() };
+        let simulation = Simulation::with_config(random_bodies(200, 9), unit_box(), config);
+        let tree = simulation.diagnostics(PotentialMethod::Tree).potential_energy;
+        let direct = simulation.diagnostics(PotentialMethod::Direct).potential_energy;
+        assert!((tree - direct).abs() < 1e-10 * direct.abs(), "{} against {}", tree, direct);
+    }
+
+    #[test]
+    fn backends_diff_to_rounding() {
+        let bodies = random_bodies(300, 11);
+        let linear = SimulationConfig { backend: TreeBackend::Linear, ..config(EscapePolicy::Expand) };
+        let mut a = Simulation::with_config(bodies.clone(), unit_box(), config(EscapePolicy::Expand));
+        let mut b = Simulation::with_config(bodies, unit_box(), linear);
+        assert!(a.diff(&b).is_identical());
+        for _ in 0..5 {
+            a.step(0.001);
+            b.step(0.001);
+        }
+        let report = a.diff(&b);
+        assert_eq!(report.bodies.len(), 300);
+        assert!(report.max_position < 1e-9, "{:?}", report.max_position);
+    }
+
+    #[test]
+    fn a_tree_refitted_between_rebuilds_drifts_off_gradually_and_costs_less() {
+        let run = |config: SimulationConfig| {
+            let mut bodies = random_bodies(2000, 14);
+            for body in &mut bodies {
+                body.mass *= 1e-4;
+                body.velocity = Point { x: body.location.y - 0.5, y: 0.5 - body.location.x, z: 0. } * 0.2;
+            }
+            let mut simulation = Simulation::with_config(bodies, unit_box(), config);
+            let mut tree = Duration::ZERO;
+            for _ in 0..16 {
+                tree += simulation.step_timed(0.01).1.tree;
+            }
+            (simulation.bodies().to_vec(), tree)
+        };
+        let every = |rebuild_every| run(SimulationConfig { rebuild_every, ..config(EscapePolicy::Clamp) });
+        let (exact, rebuilding) = every(1);
+        assert_eq!(run(config(EscapePolicy::Clamp)).0, exact);
+        let error = |bodies: &[Body]| {
+            let errors = bodies.iter().zip(&exact).map(|(a, b)| a.location.distance_squared(&b.location).sqrt());
+            errors.fold(0., f64::max)
+        };
+        let mut last = 0.;
+        for k in [2, 4, 16] {
+            let (bodies, refitting) = every(k);
+            let error = error(&bodies);
+            assert!(error > last && error < 1e-4, "{} after rebuilding every {}", error, k);
+            last = error;
+            if k == 16 {
+                assert!(refitting * 2 < rebuilding, "{:?} vs {:?}", refitting, rebuilding);
+            }
+        }
+    }
+
+    #[test]
+    fn morton_ordered_bodies_keep_their_ids_and_their_motion() {
+        let incremental = SimulationConfig {
+            rebuild: RebuildStrategy::Incremental { max_moved: 0.5 },
+            ..config(EscapePolicy::Clamp)
+        };
+        let mut plain = Simulation::with_config(random_bodies(500, 15), unit_box(), incremental);
+        let morton = SimulationConfig { morton_order: true, ..incremental };
+        let mut sorted = Simulation::with_config(random_bodies(500, 15), unit_box(), morton);
+        for _ in 0..4 {
+            plain.step(0.01);
+            sorted.step(0.01);
+        }
+        let before = sorted.bodies().to_vec();
+        plain.step(0.01);
+        sorted.step(0.01);
+        // in the order of the keys at the start of the step
+        let mut expected = before;
+        expected.sort_by_key(|body| morton_key(&unit_box(), &body.location));
+        let ids = |bodies: &[Body]| bodies.iter().map(|body| body.id).collect::<Vec<_>>();
+        assert_eq!(ids(sorted.bodies()), ids(&expected));
+        assert_ne!(ids(sorted.bodies()), ids(plain.bodies()));
+        assert_eq!(sorted.tree().unwrap().validate(), Ok(()));
+        // the same bodies in the same places, to the rounding of summing their forces in another order
+        for body in plain.bodies() {
+            let other = sorted.body(body.id).unwrap();
+            assert_eq!(other.mass, body.mass);
+            assert!(other.location.distance_squared(&body.location) < 1e-24, "{:?} vs {:?}", other, body);
+        }
+    }
+
+    #[test]
+    fn a_regularized_binary_keeps_its_orbit_where_a_softened_one_loses_it() {
+        // an eccentric binary of semi-major axis 0.01 among light bodies, with softening half its size, starting
+        // from apocenter and taking 8 steps an orbit for 25 orbits, before the light bodies fall in on it
+        let (a, e): (f64, f64) = (0.01, 0.5);
+        let center = Point { x: 0.5, y: 0.5, z: 0.5 };
+        let (offset, speed) = (a * (1. + e), (1. / a * (1. - e) / (1. + e)).sqrt());
+        let pair = [(-0.5, -0.5), (0.5, 0.5)].map(|(at, moving)| Body {
+            mass: 0.5,
+            location: center + Point { x: at * offset, y: 0., z: 0. },
+            velocity: Point { x: 0., y: moving * speed, z: 0. },
+            ..Body::default()
+        });
+        let mut bodies = pair.to_vec();
+        bodies.extend(random_bodies(50, 16).into_iter().map(|body| Body { mass: body.mass * 1e-3, ..body }));
+        let semi_major_axis = |simulation: &Simulation| {
+            let (first, second) = (simulation.body(0).unwrap(), simulation.body(1).unwrap());
+            let velocity = second.velocity - first.velocity;
+            let energy = velocity.dot(&velocity) / 2. - 1. / first.location.distance_squared(&second.location).sqrt();
+            -1. / (2. * energy)
+        };
+        let dt = 2. * std::f64::consts::PI * a.powf(1.5) / 8.;
+        // the steps until the pair's semi-major axis is a percent off
+        let lasts = |regularization| {
+            let config = SimulationConfig { softening: a / 2., regularization, ..config(EscapePolicy::Expand) };
+            let mut simulation = Simulation::with_config(bodies.clone(), unit_box(), config);
+            (0..200)
+                .find(|_| {
+                    simulation.step(dt);
+                    (semi_major_axis(&simulation) - a).abs() > 0.01 * a
+                })
+                .unwrap_or(200)
+        };
+        let softened = lasts(Regularization::Off);
+        let regularized = lasts(Regularization::Kepler { radius: 0.05 });
+        assert!(softened < 8, "softened for {} steps", softened);
+        assert_eq!(regularized, 200);
+    }
+
+    #[test]
+    fn a_tight_pair_is_bound_and_two_passing_bodies_are_not() {
+        let body = |x: f64, vy: f64| Body {
+            mass: 1.,
+            location: Point { x, y: 0.5, z: 0.5 },
+            velocity: Point { x: 0., y: vy, z: 0. },
+            ..Body::default()
+        };
+        // both drifting along at 3, which the clump's own frame takes out
+        let bound = vec![body(0.45, 3.5), body(0.55, 2.5), body(0.9, 0.)];
+        let passing = vec![body(0.45, 8.), body(0.55, -8.), body(0.9, 0.)];
+        let region = Cuboid::from(([0.3; 3], [0.7; 3]));
+        let config = SimulationConfig { softening: 0.01, ..SimulationConfig::default() };
+        let bound = Simulation::with_config(bound, unit_box(), config).binding_energy(&region);
+        assert!(bound < 0., "{}", bound);
+        // 0.5 * 2 * 0.5^2 kinetic against -1 / sqrt(0.1^2 + 0.01^2), the third body left out
+        assert!((bound - (0.25 - 1. / 0.0101f64.sqrt())).abs() < 1e-12, "{}", bound);
+        let passing = Simulation::with_config(passing, unit_box(), config).binding_energy(&region);
+        assert!(passing > 0., "{}", passing);
+        assert_eq!(Simulation::with_config(vec![], unit_box(), config).binding_energy(&region), 0.);
+    }
+
+    #[test]
+    fn bodies_without_self_gravity_follow_the_kepler_ellipses_of_a_central_mass() {
+        // two heavy bodies close enough to swing each other around if they pulled, on orbits of eccentricity
+        // about 0.5 round a unit mass at the middle of the box, and a tracer on a third
+        let center = Point { x: 0.5, y: 0.5, z: 0.5 };
+        let starts = [
+            (Point { x: 0.3, y: 0., z: 0. }, Point { x: 0., y: 1.1, z: 0.2 }, Species::Live),
+            (Point { x: 0.31, y: 0.01, z: 0. }, Point { x: 0.1, y: -1.2, z: 0. }, Species::Live),
+            (Point { x: 0., y: -0.2, z: 0.1 }, Point { x: 1.6, y: 0., z: 0. }, Species::Tracer),
+        ];
+        let bodies: Vec<Body> = starts
+            .iter()
+            .map(|&(offset, velocity, species)| {
+                Body { mass: 0.1, location: center + offset, velocity, species, ..Body::default() }
+            })
+            .collect();
+        let (dt, steps) = (1e-3, 2000);
+        let run = |self_gravity| {
+            let config =
+                SimulationConfig { self_gravity, integrator: Scheme::Yoshida4, ..config(EscapePolicy::Expand) };
+            let mut simulation = Simulation::with_config(bodies.clone(), unit_box(), config);
+            simulation.add_potential(crate::external::Kepler::new(1., center));
+            let energy = simulation.total_energy();
+            for _ in 0..steps {
+                simulation.step(dt);
+            }
+            let offs: Vec<f64> = starts
+                .iter()
+                .enumerate()
+                .map(|(id, &(offset, velocity, _))| {
+                    let (expected, _) = regularization::kepler_drift(offset, velocity, 1., steps as f64 * dt);
+                    (simulation.body(id as u64).unwrap().location - center).distance_squared(&expected).sqrt()
+                })
+                .collect();
+            (offs, (simulation.total_energy() - energy).abs() / energy.abs())
+        };
+        let (offs, drift) = run(false);
+        assert!(offs.iter().all(|&off| off < 1e-4), "{:?}", offs);
+        assert!(drift < 1e-6, "{}", drift);
+        // pulling on each other, the heavy two wander off their ellipses
+        let (pulled, _) = run(true);
+        assert!(pulled[0] > 1e-2 && pulled[1] > 1e-2, "{:?}", pulled);
+    }
+
+    #[test]
+    fn a_slab_wraps_bodies_round_x_and_drops_them_off_z() {
+        let slab = [BoundaryCondition::Periodic, BoundaryCondition::Periodic, BoundaryCondition::Open];
+        let config = SimulationConfig { boundary: slab, ..config(EscapePolicy::Remove) };
+        let body = |location: [f64; 3], velocity: [f64; 3]| Body {
+            mass: 1e-6,
+            location: location.into(),
+            velocity: velocity.into(),
+            ..Body::default()
+        };
+        let bodies = vec![
+            body([0.99, 0.5, 0.5], [2., 0., 0.]),
+            body([0.5, 0.5, 0.99], [0., 0., 2.]),
+            body([0.5, 0.2, 0.5], [0., 0., 0.]),
+        ];
+        let mut simulation = Simulation::with_config(bodies, unit_box(), config);
+        simulation.set_event_log(true);
+        simulation.step(0.01);
+        assert_eq!(simulation.len(), 2);
+        let wrapped = simulation.body(0).unwrap().location;
+        assert!((wrapped.x - 0.01).abs() < 1e-9 && (wrapped.y - 0.5).abs() < 1e-9, "{:?}", wrapped);
+        assert!(simulation.body(1).is_none());
+        let kinds: Vec<_> = simulation.events().iter().map(|event| (event.kind, event.body_ids.clone())).collect();
+        assert_eq!(kinds, vec![(EventKind::Wrapped, vec![0]), (EventKind::Removed, vec![1])]);
+    }
+
+    #[test]
+    fn slab_forces_act_across_the_periodic_faces_only() {
+        let slab = [BoundaryCondition::Periodic, BoundaryCondition::Periodic, BoundaryCondition::Open];
+        let config = SimulationConfig { theta: 0.2, boundary: slab, ..config(EscapePolicy::Clamp) };
+        let simulation = Simulation::with_config(random_bodies(300, 17), unit_box(), config);
+        let tree = simulation.compute_accelerations();
+        let direct = simulation.compute_accelerations_direct();
+        let error = ForceError::between(&tree, &direct);
+        assert!(error.rms < 1e-2, "{:?}", error);
+        // a pair on either side of each face: they pull on each other across the x face, the short way round,
+        // but not across the z face, where they are most of a box apart
+        let pair = |a: [f64; 3], b: [f64; 3]| {
+            let bodies = [a, b].map(|location| Body { mass: 1., location: location.into(), ..Body::default() });
+            let exact = SimulationConfig { theta: 0., ..config };
+            Simulation::with_config(bodies.to_vec(), unit_box(), exact).compute_accelerations()[0]
+        };
+        assert!(pair([0.05, 0.5, 0.5], [0.95, 0.5, 0.5]).x < 0.);
+        assert!(pair([0.5, 0.5, 0.05], [0.5, 0.5, 0.95]).z > 0.);
+    }
+
+    #[test]
+    fn a_cutoff_past_the_box_is_full_gravity_and_a_short_one_sums_the_neighbors_only() {
+        let bodies = random_bodies(400, 23);
+        let whole = SimulationConfig { cutoff: Some(10.), ..config(EscapePolicy::Clamp) };
+        let simulation = Simulation::with_config(bodies.clone(), unit_box(), whole);
+        let direct = simulation.compute_accelerations_direct();
+        let error = ForceError::between(&simulation.compute_accelerations(), &direct);
+        assert!(error.max < 1e-12, "{:?}", error);
+        let cutoff = 0.15;
+        let slab = [BoundaryCondition::Periodic, BoundaryCondition::Periodic, BoundaryCondition::Open];
+        for boundary in [[BoundaryCondition::Open; 3], slab] {
+            let config = SimulationConfig { cutoff: Some(cutoff), boundary, ..config(EscapePolicy::Clamp) };
+            let mut simulation = Simulation::with_config(bodies.clone(), unit_box(), config);
+            simulation.step(1e-3);
+            let axes = periodic_axes(boundary);
+            let space = *simulation.bounds();
+            let accelerations = simulation.compute_accelerations();
+            for (body, acceleration) in simulation.bodies().iter().zip(accelerations) {
+                let mut expected = Point::default();
+                for other in simulation.bodies() {
+                    let image = space.nearest_image_along(&other.location, &body.location, axes);
+                    if other.id != body.id && image.distance_squared(&body.location) <= cutoff * cutoff {
+                        expected += Gravity.pair_acceleration(&body.location, &image, other.mass, 0.05);
+                    }
+                }
+                let error = (acceleration - expected).length();
+                assert!(error <= 1e-12 * expected.length().max(1.), "{} off {:?}", error, expected);
+            }
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn a_light_body_shows_beside_a_heavy_one() {
+        let body = |mass, x| Body { mass, location: Point { x, y: 0.5, z: 0.5 }, ..Body::default() };
+        let simulation = Simulation::new(vec![body(1e6, 0.25), body(1e-3, 0.75)], unit_box());
+        let path = std::env::temp_dir().join(format!("barneshutt3d-projection-{}.png", std::process::id()));
+        simulation.render_projection(Axis::Z, 8, 8, &path).unwrap();
+        let image = image::open(&path).unwrap().to_luma8();
+        std::fs::remove_file(&path).unwrap();
+        let (heavy, light) = (image.get_pixel(2, 3).0[0], image.get_pixel(6, 3).0[0]);
+        assert_eq!(heavy, 255);
+        assert!(light > 0 && light < heavy, "{}", light);
+        assert_eq!(image.pixels().filter(|pixel| pixel.0[0] > 0).count(), 2);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn a_projection_without_pixels_is_an_error() {
+        let simulation = Simulation::new(random_bodies(10, 48), unit_box());
+        let path = std::env::temp_dir().join(format!("barneshutt3d-empty-projection-{}.png", std::process::id()));
+        for (width, height) in [(0, 8), (8, 0), (0, 0)] {
+            let result = simulation.render_projection(Axis::Z, width, height, &path);
+            assert!(matches!(result, Err(image::ImageError::Parameter(_))), "{}x{}: {:?}", width, height, result);
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn interactions_fall_as_theta_opens_and_reach_n_squared_at_zero() {
+        let n = 1000;
+        let counted = |theta: f64| {
+            let config = SimulationConfig { theta, ..config(EscapePolicy::Expand) };
+            let mut simulation = Simulation::with_config(random_bodies(n, 29), unit_box(), config);
+            assert_eq!(simulation.step_timed(1e-4).1.interactions, None);
+            simulation.set_interaction_counting(true);
+            // a leapfrog step past the first takes the one force pass
+            simulation.step_timed(1e-4).1.interactions.unwrap()
+        };
+        let exact = counted(0.);
+        assert_eq!(exact, Interactions { body_body: (n * n) as u64, body_cell: 0 });
+        let totals: Vec<u64> = [0.3, 0.6, 1.].into_iter().map(|theta| counted(theta).total()).collect();
+        assert!(totals[0] < exact.total() && totals.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", totals);
+        assert!(totals[2] * 5 < exact.total(), "{:?}", totals);
+    }
+
+    // first order and not symplectic: drift and kick both from the start of the step
+    struct Euler;
+
+    impl Integrator for Euler {
+        fn advance<S: Scalar, F: ForceModel>(&self, stage: &mut Stage<'_, S, F>, dt: f64) {
+            stage.drift(dt);
+            stage.kick(dt);
+            stage.settle();
+            stage.update_forces();
+        }
+    }
+
+    #[test]
+    fn leapfrog_keeps_what_it_should_and_euler_loses_energy() {
+        let scenario = Scenario::FigureEight;
+        let mut simulation = scenario.simulation();
+        let reference = simulation.conserved_quantities();
+        for _ in 0..2000 {
+            simulation.step(scenario.dt());
+        }
+        assert_eq!(simulation.check_conservation(&reference, 1e-4), Ok(()));
+        let mut simulation = scenario.simulation();
+        for _ in 0..2000 {
+            simulation.step_with(scenario.dt(), &Euler);
+        }
+        match simulation.check_conservation(&reference, 1e-4) {
+            Err(ConservationError::Energy { drift }) => assert!(drift.abs() > 1e-2, "{}", drift),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn step_timing_adds_up_to_the_step() {
+        let config = SimulationConfig { multipole: MultipoleOrder::Quadrupole, ..config(EscapePolicy::Expand) };
+        let mut simulation = Simulation::with_config(random_bodies(2000, 10), unit_box(), config);
+        simulation.step(0.001);
+        let instant = Instant::now();
+        let (report, timing) = simulation.step_timed(0.001);
+        let elapsed = instant.elapsed();
+        assert_eq!(report.force_evaluations, 2000);
+        for part in [timing.tree, timing.moments, timing.forces, timing.integration] {
+            assert!(part > Duration::ZERO, "{:?}", timing);
+        }
+        assert!(timing.total() <= elapsed && timing.total() * 10 >= elapsed * 9, "{:?} of {:?}", timing, elapsed);
+        assert!(timing.forces > timing.moments);
+    }
+
+    #[test]
+    fn one_fast_body_sets_the_max_speed_and_the_crossing_time() {
+        let config = config(EscapePolicy::Expand);
+        let mut simulation = Simulation::with_config(random_bodies(200, 12), unit_box(), config);
+        let before = simulation.motion_stats();
+        assert_eq!(before.max_speed, 0.);
+        assert!(before.min_separation > 0. && before.min_separation < 0.5, "{}", before);
+        let fast = Body { mass: 1e-3, velocity: Point { x: 50., y: 0., z: 0. }, ..Body::default() };
+        simulation.add_bodies(vec![Body { location: Point { x: 0.5, y: 0.5, z: 0.5 }, ..fast }]);
+        let after = simulation.motion_stats();
+        assert_eq!(after.max_speed, 50.);
+        assert!(after.min_separation <= before.min_separation);
+        assert_eq!(after.min_crossing_time, after.min_separation / 50.);
+    }
+
+    #[test]
+    fn a_theta_field_spends_its_accuracy_where_it_is_asked_to() {
+        let center = Point { x: 0.5, y: 0.5, z: 0.5 };
+        let inside = move |location: &Point| location.distance_squared(&center) < 0.04;
+        let coarse = SimulationConfig { theta: 1., ..config(EscapePolicy::Expand) };
+        let fine = SimulationConfig { theta: 0.1, ..coarse };
+        let mut regional = Simulation::with_config(random_bodies(3000, 14), unit_box(), coarse);
+        regional.set_theta_field(move |location| if inside(location) { 0.1 } else { 1. });
+        let uniform = Simulation::with_config(random_bodies(3000, 14), unit_box(), coarse);
+        let everywhere = Simulation::with_config(random_bodies(3000, 14), unit_box(), fine);
+        let exact = uniform.compute_accelerations_direct();
+        let timed = |simulation: &Simulation| {
+            let instant = Instant::now();
+            let accelerations = simulation.compute_accelerations();
+            (ForceError::between(&accelerations, &exact), instant.elapsed())
+        };
+        let ((regional_error, regional_time), (uniform_error, _)) = (timed(&regional), timed(&uniform));
+        let (_, everywhere_time) = timed(&everywhere);
+        // rms of the relative error over the bodies in the region, and over the rest
+        let rms = |error: &ForceError, inner: bool| {
+            let errors: Vec<f64> = error
+                .relative
+                .iter()
+                .zip(regional.bodies())
+                .filter(|(_, body)| inside(&body.location) == inner)
+                .map(|(error, _)| error * error)
+                .collect();
+            (errors.iter().sum::<f64>() / errors.len() as f64).sqrt()
+        };
+        assert!(rms(&regional_error, true) * 10. < rms(&uniform_error, true));
+        assert_eq!(rms(&regional_error, false), rms(&uniform_error, false));
+        assert!(regional_time < everywhere_time, "{:?} vs {:?}", regional_time, everywhere_time);
+    }
+
+    #[test]
+    fn light_bodies_under_the_threshold_ride_along_as_tracers() {
+        let mut bodies = random_bodies(40, 15);
+        for mut body in random_bodies(4000, 16) {
+            body.mass *= 1e-12;
+            bodies.push(body);
+        }
+        let all = config(EscapePolicy::Expand);
+        let pruned = SimulationConfig { tracer_mass: 1e-6, ..all };
+        let mut full = Simulation::with_config(bodies.clone(), unit_box(), all);
+        let mut light = Simulation::with_config(bodies, unit_box(), pruned);
+        assert_eq!(light.bodies().iter().filter(|body| body.is_source()).count(), 40);
+        assert_eq!(light.tree().unwrap().len(), 40);
+        let (mut full_forces, mut light_forces) = (Duration::ZERO, Duration::ZERO);
+        for _ in 0..5 {
+            full_forces += full.step_timed(0.001).1.forces;
+            light_forces += light.step_timed(0.001).1.forces;
+        }
+        assert!(light_forces < full_forces, "{:?} vs {:?}", light_forces, full_forces);
+        let difference = full.diff(&light);
+        assert!(difference.max_position < 1e-9, "{}", difference.max_position);
+        let added = light.add_bodies(vec![Body { mass: 1e-9, location: unit_box().center(), ..Body::default() }]);
+        assert!(!light.body(added.start).unwrap().is_source());
+    }
+
+    #[test]
+    fn pairwise_sums_hold_angular_momentum_to_rounding() {
+        let drift = |traversal| {
+            let config = SimulationConfig { traversal, theta: 0.5, ..config(EscapePolicy::Expand) };
+            // light and spun about the middle of the box, so the tree errors stay small enough to bound
+            let mut bodies = random_bodies(64, 17);
+            for body in &mut bodies {
+                let offset = body.location - unit_box().center();
+                body.mass *= 0.01;
+                body.velocity = Point { x: -offset.y, y: offset.x, z: 0.1 * offset.x };
+            }
+            let mut simulation = Simulation::with_config(bodies, unit_box(), config);
+            let initial = diagnostics::angular_momentum(simulation.bodies());
+            for _ in 0..2000 {
+                simulation.step(0.001);
+            }
+            (diagnostics::angular_momentum(simulation.bodies()) - initial).length() / initial.length()
+        };
+        let (pairwise, tree) = (drift(Traversal::Pairwise), drift(Traversal::Single));
+        assert!(pairwise < 1e-12, "{}", pairwise);
+        assert!(tree > pairwise && tree < 1e-2, "{} {}", tree, pairwise);
+        let simulation = Simulation::with_config(random_bodies(200, 18), unit_box(), config(EscapePolicy::Expand));
+        let pairwise = simulation.compute_accelerations_pairwise();
+        let error = ForceError::between(&pairwise, &simulation.compute_accelerations_direct());
+        assert!(error.max < 1e-12, "{}", error.max);
+    }
+
+    #[test]
+    fn a_head_on_merge_is_logged_once() {
+        let body = |id, x: f64, vx| Body {
+            id,
+            mass: 1.,
+            location: Point { x, y: 0.5, z: 0.5 },
+            velocity: Point { x: vx, y: 0., z: 0. },
+            ..Body::default()
+        };
+        let config = SimulationConfig {
+            collisions: CollisionPolicy::Merge { radius: 0.02 },
+            escape: EscapePolicy::Remove,
+            ..config(EscapePolicy::Expand)
+        };
+        let bodies = vec![body(0, 0.3, 1.), body(1, 0.7, -1.), body(2, 0.9, 5.)];
+        let mut simulation = Simulation::with_config(bodies, unit_box(), config);
+        simulation.set_event_log(true);
+        for _ in 0..30 {
+            simulation.step(0.01);
+        }
+        let merges: Vec<_> = simulation.events().iter().filter(|event| event.kind == EventKind::Merge).collect();
+        assert_eq!(merges.len(), 1, "{:?}", simulation.events());
+        assert_eq!(merges[0].body_ids, vec![0, 1]);
+        assert!(merges[0].step > 0 && (merges[0].time - merges[0].step as f64 * 0.01).abs() < 1e-12);
+        let removals: Vec<_> = simulation.events().iter().filter(|event| event.kind == EventKind::Removed).collect();
+        assert_eq!(removals.len(), 1);
+        assert_eq!(removals[0].body_ids, vec![2]);
+        assert_eq!(simulation.take_events().len(), 2);
+        assert!(simulation.events().is_empty());
+    }
+
+    #[test]
+    fn undo_goes_back_bit_for_bit() {
+        let config = config(EscapePolicy::Expand);
+        let mut simulation = Simulation::with_config(random_bodies(200, 13), unit_box(), config);
+        simulation.set_undo_depth(2);
+        simulation.step(0.01);
+        let (before, time) = (simulation.bodies().to_vec(), simulation.time());
+        simulation.step(0.01);
+        let after = simulation.bodies().to_vec();
+        simulation.step(0.01);
+        assert_eq!(simulation.undoable(), 2);
+        assert!(simulation.undo() && simulation.undo());
+        assert!(!simulation.undo());
+        assert_eq!(simulation.bodies(), &before[..]);
+        assert_eq!((simulation.time(), simulation.steps()), (time, 1));
+        // the carried accelerations come back too, so the step is taken again exactly
+        simulation.step(0.01);
+        assert_eq!(simulation.bodies(), &after[..]);
+    }
+
+    #[test]
+    fn bounding_spheres_open_more_of_a_filled_box() {
+        for backend in [TreeBackend::Pointer, TreeBackend::Linear] {
+            let boxes = SimulationConfig { backend, bucket_size: 4, ..config(EscapePolicy::Expand) };
+            let spheres = SimulationConfig { opening: Opening::BoundingSphere, ..boxes };
+            let boxed = Simulation::with_config(random_bodies(1000, 11), unit_box(), boxes);
+            let mut sphered = Simulation::with_config(random_bodies(1000, 11), unit_box(), spheres);
+            let (box_error, sphere_error) = (boxed.force_error(0.7), sphered.force_error(0.7));
+            assert!(sphere_error.rms <= box_error.rms, "{:?}: {} > {}", backend, sphere_error.rms, box_error.rms);
+            assert!(sphere_error.rms < 0.01, "{}", sphere_error.rms);
+            // bodies added in place keep the radii current
+            sphered.add_bodies(random_bodies(100, 12));
+            assert!(sphered.force_error(0.7).rms < 0.01);
+        }
+    }
+
+    // a cubic lattice of `side`^3 unit masses `spacing` apart, starting at the origin
+    fn lattice(side: usize, spacing: f64) -> Vec<Body> {
+        let mut bodies = vec![];
+        for i in 0..side {
+            for j in 0..side {
+                for k in 0..side {
+                    let location = Point { x: i as f64, y: j as f64, z: k as f64 } * spacing;
+                    bodies.push(Body { mass: 1., location, ..Body::default() });
+                }
+            }
+        }
+        bodies
+    }
+
+    #[test]
+    fn lattice_density_scales_with_spacing() {
+        let density = |spacing: f64| {
+            let bodies = lattice(5, spacing);
+            let space = Cuboid::bounding(&bodies).pad(0.1);
+            let simulation = Simulation::new(bodies, space);
+            // the six face neighbors of the center body at one spacing
+            simulation.local_density(6)[62]
+        };
+        let expected = 6. / (4. / 3. * std::f64::consts::PI);
+        assert!((density(1.) - expected).abs() < 1e-9 * expected, "{}", density(1.));
+        assert!((density(0.5) / density(1.) - 8.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clumped_bodies_are_denser_than_isolated_ones() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let clump = Cuboid::from(([0.4; 3], [0.45; 3]));
+        let mut bodies = ic::uniform_box(20, &clump, &mut rng);
+        bodies.iter_mut().for_each(|body| body.mass = 1.);
+        bodies.push(Body { mass: 1., location: Point { x: 0.05, y: 0.9, z: 0.1 }, ..Body::default() });
+        bodies.push(Body { mass: 1., location: Point { x: 0.95, y: 0.1, z: 0.9 }, ..Body::default() });
+        let simulation = Simulation::new(bodies, unit_box());
+        let density = simulation.local_density(1);
+        assert!(density.iter().all(|density| density.is_finite() && *density > 0.));
+        let least_clumped = density[..20].iter().cloned().fold(f64::INFINITY, f64::min);
+        assert!(least_clumped > density[20].max(density[21]));
+    }
+
+    #[test]
+    fn a_lone_source_has_no_density() {
+        let lone = Body { mass: 1., location: Point::from([0.5; 3]), ..Body::default() };
+        assert_eq!(Simulation::new(vec![lone], unit_box()).local_density(3), [0.]);
+        // a tracer measures by the source, which still has nothing but itself
+        let tracer = Body { mass: 1., location: Point::from([0.75, 0.5, 0.5]), species: Species::Tracer, ..lone };
+        let density = Simulation::new(vec![lone, tracer], unit_box()).local_density(1);
+        assert_eq!(density[0], 0.);
+        assert!((density[1] - 1. / (4. / 3. * std::f64::consts::PI * 0.25f64.powi(3))).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one neighbor")]
+    fn density_needs_a_neighbor() {
+        Simulation::new(random_bodies(5, 8), unit_box()).local_density(0);
+    }
+
+    #[test]
+    fn two_masses_on_the_x_axis_have_only_an_xx_moment() {
+        let body = |mass, x| Body { mass, location: Point { x, y: 0., z: 0. }, ..Body::default() };
+        let tracer = Body { species: Species::Tracer, ..body(5., 3.) };
+        let space = Cuboid::from(([-4.; 3], [4.; 3]));
+        let simulation = Simulation::new(vec![body(2., 1.), body(1., -2.), tracer], space);
+        let moment = simulation.mass_quadrupole();
+        assert_eq!(moment[0][0], 2. * 1. + 1. * 4.);
+        for (i, row) in moment.iter().enumerate() {
+            for (j, &entry) in row.iter().enumerate() {
+                if (i, j) != (0, 0) {
+                    assert_eq!(entry, 0., "[{}][{}]", i, j);
+                }
+            }
+        }
+        // rotated onto the diagonal of the xy plane the moment splits evenly, and stays symmetric
+        let diagonal = |mass, r: f64| {
+            let along = r / 2f64.sqrt();
+            Body { mass, location: Point { x: along, y: along, z: 0. }, ..Body::default() }
+        };
+        let moment = Simulation::new(vec![diagonal(2., 1.), diagonal(1., -2.)], space).mass_quadrupole();
+        for entry in [moment[0][0], moment[1][1], moment[0][1], moment[1][0]] {
+            assert!((entry - 3.).abs() < 1e-12, "{:?}", moment);
+        }
+        assert_eq!(moment[2], [0.; 3]);
+    }
+
+    #[test]
+    fn tracers_are_pulled_but_add_no_mass_to_the_tree() {
+        let live = random_bodies(50, 51);
+        let mut bodies = live.clone();
+        for mut body in random_bodies(20, 52) {
+            body.mass = 5.;
+            body.species = Species::Tracer;
+            bodies.push(body);
+        }
+        let mass: f64 = live.iter().map(|body| body.mass).sum();
+        let (center, _) = diagnostics::center_of_mass(&live).unwrap();
+        for backend in [TreeBackend::Pointer, TreeBackend::Linear] {
+            let config = SimulationConfig { backend, theta: 0., ..config(EscapePolicy::Expand) };
+            let with = Simulation::with_config(bodies.clone(), unit_box(), config);
+            let without = Simulation::with_config(live.clone(), unit_box(), config);
+            let (root_mass, root_center) = match backend {
+                TreeBackend::Pointer => {
+                    let root = &with.tree().unwrap().nodes()[0];
+                    (root.mass(), *root.center_of_mass())
+                }
+                _ => {
+                    let root = &with.linear_tree().unwrap().nodes()[0];
+                    (root.mass(), *root.center_of_mass())
+                }
+            };
+            assert!((root_mass - mass).abs() < 1e-12, "{:?}: {} against {}", backend, root_mass, mass);
+            assert!(root_center.distance_squared(&center) < 1e-24, "{:?}", backend);
+            let accelerations = with.compute_accelerations();
+            assert_eq!(&accelerations[..50], &without.compute_accelerations()[..]);
+            // each tracer feels what the live bodies alone pull with
+            let direct = with.compute_accelerations_direct();
+            for (tree, direct) in accelerations[50..].iter().zip(&direct[50..]) {
+                assert!(tree.length() > 0. && tree.distance_squared(direct).sqrt() < 1e-10 * direct.length());
+            }
+        }
+    }
+
+    #[test]
+    fn an_empty_simulation_measures_nothing_and_steps_through_time() {
+        let configs = [
+            SimulationConfig::default(),
+            SimulationConfig { backend: TreeBackend::Linear, ..SimulationConfig::default() },
+            SimulationConfig { traversal: Traversal::Dual, ..SimulationConfig::default() },
+            SimulationConfig { traversal: Traversal::Pairwise, ..SimulationConfig::default() },
+        ];
+        for config in configs {
+            let mut simulation: Simulation = Simulation::with_config(vec![], unit_box(), config);
+            assert_eq!(simulation.total_energy(), 0.);
+            for method in [PotentialMethod::Tree, PotentialMethod::Direct] {
+                let diagnostics = simulation.diagnostics(method);
+                assert_eq!(diagnostics, Diagnostics::default());
+                assert_eq!(diagnostics.linear_momentum, Point::default());
+            }
+            assert_eq!(diagnostics::center_of_mass(simulation.bodies()), None);
+            assert_eq!(simulation.to_com_frame(), None);
+            assert!(simulation.compute_accelerations().is_empty());
+            assert_eq!(simulation.bodies_by_id().count(), 0);
+            assert_eq!(simulation.bodies_with_acceleration().count(), 0);
+            for _ in 0..3 {
+                let report = simulation.step(0.1);
+                assert_eq!(report.dt, 0.1);
+                assert_eq!(report.force_evaluations, 0);
+                assert!(report.collisions.is_empty());
+            }
+            assert!((simulation.time() - 0.3).abs() < 1e-12);
+            assert!(simulation.is_empty());
+            assert_eq!(simulation.total_energy(), 0.);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn forces_on_a_clump_are_the_same_on_any_number_of_threads() {
+        // most of the box nearly empty, and a fifth of the bodies packed in a corner a hundredth its size
+        let mut rng = StdRng::seed_from_u64(49);
+        let mut bodies = ic::uniform_box(1600, &unit_box(), &mut rng);
+        bodies.extend(ic::uniform_box(400, &Cuboid::from(([0.1; 3], [0.11; 3])), &mut rng));
+        for traversal in [Traversal::Single, Traversal::Dual] {
+            let config = SimulationConfig { traversal, ..config(EscapePolicy::Remove) };
+            let simulation = Simulation::with_config(bodies.clone(), unit_box(), config);
+            let on = |threads| {
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                pool.install(|| simulation.compute_accelerations())
+            };
+            let serial = on(1);
+            for threads in [2, 4, 7] {
+                let parallel = on(threads);
+                assert!(serial.iter().zip(&parallel).all(|(a, b)| a == b), "{:?} on {}", traversal, threads);
+            }
+        }
+    }
+}
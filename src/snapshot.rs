@@ -0,0 +1,571 @@
+//! periodic dumps of body state for analysis outside the simulator
+
+use crate::body::{Body, Species};
+use crate::diagnostics;
+use crate::force::ForceModel;
+use crate::geometry::{Cuboid, Point};
+use crate::scalar::{Precision, Scalar};
+use crate::sim::Simulation;
+use crate::snapshot_file::BinarySink;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotFormat {
+    /// one row per body: step,time,total_energy,body,mass,x,y,z,vx,vy,vz,species with the body's id in `body`
+    /// and `live` or `tracer` in `species`, after a `# units:` comment line
+    Csv,
+    /// one object per snapshot carrying the metadata and a `bodies` array, each with its id and any label,
+    /// and tracers marked `"tracer":true`. appended files hold one object per line (json lines)
+    Json,
+    /// legacy vtk polydata for paraview: the bodies as vertices with id, mass and velocity attributes, and
+    /// the time as field data. a file holds one snapshot, so this needs the file-per-snapshot layout
+    Vtk,
+    /// extended xyz for ovito and other trajectory viewers: a count line, a comment line naming the columns
+    /// and carrying the step and time, then one `X id x y z mass vx vy vz` line per body, with species `T`
+    /// in place of `X` for tracers. appended files are trajectories
+    Xyz,
+    /// the chunked binary format of `snapshot_file`, for reading any step back with `SnapshotFile` without
+    /// parsing the rest. needs the single-file layout
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SnapshotLayout {
+    /// `path` is a directory that gets a `snapshot_<step>.csv` / `.json` / `.vtk` / `.xyz` per snapshot, for all
+    /// but the binary format
+    FilePerSnapshot,
+    /// `path` is a single file, truncated on the first snapshot and appended to after that
+    SingleFile,
+}
+
+/// the coordinates a `SnapshotWriter` writes positions in. only the written files change; the simulation
+/// keeps its own, unlike with `Simulation::to_com_frame`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFrame {
+    /// the simulation's coordinates
+    #[default]
+    Absolute,
+    /// relative to the center of mass of the live bodies at the time of the snapshot, so a drifting system
+    /// stays centered. velocities are written as they are
+    CenterOfMass,
+}
+
+/// which bodies a `SnapshotWriter` writes, to keep the files of big runs manageable. the id-based filters pick
+/// the same bodies in every snapshot, so they still make trajectories
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFilter {
+    /// bodies inside the box, faces included
+    Region(Cuboid),
+    /// bodies within `radius` of `center`
+    Sphere { center: Point, radius: f64 },
+    /// bodies whose id is a multiple of this, every k-th body of those made together
+    EveryNth(u64),
+    /// about `fraction` of the bodies, picked at random by id: the same ones for the same seed
+    Sample { fraction: f64, seed: u64 },
+    /// bodies of one species, e.g. for a writer per species that splits them into files of their own
+    Species(Species),
+}
+
+impl OutputFilter {
+    pub fn keeps<S: Scalar>(&self, body: &Body<S>) -> bool {
+        match *self {
+            OutputFilter::Region(region) => region.contains(&body.location.cast()),
+            OutputFilter::Sphere { center, radius } => {
+                body.location.cast().distance_squared(&center) <= radius * radius
+            }
+            OutputFilter::EveryNth(k) => body.id.is_multiple_of(k),
+            OutputFilter::Species(species) => body.species == species,
+            OutputFilter::Sample { fraction, seed } => {
+                // splitmix64 of the id and seed, as a uniform number in [0, 1)
+                let mut z = (body.id ^ seed.rotate_left(32)).wrapping_add(0x9e37_79b9_7f4a_7c15);
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^= z >> 31;
+                ((z >> 11) as f64 / (1u64 << 53) as f64) < fraction
+            }
+        }
+    }
+}
+
+/// writes the simulation state every `every` steps, the bodies in the order of their ids, and the final
+/// state through `finish`
+pub struct SnapshotWriter {
+    path: PathBuf,
+    format: SnapshotFormat,
+    layout: SnapshotLayout,
+    every: u64,
+    // a body is written if it passes all of them
+    filters: Vec<OutputFilter>,
+    frame: OutputFrame,
+    // the open file in the single-file layout
+    file: Option<BufWriter<File>>,
+    // the same for the binary format
+    binary: Option<BinarySink>,
+    // the step count of the last snapshot written
+    last: Option<u64>,
+}
+
+impl SnapshotWriter {
+    /// panics if `every` is 0, for vtk in a single file, or for binary in a file per snapshot
+    pub fn new(
+        path: impl Into<PathBuf>,
+        format: SnapshotFormat,
+        layout: SnapshotLayout,
+        every: u64,
+    ) -> Self {
+        assert!(every > 0, "snapshot cadence must be at least one step");
+        assert!(
+            !(format == SnapshotFormat::Vtk && layout == SnapshotLayout::SingleFile),
+            "a vtk file holds a single snapshot"
+        );
+        assert!(
+            !(format == SnapshotFormat::Binary && layout == SnapshotLayout::FilePerSnapshot),
+            "a binary snapshot file holds every snapshot"
+        );
+        SnapshotWriter {
+            path: path.into(),
+            format,
+            layout,
+            every,
+            filters: Vec::new(),
+            frame: OutputFrame::Absolute,
+            file: None,
+            binary: None,
+            last: None,
+        }
+    }
+
+    /// writes only the bodies that `filter` keeps, on top of any filters added before. panics on
+    /// `EveryNth(0)` or a sample fraction outside [0, 1]
+    pub fn with_filter(mut self, filter: OutputFilter) -> Self {
+        match filter {
+            OutputFilter::EveryNth(k) => assert!(k > 0, "every nth body needs n of at least 1"),
+            OutputFilter::Sample { fraction, .. } => assert!(
+                (0. ..=1.).contains(&fraction),
+                "a sample fraction must be between 0 and 1"
+            ),
+            OutputFilter::Region(_) | OutputFilter::Sphere { .. } | OutputFilter::Species(_) => {}
+        }
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn filters(&self) -> &[OutputFilter] {
+        &self.filters
+    }
+
+    /// writes positions in `frame`. the filters see the bodies as they are written, so a region is taken
+    /// in the same frame
+    pub fn with_frame(mut self, frame: OutputFrame) -> Self {
+        self.frame = frame;
+        self
+    }
+
+    pub fn frame(&self) -> OutputFrame {
+        self.frame
+    }
+
+    /// writes a snapshot if the simulation's step count is a multiple of the cadence, returning whether it did.
+    /// meant to be called once after every step, and once before the first for the initial state
+    pub fn record<S: Scalar, F: ForceModel>(
+        &mut self,
+        simulation: &Simulation<S, F>,
+    ) -> io::Result<bool> {
+        if !simulation.steps().is_multiple_of(self.every) {
+            return Ok(false);
+        }
+        self.write(simulation)?;
+        Ok(true)
+    }
+
+    /// writes the final state unless the last snapshot already holds it, as when a run stops short of the
+    /// cadence or on a stop condition, returning whether it did. meant to be called once the run is over
+    pub fn finish<S: Scalar, F: ForceModel>(
+        &mut self,
+        simulation: &Simulation<S, F>,
+    ) -> io::Result<bool> {
+        if self.last == Some(simulation.steps()) {
+            return Ok(false);
+        }
+        self.write(simulation)?;
+        Ok(true)
+    }
+
+    /// writes a snapshot regardless of the cadence
+    pub fn write<S: Scalar, F: ForceModel>(
+        &mut self,
+        simulation: &Simulation<S, F>,
+    ) -> io::Result<()> {
+        let format = self.format;
+        let shifted = match self.frame {
+            OutputFrame::Absolute => None,
+            OutputFrame::CenterOfMass => Some(centered(simulation.bodies())),
+        };
+        // in the order of their ids, as `Simulation::bodies_by_id`, so each body keeps its row from one
+        // snapshot to the next however the simulation reorders them
+        let mut bodies: Vec<&Body<S>> = shifted
+            .as_deref()
+            .unwrap_or(simulation.bodies())
+            .iter()
+            .filter(|body| self.filters.iter().all(|filter| filter.keeps(*body)))
+            .collect();
+        bodies.sort_unstable_by_key(|body| body.id);
+        self.last = Some(simulation.steps());
+        if format == SnapshotFormat::Binary {
+            if self.binary.is_none() {
+                self.binary = Some(BinarySink::create::<S>(create_with_parents(&self.path)?)?);
+            }
+            return self.binary.as_mut().unwrap().append(simulation, &bodies);
+        }
+        match self.layout {
+            SnapshotLayout::FilePerSnapshot => {
+                std::fs::create_dir_all(&self.path)?;
+                let extension = match format {
+                    SnapshotFormat::Csv => "csv",
+                    SnapshotFormat::Json => "json",
+                    SnapshotFormat::Vtk => "vtk",
+                    SnapshotFormat::Xyz => "xyz",
+                    SnapshotFormat::Binary => unreachable!("binary snapshots go in one file"),
+                };
+                let name = format!("snapshot_{:06}.{}", simulation.steps(), extension);
+                let mut out = BufWriter::new(File::create(self.path.join(name))?);
+                write_snapshot(&mut out, format, simulation, &bodies, true)?;
+                out.flush()
+            }
+            SnapshotLayout::SingleFile => {
+                let header = self.file.is_none();
+                if header {
+                    self.file = Some(BufWriter::new(create_with_parents(&self.path)?));
+                }
+                let out = self.file.as_mut().unwrap();
+                write_snapshot(out, format, simulation, &bodies, header)?;
+                out.flush()
+            }
+        }
+    }
+}
+
+// `bodies` moved so the live ones have their center of mass at the origin; unmoved when they have no mass
+fn centered<S: Scalar>(bodies: &[Body<S>]) -> Vec<Body<S>> {
+    let sources: Vec<Body<S>> = bodies
+        .iter()
+        .filter(|body| body.is_source())
+        .copied()
+        .collect();
+    let offset = diagnostics::center_of_mass(&sources)
+        .map_or(Point::default(), |(location, _)| location.cast::<S>());
+    bodies
+        .iter()
+        .map(|body| Body {
+            location: body.location - offset,
+            ..*body
+        })
+        .collect()
+}
+
+fn create_with_parents(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    File::create(path)
+}
+
+// `bodies` are those of `simulation` that passed the filters. `header` is whether this is the start of a file,
+// which only matters for the csv column names
+fn write_snapshot<S: Scalar, F: ForceModel>(
+    out: &mut impl Write,
+    format: SnapshotFormat,
+    simulation: &Simulation<S, F>,
+    bodies: &[&Body<S>],
+    header: bool,
+) -> io::Result<()> {
+    let step = simulation.steps();
+    let time = simulation.time();
+    let energy = simulation.total_energy();
+    let units = simulation.config().units;
+    match format {
+        SnapshotFormat::Csv => {
+            if header {
+                writeln!(out, "# units: {}", units)?;
+                writeln!(
+                    out,
+                    "step,time,total_energy,body,mass,x,y,z,vx,vy,vz,species"
+                )?;
+            }
+            for body in bodies {
+                let (p, v) = (&body.location, &body.velocity);
+                let species = match body.species {
+                    Species::Live => "live",
+                    Species::Tracer => "tracer",
+                };
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{}",
+                    step, time, energy, body.id, body.mass, p.x, p.y, p.z, v.x, v.y, v.z, species
+                )?;
+            }
+        }
+        SnapshotFormat::Json => {
+            write!(
+                out,
+                "{{\"step\":{},\"time\":{},\"total_energy\":{},\"units\":\"{}\",\"bodies\":[",
+                step,
+                json_number(time),
+                json_number(energy),
+                units
+            )?;
+            for (i, body) in bodies.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                let (p, v) = (&body.location, &body.velocity);
+                write!(out, "{{\"id\":{},", body.id)?;
+                if let Some(label) = simulation.label(body.id) {
+                    let label = serde_json::to_string(label).map_err(io::Error::other)?;
+                    write!(out, "\"label\":{},", label)?;
+                }
+                if !body.is_source() {
+                    write!(out, "\"tracer\":true,")?;
+                }
+                write!(
+                    out,
+                    "\"mass\":{},\"location\":[{},{},{}],\"velocity\":[{},{},{}]}}",
+                    json_number(body.mass),
+                    json_number(p.x),
+                    json_number(p.y),
+                    json_number(p.z),
+                    json_number(v.x),
+                    json_number(v.y),
+                    json_number(v.z)
+                )?;
+            }
+            writeln!(out, "]}}")?;
+        }
+        SnapshotFormat::Vtk => {
+            let n = bodies.len();
+            let kind = match S::PRECISION {
+                Precision::Single => "float",
+                Precision::Double => "double",
+            };
+            writeln!(out, "# vtk DataFile Version 3.0")?;
+            writeln!(
+                out,
+                "barneshutt3d step {} total_energy {} units {}",
+                step, energy, units
+            )?;
+            writeln!(out, "ASCII")?;
+            writeln!(out, "DATASET POLYDATA")?;
+            writeln!(out, "FIELD FieldData 1")?;
+            writeln!(out, "TIME 1 1 double")?;
+            writeln!(out, "{}", time)?;
+            writeln!(out, "POINTS {} {}", n, kind)?;
+            for body in bodies {
+                let p = &body.location;
+                writeln!(out, "{} {} {}", p.x, p.y, p.z)?;
+            }
+            // one single-point cell per body, so the points render without a glyph filter
+            writeln!(out, "VERTICES {} {}", n, 2 * n)?;
+            for i in 0..n {
+                writeln!(out, "1 {}", i)?;
+            }
+            writeln!(out, "POINT_DATA {}", n)?;
+            writeln!(out, "SCALARS id unsigned_long 1")?;
+            writeln!(out, "LOOKUP_TABLE default")?;
+            for body in bodies {
+                writeln!(out, "{}", body.id)?;
+            }
+            writeln!(out, "SCALARS mass {} 1", kind)?;
+            writeln!(out, "LOOKUP_TABLE default")?;
+            for body in bodies {
+                writeln!(out, "{}", body.mass)?;
+            }
+            writeln!(out, "VECTORS velocity {}", kind)?;
+            for body in bodies {
+                let v = &body.velocity;
+                writeln!(out, "{} {} {}", v.x, v.y, v.z)?;
+            }
+        }
+        SnapshotFormat::Xyz => {
+            writeln!(out, "{}", bodies.len())?;
+            writeln!(
+                out,
+                "Properties=species:S:1:id:I:1:pos:R:3:mass:R:1:vel:R:3 Time={} step={} total_energy={} units=\"{}\"",
+                time, step, energy, units
+            )?;
+            for body in bodies {
+                let (p, v) = (&body.location, &body.velocity);
+                writeln!(
+                    out,
+                    "{} {} {} {} {} {} {} {} {}",
+                    if body.is_source() { "X" } else { "T" },
+                    body.id,
+                    p.x,
+                    p.y,
+                    p.z,
+                    body.mass,
+                    v.x,
+                    v.y,
+                    v.z
+                )?;
+            }
+        }
+        SnapshotFormat::Binary => unreachable!("binary snapshots are written by their own sink"),
+    }
+    Ok(())
+}
+
+// json has no nan or infinity, so those become null
+fn json_number<S: Scalar>(value: S) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ic;
+    use crate::trajectory::Trajectory;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn two_species_keep_their_labels_and_split_into_files_of_their_own() {
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let mut bodies = ic::uniform_box(30, &space, &mut StdRng::seed_from_u64(2));
+        for body in bodies.iter_mut().skip(20) {
+            body.species = Species::Tracer;
+        }
+        let mut simulation = Simulation::new(bodies, space);
+        simulation.step(0.01);
+        let directory =
+            std::env::temp_dir().join(format!("barneshutt3d-species-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let writer = |path: PathBuf| {
+            SnapshotWriter::new(path, SnapshotFormat::Csv, SnapshotLayout::SingleFile, 1)
+        };
+        let mut both = writer(directory.join("all.csv"));
+        let mut live =
+            writer(directory.join("live.csv")).with_filter(OutputFilter::Species(Species::Live));
+        let mut tracers = writer(directory.join("tracers.csv"))
+            .with_filter(OutputFilter::Species(Species::Tracer));
+        for writer in [&mut both, &mut live, &mut tracers] {
+            writer.write(&simulation).unwrap();
+        }
+        drop((both, live, tracers));
+        let read = |name: &str| crate::load::read_bodies(directory.join(name)).unwrap();
+        let species = |bodies: &[Body]| {
+            bodies
+                .iter()
+                .map(|body| (body.id, body.species))
+                .collect::<Vec<_>>()
+        };
+        let expected: Vec<_> = (0..30)
+            .map(|id| {
+                (
+                    id,
+                    if id < 20 {
+                        Species::Live
+                    } else {
+                        Species::Tracer
+                    },
+                )
+            })
+            .collect();
+        assert_eq!(species(&read("all.csv")), expected);
+        assert_eq!(species(&read("live.csv")), expected[..20]);
+        assert_eq!(species(&read("tracers.csv")), expected[20..]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn center_of_mass_positions_weigh_to_zero_however_the_system_drifts() {
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let mut bodies = ic::uniform_box(50, &space, &mut StdRng::seed_from_u64(1));
+        for (i, body) in bodies.iter_mut().enumerate() {
+            body.mass = 1e-3 * (1 + i % 3) as f64;
+            body.velocity = Point {
+                x: 3.,
+                y: -2.,
+                z: 1.,
+            };
+        }
+        let mut simulation = Simulation::new(bodies, space);
+        let directory =
+            std::env::temp_dir().join(format!("barneshutt3d-frame-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let mut writer = SnapshotWriter::new(
+            &directory,
+            SnapshotFormat::Csv,
+            SnapshotLayout::FilePerSnapshot,
+            1,
+        )
+        .with_frame(OutputFrame::CenterOfMass);
+        for _ in 0..4 {
+            simulation.step(0.05);
+            writer.write(&simulation).unwrap();
+        }
+        let trajectory = Trajectory::load_series(directory.join("snapshot_*.csv")).unwrap();
+        assert_eq!(trajectory.len(), 4);
+        for frame in &trajectory.frames {
+            let weighted = frame.iter().fold(Point::default(), |total, body| {
+                total + body.location * body.mass
+            });
+            assert!(weighted.length() < 1e-15, "{:?}", weighted);
+        }
+        // the simulation itself has drifted away from the origin and stayed there
+        let (location, _) = diagnostics::center_of_mass(simulation.bodies()).unwrap();
+        assert!(location.x > 1., "{:?}", location);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn the_final_state_is_written_once_whatever_the_cadence() {
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let bodies = ic::uniform_box(10, &space, &mut StdRng::seed_from_u64(4));
+        // the step counts written by a run of `steps` steps every `every`
+        let run = |steps: u64, every: u64| {
+            let directory = std::env::temp_dir().join(format!(
+                "barneshutt3d-cadence-{}-{}-{}",
+                std::process::id(),
+                steps,
+                every
+            ));
+            let _ = std::fs::remove_dir_all(&directory);
+            let mut simulation = Simulation::new(bodies.clone(), space);
+            let mut writer = SnapshotWriter::new(
+                &directory,
+                SnapshotFormat::Csv,
+                SnapshotLayout::FilePerSnapshot,
+                every,
+            );
+            writer.record(&simulation).unwrap();
+            for _ in 0..steps {
+                simulation.step(1e-3);
+                writer.record(&simulation).unwrap();
+            }
+            let finished = writer.finish(&simulation).unwrap();
+            let mut written: Vec<String> = std::fs::read_dir(&directory)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            written.sort();
+            std::fs::remove_dir_all(&directory).unwrap();
+            (written, finished)
+        };
+        let (written, finished) = run(100, 10);
+        assert_eq!(written.len(), 11);
+        assert_eq!(written.last().unwrap(), "snapshot_000100.csv");
+        assert!(!finished);
+        let (written, finished) = run(25, 10);
+        assert_eq!(
+            written,
+            [0, 10, 20, 25].map(|step| format!("snapshot_{:06}.csv", step))
+        );
+        assert!(finished);
+    }
+}
@@ -0,0 +1,542 @@
+//! a chunked binary snapshot format for post-processing long runs, read back by memory-mapping the file so any
+//! step can be had without parsing the ones before it. written by a `SnapshotWriter` in
+//! `SnapshotFormat::Binary`, read with `SnapshotFile`
+//!
+//! everything is little-endian and starts on a multiple of 8 bytes. the file opens with a 16-byte header: the
+//! magic `BH3DSNAP`, the layout version as a u32 and the float width in bytes as a u32. each snapshot
+//! follows as a chunk: step u64, time f64, total energy f64 and body count n u64, then the ids as n u64, the
+//! masses as n floats, the positions and the velocities as 3n floats each, x y z per body, and one byte per
+//! body that is 1 for tracers, each array padded to 8 bytes. the index comes after the last chunk, as offset
+//! u64, step u64, time f64 and n u64 for every chunk, and the file ends with the index's offset u64, the
+//! chunk count u64 and the magic `BH3DINDX`. the index is rewritten after every chunk, so the file can be
+//! read while a run is still adding to it
+
+use crate::body::{Body, Species};
+use crate::force::ForceModel;
+use crate::geometry::Point;
+use crate::scalar::plain::Plain;
+use crate::scalar::{Precision, Scalar};
+use crate::sim::Simulation;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"BH3DSNAP";
+const INDEX_MAGIC: &[u8; 8] = b"BH3DINDX";
+/// bumped whenever the layout changes
+pub const VERSION: u32 = 1;
+const HEADER: u64 = 16;
+const CHUNK_HEADER: u64 = 32;
+const INDEX_ENTRY: u64 = 32;
+const FOOTER: u64 = 24;
+
+#[derive(Debug)]
+pub enum SnapshotFileError {
+    Io(io::Error),
+    /// the file is not a binary snapshot file, or is cut short or damaged
+    Invalid(&'static str),
+    /// the file was written by a different layout version than this build reads
+    Version(u32),
+    /// the file holds floats of a different width than the ones asked for
+    Precision {
+        found: Precision,
+        expected: Precision,
+    },
+}
+
+impl std::fmt::Display for SnapshotFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotFileError::Io(err) => write!(f, "{}", err),
+            SnapshotFileError::Invalid(reason) => write!(f, "invalid snapshot file: {}", reason),
+            SnapshotFileError::Version(version) => write!(
+                f,
+                "snapshot file is version {}, this build reads version {}",
+                version, VERSION
+            ),
+            SnapshotFileError::Precision { found, expected } => {
+                write!(
+                    f,
+                    "snapshot file is in {} precision, not {}",
+                    found, expected
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotFileError::Io(err) => Some(err),
+            SnapshotFileError::Invalid(_)
+            | SnapshotFileError::Version(_)
+            | SnapshotFileError::Precision { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotFileError {
+    fn from(err: io::Error) -> Self {
+        SnapshotFileError::Io(err)
+    }
+}
+
+// bytes taken by `count` items of `width` bytes, padded to 8
+fn padded(count: u64, width: u64) -> u64 {
+    (count * width).div_ceil(8) * 8
+}
+
+// bytes of a chunk of `count` bodies with floats of `width` bytes
+fn chunk_len(count: u64, width: u64) -> u64 {
+    CHUNK_HEADER
+        + 8 * count
+        + padded(count, width)
+        + 2 * padded(3 * count, width)
+        + padded(count, 1)
+}
+
+fn width<S: Scalar>() -> u32 {
+    match S::PRECISION {
+        Precision::Single => 4,
+        Precision::Double => 8,
+    }
+}
+
+// one chunk as the index records it
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: u64,
+    step: u64,
+    time: f64,
+    count: u64,
+}
+
+// the open file of a `SnapshotWriter` in the binary format
+pub(crate) struct BinarySink {
+    out: BufWriter<File>,
+    width: u32,
+    index: Vec<Entry>,
+    // where the next chunk goes, over the current index
+    end: u64,
+}
+
+impl BinarySink {
+    pub(crate) fn create<S: Scalar>(file: File) -> io::Result<Self> {
+        let mut out = BufWriter::new(file);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&width::<S>().to_le_bytes())?;
+        Ok(BinarySink {
+            out,
+            width: width::<S>(),
+            index: Vec::new(),
+            end: HEADER,
+        })
+    }
+
+    // adds a chunk of `bodies` and writes the index anew after it
+    pub(crate) fn append<S: Scalar, F: ForceModel>(
+        &mut self,
+        simulation: &Simulation<S, F>,
+        bodies: &[&Body<S>],
+    ) -> io::Result<()> {
+        let count = bodies.len() as u64;
+        let entry = Entry {
+            offset: self.end,
+            step: simulation.steps(),
+            time: simulation.time(),
+            count,
+        };
+        let out = &mut self.out;
+        out.seek(SeekFrom::Start(self.end))?;
+        out.write_all(&entry.step.to_le_bytes())?;
+        out.write_all(&entry.time.to_le_bytes())?;
+        out.write_all(&simulation.total_energy().to_le_bytes())?;
+        out.write_all(&count.to_le_bytes())?;
+        for body in bodies {
+            out.write_all(&body.id.to_le_bytes())?;
+        }
+        let width = self.width as u64;
+        let floats = |out: &mut BufWriter<File>, values: &mut dyn Iterator<Item = S>| {
+            let mut written = 0;
+            for value in values {
+                match S::PRECISION {
+                    Precision::Single => out.write_all(&(value.as_f64() as f32).to_le_bytes())?,
+                    Precision::Double => out.write_all(&value.as_f64().to_le_bytes())?,
+                }
+                written += 1;
+            }
+            pad(out, written * width)
+        };
+        floats(out, &mut bodies.iter().map(|body| body.mass))?;
+        floats(
+            out,
+            &mut bodies.iter().flat_map(|body| body.location.as_array()),
+        )?;
+        floats(
+            out,
+            &mut bodies.iter().flat_map(|body| body.velocity.as_array()),
+        )?;
+        for body in bodies {
+            out.write_all(&[u8::from(!body.is_source())])?;
+        }
+        pad(out, count)?;
+        self.index.push(entry);
+        self.end += chunk_len(count, width);
+        for entry in &self.index {
+            out.write_all(&entry.offset.to_le_bytes())?;
+            out.write_all(&entry.step.to_le_bytes())?;
+            out.write_all(&entry.time.to_le_bytes())?;
+            out.write_all(&entry.count.to_le_bytes())?;
+        }
+        out.write_all(&self.end.to_le_bytes())?;
+        out.write_all(&(self.index.len() as u64).to_le_bytes())?;
+        out.write_all(INDEX_MAGIC)?;
+        out.flush()
+    }
+}
+
+// zeros up to the next multiple of 8 after `written` bytes
+fn pad(out: &mut impl Write, written: u64) -> io::Result<()> {
+    let padding = written.next_multiple_of(8) - written;
+    out.write_all(&[0; 8][..padding as usize])
+}
+
+/// one snapshot of a `SnapshotFile`, borrowing its arrays straight from the mapped file
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotStep<'a, S = f64> {
+    pub step: u64,
+    pub time: f64,
+    pub total_energy: f64,
+    pub ids: &'a [u64],
+    pub masses: &'a [S],
+    pub positions: &'a [[S; 3]],
+    pub velocities: &'a [[S; 3]],
+    /// 1 for tracers, 0 for live bodies
+    pub tracers: &'a [u8],
+}
+
+impl<S: Scalar> SnapshotStep<'_, S> {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// the bodies as `Body`s, copied out of the file
+    pub fn bodies(&self) -> Vec<Body<S>> {
+        (0..self.len())
+            .map(|k| {
+                let point = |[x, y, z]: [S; 3]| Point { x, y, z };
+                Body {
+                    id: self.ids[k],
+                    mass: self.masses[k],
+                    location: point(self.positions[k]),
+                    velocity: point(self.velocities[k]),
+                    species: if self.tracers[k] == 0 {
+                        Species::Live
+                    } else {
+                        Species::Tracer
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// a binary snapshot file opened for random access. `S` is the precision it was written in, so open it as
+/// `SnapshotFile::<f32>::open` for a single-precision run. the file is mapped into memory on unix and read
+/// whole elsewhere; it must not be truncated while open, but a run can go on appending to it
+pub struct SnapshotFile<S = f64> {
+    data: Data,
+    index: Vec<Entry>,
+    precision: PhantomData<S>,
+}
+
+impl<S: Scalar> SnapshotFile<S> {
+    /// maps the file and checks its header and index, and that every chunk they name is in the file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SnapshotFileError> {
+        if cfg!(target_endian = "big") {
+            return Err(SnapshotFileError::Invalid(
+                "the format is little-endian and this machine is not",
+            ));
+        }
+        let data = Data::open(path.as_ref())?;
+        let bytes = data.bytes();
+        let len = bytes.len() as u64;
+        if len < HEADER + FOOTER || &bytes[..8] != MAGIC {
+            return Err(SnapshotFileError::Invalid("not a binary snapshot file"));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(SnapshotFileError::Version(version));
+        }
+        let found = match u32::from_le_bytes(bytes[12..16].try_into().unwrap()) {
+            4 => Precision::Single,
+            8 => Precision::Double,
+            _ => return Err(SnapshotFileError::Invalid("unknown float width")),
+        };
+        if found != S::PRECISION {
+            return Err(SnapshotFileError::Precision {
+                found,
+                expected: S::PRECISION,
+            });
+        }
+        let word =
+            |at: u64| u64::from_le_bytes(bytes[at as usize..at as usize + 8].try_into().unwrap());
+        let footer = len - FOOTER;
+        if &bytes[footer as usize + 16..] != INDEX_MAGIC {
+            return Err(SnapshotFileError::Invalid(
+                "no index at the end of the file",
+            ));
+        }
+        let (start, chunks) = (word(footer), word(footer + 8));
+        if chunks
+            .checked_mul(INDEX_ENTRY)
+            .and_then(|size| size.checked_add(start))
+            != Some(footer)
+        {
+            return Err(SnapshotFileError::Invalid("index does not fit the file"));
+        }
+        let width = width::<S>() as u64;
+        let mut index = Vec::with_capacity(chunks as usize);
+        for k in 0..chunks {
+            let at = start + k * INDEX_ENTRY;
+            let entry = Entry {
+                offset: word(at),
+                step: word(at + 8),
+                time: f64::from_bits(word(at + 16)),
+                count: word(at + 24),
+            };
+            // a chunk holds at least 8 bytes a body, which bounds the count before working out the length
+            if !entry.offset.is_multiple_of(8)
+                || entry.offset < HEADER
+                || entry.offset >= start
+                || entry.count > (start - entry.offset) / 8
+                || entry.offset + chunk_len(entry.count, width) > start
+                || word(entry.offset) != entry.step
+                || word(entry.offset + 24) != entry.count
+            {
+                return Err(SnapshotFileError::Invalid(
+                    "index names a chunk that is not there",
+                ));
+            }
+            index.push(entry);
+        }
+        Ok(SnapshotFile {
+            data,
+            index,
+            precision: PhantomData,
+        })
+    }
+
+    /// the number of snapshots in the file
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// the simulation step of every snapshot, in the order they were written
+    pub fn steps(&self) -> impl Iterator<Item = u64> + '_ {
+        self.index.iter().map(|entry| entry.step)
+    }
+
+    /// which snapshot was taken at simulation step `step`, if any
+    pub fn position(&self, step: u64) -> Option<usize> {
+        self.index
+            .binary_search_by_key(&step, |entry| entry.step)
+            .ok()
+    }
+
+    /// the `i`-th snapshot in the file, or none past the end
+    pub fn step(&self, i: usize) -> Option<SnapshotStep<'_, S>> {
+        let entry = self.index.get(i)?;
+        let bytes = self.data.bytes();
+        let (n, width) = (entry.count as usize, width::<S>() as usize);
+        let mut at = entry.offset as usize;
+        let total_energy = f64::from_le_bytes(bytes[at + 16..at + 24].try_into().unwrap());
+        at += CHUNK_HEADER as usize;
+        let ids = cast::<u64>(&bytes[at..at + 8 * n]);
+        at += 8 * n;
+        let masses = cast::<S>(&bytes[at..at + width * n]);
+        at += padded(n as u64, width as u64) as usize;
+        let positions = cast::<[S; 3]>(&bytes[at..at + 3 * width * n]);
+        at += padded(3 * n as u64, width as u64) as usize;
+        let velocities = cast::<[S; 3]>(&bytes[at..at + 3 * width * n]);
+        at += padded(3 * n as u64, width as u64) as usize;
+        Some(SnapshotStep {
+            step: entry.step,
+            time: entry.time,
+            total_energy,
+            ids,
+            masses,
+            positions,
+            velocities,
+            tracers: &bytes[at..at + n],
+        })
+    }
+}
+
+// `bytes` as the plain numbers they hold, or arrays of them, on slices that start on a multiple of 8 into
+// 8-aligned memory
+fn cast<T: Plain>(bytes: &[u8]) -> &[T] {
+    assert!(
+        (bytes.as_ptr() as usize).is_multiple_of(align_of::<T>())
+            && bytes.len().is_multiple_of(size_of::<T>())
+    );
+    // SAFETY: alignment and length are checked above, and every bit pattern is a valid T
+    unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast::<T>(), bytes.len() / size_of::<T>()) }
+}
+
+// the bytes of an open file: mapped on unix, read into 8-aligned memory elsewhere
+enum Data {
+    #[cfg(unix)]
+    Mapped {
+        pointer: *mut libc::c_void,
+        len: usize,
+    },
+    Read {
+        words: Vec<u64>,
+        len: usize,
+    },
+}
+
+// the mapping is read-only and owned by the `Data`
+unsafe impl Send for Data {}
+unsafe impl Sync for Data {}
+
+impl Data {
+    #[cfg(unix)]
+    fn open(path: &Path) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Data::Read {
+                words: Vec::new(),
+                len,
+            });
+        }
+        // SAFETY: a fresh private read-only mapping of the whole file, unmapped in drop
+        let pointer = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if pointer == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Data::Mapped { pointer, len })
+    }
+
+    #[cfg(not(unix))]
+    fn open(path: &Path) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let mut words = vec![0u64; bytes.len().div_ceil(8)];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+            let mut buffer = [0; 8];
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_le_bytes(buffer);
+        }
+        Ok(Data::Read {
+            words,
+            len: bytes.len(),
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            // SAFETY: the mapping covers `len` bytes and lives as long as `self`
+            #[cfg(unix)]
+            Data::Mapped { pointer, len } => unsafe {
+                std::slice::from_raw_parts(pointer.cast::<u8>(), *len)
+            },
+            Data::Read { words, len } => &cast_bytes(words)[..*len],
+        }
+    }
+}
+
+// the bytes of `words` in memory order, little-endian on the machines that open files
+fn cast_bytes(words: &[u64]) -> &[u8] {
+    // SAFETY: any u64 is 8 valid bytes
+    unsafe { std::slice::from_raw_parts(words.as_ptr().cast::<u8>(), words.len() * 8) }
+}
+
+impl Drop for Data {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Data::Mapped { pointer, len } = *self {
+            // SAFETY: mapped in open with this length and not used after
+            unsafe {
+                libc::munmap(pointer, len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cuboid;
+    use crate::snapshot::{SnapshotFormat, SnapshotLayout, SnapshotWriter};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn written<S: Scalar>(name: &str, steps: u64) -> (std::path::PathBuf, Vec<Vec<Body<S>>>) {
+        let path =
+            std::env::temp_dir().join(format!("barneshutt3d-{}-{}.bin", name, std::process::id()));
+        let space = Cuboid::from(([0.; 3], [1.; 3]));
+        let mut simulation =
+            Simulation::<S>::new_random(20, space.cast(), &mut StdRng::seed_from_u64(1));
+        let mut writer =
+            SnapshotWriter::new(&path, SnapshotFormat::Binary, SnapshotLayout::SingleFile, 1);
+        let mut states = vec![];
+        for _ in 0..=steps {
+            writer.record(&simulation).unwrap();
+            states.push(simulation.bodies().to_vec());
+            simulation.step(0.001);
+        }
+        (path, states)
+    }
+
+    #[test]
+    fn steps_read_back_as_written() {
+        let (path, states) = written::<f64>("double", 3);
+        let file = SnapshotFile::<f64>::open(&path).unwrap();
+        assert_eq!(file.len(), 4);
+        assert_eq!(file.steps().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        for (i, bodies) in states.iter().enumerate() {
+            assert_eq!(&file.step(i).unwrap().bodies(), bodies);
+        }
+        assert!(file.step(4).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn single_precision_reads_back_as_written() {
+        let (path, states) = written::<f32>("single", 2);
+        let file = SnapshotFile::<f32>::open(&path).unwrap();
+        assert_eq!(file.step(2).unwrap().bodies(), states[2]);
+        assert!(SnapshotFile::<f64>::open(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn cast_refuses_misaligned_bytes() {
+        let words = [0u64; 4];
+        cast::<u64>(&cast_bytes(&words)[4..20]);
+    }
+}
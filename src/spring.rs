@@ -0,0 +1,178 @@
+//! harmonic springs between pairs of bodies, for tethered bodies or molecular-dynamics-style experiments.
+//! springs are added with `Simulation::add_spring` and pull on top of gravity, each end feeling
+//! F = -k (r - r0) û along the line between them, so they conserve momentum and, with their energy counted in
+//! the potential, energy too. bodies without mass are not moved by them
+//!
+//! springs are not saved in a checkpoint, so add them again after resuming
+
+use crate::body::Body;
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use std::collections::HashMap;
+
+/// a spring between the bodies with ids `a` and `b`, in f64 whatever the simulation's precision
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spring {
+    pub a: u64,
+    pub b: u64,
+    /// the force per unit of stretch, k
+    pub stiffness: f64,
+    /// the separation at which it pulls neither way, r0
+    pub rest_length: f64,
+}
+
+impl Spring {
+    pub fn new(a: u64, b: u64, stiffness: f64, rest_length: f64) -> Self {
+        Spring {
+            a,
+            b,
+            stiffness,
+            rest_length,
+        }
+    }
+
+    /// the force on `a` from a spring reaching to `b`; `b` feels the opposite. none when the two sit on top of
+    /// each other and the spring has no direction
+    pub fn force(&self, a: &Point, b: &Point) -> Point {
+        let offset = *b - *a;
+        let length = offset.length();
+        if length == 0. {
+            return Point::default();
+        }
+        offset * (self.stiffness * (length - self.rest_length) / length)
+    }
+
+    /// the energy stored in the spring with its ends at `a` and `b`
+    pub fn potential(&self, a: &Point, b: &Point) -> f64 {
+        let stretch = a.distance_squared(b).sqrt() - self.rest_length;
+        0.5 * self.stiffness * stretch * stretch
+    }
+}
+
+// the ends of every spring whose bodies are both still there, as indices into `bodies`
+fn ends<'a, S: Scalar>(
+    springs: &'a [Spring],
+    bodies: &[Body<S>],
+) -> impl Iterator<Item = (&'a Spring, usize, usize)> {
+    let index: HashMap<u64, usize> = bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| (body.id, i))
+        .collect();
+    springs
+        .iter()
+        .filter_map(move |spring| Some((spring, *index.get(&spring.a)?, *index.get(&spring.b)?)))
+}
+
+// the acceleration from `springs` on each of `bodies`, in their order
+pub(crate) fn accelerations<S: Scalar>(springs: &[Spring], bodies: &[Body<S>]) -> Vec<Point<S>> {
+    let mut accelerations = vec![Point::default(); bodies.len()];
+    for (spring, a, b) in ends(springs, bodies) {
+        let force = spring.force(&bodies[a].location.cast(), &bodies[b].location.cast());
+        for (i, force) in [(a, force), (b, -force)] {
+            let mass = bodies[i].mass.as_f64();
+            if mass != 0. {
+                accelerations[i] += (force / mass).cast();
+            }
+        }
+    }
+    accelerations
+}
+
+// the energy stored in `springs` between bodies for which `counted` holds
+pub(crate) fn potential_energy<S: Scalar>(
+    springs: &[Spring],
+    bodies: &[Body<S>],
+    counted: impl Fn(&Body<S>) -> bool,
+) -> f64 {
+    ends(springs, bodies)
+        .filter(|&(_, a, b)| counted(&bodies[a]) && counted(&bodies[b]))
+        .map(|(spring, a, b)| {
+            spring.potential(&bodies[a].location.cast(), &bodies[b].location.cast())
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cuboid;
+    use crate::sim::Simulation;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn a_stretched_pair_rings_at_the_reduced_mass_frequency() {
+        // light enough that their gravity is a few millionths of the spring's pull
+        let (m1, m2, k): (f64, f64, f64) = (1e-6, 2e-6, 6e-6);
+        let omega = (k / (m1 * m2 / (m1 + m2))).sqrt();
+        let bodies = vec![
+            Body {
+                mass: m1,
+                location: Point {
+                    x: -0.55,
+                    y: 0.,
+                    z: 0.,
+                },
+                ..Body::default()
+            },
+            Body {
+                mass: m2,
+                location: Point {
+                    x: 0.55,
+                    y: 0.,
+                    z: 0.,
+                },
+                ..Body::default()
+            },
+        ];
+        let mut simulation = Simulation::new(bodies, Cuboid::from(([-2.; 3], [2.; 3])));
+        simulation.add_spring(Spring::new(0, 1, k, 1.));
+        let stretch = |simulation: &Simulation| {
+            let bodies = simulation.bodies();
+            bodies[0]
+                .location
+                .distance_squared(&bodies[1].location)
+                .sqrt()
+                - 1.
+        };
+        let energy = simulation.total_energy();
+        let dt = 1e-3;
+        let mut crossings = vec![];
+        let mut last = stretch(&simulation);
+        while crossings.len() < 6 {
+            simulation.step(dt);
+            let now = stretch(&simulation);
+            if last < 0. && now >= 0. {
+                // between the two steps, where the line through them crosses zero
+                crossings.push(simulation.time() - dt * now / (now - last));
+            }
+            last = now;
+        }
+        let period = (crossings[5] - crossings[0]) / 5.;
+        let expected = 2. * PI / omega;
+        assert!(
+            (period - expected).abs() < 1e-4 * expected,
+            "{} vs {}",
+            period,
+            expected
+        );
+        let drift = (simulation.total_energy() - energy).abs() / energy;
+        assert!(drift < 1e-4, "{}", drift);
+    }
+
+    #[test]
+    fn a_spring_missing_an_end_pulls_on_neither() {
+        let bodies = vec![Body {
+            mass: 1.,
+            location: Point {
+                x: 0.5,
+                y: 0.5,
+                z: 0.5,
+            },
+            ..Body::default()
+        }];
+        let spring = Spring::new(0, 7, 1., 0.);
+        assert_eq!(accelerations(&[spring], &bodies), vec![Point::default()]);
+        assert_eq!(potential_energy(&[spring], &bodies, |_| true), 0.);
+    }
+}
@@ -0,0 +1,69 @@
+//! the state of a simulation published at the end of every step, for other threads to read while it steps,
+//! such as the render thread of an app the simulation is embedded in. each step swaps in a fresh snapshot
+//! behind an `Arc`, so a reader holds on to a whole, consistent state for as long as it likes, and the lock
+//! around the swap is only ever held for the length of an `Arc` clone
+
+use crate::body::Body;
+use crate::geometry::{Cuboid, Point};
+use crate::scalar::Scalar;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// the bodies and clock as they were at the end of a step
+#[derive(Debug, Clone)]
+pub struct StateSnapshot<S = f64> {
+    /// steps taken at the time, 0 for the state a simulation was made with
+    pub step: u64,
+    pub time: f64,
+    /// the root box
+    pub bounds: Cuboid<S>,
+    pub bodies: Vec<Body<S>>,
+}
+
+impl<S: Scalar> StateSnapshot<S> {
+    pub fn positions(&self) -> impl Iterator<Item = &Point<S>> + '_ {
+        self.bodies.iter().map(|body| &body.location)
+    }
+
+    pub fn velocities(&self) -> impl Iterator<Item = &Point<S>> + '_ {
+        self.bodies.iter().map(|body| &body.velocity)
+    }
+}
+
+/// where a simulation publishes its state, from `Simulation::state_handle`. clones share the one slot, and
+/// can be sent to other threads
+#[derive(Debug)]
+pub struct StateHandle<S = f64> {
+    slot: Arc<Mutex<Arc<StateSnapshot<S>>>>,
+}
+
+impl<S> Clone for StateHandle<S> {
+    fn clone(&self) -> Self {
+        StateHandle {
+            slot: Arc::clone(&self.slot),
+        }
+    }
+}
+
+impl<S: Scalar> StateHandle<S> {
+    pub(crate) fn new(state: StateSnapshot<S>) -> Self {
+        StateHandle {
+            slot: Arc::new(Mutex::new(Arc::new(state))),
+        }
+    }
+
+    /// the state last published
+    pub fn latest(&self) -> Arc<StateSnapshot<S>> {
+        // a panic cannot leave the slot half written, so a poisoned lock still holds a whole state
+        Arc::clone(&self.slot.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub(crate) fn publish(&self, state: StateSnapshot<S>) {
+        let state = Arc::new(state);
+        let old = std::mem::replace(
+            &mut *self.slot.lock().unwrap_or_else(PoisonError::into_inner),
+            state,
+        );
+        // the old state is dropped here, outside the lock, if no reader still holds it
+        drop(old);
+    }
+}
@@ -0,0 +1,124 @@
+//! a simulation as an iterator of its states, one per fixed-length step, so runs compose with iterator
+//! adapters and channels instead of needing a loop around `step`. each snapshot shares one copy of the bodies
+//! behind an `Arc`, so cloning it is cheap and it can be sent to another thread while the run goes on
+
+use crate::body::Body;
+use crate::diagnostics::{Diagnostics, PotentialMethod};
+use crate::force::ForceModel;
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use crate::sim::{Simulation, StepReport};
+use std::sync::Arc;
+
+/// the state after one step
+#[derive(Debug, Clone)]
+pub struct StepSnapshot<S = f64> {
+    /// steps taken so far, this one included
+    pub step: u64,
+    pub time: f64,
+    pub report: StepReport,
+    /// energies and momenta with the tree potential
+    pub diagnostics: Diagnostics,
+    pub bodies: Arc<[Body<S>]>,
+}
+
+impl<S: Scalar> StepSnapshot<S> {
+    fn of<F: ForceModel>(simulation: &Simulation<S, F>, report: StepReport) -> Self {
+        StepSnapshot {
+            step: simulation.steps(),
+            time: simulation.time(),
+            report,
+            diagnostics: simulation.diagnostics(PotentialMethod::Tree),
+            bodies: simulation.bodies().into(),
+        }
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &Point<S>> + '_ {
+        self.bodies.iter().map(|body| &body.location)
+    }
+
+    pub fn velocities(&self) -> impl Iterator<Item = &Point<S>> + '_ {
+        self.bodies.iter().map(|body| &body.velocity)
+    }
+
+    pub fn total_energy(&self) -> f64 {
+        self.diagnostics.total_energy()
+    }
+}
+
+/// steps a borrowed simulation by `dt` per item, from `Simulation::iter_steps`. it never ends on its own
+/// unless an observer asks for a stop; use `take` or `take_while` to bound it
+pub struct Steps<'a, S: Scalar, F: ForceModel> {
+    simulation: &'a mut Simulation<S, F>,
+    dt: f64,
+    stopped: bool,
+}
+
+/// `Steps` owning its simulation, from `Simulation::into_steps`, so the whole run can be moved into a thread
+/// that feeds a channel. `into_inner` gives the simulation back
+pub struct IntoSteps<S: Scalar, F: ForceModel> {
+    simulation: Simulation<S, F>,
+    dt: f64,
+    stopped: bool,
+}
+
+// one step, or none once an observer has asked for a stop
+fn advance<S: Scalar, F: ForceModel>(
+    simulation: &mut Simulation<S, F>,
+    dt: f64,
+    stopped: &mut bool,
+) -> Option<StepSnapshot<S>> {
+    if *stopped {
+        return None;
+    }
+    let report = simulation.step(dt);
+    *stopped = report.stop_requested;
+    Some(StepSnapshot::of(simulation, report))
+}
+
+impl<S: Scalar, F: ForceModel> Iterator for Steps<'_, S, F> {
+    type Item = StepSnapshot<S>;
+
+    fn next(&mut self) -> Option<StepSnapshot<S>> {
+        advance(self.simulation, self.dt, &mut self.stopped)
+    }
+}
+
+impl<S: Scalar, F: ForceModel> Iterator for IntoSteps<S, F> {
+    type Item = StepSnapshot<S>;
+
+    fn next(&mut self) -> Option<StepSnapshot<S>> {
+        advance(&mut self.simulation, self.dt, &mut self.stopped)
+    }
+}
+
+impl<S: Scalar, F: ForceModel> IntoSteps<S, F> {
+    pub fn simulation(&self) -> &Simulation<S, F> {
+        &self.simulation
+    }
+
+    pub fn into_inner(self) -> Simulation<S, F> {
+        self.simulation
+    }
+}
+
+impl<S: Scalar, F: ForceModel> Simulation<S, F> {
+    /// the states after each of the coming steps of `dt`. the step that an observer stops on is the last
+    /// item. each item costs a copy of the bodies and a tree walk for the potential energy on top of the step
+    pub fn iter_steps(&mut self, dt: f64) -> Steps<'_, S, F> {
+        Steps {
+            simulation: self,
+            dt,
+            stopped: false,
+        }
+    }
+
+    /// `iter_steps`, taking the simulation along
+    pub fn into_steps(self, dt: f64) -> IntoSteps<S, F> {
+        IntoSteps {
+            simulation: self,
+            dt,
+            stopped: false,
+        }
+    }
+}
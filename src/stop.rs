@@ -0,0 +1,152 @@
+//! conditions a run halts on, checked by the simulation at the end of every step. a condition that holds sets
+//! `StepReport::stop` to what happened, and `stop_requested` with it, for the caller's loop to act on
+
+use crate::collision::close_pairs;
+use crate::diagnostics::{center_of_mass, PotentialMethod};
+use crate::force::ForceModel;
+use crate::scalar::Scalar;
+use crate::sim::{sources, Simulation};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// when to stop a run, set with `Simulation::set_stop_condition`. `and` and `or` build up compound ones
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StopCondition {
+    /// a body is farther than `radius` from the center of mass of the sources
+    Escape { radius: f64 },
+    /// two bodies are closer than `distance`
+    Approach { distance: f64 },
+    /// the total energy, less the work done by drag, is off from what it was when the condition was set by
+    /// more than `tolerance` of it. costs a tree walk for the potential every step
+    EnergyDrift { tolerance: f64 },
+    /// every one of these holds after the same step
+    All(Vec<StopCondition>),
+    /// any one of these holds
+    Any(Vec<StopCondition>),
+}
+
+impl StopCondition {
+    /// holds when both `self` and `other` do
+    pub fn and(self, other: StopCondition) -> Self {
+        match self {
+            StopCondition::All(mut conditions) => {
+                conditions.push(other);
+                StopCondition::All(conditions)
+            }
+            condition => StopCondition::All(vec![condition, other]),
+        }
+    }
+
+    /// holds when either `self` or `other` does
+    pub fn or(self, other: StopCondition) -> Self {
+        match self {
+            StopCondition::Any(mut conditions) => {
+                conditions.push(other);
+                StopCondition::Any(conditions)
+            }
+            condition => StopCondition::Any(vec![condition, other]),
+        }
+    }
+
+    // what makes the condition hold now, if it does. `initial_energy` is the reference for the drift
+    pub(crate) fn check<S: Scalar, F: ForceModel>(
+        &self,
+        simulation: &Simulation<S, F>,
+        initial_energy: f64,
+    ) -> Option<StopEvent> {
+        match self {
+            StopCondition::Escape { radius } => {
+                let (center, _) = center_of_mass(&sources(simulation.bodies()))?;
+                simulation
+                    .bodies()
+                    .iter()
+                    .map(|body| (body.id, body.location.cast().distance_squared(&center)))
+                    .filter(|&(_, distance_squared)| distance_squared > radius * radius)
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(id, distance_squared)| StopEvent::Escape {
+                        id,
+                        distance: distance_squared.sqrt(),
+                    })
+            }
+            StopCondition::Approach { distance } => {
+                let bodies = simulation.bodies();
+                close_pairs(bodies, S::from_f64(*distance))
+                    .into_iter()
+                    .map(|(i, j)| {
+                        let distance_squared =
+                            bodies[i].location.distance_squared(&bodies[j].location);
+                        ((i, j), distance_squared.as_f64())
+                    })
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|((i, j), distance_squared)| StopEvent::Approach {
+                        ids: (bodies[i].id, bodies[j].id),
+                        distance: distance_squared.sqrt(),
+                    })
+            }
+            StopCondition::EnergyDrift { tolerance } => {
+                let energy = simulation.diagnostics(PotentialMethod::Tree).conserved_energy();
+                let drift = (energy - initial_energy) / initial_energy.abs();
+                (drift.abs() > *tolerance).then_some(StopEvent::EnergyDrift { drift })
+            }
+            StopCondition::All(conditions) => conditions
+                .iter()
+                .map(|condition| condition.check(simulation, initial_energy))
+                .collect::<Option<Vec<_>>>()
+                .map(StopEvent::All),
+            StopCondition::Any(conditions) => conditions
+                .iter()
+                .find_map(|condition| condition.check(simulation, initial_energy)),
+        }
+    }
+
+    // whether the condition needs the energy at the start
+    pub(crate) fn watches_energy(&self) -> bool {
+        match self {
+            StopCondition::EnergyDrift { .. } => true,
+            StopCondition::All(conditions) | StopCondition::Any(conditions) => {
+                conditions.iter().any(StopCondition::watches_energy)
+            }
+            StopCondition::Escape { .. } | StopCondition::Approach { .. } => false,
+        }
+    }
+}
+
+/// what made a `StopCondition` hold
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopEvent {
+    /// the body farthest out past the radius, and how far out it is
+    Escape { id: u64, distance: f64 },
+    /// the closest of the pairs within the distance
+    Approach { ids: (u64, u64), distance: f64 },
+    /// the relative change in total energy, signed
+    EnergyDrift { drift: f64 },
+    /// the events of every part of an `All`, in order. an `Any` reports the first part that held
+    All(Vec<StopEvent>),
+}
+
+impl fmt::Display for StopEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopEvent::Escape { id, distance } => {
+                write!(
+                    f,
+                    "body {} escaped to {} from the center of mass",
+                    id, distance
+                )
+            }
+            StopEvent::Approach { ids, distance } => {
+                write!(f, "bodies {} and {} came within {}", ids.0, ids.1, distance)
+            }
+            StopEvent::EnergyDrift { drift } => write!(f, "energy drifted by {:e}", drift),
+            StopEvent::All(events) => {
+                for (i, event) in events.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " and ")?;
+                    }
+                    write!(f, "{}", event)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! the tipsy binary snapshot format of gasoline, changa and pkdgrav, behind the `interop` feature: a header
+//! with the time and the particle counts, then gas, dark matter and star records of f32 fields, each opening
+//! with mass, position and velocity. files are read in either byte order, with or without the padding after
+//! the header, and written big-endian with it, like the `std` files those codes write
+
+use crate::body::{Body, Species};
+use crate::geometry::Point;
+use crate::load::LoadError;
+use crate::scalar::Scalar;
+use std::io::{self, Write};
+use std::path::Path;
+
+// f32 fields per gas, dark matter and star record
+const GAS: usize = 12;
+const DARK: usize = 9;
+const STAR: usize = 11;
+
+/// the time and the bodies of a tipsy file: the gas, then the dark matter, then the stars, numbered from 0
+/// in that order. the fields past the velocity, like the gas temperature or the softening, are not kept
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tipsy {
+    pub time: f64,
+    pub bodies: Vec<Body>,
+}
+
+impl Tipsy {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, LoadError> {
+        let fail = |offset: usize, message: &str| LoadError::Binary {
+            offset,
+            message: message.to_string(),
+        };
+        if bytes.len() < 28 {
+            return Err(fail(bytes.len(), "too short for a tipsy header"));
+        }
+        // the dimension count says which byte order the file is in
+        let big = i32::from_be_bytes(bytes[12..16].try_into().unwrap()) == 3;
+        if !big && i32::from_le_bytes(bytes[12..16].try_into().unwrap()) != 3 {
+            return Err(fail(12, "not a tipsy file: the dimension count is not 3"));
+        }
+        let int = |at: usize| {
+            let field = bytes[at..at + 4].try_into().unwrap();
+            let value = if big {
+                i32::from_be_bytes(field)
+            } else {
+                i32::from_le_bytes(field)
+            };
+            usize::try_from(value).map_err(|_| fail(at, "a negative particle count"))
+        };
+        let time = bytes[0..8].try_into().unwrap();
+        let time = if big {
+            f64::from_be_bytes(time)
+        } else {
+            f64::from_le_bytes(time)
+        };
+        let (total, gas, dark, stars) = (int(8)?, int(16)?, int(20)?, int(24)?);
+        if gas + dark + stars != total {
+            return Err(fail(8, "the particle counts do not add up to the total"));
+        }
+        let records = 4 * (gas * GAS + dark * DARK + stars * STAR);
+        // most writers pad the header to 32 bytes, some older ones do not
+        let start = if bytes.len() == 32 + records {
+            32
+        } else if bytes.len() == 28 + records {
+            28
+        } else {
+            return Err(fail(
+                bytes.len(),
+                "the file is not as long as its particle counts make it",
+            ));
+        };
+        let float = |at: usize| {
+            let field = bytes[at..at + 4].try_into().unwrap();
+            let value = if big {
+                f32::from_be_bytes(field)
+            } else {
+                f32::from_le_bytes(field)
+            };
+            value as f64
+        };
+        let mut bodies = Vec::with_capacity(total);
+        let mut at = start;
+        for (count, width) in [(gas, GAS), (dark, DARK), (stars, STAR)] {
+            for _ in 0..count {
+                let field = |i: usize| float(at + 4 * i);
+                let point = |first: usize| Point {
+                    x: field(first),
+                    y: field(first + 1),
+                    z: field(first + 2),
+                };
+                bodies.push(Body {
+                    id: bodies.len() as u64,
+                    mass: field(0),
+                    location: point(1),
+                    velocity: point(4),
+                    species: Species::Live,
+                });
+                at += 4 * width;
+            }
+        }
+        Ok(Tipsy { time, bodies })
+    }
+
+    /// writes `bodies` as dark matter, with a softening of `softening` and no potential, tracers along with
+    /// the live bodies, as tipsy has no test particles. positions, velocities and masses are rounded to f32
+    pub fn write<S: Scalar>(
+        path: impl AsRef<Path>,
+        time: f64,
+        bodies: &[Body<S>],
+        softening: f64,
+    ) -> io::Result<()> {
+        let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+        out.write_all(&Self::to_bytes(time, bodies, softening)?)?;
+        out.flush()
+    }
+
+    /// the bytes `write` writes. fails for more bodies than the header's 32-bit count holds
+    pub fn to_bytes<S: Scalar>(
+        time: f64,
+        bodies: &[Body<S>],
+        softening: f64,
+    ) -> io::Result<Vec<u8>> {
+        let count = i32::try_from(bodies.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tipsy files hold at most 2^31 - 1 particles",
+            )
+        })?;
+        let mut bytes = Vec::with_capacity(32 + 4 * DARK * bodies.len());
+        bytes.extend(time.to_be_bytes());
+        for int in [count, 3, 0, count, 0, 0] {
+            bytes.extend(int.to_be_bytes());
+        }
+        for body in bodies {
+            let Point { x, y, z } = body.location.cast::<f64>();
+            let velocity = body.velocity.cast::<f64>();
+            let fields = [
+                body.mass.as_f64(),
+                x,
+                y,
+                z,
+                velocity.x,
+                velocity.y,
+                velocity.z,
+                softening,
+                0.,
+            ];
+            for field in fields {
+                bytes.extend((field as f32).to_be_bytes());
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cuboid;
+    use crate::ic;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn rounded(value: f64) -> f64 {
+        value as f32 as f64
+    }
+
+    #[test]
+    fn bodies_round_trip_to_f32() {
+        let space = Cuboid::from(([-1.; 3], [1.; 3]));
+        let mut bodies = ic::uniform_box(50, &space, &mut StdRng::seed_from_u64(1));
+        for (i, body) in bodies.iter_mut().enumerate() {
+            body.id = i as u64;
+            body.velocity = Point {
+                x: 0.1 * i as f64,
+                y: -0.3,
+                z: 1e-3,
+            };
+        }
+        let path = std::env::temp_dir().join(format!("barneshutt3d-{}.std", std::process::id()));
+        Tipsy::write(&path, 2.5, &bodies, 0.01).unwrap();
+        let read = Tipsy::read(&path).unwrap();
+        assert_eq!(crate::load::read_bodies(&path).unwrap(), read.bodies);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.time, 2.5);
+        assert_eq!(read.bodies.len(), bodies.len());
+        for (read, body) in read.bodies.iter().zip(&bodies) {
+            assert_eq!(read.id, body.id);
+            assert_eq!(read.mass, rounded(body.mass));
+            let coordinates = |p: Point| [p.x, p.y, p.z];
+            let location = coordinates(body.location).map(rounded);
+            let velocity = coordinates(body.velocity).map(rounded);
+            assert_eq!(coordinates(read.location), location);
+            assert_eq!(coordinates(read.velocity), velocity);
+        }
+    }
+
+    // one each of gas, dark matter and stars, with `mass`, x and vz set to the particle's number and every
+    // other field to -1, in either byte order and with or without the header's padding
+    fn mixed(big: bool, padded: bool) -> Vec<u8> {
+        let int = |value: i32| {
+            if big {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let float = |value: f32| {
+            if big {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut bytes = Vec::new();
+        bytes.extend(if big {
+            0.5f64.to_be_bytes()
+        } else {
+            0.5f64.to_le_bytes()
+        });
+        for count in [3, 3, 1, 1, 1] {
+            bytes.extend(int(count));
+        }
+        if padded {
+            bytes.extend(int(0));
+        }
+        for (n, width) in [(1., GAS), (2., DARK), (3., STAR)] {
+            for i in 0..width {
+                bytes.extend(float(match i {
+                    0 | 1 | 6 => n,
+                    _ => -1.,
+                }));
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn gas_dark_matter_and_stars_read_in_either_byte_order() {
+        for big in [true, false] {
+            for padded in [true, false] {
+                let tipsy = Tipsy::parse(&mixed(big, padded)).unwrap();
+                assert_eq!(tipsy.time, 0.5);
+                let read: Vec<_> = tipsy
+                    .bodies
+                    .iter()
+                    .map(|body| (body.id, body.mass, body.location.x, body.velocity.z))
+                    .collect();
+                assert_eq!(
+                    read,
+                    vec![(0, 1., 1., 1.), (1, 2., 2., 2.), (2, 3., 3., 3.)]
+                );
+            }
+        }
+        let mut short = mixed(true, true);
+        short.pop();
+        assert!(matches!(
+            Tipsy::parse(&short),
+            Err(LoadError::Binary { .. })
+        ));
+    }
+}
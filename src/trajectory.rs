@@ -0,0 +1,449 @@
+//! reading a run's snapshots back, for post-processing without running it again. `Trajectory::load_series`
+//! takes the files a `SnapshotWriter` wrote in the file-per-snapshot layout, in csv, json, vtk or xyz, and
+//! holds one frame of bodies per file, with their ids. frames written every few steps can be filled in
+//! between with `interpolate` for smooth playback, matching bodies across frames by id
+
+use crate::body::{Body, Species};
+use crate::geometry::Point;
+use crate::load::{self, LoadError};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// how `interpolate` fills in between two frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// straight lines between the two positions and the two velocities
+    #[default]
+    Linear,
+    /// the cubic that leaves each frame at its velocity, which follows a curved orbit far more closely
+    Hermite,
+}
+
+/// the bodies of every snapshot of a series, in the order of their file names, which for the writer's
+/// zero-padded `snapshot_<step>` names is the order of their steps
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trajectory {
+    /// the file each frame came from
+    pub paths: Vec<PathBuf>,
+    pub frames: Vec<Vec<Body>>,
+}
+
+impl Trajectory {
+    /// loads every file matching `pattern`, a path whose last part may hold `*` for any run of characters and
+    /// `?` for any one, e.g. `out/snapshot_*.vtk`. the format comes from each file's extension: `.vtk` and
+    /// `.xyz` files are read as the writer writes them, anything else with `load::read_bodies`. fails with
+    /// `NoMatch` when no file matches
+    pub fn load_series(pattern: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let pattern = pattern.as_ref();
+        let name = pattern
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| LoadError::NoMatch(pattern.display().to_string()))?;
+        let directory = match pattern.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            let matched = path
+                .file_name()
+                .and_then(|file| file.to_str())
+                .is_some_and(|file| matches(name, file));
+            if matched && path.is_file() {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            return Err(LoadError::NoMatch(pattern.display().to_string()));
+        }
+        paths.sort();
+        let frames = paths
+            .iter()
+            .map(|path| read_frame(path))
+            .collect::<Result<_, _>>()?;
+        Ok(Trajectory { paths, frames })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn frame(&self, i: usize) -> Option<&[Body]> {
+        self.frames.get(i).map(Vec::as_slice)
+    }
+
+    /// frames `per_interval` apart between each pair of frames, ending on the last, such that frame
+    /// `k * per_interval` of the result is frame `k` of this. `interval` is the time between two frames
+    pub fn resample(
+        &self,
+        per_interval: usize,
+        interval: f64,
+        method: Interpolation,
+    ) -> Vec<Vec<Body>> {
+        let mut frames = Vec::new();
+        for pair in self.frames.windows(2) {
+            for k in 0..per_interval.max(1) {
+                let fraction = k as f64 / per_interval.max(1) as f64;
+                frames.push(interpolate(&pair[0], &pair[1], fraction, interval, method));
+            }
+        }
+        frames.extend(self.frames.last().cloned());
+        frames
+    }
+
+    /// where the body with `id` is in each frame, none in frames it is missing from
+    pub fn path_of(&self, id: u64) -> Vec<Option<Point>> {
+        self.frames
+            .iter()
+            .map(|frame| {
+                frame
+                    .iter()
+                    .find(|body| body.id == id)
+                    .map(|body| body.location)
+            })
+            .collect()
+    }
+}
+
+/// the bodies `fraction` of the way from frame `from` to frame `to`, `interval` apart in time, in the order
+/// of `from`. a body is matched to the one of the same id in `to`, and left out without one; its mass goes
+/// linearly between the two and its species is that of `from`
+pub fn interpolate(
+    from: &[Body],
+    to: &[Body],
+    fraction: f64,
+    interval: f64,
+    method: Interpolation,
+) -> Vec<Body> {
+    let index: HashMap<u64, &Body> = to.iter().map(|body| (body.id, body)).collect();
+    let t = fraction;
+    from.iter()
+        .filter_map(|a| {
+            let b = index.get(&a.id)?;
+            let (location, velocity) = match method {
+                Interpolation::Linear => (
+                    a.location + (b.location - a.location) * t,
+                    a.velocity + (b.velocity - a.velocity) * t,
+                ),
+                Interpolation::Hermite => {
+                    // the cubic hermite basis and its derivative, over a unit interval
+                    let (t2, t3) = (t * t, t * t * t);
+                    let (h00, h10, h01, h11) = (
+                        2. * t3 - 3. * t2 + 1.,
+                        t3 - 2. * t2 + t,
+                        -2. * t3 + 3. * t2,
+                        t3 - t2,
+                    );
+                    let (d00, d10, d01, d11) = (
+                        6. * t2 - 6. * t,
+                        3. * t2 - 4. * t + 1.,
+                        -6. * t2 + 6. * t,
+                        3. * t2 - 2. * t,
+                    );
+                    let location = a.location * h00
+                        + a.velocity * (h10 * interval)
+                        + b.location * h01
+                        + b.velocity * (h11 * interval);
+                    let velocity = (a.location * d00 + b.location * d01) / interval
+                        + a.velocity * d10
+                        + b.velocity * d11;
+                    (location, velocity)
+                }
+            };
+            Some(Body {
+                id: a.id,
+                mass: a.mass + (b.mass - a.mass) * t,
+                location,
+                velocity,
+                species: a.species,
+            })
+        })
+        .collect()
+}
+
+// whether `name` fits `pattern`, with `*` for any run of characters and `?` for any one
+fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    // where the last `*` was, and the character of `name` it has taken up to
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((at, taken)) = star {
+            // give the last `*` one more character and try again from there
+            star = Some((at, taken + 1));
+            p = at + 1;
+            n = taken + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn read_frame(path: &Path) -> Result<Vec<Body>, LoadError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("vtk") => parse_vtk(&std::fs::read_to_string(path)?),
+        Some("xyz") => parse_xyz(&std::fs::read_to_string(path)?),
+        _ => load::read_bodies(path),
+    }
+}
+
+// the polydata the writer writes: the points, then id and mass scalars and velocity vectors over them
+fn parse_vtk(text: &str) -> Result<Vec<Body>, LoadError> {
+    let lines: Vec<&str> = text.lines().collect();
+    let fail = |line: usize, message: String| LoadError::Row {
+        line: line + 1,
+        message,
+    };
+    // the `count` rows of numbers after line `start`, skipping a lookup table line
+    let rows = |start: usize, count: usize, width: usize| -> Result<Vec<Vec<f64>>, LoadError> {
+        let mut first = start + 1;
+        if lines
+            .get(first)
+            .is_some_and(|l| l.starts_with("LOOKUP_TABLE"))
+        {
+            first += 1;
+        }
+        (first..first + count)
+            .map(|i| {
+                let line = lines
+                    .get(i)
+                    .ok_or_else(|| fail(i, "the file ends early".to_string()))?;
+                let values: Vec<f64> = line
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| fail(i, format!("`{}` is not {} numbers", line, width)))?;
+                if values.len() != width {
+                    return Err(fail(i, format!("expected {} numbers", width)));
+                }
+                Ok(values)
+            })
+            .collect()
+    };
+    let find = |prefix: &str| lines.iter().position(|line| line.starts_with(prefix));
+    let points = find("POINTS ").ok_or_else(|| fail(0, "no POINTS".to_string()))?;
+    let count: usize = lines[points]
+        .split_whitespace()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| fail(points, "POINTS needs a count".to_string()))?;
+    let mut bodies: Vec<Body> = rows(points, count, 3)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| Body {
+            id: i as u64,
+            location: Point {
+                x: p[0],
+                y: p[1],
+                z: p[2],
+            },
+            ..Body::default()
+        })
+        .collect();
+    if let Some(at) = find("SCALARS id ") {
+        for (body, row) in bodies.iter_mut().zip(rows(at, count, 1)?) {
+            body.id = row[0] as u64;
+        }
+    }
+    if let Some(at) = find("SCALARS mass ") {
+        for (body, row) in bodies.iter_mut().zip(rows(at, count, 1)?) {
+            body.mass = row[0];
+        }
+    }
+    if let Some(at) = find("VECTORS velocity ") {
+        for (body, v) in bodies.iter_mut().zip(rows(at, count, 3)?) {
+            body.velocity = Point {
+                x: v[0],
+                y: v[1],
+                z: v[2],
+            };
+        }
+    }
+    Ok(bodies)
+}
+
+// a count line, a comment line, then `species id x y z mass vx vy vz` per body
+fn parse_xyz(text: &str) -> Result<Vec<Body>, LoadError> {
+    let mut lines = text.lines().enumerate();
+    let count: usize = lines
+        .next()
+        .and_then(|(_, line)| line.trim().parse().ok())
+        .ok_or_else(|| LoadError::Row {
+            line: 1,
+            message: "expected a body count".to_string(),
+        })?;
+    lines
+        .skip(1)
+        .take(count)
+        .map(|(i, line)| {
+            let fail = |message: String| LoadError::Row {
+                line: i + 1,
+                message,
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 9 {
+                return Err(fail(format!("expected 9 fields, found {}", fields.len())));
+            }
+            let id = fields[1]
+                .parse()
+                .map_err(|_| fail(format!("`{}` is not an id", fields[1])))?;
+            let values: Vec<f64> = fields[2..]
+                .iter()
+                .map(|field| field.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| fail("position, mass or velocity is not a number".to_string()))?;
+            Ok(Body {
+                id,
+                mass: values[3],
+                location: Point {
+                    x: values[0],
+                    y: values[1],
+                    z: values[2],
+                },
+                velocity: Point {
+                    x: values[4],
+                    y: values[5],
+                    z: values[6],
+                },
+                species: if fields[0] == "T" {
+                    Species::Tracer
+                } else {
+                    Species::Live
+                },
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cuboid;
+    use crate::sim::Simulation;
+    use crate::snapshot::{SnapshotFormat, SnapshotLayout, SnapshotWriter};
+
+    #[test]
+    fn star_and_question_mark_match_as_in_a_shell() {
+        assert!(matches("snapshot_*.csv", "snapshot_000010.csv"));
+        assert!(matches("*_00001?.*", "snapshot_000010.vtk"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(!matches("snapshot_*.csv", "snapshot_000010.vtk"));
+        assert!(!matches("a*b", "ab c"));
+    }
+
+    fn moving(id: u64, location: [f64; 3], velocity: [f64; 3]) -> Body {
+        Body {
+            id,
+            mass: 1.,
+            location: location.into(),
+            velocity: velocity.into(),
+            ..Body::default()
+        }
+    }
+
+    #[test]
+    fn halfway_along_a_straight_line_is_the_midpoint() {
+        let velocity = [0.5, -1., 2.];
+        let from = [
+            moving(3, [0., 0., 0.], velocity),
+            moving(4, [9., 9., 9.], [0.; 3]),
+        ];
+        // 2 time units on, listed the other way round, and without body 4
+        let to = [
+            moving(5, [1., 1., 1.], [0.; 3]),
+            moving(3, [1., -2., 4.], velocity),
+        ];
+        for method in [Interpolation::Linear, Interpolation::Hermite] {
+            let halfway = interpolate(&from, &to, 0.5, 2., method);
+            assert_eq!(halfway.len(), 1);
+            assert_eq!(halfway[0].id, 3);
+            assert_eq!(halfway[0].location, Point::from([0.5, -1., 2.]));
+            assert_eq!(halfway[0].velocity, Point::from(velocity));
+        }
+        let trajectory = Trajectory {
+            paths: vec![PathBuf::new(); 2],
+            frames: vec![from.to_vec(), to.to_vec()],
+        };
+        let frames = trajectory.resample(4, 2., Interpolation::Linear);
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[4], to.to_vec());
+        assert_eq!(frames[1][0].location, Point::from([0.25, -0.5, 1.]));
+    }
+
+    #[test]
+    fn hermite_follows_a_circle_that_linear_cuts_across() {
+        // a quarter of a unit circle at unit speed between two frames
+        let interval = std::f64::consts::FRAC_PI_2;
+        let from = [moving(0, [1., 0., 0.], [0., 1., 0.])];
+        let to = [moving(0, [0., 1., 0.], [-1., 0., 0.])];
+        let radius = |method| {
+            interpolate(&from, &to, 0.5, interval, method)[0]
+                .location
+                .length()
+        };
+        let (linear, hermite) = (
+            radius(Interpolation::Linear),
+            radius(Interpolation::Hermite),
+        );
+        assert!((hermite - 1.).abs() < 0.02, "{}", hermite);
+        assert!((linear - 1.).abs() > 0.25, "{}", linear);
+    }
+
+    #[test]
+    fn five_snapshots_load_back_as_five_frames() {
+        for (format, extension) in [
+            (SnapshotFormat::Csv, "csv"),
+            (SnapshotFormat::Json, "json"),
+            (SnapshotFormat::Vtk, "vtk"),
+            (SnapshotFormat::Xyz, "xyz"),
+        ] {
+            let directory = std::env::temp_dir().join(format!(
+                "barneshutt3d-series-{}-{}",
+                extension,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&directory);
+            let space = Cuboid::from(([0.; 3], [1.; 3]));
+            let mut simulation = Simulation::<f64>::new_seeded(20, space, 3);
+            let mut writer =
+                SnapshotWriter::new(&directory, format, SnapshotLayout::FilePerSnapshot, 1);
+            let mut written = vec![];
+            for _ in 0..5 {
+                simulation.step(0.001);
+                writer.write(&simulation).unwrap();
+                written.push(simulation.bodies().to_vec());
+            }
+            let trajectory =
+                Trajectory::load_series(directory.join(format!("snapshot_*.{}", extension)))
+                    .unwrap();
+            assert_eq!(trajectory.len(), 5, "{}", extension);
+            for (frame, bodies) in trajectory.frames.iter().zip(&written) {
+                assert_eq!(frame.len(), 20);
+                for (read, body) in frame.iter().zip(bodies) {
+                    assert_eq!(
+                        (read.id, read.location, read.velocity),
+                        (body.id, body.location, body.velocity)
+                    );
+                }
+            }
+            assert_eq!(trajectory.path_of(7)[4], Some(written[4][7].location));
+            std::fs::remove_dir_all(&directory).unwrap();
+        }
+        assert!(matches!(
+            Trajectory::load_series(std::env::temp_dir().join("barneshutt3d-nothing-*.csv")),
+            Err(LoadError::NoMatch(_))
+        ));
+    }
+}
@@ -0,0 +1,2543 @@
+use crate::body::Body;
+use crate::dual;
+use crate::force::{ForceModel, Gravity};
+use crate::geometry::{Axis, Cuboid, Point, Range};
+use crate::kernel::NearField;
+use crate::scalar::Scalar;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::Arc;
+
+/// how a node's box is divided among its children; a scheme may use fewer than the eight child slots
+pub trait Subdivision<S: Scalar = f64>: std::fmt::Debug + Send + Sync {
+    /// child slots in use, starting from 0: at least 1 and at most the 8 a node has room for
+    fn arity(&self) -> usize;
+    fn child_box(&self, space: &Cuboid<S>, index: usize) -> Cuboid<S>;
+    /// the slot `point` falls in, or none if it falls in none of them, e.g. outside `space`. the tree then files
+    /// it under the slot whose box is nearest, so nothing is lost
+    fn child_index(&self, space: &Cuboid<S>, point: &Point<S>) -> Option<usize>;
+}
+
+// the slot `point` goes in under `scheme`: its own, or the nearest box's when the scheme has none for it
+fn slot_of<S: Scalar>(scheme: &dyn Subdivision<S>, space: &Cuboid<S>, point: &Point<S>) -> usize {
+    scheme.child_index(space, point).unwrap_or_else(|| {
+        (0..scheme.arity())
+            .min_by(|&a, &b| {
+                let distance = |slot| scheme.child_box(space, slot).distance_squared_to(point);
+                distance(a).total_cmp(&distance(b))
+            })
+            .expect("a subdivision has at least one slot")
+    })
+}
+
+/// the regular split into eight octants at the box's midpoint
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Octants;
+
+impl<S: Scalar> Subdivision<S> for Octants {
+    fn arity(&self) -> usize {
+        8
+    }
+
+    fn child_box(&self, space: &Cuboid<S>, index: usize) -> Cuboid<S> {
+        space.split()[index]
+    }
+
+    fn child_index(&self, space: &Cuboid<S>, point: &Point<S>) -> Option<usize> {
+        space.octant_contains_point(point)
+    }
+}
+
+/// a kd-style split in two halves across the box's longest axis
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LongestAxis;
+
+impl LongestAxis {
+    fn axis<S: Scalar>(space: &Cuboid<S>) -> Axis {
+        let x = space.x.end - space.x.start;
+        let y = space.y.end - space.y.start;
+        let z = space.z.end - space.z.start;
+        if x >= y && x >= z {
+            Axis::X
+        } else if y >= z {
+            Axis::Y
+        } else {
+            Axis::Z
+        }
+    }
+}
+
+impl<S: Scalar> Subdivision<S> for LongestAxis {
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn child_box(&self, space: &Cuboid<S>, index: usize) -> Cuboid<S> {
+        let mut half = *space;
+        let range = match LongestAxis::axis(space) {
+            Axis::X => &mut half.x,
+            Axis::Y => &mut half.y,
+            Axis::Z => &mut half.z,
+        };
+        let mid = range.midpoint();
+        if index == 0 {
+            range.end = mid;
+        } else {
+            range.start = mid;
+        }
+        half
+    }
+
+    fn child_index(&self, space: &Cuboid<S>, point: &Point<S>) -> Option<usize> {
+        let (range, value) = match LongestAxis::axis(space) {
+            Axis::X => (&space.x, point.x),
+            Axis::Y => (&space.y, point.y),
+            Axis::Z => (&space.z, point.z),
+        };
+        Some(if value < range.midpoint() { 0 } else { 1 })
+    }
+}
+
+/// below this many levels of octants a full leaf splits; at it, leaves take any number of bodies, so bodies
+/// sharing a position cannot subdivide forever. a scheme splitting fewer ways gets proportionally more levels,
+/// see `Octree::max_depth`, so its leaves bottom out at the same size
+pub const MAX_DEPTH: usize = 32;
+
+/// what an `Octree` can index: anything with a place in space
+pub trait HasPosition: Send {
+    type Scalar: Scalar;
+    fn position(&self) -> Point<Self::Scalar>;
+}
+
+impl<S: Scalar> HasPosition for Body<S> {
+    type Scalar = S;
+
+    fn position(&self) -> Point<S> {
+        self.location
+    }
+}
+
+impl<S: Scalar> HasPosition for Point<S> {
+    type Scalar = S;
+
+    fn position(&self) -> Point<S> {
+        *self
+    }
+}
+
+/// a summary an `Octree` keeps of the items beneath each node, such as how many there are or their total mass.
+/// `()` keeps nothing, for a plain spatial index, and `MassMoments` is what barnes-hut needs
+pub trait Aggregate<T: HasPosition>: Clone + Send {
+    /// the summary of no items, for a node over `space`
+    fn empty(space: &Cuboid<T::Scalar>) -> Self;
+    /// folds in one more item beneath the node, as an insert passes through it on the way down
+    fn add(&mut self, item: &T, space: &Cuboid<T::Scalar>);
+    /// the summary from scratch, of a leaf's `items` or of an internal node's `children`
+    fn gather<'a>(items: &[T], children: impl Iterator<Item = &'a Self>, space: &Cuboid<T::Scalar>) -> Self
+    where
+        Self: 'a;
+    /// whether this summary agrees with `recomputed`, one gathered from scratch, to within a relative
+    /// `tolerance`. `Octree::validate` asks it of every node; a summary that cannot drift agrees by default
+    fn agrees_with(&self, recomputed: &Self, space: &Cuboid<T::Scalar>, tolerance: f64) -> bool {
+        let _ = (recomputed, space, tolerance);
+        true
+    }
+}
+
+impl<T: HasPosition> Aggregate<T> for () {
+    fn empty(_: &Cuboid<T::Scalar>) -> Self {}
+
+    fn add(&mut self, _: &T, _: &Cuboid<T::Scalar>) {}
+
+    fn gather<'a>(_: &[T], _: impl Iterator<Item = &'a Self>, _: &Cuboid<T::Scalar>) -> Self {}
+}
+
+/// scratch space for walking a tree without recursion, see `Octree::acceleration_at_with`. keeping one between
+/// walks means repeated force evaluations reuse its allocation instead of growing a new one each time
+#[derive(Debug, Clone)]
+pub struct WalkStack<S = f64> {
+    // the pointer tree's opened nodes down to the current one, each with the next child slot to try and the pull
+    // summed over the children before it; just what a recursive call would hold
+    pub(crate) frames: Vec<(usize, usize, Point<S>)>,
+    // the linear tree's nodes still to visit
+    pub(crate) nodes: Vec<usize>,
+    // what the walks on this stack took, summed until taken
+    pub(crate) interactions: Interactions,
+}
+
+impl<S> WalkStack<S> {
+    pub fn new() -> Self {
+        WalkStack { frames: vec![], nodes: vec![], interactions: Interactions::default() }
+    }
+
+    /// the terms the force walks on this stack have summed since they were last taken, leaving none
+    pub fn take_interactions(&mut self) -> Interactions {
+        std::mem::take(&mut self.interactions)
+    }
+}
+
+/// how many terms went into some forces: bodies pulling one by one and accepted nodes standing in for theirs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Interactions {
+    /// bodies summed directly, counting every body of an opened leaf, the target's own included
+    pub body_body: u64,
+    /// nodes accepted by the opening test
+    pub body_cell: u64,
+}
+
+impl Interactions {
+    pub fn total(&self) -> u64 {
+        self.body_body + self.body_cell
+    }
+}
+
+impl std::ops::AddAssign for Interactions {
+    fn add_assign(&mut self, other: Interactions) {
+        self.body_body += other.body_body;
+        self.body_cell += other.body_cell;
+    }
+}
+
+impl<S> Default for WalkStack<S> {
+    fn default() -> Self {
+        WalkStack::new()
+    }
+}
+
+/// the total mass and center of mass of the bodies beneath a node, and their quadrupole once the tree has
+/// computed it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MassMoments<S = f64> {
+    // the net mass, which negative masses can bring to zero or below
+    pub(crate) mass: S,
+    // the sum of the masses' sizes, zero only when nothing beneath pulls
+    pub(crate) absolute_mass: S,
+    // the sum of mass times location, which the center is worked out from so it survives the net mass passing
+    // through zero on the way
+    pub(crate) first_moment: Point<S>,
+    // the first moment over the net mass; a node with no net mass, empty or not, sits at its box's center
+    pub(crate) center_of_mass: Point<S>,
+    // traceless quadrupole about the center of mass, zero until BodyTree::compute_quadrupoles
+    pub(crate) quadrupole: [S; 6],
+    // whether `quadrupole` is up to date: compute_quadrupoles sets it everywhere and any change beneath clears
+    // it, so the root's says whether the tree evaluates quadrupoles
+    pub(crate) quadrupole_current: bool,
+    // how far the bodies reach from the center of mass, infinite until BodyTree::compute_radii and after any
+    // change beneath, which keeps a stale node from passing a bounding-sphere test
+    pub(crate) radius: S,
+}
+
+impl<S: Scalar> MassMoments<S> {
+    pub fn mass(&self) -> S {
+        self.mass
+    }
+
+    /// the sum of the sizes of the masses beneath, the net mass unless some are negative
+    pub fn absolute_mass(&self) -> S {
+        self.absolute_mass
+    }
+
+    pub fn center_of_mass(&self) -> &Point<S> {
+        &self.center_of_mass
+    }
+
+    /// see `OctreeNode::quadrupole`
+    pub fn quadrupole(&self) -> &[S; 6] {
+        &self.quadrupole
+    }
+
+    /// see `OctreeNode::radius`
+    pub fn radius(&self) -> S {
+        self.radius
+    }
+}
+
+impl<S: Scalar> Aggregate<Body<S>> for MassMoments<S> {
+    fn empty(space: &Cuboid<S>) -> Self {
+        MassMoments {
+            mass: S::zero(),
+            absolute_mass: S::zero(),
+            first_moment: Point::default(),
+            center_of_mass: space.center(),
+            quadrupole: [S::zero(); 6],
+            quadrupole_current: false,
+            radius: S::infinity(),
+        }
+    }
+
+    fn add(&mut self, body: &Body<S>, space: &Cuboid<S>) {
+        self.mass += body.mass;
+        self.absolute_mass += body.mass.abs();
+        self.first_moment += body.location * body.mass;
+        self.center_of_mass = center_of(self.first_moment, self.mass, space);
+        self.quadrupole_current = false;
+        self.radius = S::infinity();
+    }
+
+    fn gather<'a>(bodies: &[Body<S>], children: impl Iterator<Item = &'a Self>, space: &Cuboid<S>) -> Self {
+        let (mut mass, mut absolute_mass) = (S::zero(), S::zero());
+        let mut weighted = Point::default();
+        for body in bodies {
+            mass += body.mass;
+            absolute_mass += body.mass.abs();
+            weighted += body.location * body.mass;
+        }
+        for child in children {
+            mass += child.mass;
+            absolute_mass += child.absolute_mass;
+            weighted += child.first_moment;
+        }
+        MassMoments {
+            mass,
+            absolute_mass,
+            first_moment: weighted,
+            center_of_mass: center_of(weighted, mass, space),
+            quadrupole: [S::zero(); 6],
+            quadrupole_current: false,
+            radius: S::infinity(),
+        }
+    }
+
+    // the mass relative to the larger of the two, the center relative to the node's size or its distance from
+    // the origin, whichever is larger, since that is what the rounding scales with. quadrupoles are left out,
+    // being zero in a fresh gather
+    fn agrees_with(&self, recomputed: &Self, space: &Cuboid<S>, tolerance: f64) -> bool {
+        let (mass, expected) = (self.mass.as_f64(), recomputed.mass.as_f64());
+        let scale = self.absolute_mass.as_f64().max(recomputed.absolute_mass.as_f64());
+        if (mass - expected).abs() > tolerance * scale {
+            return false;
+        }
+        // masses that all but cancel leave the center anywhere, so there is nothing to hold it to
+        if expected.abs() <= tolerance * scale {
+            return true;
+        }
+        let center: Point = self.center_of_mass.cast();
+        let expected: Point = recomputed.center_of_mass.cast();
+        let scale = center.length().max(expected.length()).max(space.size().as_f64());
+        (center - expected).length() <= tolerance * scale
+    }
+}
+
+/// a node in an `Octree`. its children live in the same tree and are named by their index into
+/// `Octree::nodes`
+#[derive(Debug, Clone)]
+pub struct OctreeNode<T: HasPosition, A = ()> {
+    // the arena index of the child in each slot. the root sits at 0 and is nobody's child, so a link is never
+    // 0 and the option is free
+    pub(crate) children: [Option<NonZeroU32>; 8],
+    // only leaves hold items, at most the tree's bucket size of them above its max depth
+    pub(crate) items: Vec<T>,
+    // the place in insertion order of each of `items`, which `Octree::relocate` finds them by
+    pub(crate) ids: Vec<u32>,
+    pub(crate) bounding_box: Cuboid<T::Scalar>,
+    // the summary of everything beneath this node, kept current by every insert and removal
+    pub(crate) aggregate: A,
+}
+
+impl<T: HasPosition, A: Aggregate<T>> OctreeNode<T, A> {
+    fn empty(space: Cuboid<T::Scalar>) -> Self {
+        OctreeNode {
+            children: [None; 8],
+            items: Vec::new(),
+            ids: Vec::new(),
+            bounding_box: space,
+            aggregate: A::empty(&space),
+        }
+    }
+}
+
+impl<T: HasPosition, A> OctreeNode<T, A> {
+    pub fn bounding_box(&self) -> &Cuboid<T::Scalar> {
+        &self.bounding_box
+    }
+
+    /// the items held by this node; only leaves hold any
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// the children that exist, as their slot and their index into `Octree::nodes`
+    pub fn children(&self) -> impl DoubleEndedIterator<Item = (usize, usize)> + '_ {
+        self.children
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, child)| child.map(|child| (slot, child.get() as usize)))
+    }
+
+    /// the summary of the items beneath
+    pub fn aggregate(&self) -> &A {
+        &self.aggregate
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.iter().all(|child| child.is_none())
+    }
+}
+
+impl<S: Scalar> OctreeNode<Body<S>, MassMoments<S>> {
+    pub fn mass(&self) -> S {
+        self.aggregate.mass
+    }
+
+    /// see `MassMoments::absolute_mass`
+    pub fn absolute_mass(&self) -> S {
+        self.aggregate.absolute_mass
+    }
+
+    pub fn center_of_mass(&self) -> &Point<S> {
+        &self.aggregate.center_of_mass
+    }
+
+    /// the traceless quadrupole sum(m (3 d dᵀ - |d|² I)) of the bodies beneath, with d their offsets from the
+    /// center of mass, as xx, xy, xz, yy, yz, zz. zero unless the tree has computed quadrupoles
+    pub fn quadrupole(&self) -> &[S; 6] {
+        &self.aggregate.quadrupole
+    }
+
+    /// the radius of a sphere about the center of mass holding every body beneath, for the bounding-sphere
+    /// opening test. infinite unless the tree has computed radii
+    pub fn radius(&self) -> S {
+        self.aggregate.radius
+    }
+}
+
+// the id of the body inserted `index`th
+fn id(index: usize) -> u32 {
+    u32::try_from(index).expect("an octree holds at most u32::MAX bodies")
+}
+
+// the link to the node at `index`, which must not be the root
+fn link(index: usize) -> NonZeroU32 {
+    let index = u32::try_from(index).expect("an octree holds at most u32::MAX nodes");
+    NonZeroU32::new(index).expect("the root is never a child")
+}
+
+/// the far-field expansion a tree evaluates accepted nodes with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum MultipoleOrder {
+    /// a point mass at the node's center of mass
+    #[default]
+    Monopole,
+    /// the point mass plus the node's quadrupole. costs a pass over the tree after building and a little per
+    /// accepted node, and cuts the force error enough that theta can go up for the same accuracy
+    Quadrupole,
+}
+
+/// how a walk tells whether a node is far enough from the target to stand in for its bodies: a size s of the
+/// node against the distance d from the target to its center of mass, accepted when s < theta · d, or the
+/// node's mass weighed in as well. the dual walk compares bounding spheres its own way whatever this is, and
+/// the gpu and distributed runs only take the box
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Opening {
+    /// s is the longest edge of the node's box
+    #[default]
+    Box,
+    /// s is the diameter of a sphere about the node's center of mass holding all of its bodies, worked out
+    /// after building. bodies filling their box make that larger than the box, so more nodes are opened at the
+    /// same theta; bodies clumped into a corner of a big box make it smaller, and it stops their node from being
+    /// opened for the space around them
+    BoundingSphere,
+    /// accepted when the node's monopole error estimate |M| s² / d³, with s its longest box edge, is below
+    /// `tol`, in units of mass over length, and the node is no nearer than its size. heavy nodes are then
+    /// opened further out than light ones, which spreads the error more evenly over the bodies than one theta
+    /// does. theta, and any theta field, has no say
+    MultipoleAcceptance { tol: f64 },
+}
+
+// an opening criterion at its theta, as the walks take it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Acceptance<S> {
+    pub(crate) theta: S,
+    pub(crate) opening: Opening,
+}
+
+impl<S: Scalar> Acceptance<S> {
+    // the box test at `theta`
+    pub(crate) fn theta(theta: S) -> Self {
+        Acceptance { theta, opening: Opening::Box }
+    }
+
+    // whether a node with a box edge of `size`, whose bodies reach `radius` from its center of mass and weigh
+    // `absolute_mass` all told, stands in for them at a squared `distance_squared` from that center. both sides
+    // are squared, s² < θ² d², so a walk takes no square root for the nodes it only opens
+    pub(crate) fn accepts(&self, size: S, radius: S, absolute_mass: S, distance_squared: S) -> bool {
+        let extent = match self.opening {
+            Opening::Box => size,
+            Opening::BoundingSphere => radius + radius,
+            Opening::MultipoleAcceptance { tol } => {
+                // the size test first keeps the target's own nodes out, and the root out of the near ones
+                let s2 = size * size;
+                return s2 < distance_squared
+                    && absolute_mass * s2 < S::from_f64(tol) * distance_squared * distance_squared.sqrt();
+            }
+        };
+        extent * extent < self.theta * self.theta * distance_squared
+    }
+}
+
+// adds `quadrupole`, about a center `offset` away holding `mass`, to `total` about the new center
+pub(crate) fn add_shifted<S: Scalar>(total: &mut [S; 6], quadrupole: &[S; 6], mass: S, offset: &Point<S>) {
+    let Point { x, y, z } = *offset;
+    let r2 = offset.dot(offset);
+    let three = S::from_f64(3.);
+    let shift = [
+        three * x * x - r2,
+        three * x * y,
+        three * x * z,
+        three * y * y - r2,
+        three * y * z,
+        three * z * z - r2,
+    ];
+    for ((total, &q), s) in total.iter_mut().zip(quadrupole).zip(shift) {
+        *total += q + mass * s;
+    }
+}
+
+// Q r and r^T Q r for the separation r from the expansion center to `target`, with the softened r²
+fn quadrupole_terms<S: Scalar>(
+    target: &Point<S>,
+    center: &Point<S>,
+    q: &[S; 6],
+    softening: S,
+) -> Option<(Point<S>, Point<S>, S, S)> {
+    let r = *target - *center;
+    let distance_squared = r.dot(&r);
+    if distance_squared.is_zero() {
+        return None;
+    }
+    let qr = Point {
+        x: q[0] * r.x + q[1] * r.y + q[2] * r.z,
+        y: q[1] * r.x + q[3] * r.y + q[4] * r.z,
+        z: q[2] * r.x + q[4] * r.y + q[5] * r.z,
+    };
+    let rqr = r.dot(&qr);
+    Some((r, qr, rqr, distance_squared + softening * softening))
+}
+
+// the quadrupole's part of the acceleration, -∇ of `quadrupole_potential`
+pub(crate) fn quadrupole_acceleration<S: Scalar>(
+    target: &Point<S>,
+    center: &Point<S>,
+    q: &[S; 6],
+    softening: S,
+) -> Point<S> {
+    let Some((r, qr, rqr, softened)) = quadrupole_terms(target, center, q, softening) else {
+        return Point::default();
+    };
+    let inverse_fifth = (softened * softened * softened.sqrt()).recip();
+    qr * inverse_fifth - r * (S::from_f64(2.5) * rqr * inverse_fifth / softened)
+}
+
+// the quadrupole's part of the potential, -r^T Q r / (2 r^5)
+pub(crate) fn quadrupole_potential<S: Scalar>(
+    target: &Point<S>,
+    center: &Point<S>,
+    q: &[S; 6],
+    softening: S,
+) -> S {
+    let Some((_, _, rqr, softened)) = quadrupole_terms(target, center, q, softening) else {
+        return S::zero();
+    };
+    -S::from_f64(0.5) * rqr / (softened * softened * softened.sqrt())
+}
+
+// the center of mass from the first moment, or the center of `space` when there is no net mass to divide by
+pub(crate) fn center_of<S: Scalar>(first_moment: Point<S>, mass: S, space: &Cuboid<S>) -> Point<S> {
+    if mass.is_zero() {
+        space.center()
+    } else {
+        first_moment / mass
+    }
+}
+
+// a box that repeats along some of its axes, the x, y and z that `axes` holds, and is open along the rest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Period<S: Scalar> {
+    pub(crate) space: Cuboid<S>,
+    pub(crate) axes: [bool; 3],
+}
+
+impl<S: Scalar> Period<S> {
+    // `space` repeating in every direction
+    pub(crate) fn all(space: Cuboid<S>) -> Self {
+        Period { space, axes: [true; 3] }
+    }
+}
+
+// the copy of `source` that acts on `target`: itself in open space, its nearest image in a periodic box
+pub(crate) fn image_of<S: Scalar>(period: Option<&Period<S>>, source: &Point<S>, target: &Point<S>) -> Point<S> {
+    match period {
+        Some(period) => period.space.nearest_image_along(source, target, period.axes),
+        None => *source,
+    }
+}
+
+// whether every point of `space` has its nearest image to `target` on the same side, so one image of the
+// node's center of mass can stand in for all of its bodies. always true in open space, and along open axes
+pub(crate) fn within_half_period<S: Scalar>(
+    period: Option<&Period<S>>,
+    space: &Cuboid<S>,
+    target: &Point<S>,
+) -> bool {
+    let Some(period) = period else {
+        return true;
+    };
+    let center = image_of(Some(period), &space.center(), target);
+    let fits = |offset: S, extent: S, range: &Range<S>, periodic: bool| {
+        !periodic || offset.abs() + offset.abs() + extent <= range.end - range.start
+    };
+    let (box_, axes) = (&period.space, period.axes);
+    fits(center.x - target.x, space.x.end - space.x.start, &box_.x, axes[0])
+        && fits(center.y - target.y, space.y.end - space.y.start, &box_.y, axes[1])
+        && fits(center.z - target.z, space.z.end - space.z.start, &box_.z, axes[2])
+}
+
+// plummer-softened pull of a point mass on `target`; zero when they coincide
+pub(crate) fn point_mass_acceleration<S: Scalar>(
+    target: &Point<S>,
+    source: &Point<S>,
+    mass: S,
+    softening: S,
+) -> Point<S> {
+    let offset = *source - *target;
+    let distance_squared = offset.dot(&offset);
+    if distance_squared.is_zero() {
+        return Point::default();
+    }
+    let softened = distance_squared + softening * softening;
+    offset * (mass / (softened * softened.sqrt()))
+}
+
+// plummer-softened potential of a point mass at `target`; zero when they coincide
+pub(crate) fn point_mass_potential<S: Scalar>(target: &Point<S>, source: &Point<S>, mass: S, softening: S) -> S {
+    let distance_squared = source.distance_squared(target);
+    if distance_squared.is_zero() {
+        return S::zero();
+    }
+    -mass / (distance_squared + softening * softening).sqrt()
+}
+
+// a candidate in the k-nearest search, ordered by distance so the heap keeps the farthest on top
+pub(crate) struct Neighbor<'a, T: HasPosition> {
+    pub(crate) distance_squared: T::Scalar,
+    pub(crate) item: &'a T,
+}
+
+impl<T: HasPosition> PartialEq for Neighbor<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: HasPosition> Eq for Neighbor<'_, T> {}
+
+impl<T: HasPosition> PartialOrd for Neighbor<'_, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: Scalar, T: HasPosition<Scalar = S>> Ord for Neighbor<'_, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance_squared.total_cmp(&other.distance_squared)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeError {
+    /// a body sits outside the bounding box of the node holding it
+    BodyOutsideBox { depth: usize },
+    /// a node with children also holds a body
+    BodyInInternalNode { depth: usize },
+    /// a child's box is not the one its parent's subdivision gives that slot
+    ChildBoxMismatch { depth: usize, octant: usize },
+    /// a leaf above the tree's max depth holds more bodies than its bucket size
+    OverfullLeaf { depth: usize },
+    /// a node's cached aggregate, such as its mass or center of mass, differs from the one recomputed from
+    /// its items and children
+    AggregateMismatch { depth: usize },
+}
+
+impl std::fmt::Display for TreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeError::BodyOutsideBox { depth } => {
+                write!(f, "body outside its node's bounding box at depth {}", depth)
+            }
+            TreeError::BodyInInternalNode { depth } => {
+                write!(f, "internal node holds a body at depth {}", depth)
+            }
+            TreeError::ChildBoxMismatch { depth, octant } => write!(
+                f,
+                "child {} of node at depth {} does not match its octant",
+                octant, depth
+            ),
+            TreeError::OverfullLeaf { depth } => {
+                write!(f, "leaf holds more bodies than its bucket size at depth {}", depth)
+            }
+            TreeError::AggregateMismatch { depth } => {
+                write!(f, "cached aggregate differs from the recomputed one at depth {}", depth)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TreeError {}
+
+/// the shape of a tree, for tuning theta and the bucket size
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    /// depth of the deepest leaf; the root is at depth 0
+    pub max_depth: usize,
+    /// depth of the average body, i.e. of the leaves weighted by the bodies they hold
+    pub mean_depth: f64,
+    /// `occupancy[k]` is the number of leaves holding k bodies
+    pub occupancy: Vec<usize>,
+    /// bytes taken by the nodes and the bodies they hold, leaving out allocator overhead
+    pub memory: usize,
+}
+
+impl TreeStats {
+    // from the depth and body count of every leaf
+    pub(crate) fn from_leaves(nodes: usize, leaves: impl IntoIterator<Item = (usize, usize)>, memory: usize) -> Self {
+        let mut stats = TreeStats { nodes, leaves: 0, max_depth: 0, mean_depth: 0., occupancy: vec![], memory };
+        let mut bodies = 0;
+        for (depth, count) in leaves {
+            stats.leaves += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            stats.mean_depth += (depth * count) as f64;
+            bodies += count;
+            if stats.occupancy.len() <= count {
+                stats.occupancy.resize(count + 1, 0);
+            }
+            stats.occupancy[count] += 1;
+        }
+        if bodies > 0 {
+            stats.mean_depth /= bodies as f64;
+        }
+        stats
+    }
+}
+
+/// how `BodyTree::force_on_traced` got a force: the nodes that stood in for their bodies and the bodies of the
+/// opened leaves that were summed one by one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceInfo {
+    /// nodes accepted by the opening test
+    pub approximated: usize,
+    /// bodies pulling one by one, leaving out any at the body's own position
+    pub summed: usize,
+    /// the index in `nodes()` of each accepted node, in the order the walk met them
+    pub cells: Vec<usize>,
+}
+
+impl std::fmt::Display for TreeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves, depth {} max {:.2} mean, {} bytes, bodies per leaf",
+            self.nodes, self.leaves, self.max_depth, self.mean_depth, self.memory
+        )?;
+        for (count, &leaves) in self.occupancy.iter().enumerate() {
+            if leaves > 0 {
+                write!(f, " {}:{}", count, leaves)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InsertError {
+    /// a coordinate or the mass is nan or infinite
+    NonFinite,
+}
+
+impl std::fmt::Display for InsertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InsertError::NonFinite => write!(f, "body has a non-finite coordinate or mass"),
+        }
+    }
+}
+
+impl std::error::Error for InsertError {}
+
+// corner i of `b` takes the upper bound on an axis when that axis' bit is set, like the octant order
+fn corner<S: Scalar>(b: &Cuboid<S>, i: usize) -> Point<S> {
+    Point {
+        x: if i & 1 == 0 { b.x.start } else { b.x.end },
+        y: if i & 2 == 0 { b.y.start } else { b.y.end },
+        z: if i & 4 == 0 { b.z.start } else { b.z.end },
+    }
+}
+
+// a node as `Octree::export_wireframe` writes it to json
+#[derive(Serialize)]
+struct WireframeCell {
+    depth: usize,
+    bodies: usize,
+    min: [f64; 3],
+    max: [f64; 3],
+}
+
+/// a spatial index over anything with a position, each node keeping an aggregate `A` of the items beneath it.
+/// `BodyTree` is the one Simulation builds on, over bodies with the mass moments barnes-hut needs. the nodes
+/// live in a single vec and link to their children by index, so building the tree grows one allocation
+/// instead of boxing every node, and traversals stay close together in memory
+#[derive(Debug)]
+pub struct Octree<T: HasPosition, A = ()> {
+    // nodes[0] is the root, and every node comes after its parent
+    nodes: Vec<OctreeNode<T, A>>,
+    len: usize,
+    // ids handed out so far, which is `len` until an item is removed
+    inserted: usize,
+    bucket_size: usize,
+    // shared with the subtrees a parallel build splits off
+    subdivision: Arc<dyn Subdivision<T::Scalar>>,
+    // whether inserts add to the aggregates on their way down, see `set_incremental`
+    incremental: bool,
+}
+
+/// the barnes-hut tree: bodies with their mass moments
+pub type BodyTree<S = f64> = Octree<Body<S>, MassMoments<S>>;
+
+impl<S: Scalar, T: HasPosition<Scalar = S>, A: Aggregate<T>> Octree<T, A> {
+    pub fn new(space: Cuboid<S>) -> Self {
+        Octree::with_subdivision(space, Octants)
+    }
+
+    pub fn with_subdivision(space: Cuboid<S>, subdivision: impl Subdivision<S> + 'static) -> Self {
+        Octree::with_subdivision_bucketed(space, subdivision, 1)
+    }
+
+    /// a tree whose leaves hold up to `bucket_size` items before splitting. panics if it is 0
+    pub fn with_bucket_size(space: Cuboid<S>, bucket_size: usize) -> Self {
+        Octree::with_subdivision_bucketed(space, Octants, bucket_size)
+    }
+
+    /// `with_bucket_size` under another subdivision. panics if `bucket_size` is 0, or if the subdivision's arity
+    /// is 0 or more than 8
+    pub fn with_subdivision_bucketed(
+        space: Cuboid<S>,
+        subdivision: impl Subdivision<S> + 'static,
+        bucket_size: usize,
+    ) -> Self {
+        let arity = subdivision.arity();
+        assert!((1..=8).contains(&arity), "a subdivision needs 1 to 8 child slots, not {}", arity);
+        Octree::empty_with(space, Arc::new(subdivision), bucket_size)
+    }
+
+    fn empty_with(space: Cuboid<S>, subdivision: Arc<dyn Subdivision<S>>, bucket_size: usize) -> Self {
+        assert!(bucket_size > 0, "a leaf must hold at least one item");
+        let nodes = vec![OctreeNode::empty(space)];
+        Octree { nodes, len: 0, inserted: 0, bucket_size, subdivision, incremental: true }
+    }
+
+    /// builds the tree from scratch, with the aggregates gathered once at the end rather than added to by every
+    /// insert. with the `parallel` feature the eight top-level octants are built concurrently; the resulting
+    /// tree has the same nodes holding the same items either way, though not in the same order in the arena.
+    /// the shape does not depend on the order of `items` either, but the order of the nodes, and of the items
+    /// in a bucket, does, and with them the order the forces are summed in; see `build_canonical`
+    pub fn build(items: impl IntoIterator<Item = T>, space: Cuboid<S>) -> Self {
+        Octree::build_bucketed(items, space, 1)
+    }
+
+    /// `build` with leaves of up to `bucket_size` items
+    pub fn build_bucketed(items: impl IntoIterator<Item = T>, space: Cuboid<S>, bucket_size: usize) -> Self {
+        Octree::build_with(items, space, Octants, bucket_size)
+    }
+
+    /// `build_bucketed` under another subdivision; the parallel build splits the root by it as well
+    pub fn build_with(
+        items: impl IntoIterator<Item = T>,
+        space: Cuboid<S>,
+        subdivision: impl Subdivision<S> + 'static,
+        bucket_size: usize,
+    ) -> Self {
+        let mut tree = Octree::with_subdivision_bucketed(space, subdivision, bucket_size);
+        tree.incremental = false;
+        #[cfg(feature = "parallel")]
+        {
+            let items: Vec<T> = items.into_iter().collect();
+            tree.len = items.len();
+            tree.inserted = items.len();
+            tree.insert_parallel(items);
+        }
+        #[cfg(not(feature = "parallel"))]
+        for item in items {
+            tree.insert(item);
+        }
+        tree.set_incremental(true);
+        tree
+    }
+
+    /// `build_bucketed` with the items put in order of their morton keys first, ties broken by their
+    /// coordinates, and inserted one by one on one thread. any permutation of the same items then gives the same
+    /// tree down to the order of the nodes and of the items in every leaf, and so the same forces to the bit.
+    /// items at the same point keep the order they came in
+    pub fn build_canonical(items: impl IntoIterator<Item = T>, space: Cuboid<S>, bucket_size: usize) -> Self {
+        let mut items: Vec<(u64, T)> =
+            items.into_iter().map(|item| (crate::linear::morton_key(&space, &item.position()), item)).collect();
+        items.sort_by(|(a, first), (b, second)| {
+            let (first, second) = (first.position(), second.position());
+            a.cmp(b)
+                .then(first.x.as_f64().total_cmp(&second.x.as_f64()))
+                .then(first.y.as_f64().total_cmp(&second.y.as_f64()))
+                .then(first.z.as_f64().total_cmp(&second.z.as_f64()))
+        });
+        let mut tree = Octree::with_bucket_size(space, bucket_size);
+        tree.incremental = false;
+        for (_, item) in items {
+            tree.insert(item);
+        }
+        tree.set_incremental(true);
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bucket_size(&self) -> usize {
+        self.bucket_size
+    }
+
+    /// the depth at which leaves stop splitting: `MAX_DEPTH` for octants, and as many levels as take a box down
+    /// to the same size for a scheme splitting fewer ways, e.g. three times as many for halves
+    pub fn max_depth(&self) -> usize {
+        MAX_DEPTH * 3 / self.subdivision.arity().max(2).ilog2() as usize
+    }
+
+    pub fn bounds(&self) -> &Cuboid<S> {
+        &self.nodes[0].bounding_box
+    }
+
+    pub fn root(&self) -> &OctreeNode<T, A> {
+        &self.nodes[0]
+    }
+
+    /// every node, the root first; a node's children always come after it
+    pub fn nodes(&self) -> &[OctreeNode<T, A>] {
+        &self.nodes
+    }
+
+    /// recomputes the aggregate of every node from scratch, bottom up. inserts and removals already keep them
+    /// current, so this only sheds the rounding that many incremental updates pile up
+    pub fn refresh_aggregates(&mut self) {
+        // walking the arena backwards meets every child before its parent
+        for index in (0..self.nodes.len()).rev() {
+            self.gather(index);
+        }
+    }
+
+    // sets a node's aggregate from its items and its children's aggregates
+    fn gather(&mut self, index: usize) {
+        let node = &self.nodes[index];
+        let children = node.children().map(|(_, child)| &self.nodes[child].aggregate);
+        self.nodes[index].aggregate = A::gather(&node.items, children, &node.bounding_box);
+    }
+
+    /// whether inserts keep the aggregates current
+    pub fn is_incremental(&self) -> bool {
+        self.incremental
+    }
+
+    /// turns the update of the aggregates along each insert's path on or off. many inserts into a tree in one
+    /// go are cheaper with it off, leaving the aggregates stale, and then one recompute from scratch when it is
+    /// turned back on, which is what `build` does. removals and `relocate` keep the aggregates current either
+    /// way, but a walk or `validate` in between sees the stale ones
+    pub fn set_incremental(&mut self, incremental: bool) {
+        if incremental && !self.incremental {
+            self.refresh_aggregates();
+        }
+        self.incremental = incremental;
+    }
+
+    /// adds an item, keeping the aggregates current unless `set_incremental` turned that off
+    pub fn insert(&mut self, item: T) {
+        self.insert_at(0, item, id(self.inserted), 0);
+        self.len += 1;
+        self.inserted += 1;
+    }
+
+    // `depth` is counted from the root and `id` is the item's place in insertion order
+    fn insert_at(&mut self, index: usize, item: T, id: u32, depth: usize) {
+        let bucket_size = self.bucket_size;
+        let max_depth = self.max_depth();
+        let node = &mut self.nodes[index];
+        // every node on the way down gains the item, and a pushed-down item is only new to the child
+        if self.incremental {
+            node.aggregate.add(&item, &node.bounding_box);
+        }
+        if node.is_leaf() && (node.items.len() < bucket_size || depth >= max_depth) {
+            node.items.push(item);
+            node.ids.push(id);
+            return;
+        }
+        // a full leaf becomes internal, so its items move down too
+        let ids = std::mem::take(&mut node.ids);
+        for (existing, existing_id) in std::mem::take(&mut node.items).into_iter().zip(ids) {
+            self.insert_into_child(index, existing, existing_id, depth);
+        }
+        self.insert_into_child(index, item, id, depth);
+    }
+
+    fn insert_into_child(&mut self, index: usize, item: T, id: u32, depth: usize) {
+        let space = self.nodes[index].bounding_box;
+        let slot = slot_of(self.subdivision.as_ref(), &space, &item.position());
+        let child = match self.nodes[index].children[slot] {
+            Some(child) => child.get() as usize,
+            None => {
+                // if the child does not exist, create it at the end of the arena
+                let child = self.nodes.len();
+                self.nodes.push(OctreeNode::empty(self.subdivision.child_box(&space, slot)));
+                self.nodes[index].children[slot] = Some(link(child));
+                child
+            }
+        };
+        self.insert_at(child, item, id, depth + 1);
+    }
+
+    // fills an empty tree by splitting the root once and building each child as a tree of its own on its own
+    // thread, then appending their arenas. this is exactly what inserting one by one would give up to the
+    // order of the nodes, since an empty root given more than a bucket always ends up internal and every
+    // child only ever sees its own items, in their original order
+    #[cfg(feature = "parallel")]
+    fn insert_parallel(&mut self, items: Vec<T>) {
+        use rayon::prelude::*;
+        if items.len() <= self.bucket_size {
+            for (i, item) in items.into_iter().enumerate() {
+                self.insert_at(0, item, id(i), 0);
+            }
+            return;
+        }
+        let space = *self.bounds();
+        let mut groups: Vec<Vec<(u32, T)>> = (0..self.subdivision.arity()).map(|_| Vec::new()).collect();
+        for (i, item) in items.into_iter().enumerate() {
+            groups[slot_of(self.subdivision.as_ref(), &space, &item.position())].push((id(i), item));
+        }
+        let subdivision = &self.subdivision;
+        let bucket_size = self.bucket_size;
+        let subtrees: Vec<(usize, Vec<OctreeNode<T, A>>)> = groups
+            .into_par_iter()
+            .enumerate()
+            .filter(|(_, group)| !group.is_empty())
+            .map(|(slot, group)| {
+                let space = subdivision.child_box(&space, slot);
+                let mut subtree = Octree::<T, A>::empty_with(space, Arc::clone(subdivision), bucket_size);
+                subtree.incremental = self.incremental;
+                for (id, item) in group {
+                    subtree.insert_at(0, item, id, 1);
+                }
+                (slot, subtree.nodes)
+            })
+            .collect();
+        for (slot, nodes) in subtrees {
+            let offset = self.nodes.len();
+            self.nodes[0].children[slot] = Some(link(offset));
+            self.nodes.extend(nodes.into_iter().map(|mut node| {
+                for child in node.children.iter_mut().flatten() {
+                    *child = link(child.get() as usize + offset);
+                }
+                node
+            }));
+        }
+        self.gather(0);
+    }
+
+    /// takes out the first item `matches` accepts from the leaf `position` falls in, and brings the aggregates
+    /// above it up to date. the leaf stays, even if left empty, and the removed item's id is not handed out
+    /// again, so `relocate` no longer applies to the tree
+    pub fn remove(&mut self, position: &Point<S>, mut matches: impl FnMut(&T) -> bool) -> Option<T> {
+        let mut path = vec![0];
+        let mut index = 0;
+        while !self.nodes[index].is_leaf() {
+            let slot = slot_of(self.subdivision.as_ref(), &self.nodes[index].bounding_box, position);
+            index = self.nodes[index].children[slot]?.get() as usize;
+            path.push(index);
+        }
+        let node = &mut self.nodes[index];
+        let k = node.items.iter().position(&mut matches)?;
+        let item = node.items.swap_remove(k);
+        node.ids.swap_remove(k);
+        self.len -= 1;
+        for &index in path.iter().rev() {
+            self.gather(index);
+        }
+        Some(item)
+    }
+
+    /// brings the tree up to date after its items have moved, without building it again. `items` are the
+    /// same items in the order they went in: the order given to `build`, then later inserts. an item still
+    /// inside its leaf's box is updated where it is; the rest are taken out and inserted again from the root,
+    /// after subtrees left with at most a bucket of items are folded back into leaves. only the aggregates of
+    /// the nodes an item changed in, left, was folded into or joined, and of the nodes above them, are gathered
+    /// again, as `remove` does; the rest stay as they were. returns how many items changed leaves, or none with
+    /// the tree untouched if that is more than `limit`, `items` is not as long as the tree, or anything has been
+    /// removed from it
+    pub fn relocate(&mut self, items: &[T], limit: usize) -> Option<usize>
+    where
+        T: Clone + PartialEq,
+    {
+        if items.len() != self.len || self.inserted != self.len {
+            return None;
+        }
+        let left = |node: &OctreeNode<T, A>, id: u32| !node.bounding_box.contains(&items[id as usize].position());
+        let moved: usize = self.nodes.iter().map(|node| node.ids.iter().filter(|&&id| left(node, id)).count()).sum();
+        if moved > limit {
+            return None;
+        }
+        let mut movers = Vec::with_capacity(moved);
+        // the nodes whose aggregates have gone stale
+        let mut dirty = vec![false; self.nodes.len()];
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            let mut k = 0;
+            while k < node.ids.len() {
+                let id = node.ids[k];
+                if left(node, id) {
+                    node.items.swap_remove(k);
+                    node.ids.swap_remove(k);
+                    movers.push((id, items[id as usize].clone()));
+                    dirty[index] = true;
+                } else {
+                    if node.items[k] != items[id as usize] {
+                        node.items[k] = items[id as usize].clone();
+                        dirty[index] = true;
+                    }
+                    k += 1;
+                }
+            }
+        }
+        if moved > 0 {
+            self.collapse(0, &mut dirty);
+            dirty = self.compact().into_iter().map(|old| dirty[old]).collect();
+            for (id, item) in movers {
+                let position = item.position();
+                self.insert_at(0, item, id, 0);
+                // the leaf it joined and the nodes the insert made, whatever `incremental` says
+                dirty.resize(self.nodes.len(), true);
+                let mut index = 0;
+                while !self.nodes[index].is_leaf() {
+                    let slot = slot_of(self.subdivision.as_ref(), &self.nodes[index].bounding_box, &position);
+                    index = self.nodes[index].children[slot].expect("the item was just filed there").get() as usize;
+                }
+                dirty[index] = true;
+            }
+        }
+        // then every node above a stale one, children coming after their parents, is gathered again
+        for index in (0..self.nodes.len()).rev() {
+            if dirty[index] || self.nodes[index].children().any(|(_, child)| dirty[child]) {
+                dirty[index] = true;
+                self.gather(index);
+            }
+        }
+        Some(moved)
+    }
+
+    /// puts moved `items` back where they were in the tree, the same items in the order `relocate` takes, and
+    /// recomputes the aggregates, without moving any between leaves. items that left their leaf's box stay in
+    /// it, so the boxes go stale while the masses and centers stay exact. returns whether it could, which
+    /// needs the same conditions as `relocate`
+    pub fn refit(&mut self, items: &[T]) -> bool
+    where
+        T: Clone,
+    {
+        if items.len() != self.len || self.inserted != self.len {
+            return false;
+        }
+        for node in &mut self.nodes {
+            for (item, &id) in node.items.iter_mut().zip(&node.ids) {
+                *item = items[id as usize].clone();
+            }
+        }
+        self.refresh_aggregates();
+        true
+    }
+
+    // unlinks empty children and folds every subtree holding at most a bucket of items into a single leaf,
+    // marking the nodes it changes in `dirty`, and returns how many items are beneath `index`. the nodes cut
+    // loose stay in the arena until `compact`
+    fn collapse(&mut self, index: usize, dirty: &mut [bool]) -> usize {
+        if self.nodes[index].is_leaf() {
+            return self.nodes[index].items.len();
+        }
+        let mut count = 0;
+        for slot in 0..8 {
+            let Some(child) = self.nodes[index].children[slot] else {
+                continue;
+            };
+            let beneath = self.collapse(child.get() as usize, dirty);
+            if beneath == 0 {
+                self.nodes[index].children[slot] = None;
+                dirty[index] = true;
+            }
+            count += beneath;
+        }
+        if count <= self.bucket_size {
+            // the children hold no more than this node will, so they have all been folded into leaves already
+            let children: Vec<usize> = self.nodes[index].children().map(|(_, child)| child).collect();
+            for child in children {
+                let items = std::mem::take(&mut self.nodes[child].items);
+                let ids = std::mem::take(&mut self.nodes[child].ids);
+                self.nodes[index].items.extend(items);
+                self.nodes[index].ids.extend(ids);
+            }
+            self.nodes[index].children = [None; 8];
+            dirty[index] = true;
+        }
+        count
+    }
+
+    // drops the nodes no longer linked from the root, renumbering the rest breadth first so every node still
+    // comes after its parent, and returns the old index of each node in the new order
+    fn compact(&mut self) -> Vec<usize> {
+        let mut old: Vec<Option<OctreeNode<T, A>>> = std::mem::take(&mut self.nodes).into_iter().map(Some).collect();
+        let mut order = vec![0];
+        let mut renumbered = vec![0; old.len()];
+        let mut i = 0;
+        while i < order.len() {
+            renumbered[order[i]] = i;
+            let node = old[order[i]].as_ref().unwrap();
+            order.extend(node.children().map(|(_, child)| child));
+            i += 1;
+        }
+        self.nodes = order
+            .iter()
+            .map(|&index| {
+                let mut node = old[index].take().unwrap();
+                for child in node.children.iter_mut().flatten() {
+                    *child = link(renumbered[child.get() as usize]);
+                }
+                node
+            })
+            .collect();
+        order
+    }
+
+    /// checks the structural invariants of the tree, and that every node's cached aggregate matches the one
+    /// recomputed from its items and children to within the square root of the scalar's epsilon, well above
+    /// the rounding of incremental updates. see `validate_within` for another tolerance
+    pub fn validate(&self) -> Result<(), TreeError> {
+        self.validate_within(S::epsilon().as_f64().sqrt())
+    }
+
+    /// `validate` with aggregates allowed to differ from the recomputed ones by a relative `tolerance`
+    pub fn validate_within(&self, tolerance: f64) -> Result<(), TreeError> {
+        let scheme = self.subdivision.as_ref();
+        let mut result = Ok(());
+        self.visit(&mut |node, depth, _| {
+            if result.is_err() {
+                return;
+            }
+            if !node.items.is_empty() && !node.is_leaf() {
+                result = Err(TreeError::BodyInInternalNode { depth });
+                return;
+            }
+            if node.items.len() > self.bucket_size && depth < self.max_depth() {
+                result = Err(TreeError::OverfullLeaf { depth });
+                return;
+            }
+            if node.items.iter().any(|item| !node.bounding_box.contains(&item.position())) {
+                result = Err(TreeError::BodyOutsideBox { depth });
+                return;
+            }
+            for (octant, child) in node.children() {
+                if octant >= scheme.arity()
+                    || self.nodes[child].bounding_box != scheme.child_box(&node.bounding_box, octant)
+                {
+                    result = Err(TreeError::ChildBoxMismatch { depth, octant });
+                    return;
+                }
+            }
+            let children = node.children().map(|(_, child)| &self.nodes[child].aggregate);
+            let recomputed = A::gather(&node.items, children, &node.bounding_box);
+            if !node.aggregate.agrees_with(&recomputed, &node.bounding_box, tolerance) {
+                result = Err(TreeError::AggregateMismatch { depth });
+            }
+        });
+        result
+    }
+
+    /// the k items closest to `target`, nearest first; fewer if the tree holds fewer than k
+    pub fn k_nearest(&self, target: &Point<S>, k: usize) -> Vec<&T> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.collect_nearest(0, target, k, &mut heap);
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|neighbor| neighbor.item)
+            .collect()
+    }
+
+    fn collect_nearest<'a>(
+        &'a self,
+        index: usize,
+        target: &Point<S>,
+        k: usize,
+        heap: &mut BinaryHeap<Neighbor<'a, T>>,
+    ) {
+        let node = &self.nodes[index];
+        // the heap holds the k best so far with the worst on top, so whole boxes farther than it can be skipped
+        if heap.len() == k
+            && node.bounding_box.distance_squared_to(target) > heap.peek().unwrap().distance_squared
+        {
+            return;
+        }
+        for item in &node.items {
+            let distance_squared = item.position().distance_squared(target);
+            if heap.len() < k {
+                heap.push(Neighbor { distance_squared, item });
+            } else if distance_squared < heap.peek().unwrap().distance_squared {
+                heap.pop();
+                heap.push(Neighbor { distance_squared, item });
+            }
+        }
+        // nearer octants first so the heap tightens early
+        let mut children: Vec<usize> = node.children().map(|(_, child)| child).collect();
+        children.sort_by(|&a, &b| {
+            self.nodes[a]
+                .bounding_box
+                .distance_squared_to(target)
+                .total_cmp(&self.nodes[b].bounding_box.distance_squared_to(target))
+        });
+        for child in children {
+            self.collect_nearest(child, target, k, heap);
+        }
+    }
+
+    /// every item within `radius` of `center`
+    pub fn within_radius(&self, center: &Point<S>, radius: S) -> Vec<&T> {
+        let mut found = vec![];
+        self.collect_within(0, center, radius * radius, &mut found);
+        found
+    }
+
+    fn collect_within<'a>(&'a self, index: usize, center: &Point<S>, radius_squared: S, found: &mut Vec<&'a T>) {
+        let node = &self.nodes[index];
+        if node.bounding_box.distance_squared_to(center) > radius_squared {
+            return;
+        }
+        for item in &node.items {
+            if item.position().distance_squared(center) <= radius_squared {
+                found.push(item);
+            }
+        }
+        for (_, child) in node.children() {
+            self.collect_within(child, center, radius_squared, found);
+        }
+    }
+
+    /// every item inside `region`, faces included
+    pub fn within_box(&self, region: &Cuboid<S>) -> Vec<&T> {
+        let mut found = vec![];
+        self.collect_in_box(0, region, &mut found);
+        found
+    }
+
+    fn collect_in_box<'a>(&'a self, index: usize, region: &Cuboid<S>, found: &mut Vec<&'a T>) {
+        let node = &self.nodes[index];
+        if !node.bounding_box.intersects(region) {
+            return;
+        }
+        found.extend(node.items.iter().filter(|item| region.contains(&item.position())));
+        for (_, child) in node.children() {
+            self.collect_in_box(child, region, found);
+        }
+    }
+
+    /// within_radius for each center, in order; queries run in parallel with the `parallel` feature
+    pub fn within_radius_batch(&self, centers: &[Point<S>], radius: S) -> Vec<Vec<&T>>
+    where
+        T: Sync,
+        A: Sync,
+    {
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            centers
+                .par_iter()
+                .map(|center| self.within_radius(center, radius))
+                .collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            centers
+                .iter()
+                .map(|center| self.within_radius(center, radius))
+                .collect()
+        }
+    }
+
+    /// depth-first walk from the root, calling `f` with each node, its depth and its octant index in the parent
+    pub fn visit<'a, F: FnMut(&'a OctreeNode<T, A>, usize, Option<usize>)>(&'a self, f: &mut F) {
+        self.visit_from(0, f, 0, None);
+    }
+
+    fn visit_from<'a, F: FnMut(&'a OctreeNode<T, A>, usize, Option<usize>)>(
+        &'a self,
+        index: usize,
+        f: &mut F,
+        depth: usize,
+        octant: Option<usize>,
+    ) {
+        let node = &self.nodes[index];
+        f(node, depth, octant);
+        for (slot, child) in node.children() {
+            self.visit_from(child, f, depth + 1, Some(slot));
+        }
+    }
+
+    /// the items in depth-first order
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut stack = vec![0];
+        std::iter::from_fn(move || {
+            let node = &self.nodes[stack.pop()?];
+            // pushed last slot first, so they come off in slot order as `visit` takes them
+            stack.extend(node.children().rev().map(|(_, child)| child));
+            Some(&node.items)
+        })
+        .flatten()
+    }
+
+    /// the items held, counted by walking the tree instead of read off `len`
+    pub fn item_count(&self) -> usize {
+        self.count_beneath(self.root())
+    }
+
+    // items beneath `node`
+    fn count_beneath(&self, node: &OctreeNode<T, A>) -> usize {
+        node.items.len() + node.children().map(|(_, child)| self.count_beneath(&self.nodes[child])).sum::<usize>()
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        let mut leaves = vec![];
+        self.visit(&mut |node, depth, _| {
+            if node.is_leaf() {
+                leaves.push((depth, node.items.len()));
+            }
+        });
+        let items: usize = self.nodes.iter().map(|node| node.items.capacity()).sum();
+        let ids: usize = self.nodes.iter().map(|node| node.ids.capacity()).sum();
+        let memory = self.nodes.capacity() * std::mem::size_of::<OctreeNode<T, A>>()
+            + items * std::mem::size_of::<T>()
+            + ids * std::mem::size_of::<u32>();
+        TreeStats::from_leaves(self.nodes.len(), leaves, memory)
+    }
+
+    /// depth and box of the deepest leaf; the first one found wins ties
+    pub fn deepest_leaf(&self) -> (usize, &Cuboid<S>) {
+        let mut deepest = (0, self.bounds());
+        self.visit(&mut |node, depth, _| {
+            if node.is_leaf() && depth > deepest.0 {
+                deepest = (depth, &node.bounding_box);
+            }
+        });
+        deepest
+    }
+
+    /// among the nodes at `depth`, the one with the most items beneath it, as (item count, box)
+    pub fn densest_region(&self, depth: usize) -> Option<(usize, &Cuboid<S>)> {
+        let mut densest: Option<(usize, &Cuboid<S>)> = None;
+        self.visit(&mut |node, node_depth, _| {
+            if node_depth != depth {
+                return;
+            }
+            let count = self.count_beneath(node);
+            if densest.is_none_or(|(best, _)| count > best) {
+                densest = Some((count, &node.bounding_box));
+            }
+        });
+        densest
+    }
+
+    /// the 12 edges of every node's bounding box as line segments
+    pub fn to_wireframe(&self) -> Vec<(Point<S>, Point<S>)> {
+        let mut segments = vec![];
+        for node in &self.nodes {
+            let b = &node.bounding_box;
+            for i in 0..8 {
+                for axis in [1, 2, 4] {
+                    if i & axis == 0 {
+                        segments.push((corner(b, i), corner(b, i | axis)));
+                    }
+                }
+            }
+        }
+        segments
+    }
+
+    /// writes the bounding boxes of the nodes down to `max_depth`, to look at the subdivision next to the bodies
+    /// in blender or paraview. a path ending in `.json` gets a list of `{"depth", "bodies", "min", "max"}`
+    /// cells, with the bodies beneath each; anything else gets obj line segments, grouped as `depth_<d>` per
+    /// level so the levels can be shown one at a time
+    pub fn export_wireframe(&self, path: impl AsRef<Path>, max_depth: usize) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut cells = vec![];
+        self.visit(&mut |node, depth, _| {
+            if depth <= max_depth {
+                cells.push((depth, node));
+            }
+        });
+        cells.sort_by_key(|&(depth, _)| depth);
+        let mut out = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|extension| extension == "json") {
+            let cells: Vec<WireframeCell> = cells
+                .iter()
+                .map(|&(depth, node)| {
+                    let b = &node.bounding_box;
+                    WireframeCell {
+                        depth,
+                        bodies: self.count_beneath(node),
+                        min: [b.x.start.as_f64(), b.y.start.as_f64(), b.z.start.as_f64()],
+                        max: [b.x.end.as_f64(), b.y.end.as_f64(), b.z.end.as_f64()],
+                    }
+                })
+                .collect();
+            serde_json::to_writer(&mut out, &cells).map_err(io::Error::other)?;
+        } else {
+            writeln!(out, "# barneshutt3d octree, {} nodes down to depth {}", cells.len(), max_depth)?;
+            let mut group = None;
+            for (k, &(depth, node)) in cells.iter().enumerate() {
+                if group != Some(depth) {
+                    writeln!(out, "g depth_{}", depth)?;
+                    group = Some(depth);
+                }
+                for i in 0..8 {
+                    let p = corner(&node.bounding_box, i);
+                    writeln!(out, "v {} {} {}", p.x, p.y, p.z)?;
+                }
+                // obj counts vertices from 1, over the whole file
+                let first = 8 * k + 1;
+                for i in 0..8 {
+                    for axis in [1, 2, 4] {
+                        if i & axis == 0 {
+                            writeln!(out, "l {} {}", first + i, first + (i | axis))?;
+                        }
+                    }
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+impl<S: Scalar> BodyTree<S> {
+    /// the expansion accepted nodes are evaluated with
+    pub fn multipole(&self) -> MultipoleOrder {
+        if self.nodes[0].aggregate.quadrupole_current {
+            MultipoleOrder::Quadrupole
+        } else {
+            MultipoleOrder::Monopole
+        }
+    }
+
+    /// `refresh_aggregates` for the mass moments, which also drops quadrupoles
+    pub fn compute_mass_distribution(&mut self) {
+        self.refresh_aggregates();
+    }
+
+    /// fills in every node's quadrupole from the current moments, bottom up, and switches force and
+    /// potential evaluation to the quadrupole expansion. any later insert or removal goes back to monopoles
+    pub fn compute_quadrupoles(&mut self) {
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let center = node.aggregate.center_of_mass;
+            let mut quadrupole = [S::zero(); 6];
+            for body in &node.items {
+                add_shifted(&mut quadrupole, &[S::zero(); 6], body.mass, &(body.location - center));
+            }
+            for (_, child) in node.children() {
+                let child = &self.nodes[child].aggregate;
+                add_shifted(&mut quadrupole, &child.quadrupole, child.mass, &(child.center_of_mass - center));
+            }
+            let moments = &mut self.nodes[index].aggregate;
+            moments.quadrupole = quadrupole;
+            moments.quadrupole_current = true;
+        }
+    }
+
+    /// works out every node's `radius`, bottom up, for the bounding-sphere opening test. a leaf measures its
+    /// bodies, and a parent takes the nearer of its children's reach and its box's farthest corner. any later
+    /// insert or removal forgets the radii above it
+    pub fn compute_radii(&mut self) {
+        let radii = dual::radii(self);
+        for (node, radius) in self.nodes.iter_mut().zip(radii) {
+            node.aggregate.radius = radius;
+        }
+    }
+
+    /// gravitational acceleration at `target` from every body in the tree, in units where G = 1. a node
+    /// whose size s and distance d to its center of mass satisfy s / d < theta stands in for all of its
+    /// bodies; anything closer is opened. `softening` is the plummer length ε, replacing 1 / r² with
+    /// r / (r² + ε²)^(3/2) so close pairs stay finite; 0 is plain newtonian gravity. a body sitting exactly
+    /// at `target` is skipped, so this can be asked for a body's own position
+    pub fn acceleration_at(&self, target: &Point<S>, theta: S, softening: S) -> Point<S> {
+        self.acceleration_with(&Gravity, target, Acceptance::theta(theta), softening, None)
+    }
+
+    /// the force on `body` with gravitational constant `g`, walked as `acceleration_at` walks, along with which
+    /// nodes stood in for their bodies and how many bodies were summed directly. for seeing what the opening
+    /// angle does, not for speed: the walk takes the plain recursion and records every accepted node
+    pub fn force_on_traced(&self, body: &Body<S>, theta: S, softening: S, g: S) -> (Point<S>, TraceInfo) {
+        let mut trace = TraceInfo::default();
+        let mut near = NearField::new(&Gravity, body.location, softening);
+        let far = self.traced_from(0, &body.location, &Acceptance::theta(theta), softening, &mut near, &mut trace);
+        ((far + near.finish()) * (g * body.mass), trace)
+    }
+
+    // `acceleration_from`, noting what each node did on `trace`
+    fn traced_from(
+        &self,
+        index: usize,
+        target: &Point<S>,
+        acceptance: &Acceptance<S>,
+        softening: S,
+        near: &mut NearField<S, Gravity>,
+        trace: &mut TraceInfo,
+    ) -> Point<S> {
+        let node = &self.nodes[index];
+        if node.absolute_mass().is_zero() {
+            return Point::default();
+        }
+        if node.is_leaf() {
+            for body in &node.items {
+                near.push(&body.location, body.mass);
+                trace.summed += usize::from(body.location != *target);
+            }
+            return Point::default();
+        }
+        let (center, size) = (node.center_of_mass(), node.bounding_box.size());
+        if acceptance.accepts(size, node.radius(), node.absolute_mass(), center.distance_squared(target)) {
+            trace.approximated += 1;
+            trace.cells.push(index);
+            return Gravity.node_acceleration(target, center, node.mass(), self.quadrupole_of(node), softening);
+        }
+        let mut acceleration = Point::default();
+        for (_, child) in node.children() {
+            acceleration += self.traced_from(child, target, acceptance, softening, near, trace);
+        }
+        acceleration
+    }
+
+    /// `acceleration_at` in a periodic domain the size of the root box, under the minimum-image convention:
+    /// every body and node pulls from its copy nearest `target`, and the opening test measures the distance to
+    /// that copy. a node is also opened if its copy reaches past half a box from `target`, where its bodies'
+    /// nearest images would part ways. there is no ewald sum, so farther images are left out
+    pub fn periodic_acceleration_at(&self, target: &Point<S>, theta: S, softening: S) -> Point<S> {
+        let period = Period::all(*self.bounds());
+        self.acceleration_with(&Gravity, target, Acceptance::theta(theta), softening, Some(&period))
+    }
+
+    /// `acceleration_at` walking the tree with `stack` in place of the call stack, for evaluating many targets
+    /// on one tree without a call per node visited. the sums are taken in the same order, so the result is the
+    /// same to the bit
+    pub fn acceleration_at_with(
+        &self,
+        target: &Point<S>,
+        theta: S,
+        softening: S,
+        stack: &mut WalkStack<S>,
+    ) -> Point<S> {
+        self.acceleration_with_stack(&Gravity, target, Acceptance::theta(theta), softening, None, stack)
+    }
+
+    // `acceleration_at` under any `model`; `period` is the periodic box, if any
+    pub(crate) fn acceleration_with<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+    ) -> Point<S> {
+        let mut near = NearField::new(model, *target, softening);
+        let far = self.acceleration_from(0, target, &acceptance, softening, period, &mut near);
+        far + near.finish()
+    }
+
+    // `acceleration_with` on an explicit stack. each frame is an opened node, and a child's pull is added to
+    // its parent's sum once the child is done, just as the recursion adds up its calls
+    pub(crate) fn acceleration_with_stack<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+        stack: &mut WalkStack<S>,
+    ) -> Point<S> {
+        let mut near = NearField::new(model, *target, softening);
+        let (frames, interactions) = (&mut stack.frames, &mut stack.interactions);
+        frames.clear();
+        let mut far = Point::default();
+        match self.node_pull(0, target, &acceptance, softening, period, &mut near) {
+            Some(pull) => {
+                self.tally(0, interactions);
+                far = pull;
+            }
+            None => frames.push((0, 0, Point::default())),
+        }
+        while let Some(top) = frames.last_mut() {
+            let children = &self.nodes[top.0].children;
+            match (top.1..8).find(|&slot| children[slot].is_some()) {
+                Some(slot) => {
+                    top.1 = slot + 1;
+                    let child = children[slot].map_or(0, |child| child.get() as usize);
+                    match self.node_pull(child, target, &acceptance, softening, period, &mut near) {
+                        Some(pull) => {
+                            self.tally(child, interactions);
+                            top.2 += pull;
+                        }
+                        None => frames.push((child, 0, Point::default())),
+                    }
+                }
+                None => {
+                    let (_, _, pull) = frames.pop().expect("the frame just looked at");
+                    match frames.last_mut() {
+                        Some(parent) => parent.2 += pull,
+                        None => far = pull,
+                    }
+                }
+            }
+        }
+        far + near.finish()
+    }
+
+    // counts the terms of node `index` once `node_pull` has taken it whole: its bodies when it is a leaf, the
+    // node itself when it was accepted, nothing when it has no mass
+    fn tally(&self, index: usize, interactions: &mut Interactions) {
+        let node = &self.nodes[index];
+        if node.absolute_mass().is_zero() {
+            return;
+        }
+        if node.is_leaf() {
+            interactions.body_body += node.items.len() as u64;
+        } else {
+            interactions.body_cell += 1;
+        }
+    }
+
+    // the accepted nodes' pull, queueing the bodies of opened leaves on `near`
+    fn acceleration_from<F: ForceModel>(
+        &self,
+        index: usize,
+        target: &Point<S>,
+        acceptance: &Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+        near: &mut NearField<S, F>,
+    ) -> Point<S> {
+        if let Some(pull) = self.node_pull(index, target, acceptance, softening, period, near) {
+            return pull;
+        }
+        let mut acceleration = Point::default();
+        for (_, child) in self.nodes[index].children() {
+            acceleration += self.acceleration_from(child, target, acceptance, softening, period, near);
+        }
+        acceleration
+    }
+
+    // the pull of node `index` when it need not be opened, none when its children have to be walked. a leaf's
+    // bodies are queued on `near` and count for nothing here
+    fn node_pull<F: ForceModel>(
+        &self,
+        index: usize,
+        target: &Point<S>,
+        acceptance: &Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+        near: &mut NearField<S, F>,
+    ) -> Option<Point<S>> {
+        let node = &self.nodes[index];
+        // a node whose masses cancel is still walked, as its bodies pull apart from each other
+        if node.absolute_mass().is_zero() {
+            return Some(Point::default());
+        }
+        // leaves go to the direct sum, which also keeps a body out of its own force
+        if node.is_leaf() {
+            for body in &node.items {
+                near.push(&image_of(period, &body.location, target), body.mass);
+            }
+            return Some(Point::default());
+        }
+        let center = image_of(period, node.center_of_mass(), target);
+        let distance_squared = center.distance_squared(target);
+        if acceptance.accepts(node.bounding_box.size(), node.radius(), node.absolute_mass(), distance_squared)
+            && within_half_period(period, &node.bounding_box, target)
+        {
+            let model = near.model();
+            return Some(model.node_acceleration(target, &center, node.mass(), self.quadrupole_of(node), softening));
+        }
+        None
+    }
+
+    /// gravitational potential at `target` from every body in the tree, approximated and softened the same
+    /// way as `acceleration_at`. a body exactly at `target` is skipped
+    pub fn potential_at(&self, target: &Point<S>, theta: S, softening: S) -> S {
+        self.potential_with(&Gravity, target, Acceptance::theta(theta), softening, None)
+    }
+
+    /// `potential_at` under the minimum-image convention, like `periodic_acceleration_at`
+    pub fn periodic_potential_at(&self, target: &Point<S>, theta: S, softening: S) -> S {
+        let period = Period::all(*self.bounds());
+        self.potential_with(&Gravity, target, Acceptance::theta(theta), softening, Some(&period))
+    }
+
+    // `potential_at` under any `model`; `period` is the periodic box, if any
+    pub(crate) fn potential_with<F: ForceModel>(
+        &self,
+        model: &F,
+        target: &Point<S>,
+        acceptance: Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+    ) -> S {
+        self.potential_from(model, 0, target, &acceptance, softening, period)
+    }
+
+    fn potential_from<F: ForceModel>(
+        &self,
+        model: &F,
+        index: usize,
+        target: &Point<S>,
+        acceptance: &Acceptance<S>,
+        softening: S,
+        period: Option<&Period<S>>,
+    ) -> S {
+        let node = &self.nodes[index];
+        if node.absolute_mass().is_zero() {
+            return S::zero();
+        }
+        if node.is_leaf() {
+            return node
+                .items
+                .iter()
+                .map(|body| {
+                    let source = image_of(period, &body.location, target);
+                    model.pair_potential(target, &source, body.mass, softening)
+                })
+                .sum();
+        }
+        let center = image_of(period, node.center_of_mass(), target);
+        let distance_squared = center.distance_squared(target);
+        if acceptance.accepts(node.bounding_box.size(), node.radius(), node.absolute_mass(), distance_squared)
+            && within_half_period(period, &node.bounding_box, target)
+        {
+            return model.node_potential(target, &center, node.mass(), self.quadrupole_of(node), softening);
+        }
+        node.children()
+            .map(|(_, child)| self.potential_from(model, child, target, acceptance, softening, period))
+            .sum()
+    }
+
+    // what the node terms get for `node`'s quadrupole
+    fn quadrupole_of<'a>(&self, node: &'a OctreeNode<Body<S>, MassMoments<S>>) -> Option<&'a [S; 6]> {
+        (self.multipole() == MultipoleOrder::Quadrupole).then_some(&node.aggregate.quadrupole)
+    }
+
+    /// like insert, but refuses bodies that would corrupt the tree; nan coordinates would otherwise all land in octant 0
+    pub fn try_insert(&mut self, body: Body<S>) -> Result<(), InsertError> {
+        if !body.is_finite() {
+            return Err(InsertError::NonFinite);
+        }
+        self.insert(body);
+        Ok(())
+    }
+
+    /// inserts a pre-collapsed cluster, such as the root summary of a resolved subregion's own tree, as a single
+    /// body of its net mass at its center of mass, with `id` to find it by. it joins a leaf and every summary
+    /// above like any body and is never split up again, so beyond its softening it pulls as the cluster's
+    /// monopole; a quadrupole the cluster carries is left behind. refused like `try_insert` if non-finite
+    pub fn insert_cluster(&mut self, cluster: &MassMoments<S>, id: u64) -> Result<(), InsertError> {
+        let location = *cluster.center_of_mass();
+        self.try_insert(Body { id, mass: cluster.mass(), location, ..Body::default() })
+    }
+
+    /// projects a body outside the box back onto its boundary before inserting it, returning whether it had to.
+    /// each clamp is logged as a tracing warning
+    pub fn insert_clamped(&mut self, mut body: Body<S>) -> bool {
+        let bounds = *self.bounds();
+        let clamped = !bounds.contains(&body.location);
+        if clamped {
+            let location = bounds.clamp(&body.location);
+            tracing::warn!(id = body.id, from = ?body.location.as_array(), to = ?location.as_array(), "clamped body");
+            body.location = location;
+        }
+        self.insert(body);
+        clamped
+    }
+
+    /// one line per node, indented two spaces per level
+    pub fn format_tree(&self) -> String {
+        let mut out = String::new();
+        self.visit(&mut |node, depth, octant| {
+            let label = match octant {
+                Some(i) => format!("octant {}", i),
+                None => "root".to_string(),
+            };
+            let b = &node.bounding_box;
+            let body = match node.items.as_slice() {
+                [] => "empty".to_string(),
+                [body] => format!(
+                    "mass {} at ({}, {}, {})",
+                    body.mass, body.location.x, body.location.y, body.location.z
+                ),
+                bodies => format!("{} bodies", bodies.len()),
+            };
+            out.push_str(&format!(
+                "{}{} [{}, {}] x [{}, {}] x [{}, {}] {}\n",
+                "  ".repeat(depth),
+                label,
+                b.x.start, b.x.end, b.y.start, b.y.end, b.z.start, b.z.end,
+                body
+            ));
+        });
+        out
+    }
+
+    pub fn print_tree(&self) {
+        print!("{}", self.format_tree());
+    }
+}
+
+impl<S: Scalar, T: HasPosition<Scalar = S>, A: Aggregate<T>> From<Cuboid<S>> for Octree<T, A> {
+    fn from(value: Cuboid<S>) -> Self {
+        Octree::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ic;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn unit_box() -> Cuboid {
+        Cuboid::from(([0.; 3], [1.; 3]))
+    }
+
+    fn random_tree(n: usize, seed: u64) -> BodyTree {
+        let mut rng = StdRng::seed_from_u64(seed);
+        BodyTree::build(ic::uniform_box(n, &unit_box(), &mut rng), unit_box())
+    }
+
+    #[test]
+    fn radii_reach_every_body_and_go_stale_on_insert() {
+        let mut tree = random_tree(500, 2);
+        assert_eq!(tree.nodes()[0].radius(), f64::INFINITY);
+        tree.compute_radii();
+        let root = &tree.nodes()[0];
+        let reach = tree
+            .iter()
+            .map(|body| body.location.distance_squared(root.center_of_mass()).sqrt())
+            .fold(0., f64::max);
+        assert!(root.radius() >= reach - 1e-12 && root.radius() <= 3f64.sqrt(), "{} vs {}", root.radius(), reach);
+        tree.insert(Body { mass: 1., location: Point { x: 0.5, y: 0.5, z: 0.5 }, ..Body::default() });
+        assert_eq!(tree.nodes()[0].radius(), f64::INFINITY);
+    }
+
+    #[test]
+    fn the_stack_walk_matches_the_recursion_bit_for_bit() {
+        let mut tree = random_tree(3000, 4);
+        let probes = ic::uniform_box(200, &unit_box(), &mut StdRng::seed_from_u64(5));
+        let mut stack = WalkStack::new();
+        for theta in [0., 0.5, 1.] {
+            for probe in &probes {
+                let target = &probe.location;
+                let recursive = tree.acceleration_at(target, theta, 0.01);
+                assert_eq!(tree.acceleration_at_with(target, theta, 0.01, &mut stack), recursive);
+            }
+        }
+        // and with the other opening tests, multipoles and boundary
+        tree.compute_quadrupoles();
+        tree.compute_radii();
+        let acceptance = Acceptance { theta: 0.7, opening: Opening::BoundingSphere };
+        for probe in &probes {
+            let target = &probe.location;
+            let period = Period::all(*tree.bounds());
+            let period = Some(&period);
+            let recursive = tree.acceleration_with(&Gravity, target, acceptance, 0.01, period);
+            let iterative = tree.acceleration_with_stack(&Gravity, target, acceptance, 0.01, period, &mut stack);
+            assert_eq!(iterative, recursive);
+        }
+    }
+
+    // the walk as it was before the opening test was squared, taking a square root at every node
+    fn walk_with_square_roots(tree: &BodyTree, index: usize, target: &Point, theta: f64) -> Point {
+        let node = &tree.nodes()[index];
+        if node.is_leaf() {
+            let pull = |body: &Body| point_mass_acceleration(target, &body.location, body.mass, 0.01);
+            let pulls = node.items().iter().map(pull);
+            return pulls.fold(Point::default(), |total, pull| total + pull);
+        }
+        let distance = node.center_of_mass().distance_squared(target).sqrt();
+        if node.bounding_box.size() < theta * distance {
+            return point_mass_acceleration(target, node.center_of_mass(), node.mass(), 0.01);
+        }
+        let pulls = node.children().map(|(_, child)| walk_with_square_roots(tree, child, target, theta));
+        pulls.fold(Point::default(), |total, pull| total + pull)
+    }
+
+    #[test]
+    fn squaring_the_opening_test_leaves_the_forces_alone() {
+        let tree = random_tree(3000, 7);
+        let probes = ic::uniform_box(300, &unit_box(), &mut StdRng::seed_from_u64(8));
+        for theta in [0.3, 0.5, 0.8] {
+            for probe in &probes {
+                let expected = walk_with_square_roots(&tree, 0, &probe.location, theta);
+                let difference = tree.acceleration_at(&probe.location, theta, 0.01) - expected;
+                assert!(difference.length() <= 1e-12 * expected.length(), "{:?} vs {:?}", difference, expected);
+            }
+        }
+    }
+
+    // the interactions the walk for `target` takes: the nodes it accepts and the bodies of the leaves it opens
+    fn interactions(tree: &BodyTree, index: usize, target: &Point, acceptance: &Acceptance<f64>) -> usize {
+        let node = &tree.nodes()[index];
+        if node.is_leaf() {
+            return node.items().len();
+        }
+        let distance_squared = node.center_of_mass().distance_squared(target);
+        if acceptance.accepts(node.bounding_box.size(), node.radius(), node.absolute_mass(), distance_squared) {
+            return 1;
+        }
+        node.children().map(|(_, child)| interactions(tree, child, target, acceptance)).sum()
+    }
+
+    #[test]
+    fn weighing_in_the_mass_gets_an_error_for_less_work() {
+        // a heavy clump in a light, sparse background
+        let mut rng = StdRng::seed_from_u64(9);
+        let center = Point { x: 0.5, y: 0.5, z: 0.5 };
+        let mut bodies: Vec<Body> = ic::plummer(2000, 1000., 0.05, &mut rng)
+            .into_iter()
+            .map(|body| Body { location: body.location + center, ..body })
+            .filter(|body| unit_box().contains(&body.location))
+            .collect();
+        for body in ic::uniform_box(1000, &unit_box(), &mut rng) {
+            bodies.push(Body { mass: body.mass * 0.01, ..body });
+        }
+        let tree = BodyTree::build(bodies.clone(), unit_box());
+        let targets: Vec<Point> = bodies.iter().step_by(5).map(|body| body.location).collect();
+        let exact: Vec<Point> = targets
+            .iter()
+            .map(|target| {
+                let pull = |body: &Body| point_mass_acceleration(target, &body.location, body.mass, 0.01);
+                bodies.iter().map(pull).fold(Point::default(), |total, pull| total + pull)
+            })
+            .collect();
+        // the rms relative error of the forces and the interactions they took
+        let walk = |acceptance: Acceptance<f64>| {
+            let forces: Vec<Point> = targets
+                .iter()
+                .map(|target| tree.acceleration_with(&Gravity, target, acceptance, 0.01, None))
+                .collect();
+            let work: usize = targets.iter().map(|target| interactions(&tree, 0, target, &acceptance)).sum();
+            (crate::diagnostics::ForceError::between(&forces, &exact).rms, work)
+        };
+        // for the error a theta gets, the loosest tolerance halving down from 1000 that keeps within it takes
+        // fewer interactions, as it opens the heavy clump's nodes rather than the background's
+        for theta in [0.3, 0.7] {
+            let (budget, theta_work) = walk(Acceptance::theta(theta));
+            let (error, work) = (0..20)
+                .map(|k| Opening::MultipoleAcceptance { tol: 1e3 / 2f64.powi(k) })
+                .map(|opening| walk(Acceptance { theta: 0., opening }))
+                .find(|&(error, _)| error <= budget)
+                .expect("a tight enough tolerance");
+            assert!(work < theta_work * 9 / 10, "{} in {} vs {} in {}", error, work, budget, theta_work);
+        }
+    }
+
+    #[test]
+    fn traces_show_bodies_at_theta_zero_and_cells_at_a_wide_angle() {
+        let tree = random_tree(2000, 10);
+        let body = tree.iter().nth(100).copied().unwrap();
+        let (force, trace) = tree.force_on_traced(&body, 0., 0.01, 2.);
+        assert_eq!((trace.approximated, trace.summed), (0, 1999));
+        assert!(trace.cells.is_empty());
+        let expected = tree.acceleration_at(&body.location, 0., 0.01) * (2. * body.mass);
+        assert!((force - expected).length() <= 1e-12 * expected.length(), "{:?} vs {:?}", force, expected);
+        let (force, trace) = tree.force_on_traced(&body, 1.2, 0.01, 2.);
+        assert!(trace.approximated > 2 * trace.summed, "{:?}", trace);
+        assert_eq!(trace.cells.len(), trace.approximated);
+        // the cells hold all the other bodies but the few summed one by one
+        let mass: f64 = trace.cells.iter().map(|&cell| tree.nodes()[cell].mass()).sum();
+        let total = tree.nodes()[0].mass();
+        assert!(mass < total - body.mass && mass > 0.95 * total, "{} of {}", mass, total);
+        let expected = tree.acceleration_at(&body.location, 1.2, 0.01) * (2. * body.mass);
+        assert!((force - expected).length() <= 1e-12 * expected.length(), "{:?} vs {:?}", force, expected);
+    }
+
+    #[test]
+    fn a_collapsed_cluster_adds_its_mass_and_pulls_as_its_monopole() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let corner = Cuboid::from(([0.7; 3], [0.8; 3]));
+        let members = ic::uniform_box(100, &corner, &mut rng);
+        let resolved = BodyTree::build(members.clone(), corner);
+        let background = ic::uniform_box(500, &unit_box(), &mut rng);
+        let mut coarse = BodyTree::build(background.clone(), unit_box());
+        let before = coarse.root().mass();
+        coarse.insert_cluster(resolved.root().aggregate(), 1000).unwrap();
+        let cluster = resolved.root();
+        assert!((coarse.root().mass() - before - cluster.mass()).abs() < 1e-12 * before);
+        assert_eq!(coarse.len(), 501);
+        assert_eq!(coarse.validate(), Ok(()));
+        // with every node opened, the cluster adds the pull of a point mass at its center
+        let target = Point { x: 0.1, y: 0.2, z: 0.3 };
+        let without = BodyTree::build(background, unit_box()).acceleration_at(&target, 0., 0.);
+        let pull = point_mass_acceleration(&target, cluster.center_of_mass(), cluster.mass(), 0.);
+        let added = coarse.acceleration_at(&target, 0., 0.) - without;
+        assert!((added - pull).length() < 1e-10 * pull.length(), "{:?} vs {:?}", added, pull);
+        // which far from it is what its resolved bodies pull with
+        let resolved = members.iter().map(|body| point_mass_acceleration(&target, &body.location, body.mass, 0.));
+        let resolved = resolved.fold(Point::default(), |total, pull| total + pull);
+        assert!((pull - resolved).length() < 1e-2 * resolved.length(), "{:?} vs {:?}", pull, resolved);
+        let mut broken = *cluster.aggregate();
+        broken.center_of_mass.x = f64::NAN;
+        assert_eq!(coarse.insert_cluster(&broken, 1001), Err(InsertError::NonFinite));
+    }
+
+    #[test]
+    fn negative_masses_repel_and_are_chased() {
+        let body = |mass, x| Body { mass, location: Point { x, y: 0.5, z: 0.5 }, ..Body::default() };
+        let tree = BodyTree::build(vec![body(1., 0.25), body(-1., 0.75)], unit_box());
+        // the positive mass is pushed away from the negative one, which falls after it
+        let on_positive = tree.acceleration_at(&body(1., 0.25).location, 0.5, 0.);
+        let on_negative = tree.acceleration_at(&body(-1., 0.75).location, 0.5, 0.);
+        assert!(on_positive.x < 0. && on_negative.x < 0., "{:?} {:?}", on_positive, on_negative);
+        assert_eq!(on_positive, on_negative);
+        let tree = BodyTree::build(vec![body(1., 0.25), body(1., 0.75)], unit_box());
+        assert!(tree.acceleration_at(&body(1., 0.25).location, 0.5, 0.).x > 0.);
+    }
+
+    #[test]
+    fn a_node_whose_masses_cancel_pulls_without_nans() {
+        // pairs of opposite masses close enough to share leaves
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut bodies = vec![];
+        for body in ic::uniform_box(100, &unit_box(), &mut rng) {
+            let partner = Point { x: body.location.x + 1e-3, ..body.location };
+            bodies.push(Body { mass: 1., ..body });
+            bodies.push(Body { mass: -1., location: partner, ..body });
+        }
+        let mut tree = BodyTree::build_bucketed(bodies.clone(), unit_box(), 2);
+        assert_eq!(tree.nodes()[0].mass(), 0.);
+        assert_eq!(tree.nodes()[0].absolute_mass(), 200.);
+        assert_eq!(tree.nodes()[0].center_of_mass(), &unit_box().center());
+        assert_eq!(tree.validate(), Ok(()));
+        tree.compute_quadrupoles();
+        let mut targets: Vec<Point> = bodies.iter().map(|body| body.location).collect();
+        targets.extend(tree.nodes().iter().map(|node| node.bounding_box.center()));
+        for target in &targets {
+            for theta in [0., 0.7, 2.] {
+                let acceleration = tree.acceleration_at(target, theta, 0.01);
+                assert!(acceleration.length().is_finite(), "{:?} at {:?}", acceleration, target);
+                assert!(tree.potential_at(target, theta, 0.01).is_finite());
+            }
+        }
+        // the dipoles do pull, and the opened tree agrees with the direct sum
+        let direct = tree.acceleration_at(&Point { x: 0.5, y: 0.5, z: 0.5 }, 0., 0.01);
+        assert!(direct.length() > 0.);
+        let linear = crate::linear::LinearOctree::build_bucketed(bodies, unit_box(), 2);
+        let difference = linear.acceleration_at(&Point { x: 0.5, y: 0.5, z: 0.5 }, 0., 0.01) - direct;
+        assert!(difference.length() < 1e-9 * direct.length(), "{:?}", difference);
+    }
+
+    #[test]
+    fn the_center_survives_the_net_mass_passing_through_zero() {
+        let body = |mass, x| Body { mass, location: Point { x, y: 0.5, z: 0.5 }, ..Body::default() };
+        let mut tree = BodyTree::new(unit_box());
+        for body in [body(1., 0.1), body(-1., 0.3), body(1., 0.8)] {
+            tree.insert(body);
+        }
+        // (0.1 - 0.3 + 0.8) / 1
+        assert!((tree.nodes()[0].center_of_mass().x - 0.6).abs() < 1e-12);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_accepts_a_built_tree() {
+        let mut tree = random_tree(500, 1);
+        assert_eq!(tree.validate(), Ok(()));
+        tree.compute_quadrupoles();
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_catches_a_corrupted_mass() {
+        let mut tree = random_tree(200, 2);
+        let (_, child) = tree.nodes[0].children().next().unwrap();
+        tree.nodes[child].aggregate.mass *= 2.;
+        // the child disagrees with its own bodies before its parent disagrees with it
+        let error = tree.validate().unwrap_err();
+        assert!(matches!(error, TreeError::AggregateMismatch { depth: 0 | 1 }), "{:?}", error);
+    }
+
+    #[test]
+    fn validate_catches_a_moved_center() {
+        let mut tree = random_tree(200, 3);
+        let leaf = tree.nodes.iter().position(|node| node.is_leaf() && !node.items.is_empty()).unwrap();
+        tree.nodes[leaf].aggregate.center_of_mass.x += 0.1;
+        assert!(matches!(tree.validate(), Err(TreeError::AggregateMismatch { .. })));
+    }
+
+    #[test]
+    fn validate_catches_structural_corruption() {
+        let mut tree = random_tree(100, 4);
+        let body = tree.iter().next().copied().unwrap();
+        tree.nodes[0].items.push(body);
+        tree.nodes[0].ids.push(0);
+        assert_eq!(tree.validate(), Err(TreeError::BodyInInternalNode { depth: 0 }));
+
+        let mut tree = random_tree(100, 5);
+        let leaf = tree.nodes.iter().position(|node| node.is_leaf() && !node.items.is_empty()).unwrap();
+        tree.nodes[leaf].items[0].location.x = 2.;
+        assert!(matches!(tree.validate(), Err(TreeError::BodyOutsideBox { .. })));
+    }
+
+    // octants that, unlike `Octants`, place nothing outside the box
+    #[derive(Debug)]
+    struct StrictOctants;
+
+    impl Subdivision for StrictOctants {
+        fn arity(&self) -> usize {
+            8
+        }
+
+        fn child_box(&self, space: &Cuboid, index: usize) -> Cuboid {
+            space.split()[index]
+        }
+
+        fn child_index(&self, space: &Cuboid, point: &Point) -> Option<usize> {
+            space.contains(point).then(|| space.octant_contains_point(point)).flatten()
+        }
+    }
+
+    // a subdivision claiming any number of slots, none of which it fills
+    #[derive(Debug)]
+    struct Slots(usize);
+
+    impl Subdivision for Slots {
+        fn arity(&self) -> usize {
+            self.0
+        }
+
+        fn child_box(&self, space: &Cuboid, _: usize) -> Cuboid {
+            *space
+        }
+
+        fn child_index(&self, _: &Cuboid, _: &Point) -> Option<usize> {
+            None
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "1 to 8 child slots, not 0")]
+    fn a_subdivision_needs_a_slot() {
+        BodyTree::with_subdivision(unit_box(), Slots(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "1 to 8 child slots, not 9")]
+    fn a_subdivision_fits_in_the_eight_child_slots() {
+        let bodies = ic::uniform_box(10, &unit_box(), &mut StdRng::seed_from_u64(47));
+        BodyTree::build_with(bodies, unit_box(), Slots(9), 4);
+    }
+
+    #[test]
+    fn schemes_agree_on_mass_summaries() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let bodies = ic::uniform_box(400, &unit_box(), &mut rng);
+        let octants = BodyTree::build_with(bodies.clone(), unit_box(), Octants, 4);
+        let halves = BodyTree::build_with(bodies.clone(), unit_box(), LongestAxis, 4);
+        assert_eq!(octants.validate(), Ok(()));
+        assert_eq!(halves.validate(), Ok(()));
+        assert_eq!(halves.bucket_size(), 4);
+        assert_eq!(halves.item_count(), bodies.len());
+        assert!((octants.root().mass() - halves.root().mass()).abs() < 1e-12);
+        assert!((*octants.root().center_of_mass() - *halves.root().center_of_mass()).length() < 1e-12);
+        // a binary split never fills more than two slots
+        assert!(halves.nodes().iter().all(|node| node.children().all(|(slot, _)| slot < 2)));
+    }
+
+    #[test]
+    fn items_without_a_slot_are_kept() {
+        let mut tree = BodyTree::with_subdivision_bucketed(unit_box(), StrictOctants, 1);
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut bodies = ic::uniform_box(50, &unit_box(), &mut rng);
+        bodies[10].location = Point { x: 1.5, y: -0.5, z: 0.5 };
+        bodies[20].location = Point { x: -3., y: 0.2, z: 4. };
+        let mass: f64 = bodies.iter().map(|body| body.mass).sum();
+        for body in bodies {
+            tree.insert(body);
+        }
+        assert_eq!(tree.len(), 50);
+        assert_eq!(tree.item_count(), 50);
+        assert!((tree.root().mass() - mass).abs() < 1e-12);
+        let stray = tree.remove(&Point { x: 1.5, y: -0.5, z: 0.5 }, |body| body.location.x == 1.5);
+        assert!(stray.is_some());
+        assert_eq!(tree.item_count(), 49);
+    }
+
+    #[test]
+    fn max_depth_scales_with_arity() {
+        assert_eq!(BodyTree::new(unit_box()).max_depth(), MAX_DEPTH);
+        assert_eq!(BodyTree::with_subdivision(unit_box(), LongestAxis).max_depth(), 3 * MAX_DEPTH);
+    }
+
+    #[test]
+    fn binary_splits_resolve_close_bodies() {
+        // about 2^-30 apart, which 32 levels of octants resolve but 32 halvings, cycling through the axes, do not
+        let bodies: Vec<Body> = (0..64)
+            .map(|i| Body {
+                mass: 1.,
+                location: Point { x: 0.5 + i as f64 * 1e-9, y: 0.5, z: 0.5 },
+                ..Body::default()
+            })
+            .collect();
+        let octants = BodyTree::build_with(bodies.clone(), unit_box(), Octants, 4);
+        let halves = BodyTree::build_with(bodies, unit_box(), LongestAxis, 4);
+        assert_eq!(halves.validate(), Ok(()));
+        let largest = |tree: &BodyTree| tree.nodes().iter().map(|node| node.items().len()).max().unwrap();
+        assert!(largest(&octants) <= 4);
+        assert!(largest(&halves) <= 4, "{}", largest(&halves));
+    }
+
+    #[test]
+    fn clamped_bodies_land_on_the_boundary() {
+        let mut tree = random_tree(20, 9);
+        let body = Body { mass: 1., location: Point { x: 1.5, y: 0.5, z: -0.25 }, ..Body::default() };
+        assert!(tree.insert_clamped(body));
+        assert!(!tree.insert_clamped(Body { location: Point { x: 0.5, y: 0.5, z: 0.5 }, ..body }));
+        assert_eq!(tree.len(), 22);
+        assert_eq!(tree.validate(), Ok(()));
+        assert!(tree.iter().any(|body| body.location == Point { x: 1., y: 0.5, z: 0. }));
+    }
+
+    #[test]
+    fn tolerance_admits_accumulated_rounding() {
+        let mut tree = random_tree(300, 6);
+        tree.nodes[0].aggregate.mass *= 1. + 1e-12;
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.validate_within(1e-14), Err(TreeError::AggregateMismatch { depth: 0 }));
+    }
+
+    #[test]
+    fn three_bodies_print_one_indented_line_per_node() {
+        let bodies = [[0.1; 3], [0.3; 3], [0.9; 3]]
+            .map(|location| Body { mass: 1., location: location.into(), ..Body::default() });
+        let tree = BodyTree::build(bodies, unit_box());
+        let text = tree.format_tree();
+        let levels: Vec<usize> =
+            text.lines().map(|line| (line.len() - line.trim_start().len()) / 2).collect();
+        // the root, the octant holding the close two, each of those two, and the octant holding the far one
+        assert_eq!(levels, [0, 1, 2, 2, 1], "{}", text);
+        assert!(text.lines().next().unwrap().starts_with("root [0, 1] x [0, 1] x [0, 1]"), "{}", text);
+        assert!(text.lines().nth(1).unwrap().trim_start().starts_with("octant 0 [0, 0.5]"), "{}", text);
+        assert!(text.lines().last().unwrap().ends_with("mass 1 at (0.9, 0.9, 0.9)"), "{}", text);
+    }
+
+    #[test]
+    fn k_nearest_finds_what_sorting_every_body_finds() {
+        let tree = random_tree(500, 31);
+        let probes = ic::uniform_box(20, &unit_box(), &mut StdRng::seed_from_u64(32));
+        for probe in &probes {
+            let target = &probe.location;
+            let mut brute: Vec<&Body> = tree.iter().collect();
+            brute.sort_by(|a, b| a.location.distance_squared(target).total_cmp(&b.location.distance_squared(target)));
+            for k in [1, 5, 32, 499] {
+                let found: Vec<u64> = tree.k_nearest(target, k).iter().map(|body| body.id).collect();
+                let expected: Vec<u64> = brute[..k].iter().map(|body| body.id).collect();
+                assert_eq!(found, expected, "k = {}", k);
+            }
+        }
+        // more than there are hands back every body
+        assert_eq!(tree.k_nearest(&probes[0].location, 10_000).len(), 500);
+        assert!(tree.k_nearest(&probes[0].location, 0).is_empty());
+    }
+
+    #[test]
+    fn a_close_pair_makes_the_deepest_leaf_and_its_octant_the_densest() {
+        let bodies = [[0.1; 3], [0.101; 3], [0.2; 3], [0.6; 3], [0.9; 3]]
+            .map(|location| Body { mass: 1., location: location.into(), ..Body::default() });
+        let tree = BodyTree::build(bodies, unit_box());
+        let (depth, leaf) = tree.deepest_leaf();
+        // halving from 1 down to about the pair's 0.001 apart
+        assert!(depth >= 9, "{}", depth);
+        assert!(leaf.x.end - leaf.x.start <= 2e-3, "{:?}", leaf);
+        assert!(leaf.contains(&Point::from([0.1; 3])) || leaf.contains(&Point::from([0.101; 3])), "{:?}", leaf);
+        assert_eq!(tree.densest_region(0), Some((5, &unit_box())));
+        assert_eq!(tree.densest_region(1), Some((3, &Cuboid::from(([0.; 3], [0.5; 3])))));
+        assert_eq!(tree.densest_region(depth + 1), None);
+    }
+
+
+    #[test]
+    fn the_wireframe_has_twelve_edges_a_node() {
+        let root: BodyTree = Octree::new(unit_box());
+        let edges = root.to_wireframe();
+        assert_eq!(edges.len(), 12);
+        for (from, to) in &edges {
+            // each edge runs the whole box along exactly one axis
+            let span = *to - *from;
+            let axes = [span.x, span.y, span.z];
+            assert_eq!(axes.iter().filter(|&&extent| extent == 1.).count(), 1, "{:?} {:?}", from, to);
+            assert_eq!(axes.iter().filter(|&&extent| extent == 0.).count(), 2, "{:?} {:?}", from, to);
+        }
+        let bodies =
+            [[0.25; 3], [0.75; 3]].map(|location| Body { mass: 1., location: location.into(), ..Body::default() });
+        let split = BodyTree::build(bodies, unit_box());
+        assert!(split.nodes().len() > 1);
+        assert_eq!(split.to_wireframe().len(), 12 * split.nodes().len());
+    }
+
+    #[test]
+    fn a_plain_point_index_answers_queries_without_masses() {
+        let bodies = ic::uniform_box(300, &unit_box(), &mut StdRng::seed_from_u64(41));
+        let points: Vec<Point> = bodies.iter().map(|body| body.location).collect();
+        let index: Octree<Point, ()> = Octree::build(points.clone(), unit_box());
+        assert_eq!(index.len(), 300);
+        let center = Point::from([0.5; 3]);
+        let mut near: Vec<[f64; 3]> = index.within_radius(&center, 0.2).into_iter().map(|&p| p.into()).collect();
+        let mut expected: Vec<[f64; 3]> =
+            points.iter().filter(|p| p.distance_squared(&center) <= 0.04).map(|&p| p.into()).collect();
+        near.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(!expected.is_empty());
+        assert_eq!(near, expected);
+        let region = Cuboid::from(([0.; 3], [0.5, 0.5, 1.]));
+        let inside = points.iter().filter(|p| region.contains(p)).count();
+        assert_eq!(index.within_box(&region).len(), inside);
+        let nearest = index.k_nearest(&center, 3);
+        let mut distances: Vec<f64> = points.iter().map(|p| p.distance_squared(&center)).collect();
+        distances.sort_by(f64::total_cmp);
+        let found: Vec<f64> = nearest.iter().map(|p| p.distance_squared(&center)).collect();
+        assert_eq!(found, distances[..3]);
+    }
+
+    #[test]
+    fn non_finite_bodies_are_refused_and_leave_the_tree_alone() {
+        let mut tree = random_tree(20, 43);
+        let nodes = tree.nodes().len();
+        for body in [
+            Body { mass: 1., location: Point::from([f64::NAN, 0.5, 0.5]), ..Body::default() },
+            Body { mass: 1., location: Point::from([0.5, f64::INFINITY, 0.5]), ..Body::default() },
+            Body { mass: f64::NAN, location: Point::from([0.5; 3]), ..Body::default() },
+        ] {
+            assert_eq!(tree.try_insert(body), Err(InsertError::NonFinite));
+        }
+        assert_eq!(tree.len(), 20);
+        assert_eq!(tree.nodes().len(), nodes);
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.try_insert(Body { mass: 1., location: Point::from([0.5; 3]), ..Body::default() }), Ok(()));
+        assert_eq!(tree.len(), 21);
+    }
+
+    #[test]
+    fn relocating_one_body_leaves_the_summaries_a_full_recompute_gives() {
+        let mut bodies = ic::uniform_box(300, &unit_box(), &mut StdRng::seed_from_u64(47));
+        let mut tree = BodyTree::build(bodies.clone(), unit_box());
+        tree.refresh_aggregates();
+        let summaries = |tree: &BodyTree| tree.nodes().iter().map(|node| *node.aggregate()).collect::<Vec<_>>();
+        let untouched = summaries(&tree);
+        // a nudge that keeps the body in its leaf, then a jump across the box, then the same again
+        for (id, to, changed) in [(17, None, 0), (17, Some([0.95, 0.05, 0.5]), 1), (230, Some([0.5; 3]), 1)] {
+            let body = &mut bodies[id];
+            body.location = match to {
+                Some(to) => to.into(),
+                None => body.location + Point { x: 1e-9, y: 0., z: 0. },
+            };
+            assert_eq!(tree.relocate(&bodies, 10), Some(changed));
+            let relocated = summaries(&tree);
+            tree.refresh_aggregates();
+            assert_eq!(relocated, summaries(&tree), "moving body {}", id);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        assert_ne!(summaries(&tree)[0], untouched[0]);
+        assert_eq!(tree.len(), 300);
+    }
+
+    #[test]
+    fn inserts_keep_the_summaries_current_unless_told_not_to() {
+        let summaries = |tree: &BodyTree| tree.nodes().iter().map(|node| *node.aggregate()).collect::<Vec<_>>();
+        let more = ic::uniform_box(100, &unit_box(), &mut StdRng::seed_from_u64(54));
+        let mut tree = random_tree(200, 53);
+        assert!(tree.is_incremental());
+        // a build gathers once at the end, just as a recompute does
+        let built = summaries(&tree);
+        tree.refresh_aggregates();
+        assert_eq!(built, summaries(&tree));
+        for body in more.iter().copied() {
+            tree.insert(body);
+        }
+        assert_eq!(tree.validate_within(1e-12), Ok(()));
+        let added = summaries(&tree);
+        tree.refresh_aggregates();
+        for (added, full) in added.iter().zip(&summaries(&tree)) {
+            assert!((added.mass() - full.mass()).abs() <= 1e-12 * full.mass());
+            assert!(added.center_of_mass().distance_squared(full.center_of_mass()) < 1e-24);
+        }
+        // with the updates off the inserts leave the summaries behind, until turning them back on catches up
+        let mut tree = random_tree(200, 53);
+        tree.set_incremental(false);
+        for body in more {
+            tree.insert(body);
+        }
+        assert!(tree.validate().is_err());
+        tree.set_incremental(true);
+        assert_eq!(tree.validate(), Ok(()));
+        let caught_up = summaries(&tree);
+        tree.refresh_aggregates();
+        assert_eq!(caught_up, summaries(&tree));
+        assert_eq!(tree.len(), 300);
+    }
+
+    #[test]
+    fn batched_radius_queries_find_what_one_at_a_time_does() {
+        let tree = random_tree(800, 43);
+        let bodies = ic::uniform_box(60, &unit_box(), &mut StdRng::seed_from_u64(44));
+        let mut centers: Vec<Point> = bodies.iter().map(|body| body.location).collect();
+        // a corner, and a point outside the box that reaches only its nearest face
+        centers.extend([Point::from([0.; 3]), Point::from([1.1, 0.5, 0.5])]);
+        let batch = tree.within_radius_batch(&centers, 0.15);
+        assert_eq!(batch.len(), centers.len());
+        for (found, center) in batch.iter().zip(&centers) {
+            let one = tree.within_radius(center, 0.15);
+            assert_eq!(found.len(), one.len(), "{:?}", center);
+            assert!(found.iter().zip(&one).all(|(&a, &b)| std::ptr::eq(a, b)), "{:?}", center);
+        }
+        assert!(batch.iter().any(|found| !found.is_empty()));
+    }
+
+    #[test]
+    fn a_canonical_build_does_not_care_what_order_the_bodies_come_in() {
+        use rand::seq::SliceRandom;
+        let bodies = ic::uniform_box(2000, &unit_box(), &mut StdRng::seed_from_u64(45));
+        let mut shuffled = bodies.clone();
+        shuffled.shuffle(&mut StdRng::seed_from_u64(46));
+        // a plain build only keeps the shape
+        let plain = BodyTree::build_bucketed(bodies.clone(), unit_box(), 4);
+        let plain_shuffled = BodyTree::build_bucketed(shuffled.clone(), unit_box(), 4);
+        assert_eq!(plain.format_tree(), plain_shuffled.format_tree());
+        assert_ne!(format!("{:?}", plain), format!("{:?}", plain_shuffled));
+        let (tree, tree_shuffled) =
+            (BodyTree::build_canonical(bodies, unit_box(), 4), BodyTree::build_canonical(shuffled, unit_box(), 4));
+        assert_eq!(tree.format_tree(), plain.format_tree());
+        // every node, item and moment, floats printed exactly
+        assert_eq!(format!("{:?}", tree), format!("{:?}", tree_shuffled));
+    }
+}
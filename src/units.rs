@@ -0,0 +1,218 @@
+//! physical constants in SI units, the units a simulation is given in, and scalings to dimensionless units
+
+use crate::body::Body;
+use crate::geometry::Point;
+use serde::{Deserialize, Serialize};
+
+/// gravitational constant, m^3 kg^-1 s^-2
+pub const G: f64 = 6.674_30e-11;
+/// kg
+pub const SOLAR_MASS: f64 = 1.988_47e30;
+/// m
+pub const AU: f64 = 1.495_978_707e11;
+/// m
+pub const PARSEC: f64 = 3.085_677_581_491_367e16;
+/// julian year, s
+pub const YEAR: f64 = 3.155_76e7;
+/// a million julian years, s
+pub const MEGAYEAR: f64 = 1e6 * YEAR;
+
+/// the units masses, positions, velocities and the clock of a simulation are in, which fixes the value of G
+/// its forces are computed with. the initial conditions in `ic` are in equilibrium for G = 1 only
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Units {
+    /// no physical scale, with G = 1
+    #[default]
+    Dimensionless,
+    /// kg, m and s. lengths in metres overflow single precision beyond about 1e12 m
+    Si,
+    /// solar masses, parsecs and megayears, where G is about 4.5e-3
+    Astronomical,
+    /// solar masses, AU and julian years, where G is about 4 pi²
+    SolarSystem,
+    /// any other units, given by the value of G in them
+    Custom { gravitational_constant: f64 },
+}
+
+impl Units {
+    pub fn gravitational_constant(&self) -> f64 {
+        let scaled = |mass: f64, length: f64, time: f64| G * mass * time * time / length.powi(3);
+        match *self {
+            Units::Dimensionless => 1.,
+            Units::Si => G,
+            Units::Astronomical => scaled(SOLAR_MASS, PARSEC, MEGAYEAR),
+            Units::SolarSystem => scaled(SOLAR_MASS, AU, YEAR),
+            Units::Custom {
+                gravitational_constant,
+            } => gravitational_constant,
+        }
+    }
+
+    /// the names of the mass, length and time units, empty unless there is a preset
+    pub fn names(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Units::Dimensionless | Units::Custom { .. } => ("", "", ""),
+            Units::Si => ("kg", "m", "s"),
+            Units::Astronomical => ("Msun", "pc", "Myr"),
+            Units::SolarSystem => ("Msun", "AU", "yr"),
+        }
+    }
+}
+
+// `G = <value>`, followed by the mass, length and time units of a preset
+impl std::fmt::Display for Units {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let g = self.gravitational_constant();
+        // plain digits would run to a dozen zeros in SI
+        if (1e-3..1e6).contains(&g.abs()) {
+            write!(f, "G = {}", g)?;
+        } else {
+            write!(f, "G = {:e}", g)?;
+        }
+        let (mass, length, time) = self.names();
+        if !mass.is_empty() {
+            write!(
+                f,
+                ", mass in {}, length in {}, time in {}",
+                mass, length, time
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// internal units pick a length and a mass scale; the time scale follows from requiring G = 1. bodies
+/// converted this way run in `Units::Dimensionless`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitSystem {
+    pub length: f64,
+    pub mass: f64,
+}
+
+impl UnitSystem {
+    pub fn new(length: f64, mass: f64) -> Self {
+        UnitSystem { length, mass }
+    }
+
+    /// lengths in AU and masses in solar masses; one year is 2*pi time units
+    pub fn solar_system() -> Self {
+        UnitSystem::new(AU, SOLAR_MASS)
+    }
+
+    /// lengths in parsecs and masses in solar masses
+    pub fn stellar_cluster() -> Self {
+        UnitSystem::new(PARSEC, SOLAR_MASS)
+    }
+
+    /// seconds per internal time unit
+    pub fn time(&self) -> f64 {
+        (self.length.powi(3) / (G * self.mass)).sqrt()
+    }
+
+    /// metres per second per internal velocity unit
+    pub fn velocity(&self) -> f64 {
+        self.length / self.time()
+    }
+
+    pub fn length_to_internal(&self, metres: f64) -> f64 {
+        metres / self.length
+    }
+
+    pub fn length_to_si(&self, length: f64) -> f64 {
+        length * self.length
+    }
+
+    pub fn mass_to_internal(&self, kilograms: f64) -> f64 {
+        kilograms / self.mass
+    }
+
+    pub fn mass_to_si(&self, mass: f64) -> f64 {
+        mass * self.mass
+    }
+
+    pub fn time_to_internal(&self, seconds: f64) -> f64 {
+        seconds / self.time()
+    }
+
+    pub fn time_to_si(&self, time: f64) -> f64 {
+        time * self.time()
+    }
+
+    pub fn velocity_to_internal(&self, metres_per_second: f64) -> f64 {
+        metres_per_second / self.velocity()
+    }
+
+    pub fn velocity_to_si(&self, velocity: f64) -> f64 {
+        velocity * self.velocity()
+    }
+
+    /// converts a body given in kg, m and m/s into internal units
+    pub fn body_to_internal(&self, body: &Body) -> Body {
+        Body {
+            mass: self.mass_to_internal(body.mass),
+            location: self.point_to_internal(&body.location),
+            velocity: body.velocity / self.velocity(),
+            ..*body
+        }
+    }
+
+    /// converts a body in internal units back into kg, m and m/s
+    pub fn body_to_si(&self, body: &Body) -> Body {
+        Body {
+            mass: self.mass_to_si(body.mass),
+            location: self.point_to_si(&body.location),
+            velocity: body.velocity * self.velocity(),
+            ..*body
+        }
+    }
+
+    fn point_to_internal(&self, point: &Point) -> Point {
+        Point {
+            x: self.length_to_internal(point.x),
+            y: self.length_to_internal(point.y),
+            z: self.length_to_internal(point.z),
+        }
+    }
+
+    fn point_to_si(&self, point: &Point) -> Point {
+        Point {
+            x: self.length_to_si(point.x),
+            y: self.length_to_si(point.y),
+            z: self.length_to_si(point.z),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_earth_goes_round_in_two_pi_and_comes_back_a_year() {
+        let units = UnitSystem::solar_system();
+        let speed = (G * SOLAR_MASS / AU).sqrt();
+        let earth = Body {
+            mass: 5.972e24,
+            location: Point::from([AU, 0., 0.]),
+            velocity: Point::from([0., speed, 0.]),
+            ..Body::default()
+        };
+        let internal = units.body_to_internal(&earth);
+        assert!((internal.location.x - 1.).abs() < 1e-12);
+        // a circular orbit about a unit mass at unit radius moves at unit speed when G = 1
+        assert!((internal.velocity.y - 1.).abs() < 1e-12);
+        assert!((internal.mass - 3.0e-6).abs() < 1e-8);
+        let period = units.time_to_si(2. * std::f64::consts::PI);
+        assert!((period / YEAR - 1.).abs() < 1e-3, "{}", period / YEAR);
+        assert!((units.time_to_internal(YEAR) - 2. * std::f64::consts::PI).abs() < 1e-2);
+        let back = units.body_to_si(&internal);
+        for (a, b) in [
+            (back.mass, earth.mass),
+            (back.location.x, earth.location.x),
+            (back.velocity.y, earth.velocity.y),
+        ] {
+            assert!((a / b - 1.).abs() < 1e-12, "{} {}", a, b);
+        }
+        assert_eq!(back.location.y, 0.);
+    }
+}
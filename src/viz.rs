@@ -0,0 +1,223 @@
+//! a live window onto a running simulation, behind the `viz` feature. bodies are drawn as points through a
+//! software perspective projection, so the platform only has to provide a window
+//!
+//! dragging with the left button or the arrow keys orbit the camera, the scroll wheel or +/- zoom, space
+//! pauses, n takes a single step while paused, m toggles sizing points by mass and escape closes the window
+
+use crate::force::ForceModel;
+use crate::geometry::Point;
+use crate::scalar::Scalar;
+use crate::sim::Simulation;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use std::f64::consts::FRAC_PI_2;
+
+// radians per pixel dragged and per frame an arrow key is held
+const DRAG_SPEED: f64 = 0.01;
+const KEY_SPEED: f64 = 0.03;
+// half the vertical field of view, in radians
+const HALF_FOV: f64 = 0.5;
+// brightness a point adds to its pixels, out of 255, so dense regions saturate towards white
+const BRIGHTNESS: u32 = 96;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ViewOptions {
+    pub width: usize,
+    pub height: usize,
+    /// passed to `Simulation::step`
+    pub dt: f64,
+    /// steps taken between frames
+    pub steps_per_frame: u32,
+    /// start with points sized by mass rather than all one pixel
+    pub scale_by_mass: bool,
+}
+
+impl Default for ViewOptions {
+    fn default() -> Self {
+        ViewOptions {
+            width: 800,
+            height: 600,
+            dt: 0.01,
+            steps_per_frame: 1,
+            scale_by_mass: false,
+        }
+    }
+}
+
+// orbits `center` at `distance`, with the world z axis up on screen
+struct Camera {
+    center: Point,
+    distance: f64,
+    yaw: f64,
+    pitch: f64,
+}
+
+impl Camera {
+    // `point` as a pixel position; none if it is behind the camera
+    fn project(&self, point: &Point, width: usize, height: usize) -> Option<(f64, f64)> {
+        let p = *point - self.center;
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let x = cos_yaw * p.x - sin_yaw * p.y;
+        let y = sin_yaw * p.x + cos_yaw * p.y;
+        let depth = self.distance + cos_pitch * y - sin_pitch * p.z;
+        let up = sin_pitch * y + cos_pitch * p.z;
+        if depth <= self.distance * 1e-3 {
+            return None;
+        }
+        let focal = 0.5 * height as f64 / HALF_FOV.tan();
+        Some((
+            0.5 * width as f64 + focal * x / depth,
+            0.5 * height as f64 - focal * up / depth,
+        ))
+    }
+
+    fn orbit(&mut self, yaw: f64, pitch: f64) {
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-FRAC_PI_2, FRAC_PI_2);
+    }
+
+    fn zoom(&mut self, factor: f64) {
+        self.distance *= factor;
+    }
+}
+
+/// opens a window and steps `simulation` in it until the window is closed
+pub fn show<S: Scalar, F: ForceModel>(
+    simulation: &mut Simulation<S, F>,
+    options: &ViewOptions,
+) -> Result<(), minifb::Error> {
+    let mut window = Window::new(
+        "barneshutt3d",
+        options.width,
+        options.height,
+        WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        },
+    )?;
+    window.set_target_fps(60);
+
+    let bounds = simulation.bounds().cast::<f64>();
+    let mut camera = Camera {
+        center: bounds.center(),
+        distance: 2. * bounds.size(),
+        yaw: 0.,
+        pitch: 0.3,
+    };
+    let mut paused = false;
+    let mut scale_by_mass = options.scale_by_mass;
+    let mut dragged_from = None;
+    let mut buffer = vec![];
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let mut step = !paused;
+        for key in window.get_keys_pressed(KeyRepeat::No) {
+            match key {
+                Key::Space => paused = !paused,
+                Key::N => step = true,
+                Key::M => scale_by_mass = !scale_by_mass,
+                _ => {}
+            }
+        }
+        let held = |key| {
+            if window.is_key_down(key) {
+                KEY_SPEED
+            } else {
+                0.
+            }
+        };
+        camera.orbit(
+            held(Key::Right) - held(Key::Left),
+            held(Key::Up) - held(Key::Down),
+        );
+        if window.is_key_down(Key::Equal) || window.is_key_down(Key::NumPadPlus) {
+            camera.zoom(0.97);
+        }
+        if window.is_key_down(Key::Minus) || window.is_key_down(Key::NumPadMinus) {
+            camera.zoom(1. / 0.97);
+        }
+        if let Some((_, scroll)) = window.get_scroll_wheel() {
+            camera.zoom(0.9f64.powf(scroll as f64));
+        }
+        let mouse = window
+            .get_mouse_pos(MouseMode::Pass)
+            .filter(|_| window.get_mouse_down(MouseButton::Left));
+        if let (Some((x, y)), Some((from_x, from_y))) = (mouse, dragged_from) {
+            camera.orbit(
+                DRAG_SPEED * (x - from_x) as f64,
+                DRAG_SPEED * (y - from_y) as f64,
+            );
+        }
+        dragged_from = mouse;
+
+        if step {
+            for _ in 0..options.steps_per_frame {
+                simulation.step(options.dt);
+            }
+        }
+
+        let (width, height) = window.get_size();
+        buffer.clear();
+        buffer.resize(width * height, 0u32);
+        draw(
+            &mut buffer,
+            width,
+            height,
+            simulation,
+            &camera,
+            scale_by_mass,
+        );
+        window.set_title(&format!(
+            "barneshutt3d: {} bodies, t = {:.3}{}",
+            simulation.len(),
+            simulation.time(),
+            if paused { ", paused" } else { "" }
+        ));
+        window.update_with_buffer(&buffer, width, height)?;
+    }
+    Ok(())
+}
+
+// splats every body into `buffer` as 0RGB pixels
+fn draw<S: Scalar, F: ForceModel>(
+    buffer: &mut [u32],
+    width: usize,
+    height: usize,
+    simulation: &Simulation<S, F>,
+    camera: &Camera,
+    scale_by_mass: bool,
+) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let bodies = simulation.bodies();
+    let mean_mass =
+        bodies.iter().map(|body| body.mass.as_f64()).sum::<f64>() / bodies.len().max(1) as f64;
+    for body in bodies {
+        let Some((x, y)) = camera.project(&body.location.cast(), width, height) else {
+            continue;
+        };
+        // the radius grows with the cube root of mass, as it would for bodies of one density; 0 is one pixel
+        let radius = if scale_by_mass && mean_mass > 0. {
+            (body.mass.as_f64() / mean_mass).cbrt().clamp(1., 6.)
+        } else {
+            0.
+        };
+        let (min_x, max_x) = ((x - radius).floor(), (x + radius).floor());
+        let (min_y, max_y) = ((y - radius).floor(), (y + radius).floor());
+        if max_x < 0. || max_y < 0. || min_x >= width as f64 || min_y >= height as f64 {
+            continue;
+        }
+        for row in min_y.max(0.) as usize..=(max_y as usize).min(height - 1) {
+            for col in min_x.max(0.) as usize..=(max_x as usize).min(width - 1) {
+                let (dx, dy) = (col as f64 + 0.5 - x, row as f64 + 0.5 - y);
+                if radius > 0. && dx * dx + dy * dy > radius * radius {
+                    continue;
+                }
+                let pixel = &mut buffer[row * width + col];
+                let level = ((*pixel & 0xff) + BRIGHTNESS).min(255);
+                *pixel = (level.saturating_sub(64) << 16) | (level.saturating_sub(32) << 8) | level;
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+// the snapshots a run of the binary leaves behind
+#![cfg(feature = "cli")]
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// the snapshot files in `out` after `steps` steps written every `every`, sorted
+fn snapshots(out: &Path, steps: u64, every: u64) -> Vec<String> {
+    let _ = std::fs::remove_dir_all(out);
+    let status = Command::new(env!("CARGO_BIN_EXE_barneshutt3d"))
+        .args(["run", "--bodies", "20", "--seed", "3"])
+        .args(["--steps", &steps.to_string()])
+        .args(["--every", &every.to_string()])
+        .arg("--out")
+        .arg(out)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut written: Vec<String> = std::fs::read_dir(out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("snapshot_"))
+        .collect();
+    written.sort();
+    std::fs::remove_dir_all(out).unwrap();
+    written
+}
+
+#[test]
+fn a_run_writes_every_nth_step_and_the_last() {
+    let out = std::env::temp_dir().join(format!("barneshutt3d-cadence-{}", std::process::id()));
+    let written = snapshots(&out, 100, 10);
+    assert_eq!(written.len(), 11, "{:?}", written);
+    assert!(written.last().unwrap().starts_with("snapshot_000100."));
+    let written = snapshots(&out, 25, 10);
+    let steps: Vec<&str> = written.iter().map(|name| &name[9..15]).collect();
+    assert_eq!(steps, ["000000", "000010", "000020", "000025"]);
+}
@@ -0,0 +1,43 @@
+// two ranks of the binary over localhost, given different step counts and cadences
+#![cfg(all(feature = "distributed", feature = "cli"))]
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+fn rank(address: &str, rank: usize, steps: u64, log_every: u64) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_barneshutt3d"))
+        .args(["run", "--bodies", "100", "--seed", "1", "--ranks", "2", "--cluster", address])
+        .args(["--rank", &rank.to_string()])
+        .args(["--steps", &steps.to_string()])
+        .args(["--log-every", &log_every.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+// waits up to `limit` for the child, killing it if it is still going
+fn finished(child: &mut Child, limit: Duration) -> bool {
+    let instant = Instant::now();
+    while instant.elapsed() < limit {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.success();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    child.kill().unwrap();
+    false
+}
+
+#[test]
+fn ranks_follow_rank_zero_settings() {
+    // a free port, given up again for rank 0 to listen on
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address = format!("127.0.0.1:{}", port);
+    let mut root = rank(&address, 0, 12, 4);
+    std::thread::sleep(Duration::from_millis(200));
+    let mut other = rank(&address, 1, 5, 3);
+    assert!(finished(&mut other, Duration::from_secs(60)), "rank 1 hung or failed");
+    assert!(finished(&mut root, Duration::from_secs(60)), "rank 0 hung or failed");
+}
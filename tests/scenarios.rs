@@ -0,0 +1,95 @@
+// the analytic scenarios run end to end under each integrator, and the conserved quantities of a cluster
+use barneshutt3d::ic;
+use barneshutt3d::scenarios::{Scenario, ScenarioCheck};
+use barneshutt3d::{Cuboid, Diagnostics, PotentialMethod, Scheme, Simulation, SimulationConfig};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+#[test]
+fn every_scenario_meets_its_tolerances() {
+    for scenario in Scenario::ALL {
+        let report = scenario.run();
+        report.assert_within(&scenario.tolerances());
+        assert!(report
+            .periods
+            .iter()
+            .all(|(_, measured)| measured.is_some()));
+    }
+}
+
+#[test]
+fn every_scheme_keeps_the_figure_eight() {
+    let scenario = Scenario::FigureEight;
+    for scheme in [Scheme::Leapfrog, Scheme::Yoshida4, Scheme::Hermite] {
+        let mut simulation = scenario.simulation();
+        let mut check = ScenarioCheck::new(scenario, &simulation);
+        for _ in 0..scenario.steps() {
+            simulation.step_with(scenario.dt(), &scheme);
+            check.record(&simulation);
+        }
+        check.report().assert_within(&scenario.tolerances());
+    }
+}
+
+#[test]
+fn the_sun_earth_moon_year_and_month_come_round() {
+    let report = Scenario::SunEarthMoon.run();
+    let (year, measured_year) = report.periods[0];
+    let (month, measured_month) = report.periods[1];
+    assert!((year - 1.).abs() < 1e-5, "{}", year);
+    // a sidereal month, 27.32 days
+    assert!((month * 365.25 - 27.32).abs() < 0.3, "{}", month * 365.25);
+    assert!(
+        (measured_year.unwrap() / year - 1.).abs() < 1e-3,
+        "{}",
+        report
+    );
+    assert!(
+        (measured_month.unwrap() / month - 1.).abs() < 2e-2,
+        "{}",
+        report
+    );
+}
+
+// a softened plummer cluster stepped `steps` times under tree forces at `theta`, measured before and after
+fn cluster(theta: f64, steps: u64) -> (Diagnostics, Diagnostics) {
+    let bodies = ic::plummer(200, 1., 0.2, &mut StdRng::seed_from_u64(11));
+    let config = SimulationConfig {
+        theta,
+        softening: 0.05,
+        ..SimulationConfig::default()
+    };
+    let mut simulation = Simulation::with_config(bodies, Cuboid::from(([-8.; 3], [8.; 3])), config);
+    let before = simulation.diagnostics(PotentialMethod::Direct);
+    for _ in 0..steps {
+        simulation.step(1e-3);
+    }
+    (before, simulation.diagnostics(PotentialMethod::Direct))
+}
+
+fn energy(diagnostics: &Diagnostics) -> f64 {
+    diagnostics.kinetic_energy + diagnostics.potential_energy
+}
+
+#[test]
+fn direct_forces_conserve_momentum_and_angular_momentum_to_rounding() {
+    let (before, after) = cluster(0., 200);
+    let momentum = (after.linear_momentum - before.linear_momentum).length();
+    let angular = (after.angular_momentum - before.angular_momentum).length();
+    assert!(momentum < 1e-12, "{}", momentum);
+    assert!(angular < 1e-12, "{}", angular);
+    let drift = (energy(&after) - energy(&before)) / energy(&before);
+    assert!(drift.abs() < 1e-5, "{}", drift);
+}
+
+#[test]
+fn tree_forces_conserve_them_to_the_opening_angle() {
+    let (before, after) = cluster(0.5, 200);
+    let momentum = (after.linear_momentum - before.linear_momentum).length();
+    let angular = (after.angular_momentum - before.angular_momentum).length();
+    let drift = (energy(&after) - energy(&before)) / energy(&before);
+    // the multipole forces on a pair are not equal and opposite, so the momenta wander, but slowly
+    assert!(momentum < 1e-3, "{}", momentum);
+    assert!(angular < 1e-3, "{}", angular);
+    assert!(drift.abs() < 1e-4, "{}", drift);
+}
@@ -0,0 +1,98 @@
+//! a wasm-bindgen front end to the simulator for browser demos: a seeded plummer sphere stepped from
+//! javascript, with positions read back as a `Float32Array` ready for a webgl vertex buffer.
+//!
+//! ```js
+//! import init, { Simulation } from "./pkg/barneshutt3d_wasm.js";
+//!
+//! await init();
+//! const simulation = new Simulation(2000, 1);
+//! function frame() {
+//!     simulation.step(0.01);
+//!     gl.bufferData(gl.ARRAY_BUFFER, simulation.positions(), gl.DYNAMIC_DRAW);
+//!     gl.drawArrays(gl.POINTS, 0, simulation.len());
+//!     requestAnimationFrame(frame);
+//! }
+//! ```
+//!
+//! forces are computed in f64 on the linear tree, on one thread
+use barneshutt3d::{ic, Cuboid, PotentialMethod, SimulationConfig, TreeBackend};
+use js_sys::Float32Array;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Simulation {
+    inner: barneshutt3d::Simulation,
+    // x, y, z per body in f32, refreshed after every step for `positions` to look at
+    positions: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl Simulation {
+    /// a plummer sphere of `n` bodies with total mass 1 and scale radius 1, the same for the same seed
+    #[wasm_bindgen(constructor)]
+    pub fn new(n: usize, seed: u32) -> Simulation {
+        let bodies = ic::plummer(n, 1., 1., &mut StdRng::seed_from_u64(seed.into()));
+        let space = Cuboid::bounding(&bodies).to_power_of_two_cube();
+        let config = SimulationConfig {
+            theta: 0.7,
+            // about the interparticle spacing in the core, so close passes do not need tiny steps
+            softening: 0.5 / (n.max(1) as f64).cbrt(),
+            backend: TreeBackend::Linear,
+            bucket_size: 8,
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation {
+            inner: barneshutt3d::Simulation::with_config(bodies, space, config),
+            positions: Vec::new(),
+        };
+        simulation.refresh();
+        simulation
+    }
+
+    pub fn step(&mut self, dt: f64) {
+        self.inner.step(dt);
+        self.refresh();
+    }
+
+    /// x, y, z of every body, viewing wasm memory without a copy. the view is only good until the next call
+    /// into the module, which may grow the memory and detach it, so take a new one every frame and copy it
+    /// into a vertex buffer straight away
+    pub fn positions(&self) -> Float32Array {
+        // safety: nothing runs between handing out the view and javascript using it, as documented
+        unsafe { Float32Array::view(&self.positions) }
+    }
+
+    /// the bodies, which is the number of points in `positions`
+    pub fn len(&self) -> usize {
+        self.inner.bodies().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.bodies().is_empty()
+    }
+
+    /// simulated time so far
+    pub fn time(&self) -> f64 {
+        self.inner.time()
+    }
+
+    /// kinetic plus tree potential energy, which leapfrog keeps close to its starting value
+    #[wasm_bindgen(js_name = totalEnergy)]
+    pub fn total_energy(&self) -> f64 {
+        self.inner.diagnostics(PotentialMethod::Tree).total_energy()
+    }
+
+    fn refresh(&mut self) {
+        self.positions.clear();
+        self.positions
+            .extend(self.inner.bodies().iter().flat_map(|body| {
+                [
+                    body.location.x as f32,
+                    body.location.y as f32,
+                    body.location.z as f32,
+                ]
+            }));
+    }
+}